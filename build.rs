@@ -0,0 +1,51 @@
+//! Pre-builds the opcode database from the MarlinDocumentation front matter, so that the binary
+//! works without a docs checkout next to it.
+
+use std::path::{Path, PathBuf};
+
+#[path = "src/gcode/marlin_docs/model.rs"]
+mod model;
+
+#[path = "src/gcode/marlin_docs/embed.rs"]
+mod embed;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/gcode/marlin_docs/model.rs");
+    println!("cargo:rerun-if-changed=src/gcode/marlin_docs/embed.rs");
+    println!("cargo:rerun-if-env-changed=GCODE_VIZ_MARLIN_DOCS");
+
+    let docs_dir = match std::env::var_os("GCODE_VIZ_MARLIN_DOCS") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("vendor/MarlinDocumentation"),
+    };
+    let gcode_docs_dir = docs_dir.join("_gcode");
+    println!("cargo:rerun-if-changed={}", gcode_docs_dir.display());
+
+    let docs = match embed::read_docs(&gcode_docs_dir) {
+        Ok(docs) => docs,
+        Err(_) => {
+            println!(
+                "cargo:warning=no Marlin documentation in {}, embedding an empty opcode database",
+                gcode_docs_dir.display()
+            );
+            vec![]
+        }
+    };
+
+    let mut descriptions = vec![];
+    for (path, od) in docs {
+        match od {
+            Ok(od) => descriptions.push(od),
+            Err(err) => println!("cargo:warning=skipping {}: {}", path.display(), err),
+        }
+    }
+
+    // lets the binary warn at runtime that it can't document anything
+    println!(
+        "cargo:rustc-env=GCODE_VIZ_EMBEDDED_DOCS={}",
+        descriptions.len()
+    );
+    let out = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("marlin_docs.json.deflate");
+    std::fs::write(out, embed::encode(&descriptions)).unwrap();
+}
@@ -0,0 +1,95 @@
+use crate::commands::output;
+use clap::{Args, ValueEnum};
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::annotate::annotate_chunk;
+use gcode_viz::gcode::lines::{parse_lines_at, DisplayLine, LineIterator};
+use gcode_viz::gcode::machine::Machine;
+use gcode_viz::gcode::marlin_docs::Opcodes;
+use gcode_viz::gcode::parallel::AsChunk;
+use gcode_viz::input::{open_stream, InputOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct AnnotateArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// How to write the annotations
+    #[clap(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+
+    /// Include the whole documentation of each command in the JSON output, not just its title
+    #[clap(long, value_parser)]
+    docs: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `G1 X10 E1: Linear Move`, one command or comment per line
+    Text,
+    /// A JSON array of records
+    Json,
+    /// One JSON record per line
+    Ndjson,
+}
+
+/// Writes each command of `lines` with the title of its documentation, and each comment.
+pub fn write_text<'a, W: Write>(
+    out: &mut W,
+    lines: impl Iterator<Item = DisplayLine<'a>>,
+    opcodes: &Opcodes,
+) -> std::io::Result<()> {
+    for line in lines {
+        match line {
+            DisplayLine::Comment(c) => writeln!(out, "// {}", c.value)?,
+            DisplayLine::GCode(o, opcode) => match opcodes.get(o.as_str()) {
+                Some(od) => writeln!(out, "{}: {}", opcode, od.title)?,
+                None => writeln!(out, "{}: Unknown", opcode)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Writes the commands and comments of the file with their documentation and, in JSON, the
+/// state of the printer before and after each.
+pub fn run(args: &AnnotateArgs, opcodes: &Opcodes, input: &InputOptions) -> Result<()> {
+    let stream = open_stream(&args.file, input)?;
+    let mut out = output(args.output.as_deref())?;
+
+    // one chunk after the other, the machine carries the state over
+    let mut machine = Machine::new();
+    let mut first = true;
+    if args.format == Format::Json {
+        out.write_all(b"[")?;
+    }
+    for chunk in stream.chunks() {
+        let chunk = chunk?;
+        let chunk = chunk.as_chunk();
+        if args.format == Format::Text {
+            let lines = parse_lines_at(chunk.text, chunk.offset, chunk.first_line);
+            write_text(&mut out, LineIterator::new(lines), opcodes)?;
+            continue;
+        }
+        for annotation in annotate_chunk(chunk, &mut machine, opcodes, args.docs) {
+            if args.format == Format::Json {
+                out.write_all(if first { b"\n" } else { b",\n" })?;
+                first = false;
+            }
+            serde_json::to_writer(&mut out, &annotation)?;
+            if args.format == Format::Ndjson {
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+    if args.format == Format::Json {
+        out.write_all(b"\n]\n")?;
+    }
+    out.flush().wrap_err("writing the annotations")
+}
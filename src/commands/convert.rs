@@ -0,0 +1,96 @@
+use clap::{Args, ValueEnum};
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::bgcode::{Bgcode, Compression, ConvertOptions, GCodeEncoding};
+use gcode_viz::input::{decode_gcode, output_path, read_bytes, InputOptions};
+use std::path::{Path, PathBuf};
+
+/// Converts between text and binary G-code.
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// The file to write, binary if it ends in `.bgcode`; defaults to the input file with the
+    /// other extension
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+
+    /// Compression of the G-code blocks of binary files
+    #[clap(long, value_enum, default_value_t = BlockCompression::Heatshrink12)]
+    compression: BlockCompression,
+
+    /// Encoding of the G-code blocks of binary files
+    #[clap(long, value_enum, default_value_t = Encoding::MeatpackComments)]
+    encoding: Encoding,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum BlockCompression {
+    None,
+    Deflate,
+    Heatshrink11,
+    Heatshrink12,
+}
+
+impl From<BlockCompression> for Compression {
+    fn from(compression: BlockCompression) -> Self {
+        match compression {
+            BlockCompression::None => Compression::None,
+            BlockCompression::Deflate => Compression::Deflate,
+            BlockCompression::Heatshrink11 => Compression::Heatshrink11,
+            BlockCompression::Heatshrink12 => Compression::Heatshrink12,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Encoding {
+    None,
+    Meatpack,
+    MeatpackComments,
+}
+
+impl From<Encoding> for GCodeEncoding {
+    fn from(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::None => GCodeEncoding::None,
+            Encoding::Meatpack => GCodeEncoding::MeatPack,
+            Encoding::MeatpackComments => GCodeEncoding::MeatPackComments,
+        }
+    }
+}
+
+pub fn run(args: &ConvertArgs, input: &InputOptions) -> Result<()> {
+    let file = args.file.as_str();
+    let data = read_bytes(Path::new(file))?;
+    let size = data.len();
+    let binary = gcode_viz::gcode::bgcode::is_bgcode(&data);
+    let text = decode_gcode(Path::new(file), data, input)?;
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| output_path(Path::new(file), if binary { "gcode" } else { "bgcode" }));
+    let to_binary = output.extension().is_some_and(|ext| ext == "bgcode");
+    let converted = if to_binary {
+        let options = ConvertOptions {
+            compression: args.compression.into(),
+            encoding: args.encoding.into(),
+        };
+        Bgcode::from_ascii(&text, options).write()
+    } else {
+        text.into_bytes()
+    };
+
+    std::fs::write(&output, &converted)
+        .wrap_err_with(|| format!("writing {}", output.display()))?;
+    println!(
+        "Wrote {} ({} bytes, {:.1}% of {} bytes)",
+        output.display(),
+        converted.len(),
+        converted.len() as f64 * 100.0 / size.max(1) as f64,
+        size
+    );
+    Ok(())
+}
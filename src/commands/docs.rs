@@ -0,0 +1,164 @@
+use crate::commands::load_docs;
+use clap::{Subcommand, ValueEnum};
+use eyre::{eyre, Result};
+use gcode_viz::gcode::marlin_docs;
+use gcode_viz::gcode::marlin_docs::examples::verify_examples;
+use gcode_viz::gcode::marlin_docs::export::{export, ExportFormat};
+use gcode_viz::gcode::marlin_docs::groups::group_index;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug)]
+pub enum DocsCommand {
+    /// Validate a MarlinDocumentation checkout and list every file that fails to load
+    Check,
+    /// Search the documentation, e.g. `docs search linear advance`
+    Search {
+        /// Words to look for in the titles, descriptions and bodies
+        #[clap(value_parser, required = true)]
+        query: Vec<String>,
+
+        /// Maximum number of results
+        #[clap(short, long, value_parser, default_value_t = 10)]
+        limit: usize,
+    },
+    /// List the documentation groups, or the commands of one group
+    Groups {
+        /// Group to list the commands of, e.g. `thermal`
+        #[clap(value_parser)]
+        group: Option<String>,
+    },
+    /// Write the documentation as a static site, markdown files or man pages for offline use
+    Export {
+        #[clap(short, long, value_enum, default_value_t = Format::Html)]
+        format: Format,
+
+        /// Directory to write the pages to, created if missing
+        #[clap(value_parser)]
+        dir: PathBuf,
+    },
+    /// Parse every documented example and report commands or parameters they don't document
+    VerifyExamples,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Html,
+    Man,
+    Markdown,
+}
+
+impl From<Format> for ExportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Html => ExportFormat::Html,
+            Format::Man => ExportFormat::Man,
+            Format::Markdown => ExportFormat::Markdown,
+        }
+    }
+}
+
+pub fn run(command: &DocsCommand, marlin_docs_dir: Option<&Path>) -> Result<()> {
+    match command {
+        DocsCommand::Check => check(marlin_docs_dir),
+        DocsCommand::Search { query, limit } => search(marlin_docs_dir, &query.join(" "), *limit),
+        DocsCommand::Groups { group } => groups(marlin_docs_dir, group.as_deref()),
+        DocsCommand::Export { format, dir } => export_docs(marlin_docs_dir, *format, dir),
+        DocsCommand::VerifyExamples => verify(marlin_docs_dir),
+    }
+}
+
+fn export_docs(marlin_docs_dir: Option<&Path>, format: Format, dir: &Path) -> Result<()> {
+    let (opcodes, _) = load_docs(marlin_docs_dir)?;
+    let written = export(&opcodes, format.into(), dir)?;
+    println!(
+        "Wrote {} pages to {}, start with {}",
+        written.len(),
+        dir.display(),
+        written[0].display()
+    );
+    Ok(())
+}
+
+fn verify(marlin_docs_dir: Option<&Path>) -> Result<()> {
+    let (opcodes, _) = load_docs(marlin_docs_dir)?;
+    let report = verify_examples(&opcodes);
+    for issue in report.issues.iter() {
+        println!("{}", issue);
+    }
+    println!(
+        "Checked {} lines in {} examples, {} issues",
+        report.lines,
+        report.examples,
+        report.issues.len()
+    );
+
+    if report.issues.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "{} example lines don't match their documentation",
+            report.issues.len()
+        ))
+    }
+}
+
+fn groups(marlin_docs_dir: Option<&Path>, group: Option<&str>) -> Result<()> {
+    let (opcodes, _) = load_docs(marlin_docs_dir)?;
+    let index = group_index(&opcodes);
+
+    match group {
+        None => {
+            for (group, descriptions) in index.iter() {
+                println!("{:<20} {:>4} commands", group, descriptions.len());
+            }
+        }
+        Some(group) => {
+            let descriptions = index
+                .get(&group.to_lowercase())
+                .ok_or_else(|| eyre!("No documentation group {}", group))?;
+            for od in descriptions {
+                println!("{:<14} {} - {}", od.codes.join(", "), od.title, od.brief);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn search(marlin_docs_dir: Option<&Path>, query: &str, limit: usize) -> Result<()> {
+    let (opcodes, _) = load_docs(marlin_docs_dir)?;
+    let hits = marlin_docs::search(&opcodes, query, limit);
+    if hits.is_empty() {
+        println!("No documentation matches \"{}\"", query);
+    }
+    for hit in hits {
+        let od = &hit.description;
+        println!("{:<14} {} - {}", od.codes.join(", "), od.title, od.brief);
+    }
+    Ok(())
+}
+
+fn check(marlin_docs_dir: Option<&Path>) -> Result<()> {
+    let marlin_docs_dir = marlin_docs_dir
+        .ok_or_else(|| eyre!("Pass the checkout to check with --marlin-docs-dir"))?;
+    let gcode_docs_dir = marlin_docs_dir.join("_gcode");
+    let (opcodes, report) = marlin_docs::parse_marlin_docs(&gcode_docs_dir)?;
+
+    println!(
+        "Loaded {} files ({} opcodes) from {}",
+        report.loaded.len(),
+        opcodes.len(),
+        gcode_docs_dir.display()
+    );
+    if !report.skipped.is_empty() {
+        println!("Skipped {} non-markdown entries", report.skipped.len());
+    }
+    for failure in report.failures.iter() {
+        println!("{}", failure);
+    }
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(eyre!("{} files failed to load", report.failures.len()))
+    }
+}
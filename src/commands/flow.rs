@@ -0,0 +1,217 @@
+use crate::commands::output;
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::gcode::flow::{BinWidths, FlowReport, FlowTotals, Histogram, Limits, Quantity};
+use gcode_viz::gcode::parallel;
+use gcode_viz::gcode::profile::{load_profile, MachineProfile};
+use gcode_viz::gcode::stats::format_duration;
+use gcode_viz::input::{open_stream, InputOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct FlowArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Name of a built-in machine profile or path of a TOML profile, for the filament
+    /// diameter and the limits
+    #[clap(short, long, value_parser)]
+    profile: Option<String>,
+
+    /// Maximum volumetric flow of the hotend in mm³/s, overrides the profile
+    #[clap(long, value_parser)]
+    max_flow: Option<f64>,
+
+    /// Maximum print speed in mm/s, overrides the profile
+    #[clap(long, value_parser)]
+    max_speed: Option<f64>,
+
+    /// Filament diameter in mm, overrides the profile
+    #[clap(long, value_parser)]
+    filament_diameter: Option<f64>,
+
+    /// Width of the bins of the volumetric flow histograms in mm³/s
+    #[clap(long, value_parser, default_value_t = BinWidths::default().flow)]
+    flow_bin: f64,
+
+    /// Width of the bins of the speed histograms in mm/s
+    #[clap(long, value_parser, default_value_t = BinWidths::default().speed)]
+    speed_bin: f64,
+
+    /// How to write the histograms and the moves over the limits
+    #[clap(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+
+    /// Exit with an error when moves go over the limits
+    #[clap(long, value_parser)]
+    strict: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Histograms per feature and a line per run of moves over the limits
+    Text,
+    /// A JSON object with the limits, the histograms and the runs over the limits
+    Json,
+}
+
+/// Width of the longest bar of a histogram.
+const HISTOGRAM_WIDTH: usize = 30;
+
+/// Runs over the limits listed in the text output, the JSON has them all.
+const MAX_LISTED: usize = 20;
+
+fn title(quantity: Quantity) -> &'static str {
+    match quantity {
+        Quantity::Flow => "Volumetric flow",
+        Quantity::Speed => "Print speed",
+    }
+}
+
+fn write_histogram<W: Write>(
+    out: &mut W,
+    name: &str,
+    histogram: &Histogram,
+    limit: f64,
+) -> std::io::Result<()> {
+    writeln!(out, "  {} (max {:.1})", name, histogram.max)?;
+    let longest = histogram
+        .bins
+        .iter()
+        .map(|bin| bin.time)
+        .fold(0.0, f64::max);
+    for bin in histogram.bins.iter() {
+        let bar = if longest > 0.0 {
+            (bin.time / longest * HISTOGRAM_WIDTH as f64).ceil() as usize
+        } else {
+            0
+        };
+        let over = if bin.start + histogram.bin_width > limit {
+            "!"
+        } else {
+            " "
+        };
+        let [start, end] = histogram.edges(bin);
+        let row = format!(
+            "  {} {:>6}..{:<6}{:>8} moves {:>10}  {}",
+            over,
+            start,
+            end,
+            bin.moves,
+            format_duration(bin.time),
+            "#".repeat(bar)
+        );
+        writeln!(out, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
+
+fn write_text<W: Write>(out: &mut W, report: &FlowReport, profile: &str) -> std::io::Result<()> {
+    let limits = &report.limits;
+    writeln!(
+        out,
+        "Limits of {}: {} mm³/s, {} mm/s, {} mm filament",
+        profile, limits.max_flow, limits.max_speed, limits.filament_diameter
+    )?;
+    for quantity in [Quantity::Flow, Quantity::Speed] {
+        let limit = limits.limit(quantity);
+        writeln!(out, "\n{} ({})", title(quantity), quantity.unit())?;
+        for feature in std::iter::once(&report.total).chain(report.features.iter()) {
+            let histogram = match quantity {
+                Quantity::Flow => &feature.flow,
+                Quantity::Speed => &feature.speed,
+            };
+            write_histogram(out, &feature.feature, histogram, limit)?;
+        }
+    }
+
+    if report.violations.is_empty() {
+        writeln!(out, "\nNo moves over the limits")?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "\n{} runs of moves over the limits",
+        report.violations.len()
+    )?;
+    for v in report.violations.iter().take(MAX_LISTED) {
+        writeln!(
+            out,
+            "  lines {}-{}, layer {}, {}: {} up to {:.1} {} over {} for {} moves, {}",
+            v.lines.start,
+            v.lines.end - 1,
+            v.layer,
+            v.feature,
+            title(v.quantity).to_lowercase(),
+            v.peak,
+            v.quantity.unit(),
+            v.limit,
+            v.moves,
+            format_duration(v.time)
+        )?;
+    }
+    if report.violations.len() > MAX_LISTED {
+        writeln!(
+            out,
+            "  and {} more, see --format json",
+            report.violations.len() - MAX_LISTED
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the volumetric flow and speed histograms of the file and the moves going over the
+/// limits of the hotend.
+pub fn run(args: &FlowArgs, input: &InputOptions) -> Result<()> {
+    let profile = match args.profile.as_deref() {
+        Some(name) => load_profile(name)?,
+        None => MachineProfile::default(),
+    };
+    let mut limits = Limits::from(&profile);
+    limits.max_flow = args.max_flow.unwrap_or(limits.max_flow);
+    limits.max_speed = args.max_speed.unwrap_or(limits.max_speed);
+    limits.filament_diameter = args.filament_diameter.unwrap_or(limits.filament_diameter);
+    if args.flow_bin <= 0.0 || args.speed_bin <= 0.0 {
+        return Err(eyre!("the bins of the histograms must be wider than 0"));
+    }
+
+    let widths = BinWidths {
+        flow: args.flow_bin,
+        speed: args.speed_bin,
+    };
+    let mut totals = FlowTotals::new(limits, widths);
+    parallel::fold(
+        open_stream(&args.file, input)?.chunks(),
+        |_, _| FlowTotals::new(limits, widths),
+        |next| {
+            totals = std::mem::replace(&mut totals, FlowTotals::new(limits, widths)).merge(next);
+            Ok(())
+        },
+    )?;
+    let report = totals.finish();
+
+    let mut out = output(args.output.as_deref())?;
+    match args.format {
+        Format::Text => write_text(&mut out, &report, &profile.name)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush().wrap_err("writing the flow report")?;
+
+    if args.strict && !report.violations.is_empty() {
+        return Err(eyre!(
+            "{}: {} runs of moves over the limits",
+            args.file,
+            report.violations.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use eyre::Result;
+use gcode_viz::gcode::lines::{parse_lines, DisplayLine, LineIterator};
+use gcode_viz::gcode::marlin_docs::groups::group_usage;
+use gcode_viz::gcode::marlin_docs::Opcodes;
+use gcode_viz::input::{open_gcode, InputOptions};
+
+/// Prints which documentation groups the commands of `file` belong to, and how often.
+pub fn run(file: &str, opcodes: &Opcodes, input: &InputOptions) -> Result<()> {
+    let s = open_gcode(file, input)?;
+    let codes = LineIterator::new(parse_lines(&s)).filter_map(|line| match line {
+        DisplayLine::GCode(opcode, _) => Some(opcode),
+        DisplayLine::Comment(_) => None,
+    });
+
+    for usage in group_usage(opcodes, codes) {
+        let commands = usage
+            .commands
+            .iter()
+            .map(|(code, count)| format!("{} ({})", code, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("{:<16} {:>8}  {}", usage.group, usage.count, commands);
+    }
+    Ok(())
+}
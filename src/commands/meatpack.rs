@@ -0,0 +1,175 @@
+use crate::commands::load_docs;
+use clap::Subcommand;
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::marlin_docs::Opcodes;
+use gcode_viz::gcode::meatpack::{
+    bandwidth, decode_stream, pack, restore_spaces, Bandwidth, Command, PackOptions, StreamItem,
+};
+use gcode_viz::input::{open_gcode, output_path, read_bytes, InputError, InputOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug)]
+pub enum MeatpackCommand {
+    /// Show how much packing a GCode file saves over a serial port
+    Stats {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+
+        /// Speed of the serial port
+        #[clap(short, long, value_parser, default_value_t = 115200)]
+        baud: u32,
+    },
+    /// Pack a GCode file into the stream a host would send
+    Pack {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+
+        /// The file to write, defaults to the GCode file with a `.meatpack` extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+
+        /// Drop the spaces between words and pack `E` instead
+        #[clap(long, value_parser)]
+        no_spaces: bool,
+
+        /// Keep the comments
+        #[clap(long, value_parser)]
+        comments: bool,
+    },
+    /// Decode a captured serial stream into GCode annotated with the signals, the bytes each
+    /// line took and the documentation of its command
+    Unpack {
+        /// Name of the captured stream
+        #[clap(value_parser)]
+        file: String,
+
+        /// The file to write, defaults to the standard output
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn run(
+    command: &MeatpackCommand,
+    marlin_docs_dir: Option<&Path>,
+    input: &InputOptions,
+) -> Result<()> {
+    match command {
+        MeatpackCommand::Stats { file, baud } => stats(file, *baud, input),
+        MeatpackCommand::Pack {
+            file,
+            output,
+            no_spaces,
+            comments,
+        } => {
+            let options = PackOptions {
+                no_spaces: *no_spaces,
+                comments: *comments,
+            };
+            let output = output
+                .as_deref()
+                .map_or_else(|| output_path(Path::new(file), "meatpack"), PathBuf::from);
+            let packed = pack(&open_gcode(file, input)?, options);
+            std::fs::write(&output, &packed)
+                .wrap_err_with(|| format!("writing {}", output.display()))?;
+            println!("Wrote {} ({} bytes)", output.display(), packed.len());
+            Ok(())
+        }
+        MeatpackCommand::Unpack { file, output } => {
+            // a MeatPack stream is never packaged
+            if let Some(plate) = input.plate {
+                let path = PathBuf::from(file);
+                return Err(InputError::NotContainer { path, plate }.into());
+            }
+            let (opcodes, _) = load_docs(marlin_docs_dir)?;
+            unpack(file, output.as_deref(), &opcodes)
+        }
+    }
+}
+
+fn stats(file: &str, baud: u32, input: &InputOptions) -> Result<()> {
+    let s = open_gcode(file, input)?;
+    let packed = bandwidth(&s, false);
+    let no_spaces = bandwidth(&s, true);
+    println!("{}: {} lines sent", file, packed.lines);
+    println!(
+        "{:<10} {:>10} {:>12}",
+        "",
+        "bytes",
+        format!("{} baud", baud)
+    );
+    let row = |name: &str, bytes: usize, ratio: Option<f64>| {
+        println!(
+            "{:<10} {:>10} {:>11.1}s{}",
+            name,
+            bytes,
+            Bandwidth::seconds(bytes, baud),
+            ratio.map_or(String::new(), |ratio| format!(
+                "  {:.1}%, saves {:.1}s",
+                ratio * 100.0,
+                Bandwidth::seconds(packed.text - bytes.min(packed.text), baud)
+            ))
+        )
+    };
+    row("text", packed.text, None);
+    row("packed", packed.packed, Some(packed.ratio()));
+    row("no spaces", no_spaces.packed, Some(no_spaces.ratio()));
+    Ok(())
+}
+
+fn signal_name(command: Command) -> &'static str {
+    match command {
+        Command::EnablePacking => "enable packing",
+        Command::DisablePacking => "disable packing",
+        Command::ResetAll => "reset",
+        Command::QueryConfig => "query configuration",
+        Command::EnableNoSpaces => "enable no-spaces mode",
+        Command::DisableNoSpaces => "disable no-spaces mode",
+    }
+}
+
+fn unpack(file: &str, output: Option<&Path>, opcodes: &Opcodes) -> Result<()> {
+    let data = read_bytes(Path::new(file))?;
+    let mut out = String::new();
+    for item in decode_stream(&data) {
+        match item {
+            StreamItem::Signal(command) => {
+                out.push_str(&format!("; meatpack: {}\n", signal_name(command)))
+            }
+            StreamItem::Line {
+                text,
+                no_spaces,
+                bytes,
+            } => {
+                let line = if no_spaces {
+                    restore_spaces(&text)
+                } else {
+                    text
+                };
+                let code = line
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_uppercase();
+                let mut annotation = format!("{} bytes", bytes);
+                if let Some(od) = opcodes.get(&code) {
+                    annotation.push_str(&format!(", {}", od.title));
+                }
+                let separator = if line.contains(';') { " | " } else { " ; " };
+                out.push_str(&format!("{}{}{}\n", line, separator, annotation));
+            }
+        }
+    }
+
+    match output {
+        Some(output) => {
+            std::fs::write(output, out).wrap_err_with(|| format!("writing {}", output.display()))
+        }
+        None => std::io::stdout()
+            .write_all(out.as_bytes())
+            .wrap_err("writing to the standard output"),
+    }
+}
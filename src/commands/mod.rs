@@ -0,0 +1,68 @@
+pub mod annotate;
+pub mod convert;
+pub mod docs;
+pub mod flow;
+pub mod groups;
+pub mod meatpack;
+pub mod modify;
+pub mod moves;
+pub mod png;
+pub mod render;
+pub mod report;
+pub mod resume;
+pub mod stats;
+pub mod svg;
+pub mod thumbnails;
+pub mod widths;
+
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::marlin_docs;
+use gcode_viz::gcode::marlin_docs::{DocsLoadReport, Opcodes};
+use std::io::{BufWriter, ErrorKind, StdoutLock, Write};
+use std::path::Path;
+
+/// The output of a command: the file `path`, created or truncated, or the standard output.
+pub fn output(path: Option<&Path>) -> Result<BufWriter<Box<dyn Write>>> {
+    Ok(BufWriter::new(match path {
+        Some(path) => Box::new(
+            std::fs::File::create(path).wrap_err_with(|| format!("creating {}", path.display()))?,
+        ),
+        None => Box::new(Stdout(std::io::stdout().lock())),
+    }))
+}
+
+/// The standard output, ending the program quietly once the reader is gone, e.g. when piped
+/// into `head`: there is nobody left to read the rest of the output or an error message.
+struct Stdout(StdoutLock<'static>);
+
+impl Stdout {
+    fn check<T>(result: std::io::Result<T>) -> std::io::Result<T> {
+        match result {
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => std::process::exit(0),
+            result => result,
+        }
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Self::check(self.0.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Self::check(self.0.flush())
+    }
+}
+
+/// Loads the opcode documentation, warning when there is none to load: no `marlin_docs_dir`
+/// and a binary built without the documentation.
+pub fn load_docs(marlin_docs_dir: Option<&Path>) -> Result<(Opcodes, DocsLoadReport)> {
+    if marlin_docs_dir.is_none() && marlin_docs::embedded_count() == 0 {
+        eprintln!(
+            "warning: gcode-viz was built without the Marlin documentation, commands won't be \
+             documented. Pass --marlin-docs-dir, or rebuild with the vendor/MarlinDocumentation \
+             submodule or GCODE_VIZ_MARLIN_DOCS set."
+        );
+    }
+    Ok(marlin_docs::load_marlin_docs(marlin_docs_dir)?)
+}
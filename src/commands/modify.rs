@@ -0,0 +1,163 @@
+use crate::commands::output;
+use clap::Args;
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::gcode::modify::{apply, plan, At, Change, Modification, Snippets};
+use gcode_viz::gcode::parallel;
+use gcode_viz::input::{open_gcode_bytes, InputOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ModifyArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Change the filament with `M600` before a layer, e.g. `12`, or `z2.4` for the first
+    /// layer at or above 2.4 mm
+    #[clap(long, value_parser = parse_at, value_name = "LAYER")]
+    filament_change: Vec<At>,
+
+    /// Pause before a layer, e.g. `12` or `z2.4`
+    #[clap(long, value_parser = parse_at, value_name = "LAYER")]
+    pause: Vec<At>,
+
+    /// The command pausing the print, `M0` for the display, `M25` for a print from the SD
+    /// card or a host
+    #[clap(long, value_parser, default_value = "M0")]
+    pause_command: String,
+
+    /// Set the hotend temperature in °C from a layer on, e.g. `z10=215`, repeat it for a
+    /// temperature tower
+    #[clap(long, value_parser = parse_temperature, value_name = "LAYER=°C")]
+    temperature: Vec<(At, f64)>,
+
+    /// Set the fan speed in percent from a layer on, e.g. `3=100`
+    #[clap(long, value_parser = parse_percent, value_name = "LAYER=%")]
+    fan: Vec<(At, f64)>,
+
+    /// Set the flow factor in percent from a layer on, e.g. `z5=95`
+    #[clap(long, value_parser = parse_percent, value_name = "LAYER=%")]
+    flow: Vec<(At, f64)>,
+
+    /// G-code to insert before every layer change, `{layer}` and `{z}` are replaced by the
+    /// layer and its height and `\n` separates lines
+    #[clap(long, value_parser, value_name = "GCODE")]
+    before_layer: Vec<String>,
+
+    /// G-code to insert after the move to the height of every layer, like `--before-layer`
+    #[clap(long, value_parser, value_name = "GCODE")]
+    after_layer: Vec<String>,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+/// Parses a layer, `12`, or a height, `z2.4`.
+fn parse_at(s: &str) -> Result<At, String> {
+    match s.strip_prefix(['z', 'Z']) {
+        Some(z) => z
+            .parse::<f64>()
+            .map(At::Z)
+            .map_err(|err| format!("{}: {}", s, err)),
+        None => s
+            .parse::<usize>()
+            .map(At::Layer)
+            .map_err(|_| format!("expected a layer like 12 or a height like z2.4, got {}", s)),
+    }
+}
+
+fn parse_setting(s: &str) -> Result<(At, f64), String> {
+    let (at, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected a layer and a value like z10=215, got {}", s))?;
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("{}: {}", value, err))?;
+    Ok((parse_at(at.trim())?, value))
+}
+
+fn parse_temperature(s: &str) -> Result<(At, f64), String> {
+    let (at, temperature) = parse_setting(s)?;
+    if temperature < 0.0 {
+        return Err(format!("{} is below 0°C", temperature));
+    }
+    Ok((at, temperature))
+}
+
+fn parse_percent(s: &str) -> Result<(At, f64), String> {
+    let (at, percent) = parse_setting(s)?;
+    if percent < 0.0 {
+        return Err(format!("{}% is below 0", percent));
+    }
+    Ok((at, percent))
+}
+
+impl ModifyArgs {
+    fn modifications(&self) -> Vec<Modification> {
+        let mut modifications = vec![];
+        let mut add = |at: At, change: Change| modifications.push(Modification { at, change });
+        for at in self.filament_change.iter() {
+            add(*at, Change::FilamentChange);
+        }
+        for at in self.pause.iter() {
+            add(*at, Change::Pause(self.pause_command.clone()));
+        }
+        for (at, temperature) in self.temperature.iter() {
+            add(*at, Change::Temperature(*temperature));
+        }
+        for (at, percent) in self.fan.iter() {
+            add(*at, Change::Fan(percent.min(100.0)));
+        }
+        for (at, percent) in self.flow.iter() {
+            add(*at, Change::Flow(*percent));
+        }
+        modifications
+    }
+}
+
+/// Writes the file with filament changes, pauses, temperature, fan and flow changes and
+/// snippets inserted at its layer changes.
+pub fn run(args: &ModifyArgs, input: &InputOptions) -> Result<()> {
+    let modifications = args.modifications();
+    let lines = |snippets: &[String]| -> Vec<String> {
+        snippets.iter().map(|s| s.replace("\\n", "\n")).collect()
+    };
+    let snippets = Snippets {
+        before_layer: lines(&args.before_layer),
+        after_layer: lines(&args.after_layer),
+    };
+    if modifications.is_empty()
+        && snippets.before_layer.is_empty()
+        && snippets.after_layer.is_empty()
+    {
+        return Err(eyre!("nothing to modify, see gcode-viz modify --help"));
+    }
+
+    // the file is written back as it was read, the text is only for finding the layers
+    let data = open_gcode_bytes(&args.file, input)?;
+    let s = String::from_utf8_lossy(&data);
+    let toolpath = parallel::simulate(&s);
+    let insertions =
+        plan(&s, &toolpath, &modifications, &snippets).wrap_err_with(|| args.file.clone())?;
+    let modified = apply(&data, &insertions);
+
+    match args.output.as_deref() {
+        Some(path) => {
+            std::fs::write(path, &modified)
+                .wrap_err_with(|| format!("writing {}", path.display()))?;
+            for insertion in insertions.iter() {
+                println!("line {}: {}", insertion.line + 1, insertion.description);
+            }
+            println!("Wrote {}", path.display());
+        }
+        None => {
+            let mut out = output(None)?;
+            out.write_all(&modified)?;
+            out.flush().wrap_err("writing the modified file")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use crate::commands::output;
+use clap::{Args, ValueEnum};
+use eyre::{Report, Result, WrapErr};
+use gcode_viz::gcode::parallel;
+use gcode_viz::gcode::table::{write_csv_row, ChunkMoves, MoveRow, Rows, COLUMNS};
+use gcode_viz::input::{open_stream, InputOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct MovesArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// How to write the table
+    #[clap(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON record per line
+    Ndjson,
+}
+
+/// Writes a row for each move of the file.
+pub fn run(args: &MovesArgs, input: &InputOptions) -> Result<()> {
+    let stream = open_stream(&args.file, input)?;
+    let mut out = output(args.output.as_deref())?;
+    let format = args.format;
+    if format == Format::Csv {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+    let mut write = |row: MoveRow| match format {
+        Format::Csv => write_csv_row(&mut out, &row),
+        Format::Ndjson => {
+            serde_json::to_writer(&mut out, &row)?;
+            out.write_all(b"\n")
+        }
+    };
+    let mut table = Rows::default();
+    parallel::fold(
+        stream.chunks().map(|chunk| chunk.map_err(Report::from)),
+        |_, _| ChunkMoves::default(),
+        |chunk| {
+            for (m, temperatures) in chunk.0 {
+                table.push(m, temperatures, &mut write)?;
+            }
+            Ok(())
+        },
+    )?;
+    table.finish(&mut write)?;
+    out.flush().wrap_err("writing the moves")
+}
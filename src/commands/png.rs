@@ -0,0 +1,60 @@
+use crate::commands::render::RenderArgs;
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::input::{output_path, InputOptions};
+use gcode_viz::render::raster::{rasterize, RasterOptions};
+use gcode_viz::render::Rgb;
+use std::path::{Path, PathBuf};
+
+/// Draws `file` as PNG to `output`, next to `file` by default.
+pub fn run(
+    file: &str,
+    output: Option<&Path>,
+    args: &RenderArgs,
+    height: Option<u32>,
+    background: Rgb,
+    input: &InputOptions,
+) -> Result<()> {
+    let render = args.load(file, input)?;
+    let output = output.map_or_else(|| output_path(Path::new(file), "png"), PathBuf::from);
+
+    let options = RasterOptions {
+        width: render.width,
+        height,
+        travel: render.travel,
+        view: render.view,
+        background,
+        ..RasterOptions::default()
+    };
+    let image = rasterize(
+        &render.toolpath,
+        render.layers.clone(),
+        &render.colorizer,
+        render.profile.as_ref(),
+        &options,
+    );
+    let png = image
+        .encode_png()
+        .map_err(|err| eyre!("encoding {}: {}", output.display(), err))?;
+    std::fs::write(&output, png).wrap_err_with(|| format!("writing {}", output.display()))?;
+    println!(
+        "Wrote {} ({}x{})",
+        output.display(),
+        image.width,
+        image.height
+    );
+    Ok(())
+}
+
+/// Parses a colour written as `#rrggbb` or `rrggbb`.
+pub fn parse_color(s: &str) -> Result<Rgb, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(|| format!("expected a colour like #ffffff, got {}", s))
+    };
+    if hex.len() != 6 {
+        return Err(format!("expected a colour like #ffffff, got {}", s));
+    }
+    Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
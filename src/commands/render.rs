@@ -0,0 +1,160 @@
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Result};
+use gcode_viz::gcode::machine::Toolpath;
+use gcode_viz::gcode::parallel::simulate;
+use gcode_viz::gcode::profile::{load_profile, MachineProfile};
+use gcode_viz::input::{open_gcode, InputOptions};
+use gcode_viz::render::{ColorBy, Colorizer, View};
+use std::ops::Range;
+
+/// Which part of a file to draw and how, shared by the image exports.
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Only draw this layer, starting at 0
+    #[clap(long, value_parser, conflicts_with = "layers")]
+    layer: Option<usize>,
+
+    /// Only draw these layers, e.g. `10..20`, `10..=20` or `10..`
+    #[clap(long, value_parser = parse_layer_range)]
+    layers: Option<LayerRange>,
+
+    /// Draw an isometric view instead of the view from above
+    #[clap(long, value_parser)]
+    iso: bool,
+
+    /// What the colours of the lines show
+    #[clap(short, long, value_enum, default_value_t = Color::Feature)]
+    color: Color,
+
+    /// Name of a built-in machine profile or path of a TOML profile, draws the bed outline
+    #[clap(short, long, value_parser)]
+    profile: Option<String>,
+
+    /// Width of the image in pixels
+    #[clap(short, long, value_parser, default_value_t = 800)]
+    width: u32,
+
+    /// Also draw the travel moves
+    #[clap(long, value_parser)]
+    travel: bool,
+
+    /// Values of the first and last colour of the gradient, e.g. `20..120`, instead of the
+    /// smallest and largest value of the file
+    #[clap(long, value_parser = parse_color_range)]
+    range: Option<(f64, f64)>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Color {
+    Feature,
+    Speed,
+    Width,
+    /// Width relative to the median of the feature
+    WidthDeviation,
+    Flow,
+    Fan,
+}
+
+impl From<Color> for ColorBy {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Feature => ColorBy::Feature,
+            Color::Speed => ColorBy::Speed,
+            Color::Width => ColorBy::Width,
+            Color::WidthDeviation => ColorBy::WidthDeviation,
+            Color::Flow => ColorBy::Flow,
+            Color::Fan => ColorBy::Fan,
+        }
+    }
+}
+
+/// A range of layers, the end is `None` for all layers from the start on.
+#[derive(Clone, Copy, Debug)]
+pub struct LayerRange {
+    start: usize,
+    end: Option<usize>,
+}
+
+fn parse_layer_range(s: &str) -> Result<LayerRange, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("expected a range like 10..20, got {}", s))?;
+    let number = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|err| format!("{}: {}", n, err))
+    };
+    let start = if start.is_empty() { 0 } else { number(start)? };
+    let end = match end.strip_prefix('=') {
+        Some(end) => Some(number(end)? + 1),
+        None if end.is_empty() => None,
+        None => Some(number(end)?),
+    };
+    if end.is_some_and(|end| end <= start) {
+        return Err(format!("{} is an empty range", s));
+    }
+    Ok(LayerRange { start, end })
+}
+
+fn parse_color_range(s: &str) -> Result<(f64, f64), String> {
+    let (min, max) = s
+        .split_once("..")
+        .ok_or_else(|| format!("expected a range like 20..120, got {}", s))?;
+    let number = |n: &str| {
+        n.trim()
+            .parse::<f64>()
+            .map_err(|err| format!("{}: {}", n, err))
+    };
+    let (min, max) = (number(min)?, number(max)?);
+    if max <= min {
+        return Err(format!("{} is an empty range", s));
+    }
+    Ok((min, max))
+}
+
+/// A file simulated and ready to be drawn according to [`RenderArgs`].
+pub struct Render {
+    pub toolpath: Toolpath,
+    pub layers: Range<usize>,
+    pub colorizer: Colorizer,
+    pub profile: Option<MachineProfile>,
+    pub view: View,
+    pub width: u32,
+    pub travel: bool,
+}
+
+impl RenderArgs {
+    /// Reads and simulates `file`.
+    pub fn load(&self, file: &str, input: &InputOptions) -> Result<Render> {
+        let s = open_gcode(file, input)?;
+        let toolpath = simulate(&s);
+        let count = toolpath.layers.len();
+        let layers = match (self.layer, self.layers) {
+            (Some(layer), _) => layer..layer + 1,
+            (None, Some(range)) => range.start..range.end.unwrap_or(count),
+            (None, None) => 0..count,
+        };
+        if layers.start >= count {
+            return Err(eyre!("{} only has {} layers", file, count));
+        }
+
+        let profile = self.profile.as_deref().map(load_profile).transpose()?;
+        let mut colorizer = Colorizer::new(
+            &toolpath,
+            self.color.into(),
+            profile.as_ref().unwrap_or(&MachineProfile::default()),
+        );
+        if let Some(range) = self.range {
+            colorizer = colorizer.with_range(range);
+        }
+        Ok(Render {
+            toolpath,
+            layers,
+            colorizer,
+            profile,
+            view: if self.iso { View::Isometric } else { View::Top },
+            width: self.width,
+            travel: self.travel,
+        })
+    }
+}
@@ -0,0 +1,29 @@
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::marlin_docs::Opcodes;
+use gcode_viz::input::{open_stream, output_path, InputOptions};
+use gcode_viz::report::{html_report, ReportOptions};
+use std::path::{Path, PathBuf};
+
+/// Writes the HTML report of `file` to `output`, next to `file` by default.
+pub fn run(
+    file: &str,
+    output: Option<&Path>,
+    opcodes: &Opcodes,
+    input: &InputOptions,
+) -> Result<()> {
+    let stream = open_stream(file, input)?;
+    let options = ReportOptions {
+        metadata: stream.metadata().to_vec(),
+        ..ReportOptions::default()
+    };
+    let s = stream.text()?;
+    let output = output.map_or_else(|| output_path(Path::new(file), "html"), PathBuf::from);
+
+    let title = Path::new(file)
+        .file_name()
+        .map_or(file.into(), |name| name.to_string_lossy());
+    let report = html_report(&title, &s, opcodes, &options);
+    std::fs::write(&output, report).wrap_err_with(|| format!("writing {}", output.display()))?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use crate::commands::output;
+use clap::Args;
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::gcode::parallel;
+use gcode_viz::gcode::resume::{resume, ResumePoint};
+use gcode_viz::input::{open_gcode_bytes, InputOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ResumeArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Resume at the start of this layer, starting at 0
+    #[clap(long, value_parser, conflicts_with_all = &["line", "z"])]
+    layer: Option<usize>,
+
+    /// Resume at this line, starting at 1
+    #[clap(long, value_parser, conflicts_with = "z")]
+    line: Option<usize>,
+
+    /// Resume at the first layer printed at or above this height in mm
+    #[clap(long, value_parser)]
+    z: Option<f64>,
+
+    /// How far to lift the nozzle in mm before homing X and Y
+    #[clap(long, value_parser, default_value_t = 5.0)]
+    lift: f64,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+/// Writes the file from the resume point on, after a preamble restoring the state of the
+/// printer there.
+pub fn run(args: &ResumeArgs, input: &InputOptions) -> Result<()> {
+    let point = match (args.layer, args.line, args.z) {
+        (Some(layer), _, _) => ResumePoint::Layer(layer),
+        (_, Some(line), _) => ResumePoint::Line(line),
+        (_, _, Some(z)) => ResumePoint::Z(z),
+        _ => {
+            return Err(eyre!(
+                "give the point to resume at with --layer, --line or --z"
+            ))
+        }
+    };
+    if args.lift < 0.0 {
+        return Err(eyre!("the lift can't be negative"));
+    }
+
+    // the rest of the file is written back as it was read
+    let data = open_gcode_bytes(&args.file, input)?;
+    let toolpath = parallel::simulate(&String::from_utf8_lossy(&data));
+    let resume = resume(&data, &toolpath, point).wrap_err_with(|| args.file.clone())?;
+
+    let mut out = output(args.output.as_deref())?;
+    out.write_all(resume.preamble(args.lift).as_bytes())?;
+    out.write_all(resume.rest)?;
+    out.flush().wrap_err("writing the resumed file")
+}
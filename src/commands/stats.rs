@@ -0,0 +1,345 @@
+use crate::commands::{load_docs, output};
+use clap::{Args, ValueEnum};
+use eyre::{Result, WrapErr};
+use gcode_viz::gcode::machine::{Machine, Move, Observer};
+use gcode_viz::gcode::marlin_docs::Opcodes;
+use gcode_viz::gcode::parallel;
+use gcode_viz::gcode::stats::{count_lines_of, format_duration, LineCounts, Stats, Totals};
+use gcode_viz::input::{open_stream, InputOptions};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// How to write the statistics
+    #[clap(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns with a histogram of the opcodes
+    Text,
+    /// A JSON object with the counts, the totals and the per feature and per layer totals
+    Json,
+    /// Markdown tables
+    Markdown,
+}
+
+/// Width of the longest bar of the opcode histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    file: &'a str,
+    /// The metadata of the plate of a container, in the order of the container
+    #[serde(serialize_with = "as_map", skip_serializing_if = "<[_]>::is_empty")]
+    metadata: &'a [(String, String)],
+    counts: LineCounts,
+    stats: Stats,
+}
+
+fn as_map<S: Serializer>(pairs: &&[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(pairs.iter().map(|(key, value)| (key, value)))
+}
+
+fn metres(mm: f64) -> String {
+    format!("{:.2} m", mm / 1000.0)
+}
+
+fn percent(part: f64, whole: f64) -> f64 {
+    if whole > 0.0 {
+        part / whole * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// The range of targets, e.g. `200°C` or `195–215°C`.
+fn temperatures(min: f64, max: f64) -> String {
+    if min == max || min <= 0.0 {
+        format!("{}°C", max)
+    } else {
+        format!("{}–{}°C", min, max)
+    }
+}
+
+/// The heights of the layers with the number of layers of each, e.g. `0.3 mm × 1, 0.2 mm × 29`.
+fn layer_heights(stats: &Stats) -> String {
+    // keyed by microns so that heights differing by rounding errors add up
+    let mut heights: BTreeMap<i64, (f64, usize)> = BTreeMap::new();
+    for height in stats.layers.iter().filter_map(|layer| layer.height) {
+        let entry = heights
+            .entry((height * 1000.0).round() as i64)
+            .or_insert((height, 0));
+        entry.1 += 1;
+    }
+    heights
+        .values()
+        .rev()
+        .map(|(height, count)| format!("{:.3} mm × {}", height, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Labels and values of the summary.
+fn rows(counts: &LineCounts, stats: &Stats) -> Vec<(&'static str, String)> {
+    let mut rows = vec![
+        (
+            "Lines",
+            format!(
+                "{} ({} blank, {} with only comments)",
+                counts.lines, counts.blank_lines, counts.comment_lines
+            ),
+        ),
+        ("Commands", counts.commands.to_string()),
+        ("Comments", counts.comments.to_string()),
+        ("Layers", stats.layers.len().to_string()),
+        ("Layer heights", layer_heights(stats)),
+    ];
+    if let Some((min, max)) = stats.bounds {
+        rows.push((
+            "Size",
+            format!(
+                "{:.1} × {:.1} × {:.1} mm",
+                max[0] - min[0],
+                max[1] - min[1],
+                max[2] - min[2]
+            ),
+        ));
+        rows.push((
+            "Bounding box",
+            format!(
+                "X {:.1}..{:.1}, Y {:.1}..{:.1}, Z {:.2}..{:.2}",
+                min[0], max[0], min[1], max[1], min[2], max[2]
+            ),
+        ));
+    }
+    let distance = stats.extrusion_distance + stats.travel_distance;
+    rows.extend([
+        ("Filament", metres(stats.filament)),
+        (
+            "Extruding distance",
+            format!(
+                "{} ({:.0}%)",
+                metres(stats.extrusion_distance),
+                percent(stats.extrusion_distance, distance)
+            ),
+        ),
+        (
+            "Travel distance",
+            format!(
+                "{} ({:.0}%)",
+                metres(stats.travel_distance),
+                percent(stats.travel_distance, distance)
+            ),
+        ),
+        ("Retractions", stats.retractions.to_string()),
+        ("Estimated time", format_duration(stats.time)),
+        (
+            "Hotend temperature",
+            temperatures(stats.min_hotend_temperature, stats.max_hotend_temperature),
+        ),
+        (
+            "Bed temperature",
+            temperatures(stats.min_bed_temperature, stats.max_bed_temperature),
+        ),
+        (
+            "Fan",
+            if stats.fan_time > 0.0 {
+                format!(
+                    "on for {} ({:.0}% of the time), up to {:.0}%",
+                    format_duration(stats.fan_time),
+                    percent(stats.fan_time, stats.time),
+                    percent(stats.max_fan_speed, 255.0)
+                )
+            } else {
+                "off".to_string()
+            },
+        ),
+        (
+            "Tools",
+            format!(
+                "{}, {} changes",
+                stats
+                    .tools
+                    .iter()
+                    .map(|tool| format!("T{}", tool))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                stats.tool_changes
+            ),
+        ),
+    ]);
+    rows
+}
+
+/// The opcodes from the most used to the least, with the title of their documentation.
+fn opcode_rows<'a>(counts: &'a LineCounts, opcodes: &'a Opcodes) -> Vec<(&'a str, usize, &'a str)> {
+    let mut rows: Vec<(&str, usize, &str)> = counts
+        .opcodes
+        .iter()
+        .map(|(opcode, count)| {
+            let title = opcodes
+                .get(opcode.as_str())
+                .map_or("", |od| od.title.as_str());
+            (opcode.as_str(), *count, title)
+        })
+        .collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    rows
+}
+
+fn write_text<W: Write>(out: &mut W, summary: &Summary, opcodes: &Opcodes) -> std::io::Result<()> {
+    let (counts, stats) = (&summary.counts, &summary.stats);
+    writeln!(out, "{}", summary.file)?;
+    for (label, value) in rows(counts, stats) {
+        writeln!(out, "  {:<20}{}", label, value)?;
+    }
+    writeln!(out, "  Times ignore acceleration and heating.")?;
+
+    if !summary.metadata.is_empty() {
+        writeln!(out, "\nPackage")?;
+        for (key, value) in summary.metadata {
+            writeln!(out, "  {:<20}{}", key, value)?;
+        }
+    }
+
+    writeln!(out, "\nOpcodes")?;
+    let rows = opcode_rows(counts, opcodes);
+    let most = rows.first().map_or(1, |row| row.1);
+    for (opcode, count, title) in rows {
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(most));
+        let row = format!(
+            "  {:<8}{:>10}  {:<width$}  {}",
+            opcode,
+            count,
+            bar,
+            title,
+            width = HISTOGRAM_WIDTH
+        );
+        writeln!(out, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Escapes the characters that would end a cell of a Markdown table.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn write_markdown<W: Write>(
+    out: &mut W,
+    summary: &Summary,
+    opcodes: &Opcodes,
+) -> std::io::Result<()> {
+    let (counts, stats) = (&summary.counts, &summary.stats);
+    writeln!(out, "## {}\n", cell(summary.file))?;
+    writeln!(out, "| | |\n|---|---|")?;
+    for (label, value) in rows(counts, stats) {
+        writeln!(out, "| {} | {} |", label, cell(&value))?;
+    }
+    writeln!(out, "\nTimes ignore acceleration and heating.")?;
+
+    if !summary.metadata.is_empty() {
+        writeln!(out, "\n| Package | |\n|---|---|")?;
+        for (key, value) in summary.metadata {
+            writeln!(out, "| {} | {} |", cell(key), cell(value))?;
+        }
+    }
+
+    if !stats.features.is_empty() {
+        writeln!(
+            out,
+            "\n| Feature | Moves | Distance (m) | Filament (m) | Time |\n|---|--:|--:|--:|--:|"
+        )?;
+        for f in stats.features.iter() {
+            writeln!(
+                out,
+                "| {} | {} | {:.2} | {:.2} | {} |",
+                cell(&f.feature),
+                f.moves,
+                f.distance / 1000.0,
+                f.filament / 1000.0,
+                format_duration(f.time)
+            )?;
+        }
+    }
+
+    writeln!(
+        out,
+        "\n| Opcode | Count | Share | Title |\n|---|--:|--:|---|"
+    )?;
+    for (opcode, count, title) in opcode_rows(counts, opcodes) {
+        writeln!(
+            out,
+            "| {} | {} | {:.1}% | {} |",
+            opcode,
+            count,
+            percent(count as f64, counts.commands as f64),
+            cell(title)
+        )?;
+    }
+    Ok(())
+}
+
+/// What a chunk of the file adds to the statistics.
+struct ChunkStats {
+    counts: LineCounts,
+    totals: Totals,
+}
+
+impl Observer for ChunkStats {
+    fn observe(&mut self, machine: &Machine, moves: Vec<Move>) {
+        self.totals.observe(machine, moves);
+    }
+}
+
+/// Writes the statistics of the file, the text and markdown formats with the titles of the
+/// documentation.
+pub fn run(args: &StatsArgs, marlin_docs_dir: Option<&Path>, input: &InputOptions) -> Result<()> {
+    let stream = open_stream(&args.file, input)?;
+    let metadata = stream.metadata().to_vec();
+    let mut counts = LineCounts::default();
+    let mut totals = Totals::default();
+    let machine = parallel::fold(
+        stream.chunks(),
+        |chunk, machine| ChunkStats {
+            counts: count_lines_of(chunk),
+            totals: Totals::starting_at(machine),
+        },
+        |chunk| {
+            counts = std::mem::take(&mut counts).merge(chunk.counts);
+            totals = std::mem::take(&mut totals).merge(chunk.totals);
+            Ok(())
+        },
+    )?;
+    let stats = totals.finish(machine.line() + 1);
+    let summary = Summary {
+        file: &args.file,
+        metadata: &metadata,
+        counts,
+        stats,
+    };
+    let mut out = output(args.output.as_deref())?;
+
+    match args.format {
+        Format::Text => write_text(&mut out, &summary, &load_docs(marlin_docs_dir)?.0)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &summary)?;
+            out.write_all(b"\n")?;
+        }
+        Format::Markdown => write_markdown(&mut out, &summary, &load_docs(marlin_docs_dir)?.0)?,
+    }
+    out.flush().wrap_err("writing the statistics")
+}
@@ -0,0 +1,34 @@
+use crate::commands::render::RenderArgs;
+use eyre::{Result, WrapErr};
+use gcode_viz::input::{output_path, InputOptions};
+use gcode_viz::render::svg::{toolpath_svg, SvgOptions};
+use std::path::{Path, PathBuf};
+
+/// Draws `file` as SVG to `output`, next to `file` by default.
+pub fn run(
+    file: &str,
+    output: Option<&Path>,
+    args: &RenderArgs,
+    input: &InputOptions,
+) -> Result<()> {
+    let render = args.load(file, input)?;
+    let output = output.map_or_else(|| output_path(Path::new(file), "svg"), PathBuf::from);
+
+    let options = SvgOptions {
+        width: render.width as f64,
+        travel: render.travel,
+        view: render.view,
+        legend: true,
+        ..SvgOptions::default()
+    };
+    let svg = toolpath_svg(
+        &render.toolpath,
+        render.layers.clone(),
+        &render.colorizer,
+        render.profile.as_ref(),
+        &options,
+    );
+    std::fs::write(&output, svg).wrap_err_with(|| format!("writing {}", output.display()))?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
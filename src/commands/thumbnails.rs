@@ -0,0 +1,131 @@
+use clap::Subcommand;
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::gcode::thumbnails::{find_thumbnails, Thumbnail};
+use gcode_viz::input::{open_gcode_with_preview, output_path, InputOptions};
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug)]
+pub enum ThumbnailsCommand {
+    /// List the thumbnails embedded in a GCode file
+    List {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+    },
+    /// Write the thumbnails embedded in a GCode file to image files
+    Extract {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+
+        /// Directory to write the images to, created if missing, defaults to the directory of
+        /// the GCode file
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: &ThumbnailsCommand, input: &InputOptions) -> Result<()> {
+    match command {
+        ThumbnailsCommand::List { file } => list(file, input),
+        ThumbnailsCommand::Extract { file, output } => extract(file, output.as_deref(), input),
+    }
+}
+
+fn read_thumbnails(file: &str, input: &InputOptions) -> Result<Vec<Thumbnail>> {
+    let (s, preview) = open_gcode_with_preview(file, input)?;
+    let mut thumbnails = find_thumbnails(&s);
+    if thumbnails.is_empty() {
+        thumbnails.extend(preview);
+    }
+    if thumbnails.is_empty() {
+        return Err(eyre!("{} has no thumbnails", file));
+    }
+    Ok(thumbnails)
+}
+
+fn list(file: &str, input: &InputOptions) -> Result<()> {
+    for thumbnail in read_thumbnails(file, input)? {
+        let size = thumbnail.data().map_or_else(
+            |err| err.to_string(),
+            |data| format!("{} bytes", data.len()),
+        );
+        // the preview of a package isn't on any line of the G-code
+        let lines = if thumbnail.lines.is_empty() {
+            "preview of the package".to_string()
+        } else {
+            format!(
+                "lines {}-{}",
+                thumbnail.lines.start + 1,
+                thumbnail.lines.end
+            )
+        };
+        println!(
+            "{:>4}x{:<4} {}  {}  {}{}",
+            thumbnail.width,
+            thumbnail.height,
+            thumbnail.format,
+            lines,
+            size,
+            if thumbnail.is_complete() {
+                ""
+            } else {
+                ", incomplete"
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Writes the thumbnails of `file` as `<name>-<width>x<height>.<format>`.
+fn extract(file: &str, output: Option<&Path>, input: &InputOptions) -> Result<()> {
+    let thumbnails = read_thumbnails(file, input)?;
+    let path = Path::new(file);
+    let dir = output
+        .or_else(|| path.parent())
+        .unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir).wrap_err_with(|| format!("creating {}", dir.display()))?;
+    let stem = output_path(path, "png")
+        .file_stem()
+        .map_or("thumbnail".into(), |stem| {
+            stem.to_string_lossy().into_owned()
+        });
+
+    let mut failures = 0;
+    for (i, thumbnail) in thumbnails.iter().enumerate() {
+        let mut name = format!("{}-{}x{}", stem, thumbnail.width, thumbnail.height);
+        // slicers sometimes embed the same size in several formats or twice
+        let duplicates = thumbnails[..i]
+            .iter()
+            .filter(|t| {
+                (t.width, t.height, t.format)
+                    == (thumbnail.width, thumbnail.height, thumbnail.format)
+            })
+            .count();
+        if duplicates > 0 {
+            name.push_str(&format!("-{}", duplicates + 1));
+        }
+        let target = dir.join(format!("{}.{}", name, thumbnail.format.extension()));
+
+        match thumbnail.data() {
+            Ok(data) => {
+                std::fs::write(&target, data)
+                    .wrap_err_with(|| format!("writing {}", target.display()))?;
+                println!("Wrote {}", target.display());
+            }
+            Err(err) => {
+                failures += 1;
+                eprintln!(
+                    "{}:{}: skipping thumbnail: {}",
+                    file,
+                    thumbnail.lines.start + 1,
+                    err
+                );
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(eyre!("{} thumbnails could not be decoded", failures));
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use crate::commands::output;
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Result, WrapErr};
+use gcode_viz::gcode::parallel::{self, split, AsChunk, CHUNK_SIZE};
+use gcode_viz::gcode::profile::{load_profile, MachineProfile};
+use gcode_viz::gcode::width::{
+    filament_area, LayerHeight, WidthReport, WidthSamples, WidthTotals, DEFAULT_TOLERANCE,
+};
+use gcode_viz::input::{is_stdin, open_gcode, open_stream, GcodeStream, InputError, InputOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct WidthsArgs {
+    /// Name of the GCode file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Name of a built-in machine profile or path of a TOML profile, for the filament diameter
+    #[clap(short, long, value_parser)]
+    profile: Option<String>,
+
+    /// Filament diameter in mm, overrides the profile
+    #[clap(long, value_parser)]
+    filament_diameter: Option<f64>,
+
+    /// Deviation from the median width of the feature in percent beyond which a move is an
+    /// outlier
+    #[clap(short, long, value_parser, default_value_t = DEFAULT_TOLERANCE * 100.0)]
+    tolerance: f64,
+
+    /// How to write the widths
+    #[clap(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// The file to write, defaults to the standard output
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A table of the features, the layer heights and a line per outlier
+    Text,
+    /// A JSON object with the features, every layer and every outlier
+    Json,
+}
+
+/// Outliers listed in the text output, the JSON has them all.
+const MAX_LISTED: usize = 20;
+
+/// The layers grouped into runs of the same height, e.g. `1-28: 0.200 mm`.
+fn layer_runs(layers: &[LayerHeight]) -> Vec<String> {
+    // in microns, so that heights differing by rounding errors are the same
+    let microns = |height: Option<f64>| height.map(|h| (h * 1000.0).round() as i64);
+    let mut runs: Vec<(usize, usize, Option<f64>)> = vec![];
+    for layer in layers {
+        match runs.last_mut() {
+            Some((_, last, height)) if microns(*height) == microns(layer.height) => {
+                *last = layer.index
+            }
+            _ => runs.push((layer.index, layer.index, layer.height)),
+        }
+    }
+    runs.into_iter()
+        .map(|(first, last, height)| {
+            let layers = if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            };
+            match height {
+                Some(height) => format!("{}: {:.3} mm", layers, height),
+                None => format!("{}: no extrusion", layers),
+            }
+        })
+        .collect()
+}
+
+fn write_text<W: Write>(out: &mut W, report: &WidthReport) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "Widths for {} mm filament, outliers beyond ±{}% of the feature median",
+        report.filament_diameter,
+        report.tolerance * 100.0
+    )?;
+    writeln!(
+        out,
+        "\n  {:<24}{:>8}{:>9}{:>8}{:>8}{:>8}{:>8}",
+        "Feature", "moves", "median", "min", "max", "over", "under"
+    )?;
+    for f in report.features.iter() {
+        writeln!(
+            out,
+            "  {:<24}{:>8}{:>9.3}{:>8.3}{:>8.3}{:>8}{:>8}",
+            f.feature, f.moves, f.median, f.min, f.max, f.over, f.under
+        )?;
+    }
+
+    writeln!(out, "\nLayer heights")?;
+    for run in layer_runs(&report.layers) {
+        writeln!(out, "  {}", run)?;
+    }
+
+    if report.outliers.is_empty() {
+        writeln!(out, "\nNo outliers")?;
+        return Ok(());
+    }
+    writeln!(out, "\n{} outliers", report.outliers.len())?;
+    for o in report.outliers.iter().take(MAX_LISTED) {
+        writeln!(
+            out,
+            "  line {}, layer {}, {}: {:.3} mm, {:+.0}% of {:.3} mm",
+            o.line,
+            o.layer,
+            o.feature,
+            o.width,
+            o.deviation * 100.0,
+            o.median
+        )?;
+    }
+    if report.outliers.len() > MAX_LISTED {
+        writeln!(
+            out,
+            "  and {} more, see --format json",
+            report.outliers.len() - MAX_LISTED
+        )?;
+    }
+    Ok(())
+}
+
+/// Measures the widths of the moves of a file in two passes over the chunks `chunks` reads,
+/// the first one for the medians and the heights of the layers.
+fn measure<C, I>(
+    mut chunks: impl FnMut() -> Result<I, InputError>,
+    filament_diameter: f64,
+    tolerance: f64,
+) -> Result<WidthReport, InputError>
+where
+    C: AsChunk + Send,
+    I: Iterator<Item = Result<C, InputError>>,
+{
+    let area = filament_area(filament_diameter);
+    let mut samples = WidthSamples::new(area);
+    let machine = parallel::fold(
+        chunks()?,
+        |_, machine| WidthSamples::starting_at(machine, area),
+        |next| {
+            samples = std::mem::take(&mut samples).merge(next);
+            Ok(())
+        },
+    )?;
+    let model = samples.finish(machine.state().layer + 1);
+
+    let mut totals = WidthTotals::new(&model, tolerance);
+    parallel::fold(
+        chunks()?,
+        |_, _| WidthTotals::new(&model, tolerance),
+        |next| {
+            totals =
+                std::mem::replace(&mut totals, WidthTotals::new(&model, tolerance)).merge(next);
+            Ok(())
+        },
+    )?;
+    Ok(totals.finish(filament_diameter))
+}
+
+/// Writes the extrusion widths per feature, the layer heights and the over- and
+/// under-extruding moves of the file.
+pub fn run(args: &WidthsArgs, input: &InputOptions) -> Result<()> {
+    let profile = match args.profile.as_deref() {
+        Some(name) => load_profile(name)?,
+        None => MachineProfile::default(),
+    };
+    let filament_diameter = args.filament_diameter.unwrap_or(profile.filament_diameter);
+    if args.tolerance <= 0.0 {
+        return Err(eyre!("the tolerance must be above 0%"));
+    }
+
+    let tolerance = args.tolerance / 100.0;
+    let report = if is_stdin(Path::new(&args.file)) {
+        // the standard input can't be read twice, so it is held in memory
+        let s = open_gcode(&args.file, input)?;
+        measure(
+            || Ok(split(&s, CHUNK_SIZE).into_iter().map(Ok)),
+            filament_diameter,
+            tolerance,
+        )?
+    } else {
+        measure(
+            || open_stream(&args.file, input).map(GcodeStream::chunks),
+            filament_diameter,
+            tolerance,
+        )?
+    };
+
+    let mut out = output(args.output.as_deref())?;
+    match args.format {
+        Format::Text => write_text(&mut out, &report)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush().wrap_err("writing the widths")
+}
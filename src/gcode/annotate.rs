@@ -0,0 +1,133 @@
+//! The commands and comments of a file, each with its documentation and the state of the
+//! printer around it, for other programs to read.
+
+use crate::gcode::lines::{opcode_name, parse_lines_at, DisplayLine};
+use crate::gcode::machine::{Machine, MachineState};
+use crate::gcode::marlin_docs::{OpcodeDescription, Opcodes};
+use crate::gcode::parallel::Chunk;
+use serde::Serialize;
+use std::borrow::BorrowMut;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A command or a comment of a file.
+#[derive(Serialize)]
+pub struct Annotation<'a> {
+    /// Line of the file, starting at 1
+    pub line: usize,
+    /// Byte range of the command or comment in the file
+    pub span: Range<usize>,
+    /// The whole line
+    pub text: &'a str,
+    #[serde(flatten)]
+    pub item: DisplayLine<'a>,
+    /// The comments on the line of a command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<&'a str>,
+    /// Title of the documentation of the command
+    pub title: Option<String>,
+    /// The documentation of the command, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<Arc<OpcodeDescription>>,
+    /// State of the printer before and after the command
+    pub before: MachineState,
+    pub after: MachineState,
+}
+
+/// Annotates the commands and comments of `src`, in order; with the whole documentation of
+/// the commands if `docs` is set.
+pub fn annotate<'a>(
+    src: &'a str,
+    opcodes: &'a Opcodes,
+    docs: bool,
+) -> impl Iterator<Item = Annotation<'a>> + 'a {
+    let chunk = Chunk {
+        text: src,
+        offset: 0,
+        first_line: 0,
+    };
+    annotate_chunk(chunk, Machine::new(), opcodes, docs)
+}
+
+/// Annotates the commands and comments of `chunk` like [`annotate`], executing them on
+/// `machine`, in the state the lines before the chunk left it in.
+pub fn annotate_chunk<'a, M: BorrowMut<Machine> + 'a>(
+    chunk: Chunk<'a>,
+    mut machine: M,
+    opcodes: &'a Opcodes,
+    docs: bool,
+) -> impl Iterator<Item = Annotation<'a>> + 'a {
+    chunk
+        .text
+        .lines()
+        .zip(parse_lines_at(chunk.text, chunk.offset, chunk.first_line))
+        .flat_map(move |(text, line)| {
+            let comments: Vec<&str> = line.comments().iter().map(|c| c.value).collect();
+            let commands = line.gcodes().iter().map(|gcode| {
+                let opcode = opcode_name(gcode);
+                let od = opcodes.get(&opcode);
+                Annotation {
+                    line: gcode.span().line + 1,
+                    span: gcode.span().start..gcode.span().end,
+                    text,
+                    item: DisplayLine::GCode(opcode, gcode.clone()),
+                    comments: comments.clone(),
+                    title: od.map(|od| od.title.clone()),
+                    doc: od.filter(|_| docs).cloned(),
+                    before: MachineState::default(),
+                    after: MachineState::default(),
+                }
+            });
+            let comments = line.comments().iter().map(|comment| Annotation {
+                line: comment.span.line + 1,
+                span: comment.span.start..comment.span.end,
+                text,
+                item: DisplayLine::Comment(*comment),
+                comments: Vec::new(),
+                title: None,
+                doc: None,
+                before: MachineState::default(),
+                after: MachineState::default(),
+            });
+            commands.chain(comments).collect::<Vec<_>>()
+        })
+        .map(move |mut annotation| {
+            let machine = machine.borrow_mut();
+            annotation.before = machine.state().clone();
+            machine.execute(&annotation.item);
+            annotation.after = machine.state().clone();
+            annotation
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_annotate_commands_and_comments() {
+        let src = "; start\nG1 X10 E1.5 F600 ; prime\n";
+        let records: Vec<serde_json::Value> = annotate(src, &Opcodes::new(), false)
+            .map(|annotation| serde_json::to_value(annotation).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["comment"], "; start");
+        assert_eq!(records[0]["span"], json!({"start": 0, "end": 7}));
+
+        let command = &records[1];
+        assert_eq!(command["line"], 2);
+        assert_eq!(command["text"], "G1 X10 E1.5 F600 ; prime");
+        assert_eq!(command["opcode"], "G1");
+        assert_eq!(
+            command["parameters"],
+            json!({"X": 10.0, "E": 1.5, "F": 600.0})
+        );
+        assert_eq!(command["comments"], json!(["; prime"]));
+        assert_eq!(command["title"], serde_json::Value::Null);
+        assert_eq!(command["before"]["position"], json!([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(command["after"]["position"], json!([10.0, 0.0, 0.0, 1.5]));
+        assert_eq!(command["after"]["feedrate"], 600.0);
+        assert!(command.get("doc").is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::fmt::Display;
+
+/// Everything that can go wrong while reading a binary G-code file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BgcodeError {
+    /// The file doesn't start with `GCDE`.
+    NotBgcode,
+    UnsupportedVersion(u32),
+    UnknownChecksum(u16),
+    /// The file ends in the middle of a block.
+    Truncated {
+        offset: usize,
+    },
+    UnknownBlockType {
+        offset: usize,
+        value: u16,
+    },
+    UnknownCompression {
+        offset: usize,
+        value: u16,
+    },
+    UnknownEncoding {
+        offset: usize,
+        value: u16,
+    },
+    /// The CRC32 of the block doesn't match its content.
+    Checksum {
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    /// The block doesn't decompress to the size its header announces.
+    Decompression {
+        offset: usize,
+    },
+    /// The blocks are not in the order of the specification.
+    Order {
+        offset: usize,
+        message: String,
+    },
+}
+
+impl Display for BgcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BgcodeError::NotBgcode => write!(f, "not a binary G-code file"),
+            BgcodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported binary G-code version {}", version)
+            }
+            BgcodeError::UnknownChecksum(value) => write!(f, "unknown checksum type {}", value),
+            BgcodeError::Truncated { offset } => {
+                write!(f, "offset {}: file ends in the middle of a block", offset)
+            }
+            BgcodeError::UnknownBlockType { offset, value } => {
+                write!(f, "offset {}: unknown block type {}", offset, value)
+            }
+            BgcodeError::UnknownCompression { offset, value } => {
+                write!(f, "offset {}: unknown compression {}", offset, value)
+            }
+            BgcodeError::UnknownEncoding { offset, value } => {
+                write!(f, "offset {}: unknown encoding {}", offset, value)
+            }
+            BgcodeError::Checksum {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "offset {}: checksum mismatch, expected {:08x} but the block has {:08x}",
+                offset, expected, actual
+            ),
+            BgcodeError::Decompression { offset } => {
+                write!(f, "offset {}: block doesn't decompress to its size", offset)
+            }
+            BgcodeError::Order { offset, message } => write!(f, "offset {}: {}", offset, message),
+        }
+    }
+}
+
+impl std::error::Error for BgcodeError {}
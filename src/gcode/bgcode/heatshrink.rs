@@ -0,0 +1,185 @@
+//! Heatshrink, the LZSS compression of the G-code blocks of binary G-code.
+//!
+//! The stream is read most significant bit first. A `1` bit is followed by a literal byte, a
+//! `0` bit by a back-reference: `window` bits of distance minus one and `lookahead` bits of
+//! length minus one.
+
+/// Bit writer, most significant bit first.
+struct BitWriter {
+    out: Vec<u8>,
+    current: u8,
+    used: u8,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, bits: u8) {
+        for i in (0..bits).rev() {
+            self.current = self.current << 1 | ((value >> i) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.out.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.out.push(self.current << (8 - self.used));
+        }
+        self.out
+    }
+}
+
+/// Bit reader, most significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    /// The next `bits` bits, `None` at the end of the data.
+    fn read(&mut self, bits: u8) -> Option<u32> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            value = value << 1 | ((byte >> (7 - self.position % 8)) & 1) as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Number of candidates compared per position, bounds the time spent on repetitive data.
+const MAX_CHAIN: usize = 64;
+
+/// Compresses `data` with a window of `2^window` bytes and matches of up to
+/// `2^lookahead` bytes.
+pub fn compress(data: &[u8], window: u8, lookahead: u8) -> Vec<u8> {
+    let max_distance = 1usize << window;
+    let max_length = 1usize << lookahead;
+    // a back-reference must be shorter than the literals it replaces
+    let min_length = (1 + window as usize + lookahead as usize) / 9 + 1;
+
+    let mut writer = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        current: 0,
+        used: 0,
+    };
+    // positions by their first two bytes, chained to the previous position with the same ones
+    let mut head = vec![usize::MAX; 1 << 16];
+    let mut previous = vec![usize::MAX; data.len()];
+    let key = |i: usize| (data[i] as usize) << 8 | data[i + 1] as usize;
+    let insert = |i: usize, head: &mut [usize], previous: &mut [usize]| {
+        if i + 1 < data.len() {
+            previous[i] = head[key(i)];
+            head[key(i)] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + 1 < data.len() {
+            let limit = max_length.min(data.len() - i);
+            let mut candidate = head[key(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= max_distance && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == limit {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        let (length, distance) = best;
+        if length >= min_length {
+            writer.push(0, 1);
+            writer.push((distance - 1) as u32, window);
+            writer.push((length - 1) as u32, lookahead);
+            for j in i..i + length {
+                insert(j, &mut head, &mut previous);
+            }
+            i += length;
+        } else {
+            writer.push(1, 1);
+            writer.push(data[i] as u32, 8);
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    writer.finish()
+}
+
+/// Decompresses `data` compressed with the given `window` and `lookahead`, stopping after
+/// `size` bytes. `None` if the data ends early.
+pub fn decompress(data: &[u8], window: u8, lookahead: u8, size: usize) -> Option<Vec<u8>> {
+    let mut reader = BitReader { data, position: 0 };
+    // `size` comes from the file, don't trust it further than `data` can expand: a back
+    // reference of 1 + window + lookahead bits copies at most 2^lookahead bytes
+    let expanded = (data.len() * 8 / (1 + window as usize + lookahead as usize)) << lookahead;
+    let mut out = Vec::with_capacity(size.min(expanded));
+    while out.len() < size {
+        if reader.read(1)? == 1 {
+            out.push(reader.read(8)? as u8);
+        } else {
+            let distance = reader.read(window)? as usize + 1;
+            let length = reader.read(lookahead)? as usize + 1;
+            for _ in 0..length.min(size - out.len()) {
+                // the window starts out filled with zeros
+                let byte = out.len().checked_sub(distance).map_or(0, |j| out[j]);
+                out.push(byte);
+            }
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The encoder vectors of heatshrink's own test suite, `test_heatshrink_static.c`:
+    /// input, window, lookahead and the compressed bytes.
+    const VECTORS: [(&[u8], u8, u8, &[u8]); 3] = [
+        (
+            &[0, 1, 2, 3, 4],
+            8,
+            3,
+            &[0x80, 0x40, 0x60, 0x50, 0x38, 0x20],
+        ),
+        (b"aaaaa", 8, 7, &[0xb0, 0x80, 0x01, 0x80]),
+        (b"abcdabcd", 8, 3, &[0xb0, 0xd8, 0xac, 0x76, 0x40, 0x1b]),
+    ];
+
+    #[test]
+    fn should_match_the_published_vectors() {
+        for (input, window, lookahead, compressed) in VECTORS {
+            assert_eq!(
+                decompress(compressed, window, lookahead, input.len()).as_deref(),
+                Some(input),
+                "{:?}",
+                input
+            );
+            assert_eq!(
+                compress(input, window, lookahead),
+                compressed,
+                "{:?}",
+                input
+            );
+        }
+    }
+}
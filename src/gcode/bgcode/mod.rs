@@ -0,0 +1,849 @@
+//! Prusa binary G-code, `.bgcode`.
+//!
+//! A file header (`GCDE`, the version and the checksum type) is followed by blocks. Each
+//! block has a header (type, compression and sizes), parameters depending on its type, the
+//! possibly compressed data and a CRC32 of all of them. The blocks come in a fixed order: file
+//! metadata, printer metadata, thumbnails, print metadata, slicer metadata and G-code.
+//!
+//! [`Bgcode::to_ascii`] and [`Bgcode::from_ascii`] convert to and from the text layout
+//! PrusaSlicer writes: thumbnails as `; thumbnail begin` comments at the top, the metadata as
+//! `; key = value` comments and the slicer configuration in a `; prusaslicer_config` block at
+//! the end.
+//!
+//! Converting that text to binary and back gives the same text with
+//! [`GCodeEncoding::None`]. The MeatPack encodings drop the spaces between the words of
+//! commands and put them back when reading, so they give the same text as long as words are
+//! separated by single spaces, like slicers write them, and lines end with `\n`.
+//! [`GCodeEncoding::MeatPack`] also drops the comments.
+//!
+//! The MeatPack codec itself is [`crate::gcode::meatpack`], shared with the tools for serial
+//! streams; the Heatshrink compression is [`heatshrink`].
+
+mod error;
+pub mod heatshrink;
+
+pub use error::BgcodeError;
+
+use crate::gcode::meatpack;
+use crate::gcode::meatpack::PackOptions;
+use crate::gcode::thumbnails::{find_thumbnails, ThumbnailFormat};
+
+/// The first bytes of a binary G-code file.
+pub const MAGIC: &[u8; 4] = b"GCDE";
+/// The version of the format we read and write.
+pub const VERSION: u32 = 1;
+/// The largest G-code block the writer produces, in bytes before compression.
+pub const MAX_GCODE_BLOCK: usize = 65535;
+
+/// Whether `data` starts like a binary G-code file.
+pub fn is_bgcode(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// The types of blocks, in the order they appear in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlockKind {
+    FileMetadata,
+    PrinterMetadata,
+    Thumbnail,
+    PrintMetadata,
+    SlicerMetadata,
+    GCode,
+}
+
+impl BlockKind {
+    fn id(self) -> u16 {
+        match self {
+            BlockKind::FileMetadata => 0,
+            BlockKind::GCode => 1,
+            BlockKind::SlicerMetadata => 2,
+            BlockKind::PrinterMetadata => 3,
+            BlockKind::PrintMetadata => 4,
+            BlockKind::Thumbnail => 5,
+        }
+    }
+
+    fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            0 => BlockKind::FileMetadata,
+            1 => BlockKind::GCode,
+            2 => BlockKind::SlicerMetadata,
+            3 => BlockKind::PrinterMetadata,
+            4 => BlockKind::PrintMetadata,
+            5 => BlockKind::Thumbnail,
+            _ => return None,
+        })
+    }
+
+    pub fn is_metadata(self) -> bool {
+        !matches!(self, BlockKind::Thumbnail | BlockKind::GCode)
+    }
+}
+
+/// How the data of a block is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zlib
+    Deflate,
+    /// Heatshrink with a window of 2^11 bytes and a lookahead of 2^4
+    Heatshrink11,
+    /// Heatshrink with a window of 2^12 bytes and a lookahead of 2^4
+    Heatshrink12,
+}
+
+impl Compression {
+    fn id(self) -> u16 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Heatshrink11 => 2,
+            Compression::Heatshrink12 => 3,
+        }
+    }
+
+    fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            0 => Compression::None,
+            1 => Compression::Deflate,
+            2 => Compression::Heatshrink11,
+            3 => Compression::Heatshrink12,
+            _ => return None,
+        })
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Deflate => miniz_oxide::deflate::compress_to_vec_zlib(data, 6),
+            Compression::Heatshrink11 => heatshrink::compress(data, 11, 4),
+            Compression::Heatshrink12 => heatshrink::compress(data, 12, 4),
+        }
+    }
+
+    fn decompress(self, data: &[u8], size: usize) -> Option<Vec<u8>> {
+        let out = match self {
+            Compression::None => data.to_vec(),
+            Compression::Deflate => miniz_oxide::inflate::decompress_to_vec_zlib(data).ok()?,
+            Compression::Heatshrink11 => heatshrink::decompress(data, 11, 4, size)?,
+            Compression::Heatshrink12 => heatshrink::decompress(data, 12, 4, size)?,
+        };
+        Some(out).filter(|out| out.len() == size)
+    }
+}
+
+/// How the text of a G-code block is encoded before compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GCodeEncoding {
+    #[default]
+    None,
+    /// MeatPack in no-spaces mode, without comments
+    MeatPack,
+    /// MeatPack in no-spaces mode, keeping the comments
+    MeatPackComments,
+}
+
+impl GCodeEncoding {
+    fn id(self) -> u16 {
+        match self {
+            GCodeEncoding::None => 0,
+            GCodeEncoding::MeatPack => 1,
+            GCodeEncoding::MeatPackComments => 2,
+        }
+    }
+
+    fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            0 => GCodeEncoding::None,
+            1 => GCodeEncoding::MeatPack,
+            2 => GCodeEncoding::MeatPackComments,
+            _ => return None,
+        })
+    }
+
+    fn encode(self, text: &str) -> Vec<u8> {
+        let options = PackOptions {
+            no_spaces: true,
+            comments: self == GCodeEncoding::MeatPackComments,
+        };
+        match self {
+            GCodeEncoding::None => text.as_bytes().to_vec(),
+            _ => meatpack::pack(text, options),
+        }
+    }
+
+    fn decode(self, data: &[u8]) -> String {
+        match self {
+            GCodeEncoding::None => String::from_utf8_lossy(data).into_owned(),
+            _ => {
+                let text = meatpack::unpack(data);
+                let mut out = String::with_capacity(text.len() + text.len() / 8);
+                for line in String::from_utf8_lossy(&text).split_inclusive('\n') {
+                    let (line, newline) = match line.strip_suffix('\n') {
+                        Some(line) => (line, "\n"),
+                        None => (line, ""),
+                    };
+                    out.push_str(&meatpack::restore_spaces(line));
+                    out.push_str(newline);
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Whether blocks end with a checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Checksum {
+    None,
+    #[default]
+    Crc32,
+}
+
+/// The decoded content of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockContent {
+    /// The `key=value` pairs of a metadata block, in order
+    Metadata(Vec<(String, String)>),
+    /// A PNG, JPG or QOI image
+    Thumbnail {
+        format: ThumbnailFormat,
+        width: u16,
+        height: u16,
+        data: Vec<u8>,
+    },
+    GCode {
+        encoding: GCodeEncoding,
+        text: String,
+    },
+}
+
+/// A block of a binary G-code file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub compression: Compression,
+    pub content: BlockContent,
+}
+
+/// A binary G-code file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Bgcode {
+    pub checksum: Checksum,
+    pub blocks: Vec<Block>,
+}
+
+/// Reads little endian numbers, failing with the offset of the block when the data ends.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    block: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], BgcodeError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or(BgcodeError::Truncated { offset: self.block })?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, BgcodeError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, BgcodeError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// The keys PrusaSlicer puts in the printer metadata block.
+pub const PRINTER_METADATA_KEYS: [&str; 12] = [
+    "printer_model",
+    "filament_type",
+    "nozzle_diameter",
+    "bed_temperature",
+    "brim_width",
+    "fill_density",
+    "layer_height",
+    "temperature",
+    "ironing",
+    "support_material",
+    "max_layer_z",
+    "extruder_colour",
+];
+
+/// The keys PrusaSlicer puts in the print metadata block.
+pub const PRINT_METADATA_KEYS: [&str; 11] = [
+    "filament used [mm]",
+    "filament used [cm3]",
+    "filament used [g]",
+    "filament cost",
+    "total filament used [g]",
+    "total filament cost",
+    "total filament used for wipe tower [g]",
+    "estimated printing time (normal mode)",
+    "estimated printing time (silent mode)",
+    "estimated first layer printing time (normal mode)",
+    "estimated first layer printing time (silent mode)",
+];
+
+const CONFIG_BEGIN: &str = "; prusaslicer_config = begin";
+const CONFIG_END: &str = "; prusaslicer_config = end";
+
+/// Characters of base64 per thumbnail comment line.
+const THUMBNAIL_LINE: usize = 78;
+
+/// How [`Bgcode::from_ascii`] encodes the G-code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConvertOptions {
+    pub compression: Compression,
+    pub encoding: GCodeEncoding,
+}
+
+impl Default for ConvertOptions {
+    /// The defaults of PrusaSlicer.
+    fn default() -> Self {
+        Self {
+            compression: Compression::Heatshrink12,
+            encoding: GCodeEncoding::MeatPackComments,
+        }
+    }
+}
+
+/// Parses a `; key = value` comment.
+fn metadata_comment(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.strip_prefix("; ")?.split_once(" = ")?;
+    Some((key, value))
+}
+
+fn metadata_block(
+    kind: BlockKind,
+    compression: Compression,
+    pairs: Vec<(String, String)>,
+) -> Block {
+    Block {
+        kind,
+        compression,
+        content: BlockContent::Metadata(pairs),
+    }
+}
+
+impl Bgcode {
+    /// Parses and checks a binary G-code file.
+    pub fn read(data: &[u8]) -> Result<Bgcode, BgcodeError> {
+        if !is_bgcode(data) {
+            return Err(BgcodeError::NotBgcode);
+        }
+        let mut reader = Reader {
+            data,
+            position: MAGIC.len(),
+            block: 0,
+        };
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(BgcodeError::UnsupportedVersion(version));
+        }
+        let checksum = match reader.u16()? {
+            0 => Checksum::None,
+            1 => Checksum::Crc32,
+            other => return Err(BgcodeError::UnknownChecksum(other)),
+        };
+
+        let mut bgcode = Bgcode {
+            checksum,
+            blocks: Vec::new(),
+        };
+        while reader.position < data.len() {
+            let offset = reader.position;
+            reader.block = offset;
+            let value = reader.u16()?;
+            let kind =
+                BlockKind::from_id(value).ok_or(BgcodeError::UnknownBlockType { offset, value })?;
+            let value = reader.u16()?;
+            let compression = Compression::from_id(value)
+                .ok_or(BgcodeError::UnknownCompression { offset, value })?;
+            let size = reader.u32()? as usize;
+            let stored = match compression {
+                Compression::None => size,
+                _ => reader.u32()? as usize,
+            };
+
+            let content = match kind {
+                BlockKind::Thumbnail => {
+                    let value = reader.u16()?;
+                    let format = match value {
+                        0 => ThumbnailFormat::Png,
+                        1 => ThumbnailFormat::Jpg,
+                        2 => ThumbnailFormat::Qoi,
+                        _ => return Err(BgcodeError::UnknownEncoding { offset, value }),
+                    };
+                    let (width, height) = (reader.u16()?, reader.u16()?);
+                    let data = decompress(compression, reader.bytes(stored)?, size, offset)?;
+                    BlockContent::Thumbnail {
+                        format,
+                        width,
+                        height,
+                        data,
+                    }
+                }
+                BlockKind::GCode => {
+                    let value = reader.u16()?;
+                    let encoding = GCodeEncoding::from_id(value)
+                        .ok_or(BgcodeError::UnknownEncoding { offset, value })?;
+                    let data = decompress(compression, reader.bytes(stored)?, size, offset)?;
+                    BlockContent::GCode {
+                        encoding,
+                        text: encoding.decode(&data),
+                    }
+                }
+                _ => {
+                    let value = reader.u16()?;
+                    if value != 0 {
+                        return Err(BgcodeError::UnknownEncoding { offset, value });
+                    }
+                    let data = decompress(compression, reader.bytes(stored)?, size, offset)?;
+                    let pairs = String::from_utf8_lossy(&data)
+                        .lines()
+                        .filter_map(|line| line.split_once('='))
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect();
+                    BlockContent::Metadata(pairs)
+                }
+            };
+
+            if checksum == Checksum::Crc32 {
+                let actual = crc32fast::hash(&data[offset..reader.position]);
+                let expected = reader.u32()?;
+                if actual != expected {
+                    return Err(BgcodeError::Checksum {
+                        offset,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+
+            if let Some(previous) = bgcode.blocks.last() {
+                let repeated = previous.kind == kind && kind.is_metadata();
+                if previous.kind > kind || repeated {
+                    return Err(BgcodeError::Order {
+                        offset,
+                        message: format!("{:?} block after {:?} block", kind, previous.kind),
+                    });
+                }
+            }
+            bgcode.blocks.push(Block {
+                kind,
+                compression,
+                content,
+            });
+        }
+        Ok(bgcode)
+    }
+
+    /// Encodes the file.
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        out.extend(
+            match self.checksum {
+                Checksum::None => 0u16,
+                Checksum::Crc32 => 1,
+            }
+            .to_le_bytes(),
+        );
+
+        for block in self.blocks.iter() {
+            let start = out.len();
+            let (parameters, data) = match &block.content {
+                BlockContent::Metadata(pairs) => {
+                    let ini: String = pairs
+                        .iter()
+                        .map(|(key, value)| format!("{}={}\n", key, value))
+                        .collect();
+                    (0u16.to_le_bytes().to_vec(), ini.into_bytes())
+                }
+                BlockContent::Thumbnail {
+                    format,
+                    width,
+                    height,
+                    data,
+                } => {
+                    let format: u16 = match format {
+                        ThumbnailFormat::Png => 0,
+                        ThumbnailFormat::Jpg => 1,
+                        ThumbnailFormat::Qoi => 2,
+                    };
+                    let mut parameters = format.to_le_bytes().to_vec();
+                    parameters.extend(width.to_le_bytes());
+                    parameters.extend(height.to_le_bytes());
+                    (parameters, data.clone())
+                }
+                BlockContent::GCode { encoding, text } => {
+                    (encoding.id().to_le_bytes().to_vec(), encoding.encode(text))
+                }
+            };
+
+            out.extend(block.kind.id().to_le_bytes());
+            out.extend(block.compression.id().to_le_bytes());
+            out.extend((data.len() as u32).to_le_bytes());
+            let stored = block.compression.compress(&data);
+            if block.compression != Compression::None {
+                out.extend((stored.len() as u32).to_le_bytes());
+            }
+            out.extend(parameters);
+            out.extend(stored);
+            if self.checksum == Checksum::Crc32 {
+                let crc = crc32fast::hash(&out[start..]);
+                out.extend(crc.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// The pairs of the first metadata block of `kind`.
+    pub fn metadata(&self, kind: BlockKind) -> &[(String, String)] {
+        self.blocks
+            .iter()
+            .find_map(|block| match &block.content {
+                BlockContent::Metadata(pairs) if block.kind == kind => Some(pairs.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// The text of all G-code blocks.
+    pub fn gcode(&self) -> String {
+        self.blocks
+            .iter()
+            .filter_map(|block| match &block.content {
+                BlockContent::GCode { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The file as text G-code, laid out the way PrusaSlicer writes it.
+    ///
+    /// Printer and print metadata are only written as comments if the G-code and the slicer
+    /// configuration don't already have them.
+    pub fn to_ascii(&self) -> String {
+        let gcode = self.gcode();
+        let config = self.metadata(BlockKind::SlicerMetadata);
+        let mut out = String::with_capacity(gcode.len() + 4096);
+
+        for block in self.blocks.iter() {
+            if let BlockContent::Thumbnail {
+                format,
+                width,
+                height,
+                data,
+            } = &block.content
+            {
+                let tag = match format {
+                    ThumbnailFormat::Png => "thumbnail".to_string(),
+                    format => format!("thumbnail_{}", format),
+                };
+                let base64 = base64::encode(data);
+                out.push_str(&format!(
+                    "; {} begin {}x{} {}\n",
+                    tag,
+                    width,
+                    height,
+                    base64.len()
+                ));
+                for chunk in base64.as_bytes().chunks(THUMBNAIL_LINE) {
+                    out.push_str("; ");
+                    out.push_str(&String::from_utf8_lossy(chunk));
+                    out.push('\n');
+                }
+                out.push_str(&format!("; {} end\n;\n", tag));
+            }
+        }
+
+        let missing = |kind: BlockKind| -> String {
+            self.metadata(kind)
+                .iter()
+                .map(|(key, value)| format!("; {} = {}\n", key, value))
+                .filter(|line| {
+                    !gcode.contains(line.as_str())
+                        && !config
+                            .iter()
+                            .any(|(key, value)| *line == format!("; {} = {}\n", key, value))
+                })
+                .collect()
+        };
+        out.push_str(&missing(BlockKind::PrinterMetadata));
+        out.push_str(&gcode);
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&missing(BlockKind::PrintMetadata));
+
+        if !config.is_empty() {
+            out.push_str(CONFIG_BEGIN);
+            out.push('\n');
+            for (key, value) in config {
+                out.push_str(&format!("; {} = {}\n", key, value));
+            }
+            out.push_str(CONFIG_END);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Converts text G-code: thumbnails and the `; prusaslicer_config` block move to their
+    /// blocks, the printer and print metadata are collected from the `; key = value` comments.
+    pub fn from_ascii(src: &str, options: ConvertOptions) -> Bgcode {
+        let mut blocks = vec![metadata_block(
+            BlockKind::FileMetadata,
+            Compression::None,
+            vec![(
+                "Producer".to_string(),
+                format!("gcode-viz {}", env!("CARGO_PKG_VERSION")),
+            )],
+        )];
+
+        let mut thumbnails = Vec::new();
+        let mut skipped = vec![false; src.lines().count()];
+        for thumbnail in find_thumbnails(src) {
+            let Ok(data) = thumbnail.data() else {
+                // keep what we can't decode as comments rather than losing it
+                continue;
+            };
+            skipped[thumbnail.lines.clone()].fill(true);
+            // the empty comment PrusaSlicer puts after each thumbnail
+            if src.lines().nth(thumbnail.lines.end) == Some(";") {
+                skipped[thumbnail.lines.end] = true;
+            }
+            thumbnails.push(Block {
+                kind: BlockKind::Thumbnail,
+                compression: Compression::None,
+                content: BlockContent::Thumbnail {
+                    format: thumbnail.format,
+                    width: thumbnail.width as u16,
+                    height: thumbnail.height as u16,
+                    data,
+                },
+            });
+        }
+
+        let mut gcode = String::with_capacity(src.len());
+        let mut config = Vec::new();
+        let mut in_config = false;
+        for (line, skip) in src.lines().zip(skipped) {
+            if skip {
+                continue;
+            }
+            if line == CONFIG_BEGIN {
+                in_config = true;
+            } else if line == CONFIG_END && in_config {
+                in_config = false;
+            } else if in_config {
+                if let Some((key, value)) = metadata_comment(line) {
+                    config.push((key.to_string(), value.to_string()));
+                }
+            } else {
+                gcode.push_str(line);
+                gcode.push('\n');
+            }
+        }
+        if !src.ends_with('\n') {
+            gcode.pop();
+        }
+
+        let collect = |keys: &[&str]| -> Vec<(String, String)> {
+            let mut pairs: Vec<(String, String)> = Vec::new();
+            let comments = gcode.lines().filter_map(metadata_comment).chain(
+                config
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+            for (key, value) in comments {
+                if keys.contains(&key) && !pairs.iter().any(|(k, _)| k == key) {
+                    pairs.push((key.to_string(), value.to_string()));
+                }
+            }
+            pairs
+        };
+        blocks.push(metadata_block(
+            BlockKind::PrinterMetadata,
+            Compression::None,
+            collect(&PRINTER_METADATA_KEYS),
+        ));
+        blocks.extend(thumbnails);
+        blocks.push(metadata_block(
+            BlockKind::PrintMetadata,
+            Compression::None,
+            collect(&PRINT_METADATA_KEYS),
+        ));
+        blocks.push(metadata_block(
+            BlockKind::SlicerMetadata,
+            Compression::Deflate,
+            config,
+        ));
+
+        let mut rest = gcode.as_str();
+        while !rest.is_empty() {
+            // split after the last line feed that fits, or after the first one for long lines
+            let end = match rest.len() {
+                len if len <= MAX_GCODE_BLOCK => len,
+                _ => rest[..MAX_GCODE_BLOCK]
+                    .rfind('\n')
+                    .or_else(|| rest.find('\n'))
+                    .map_or(rest.len(), |i| i + 1),
+            };
+            let (text, tail) = rest.split_at(end);
+            blocks.push(Block {
+                kind: BlockKind::GCode,
+                compression: options.compression,
+                content: BlockContent::GCode {
+                    encoding: options.encoding,
+                    text: text.to_string(),
+                },
+            });
+            rest = tail;
+        }
+
+        Bgcode {
+            checksum: Checksum::Crc32,
+            blocks,
+        }
+    }
+}
+
+fn decompress(
+    compression: Compression,
+    data: &[u8],
+    size: usize,
+    offset: usize,
+) -> Result<Vec<u8>, BgcodeError> {
+    compression
+        .decompress(data, size)
+        .ok_or(BgcodeError::Decompression { offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> String {
+        let thumbnail = base64::encode(b"\x89PNG not really an image");
+        format!(
+            "; thumbnail begin 16x16 {}\n; {}\n; thumbnail end\n;\n\
+             ; generated by a test\nG28 ; home\nM117 Printing now\nG1 X10.5 Y20 E0.35 F1200\n\
+             ; printer_model = MK4\n; filament used [mm] = 12.5\n\
+             ; prusaslicer_config = begin\n; layer_height = 0.2\n; printer_model = MK4\n\
+             ; prusaslicer_config = end\n",
+            thumbnail.len(),
+            thumbnail
+        )
+    }
+
+    #[test]
+    fn should_round_trip_through_every_compression() {
+        let src = sample();
+        for compression in [
+            Compression::None,
+            Compression::Deflate,
+            Compression::Heatshrink11,
+            Compression::Heatshrink12,
+        ] {
+            let options = ConvertOptions {
+                compression,
+                encoding: GCodeEncoding::None,
+            };
+            let bgcode = Bgcode::from_ascii(&src, options);
+            let read = Bgcode::read(&bgcode.write()).unwrap();
+            assert_eq!(read, bgcode);
+            assert_eq!(read.to_ascii(), src, "{:?}", compression);
+        }
+
+        let bgcode = Bgcode::from_ascii(&src, ConvertOptions::default());
+        assert_eq!(
+            bgcode.metadata(BlockKind::PrinterMetadata),
+            [
+                ("printer_model".to_string(), "MK4".to_string()),
+                ("layer_height".to_string(), "0.2".to_string())
+            ]
+        );
+        let ascii = Bgcode::read(&bgcode.write()).unwrap().to_ascii();
+        assert!(ascii.contains("G1 X10.5 Y20 E0.35 F1200\n"), "{}", ascii);
+        assert!(ascii.contains("M117 Printing now\n"));
+    }
+
+    #[test]
+    fn should_round_trip_with_the_default_options() {
+        let src = sample().replace(
+            "G28 ; home\n",
+            "G28 ; home\nM862.3 P \"MK4S\" ; printer model check\nG1  X1 Y2 ; odd spacing\n",
+        );
+        let bgcode = Bgcode::from_ascii(&src, ConvertOptions::default());
+        let read = Bgcode::read(&bgcode.write()).unwrap();
+        assert_eq!(read.to_ascii(), src);
+    }
+
+    /// Built by hand from the libbgcode specification rather than by [`Bgcode::write`]: a file
+    /// metadata block, a G-code block compressed with Heatshrink 11/4 and one packed with
+    /// MeatPack in no-spaces mode and compressed with Heatshrink 12/4.
+    const SPEC_FILE: &[u8] = include_bytes!("../../../testdata/bgcode/heatshrink_meatpack.bgcode");
+
+    #[test]
+    fn should_read_a_file_written_to_the_specification() {
+        let bgcode = Bgcode::read(SPEC_FILE).unwrap();
+        assert_eq!(
+            bgcode.metadata(BlockKind::FileMetadata),
+            [("Producer".to_string(), "PrusaSlicer 2.7.0".to_string())]
+        );
+        let blocks: Vec<_> = bgcode
+            .blocks
+            .iter()
+            .map(|block| match &block.content {
+                BlockContent::GCode { encoding, .. } => Some((block.compression, *encoding)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                None,
+                Some((Compression::Heatshrink11, GCodeEncoding::None)),
+                Some((Compression::Heatshrink12, GCodeEncoding::MeatPack)),
+            ]
+        );
+        assert_eq!(
+            bgcode.gcode(),
+            "G28 ; home\nG1 Z0.2 F720\nG1 X10 Y10 E0.5\nG1 X20 Y10 E0.5\nG1 X20 Y20 E0.5\n\
+             G1 X10 Y20 E1.25\nG1 X10 Y30 E1.25\nM104 S0\n"
+        );
+    }
+
+    #[test]
+    fn should_not_trust_the_uncompressed_size() {
+        let data = heatshrink::compress(b"G1 X10 Y10\nG1 X20 Y10\n", 12, 4);
+        assert_eq!(heatshrink::decompress(&data, 12, 4, usize::MAX), None);
+    }
+
+    #[test]
+    fn should_detect_corruption() {
+        let mut data = Bgcode::from_ascii(&sample(), ConvertOptions::default()).write();
+        let last = data.len() - 10;
+        data[last] ^= 0x55;
+        assert!(matches!(
+            Bgcode::read(&data),
+            Err(BgcodeError::Checksum { .. })
+        ));
+        data.truncate(last);
+        assert!(matches!(
+            Bgcode::read(&data),
+            Err(BgcodeError::Truncated { .. })
+        ));
+    }
+}
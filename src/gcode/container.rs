@@ -0,0 +1,375 @@
+//! G-code packaged in zip containers: Bambu Studio's `.gcode.3mf` and Ultimaker's `.ufp`.
+//!
+//! Bambu files hold one `Metadata/plate_<n>.gcode` per plate, with a `plate_<n>.png` preview
+//! and the print estimates of every plate in `Metadata/slice_info.config`. UFP files hold a
+//! single `3D/model.gcode` and `Metadata/thumbnail.png`. Other zips are read as plates of their
+//! `.gcode` entries in name order.
+
+use crate::gcode::thumbnails::{Thumbnail, ThumbnailFormat};
+use std::fmt;
+use std::fmt::Display;
+use std::io::{Cursor, Read};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Whether `data` starts like a zip file.
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// The kinds of containers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Bambu3mf,
+    Ufp,
+    Zip,
+}
+
+impl Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerKind::Bambu3mf => "Bambu 3MF",
+            ContainerKind::Ufp => "Ultimaker UFP",
+            ContainerKind::Zip => "zip",
+        })
+    }
+}
+
+/// A plate of a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plate {
+    /// Number of the plate, starting at 1
+    pub number: usize,
+    /// The entry holding the G-code
+    pub gcode: String,
+    /// The entry holding the PNG preview
+    pub thumbnail: Option<String>,
+    /// The `key`, `value` pairs describing the plate, in order
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Everything that can go wrong while reading a container.
+#[derive(Debug)]
+pub enum ContainerError {
+    Zip(ZipError),
+    /// The container has no G-code entry.
+    NoGcode,
+    /// The requested plate doesn't exist.
+    Plate {
+        plate: usize,
+        plates: Vec<usize>,
+    },
+}
+
+impl Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::Zip(err) => write!(f, "invalid zip: {}", err),
+            ContainerError::NoGcode => write!(f, "no G-code in the container"),
+            ContainerError::Plate { plate, plates } => write!(
+                f,
+                "no plate {}, the plates are {}",
+                plate,
+                plates
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ContainerError::Zip(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ZipError> for ContainerError {
+    fn from(err: ZipError) -> Self {
+        ContainerError::Zip(err)
+    }
+}
+
+/// An opened container.
+pub struct Container {
+    pub kind: ContainerKind,
+    /// The plates, by number
+    pub plates: Vec<Plate>,
+    archive: ZipArchive<Cursor<Vec<u8>>>,
+}
+
+/// The number of a `Metadata/plate_<n>.gcode` entry.
+fn bambu_plate(name: &str) -> Option<usize> {
+    name.strip_prefix("Metadata/plate_")?
+        .strip_suffix(".gcode")?
+        .parse()
+        .ok()
+}
+
+impl Container {
+    /// Opens a container and finds its plates.
+    pub fn open(data: Vec<u8>) -> Result<Container, ContainerError> {
+        let mut archive = ZipArchive::new(Cursor::new(data))?;
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        let has = |name: &str| names.iter().any(|n| n == name);
+
+        let mut bambu: Vec<usize> = names.iter().filter_map(|n| bambu_plate(n)).collect();
+        bambu.sort_unstable();
+        let (kind, mut plates) = if !bambu.is_empty() {
+            let plates = bambu
+                .into_iter()
+                .map(|number| {
+                    let thumbnail = format!("Metadata/plate_{}.png", number);
+                    Plate {
+                        number,
+                        gcode: format!("Metadata/plate_{}.gcode", number),
+                        thumbnail: has(&thumbnail).then_some(thumbnail),
+                        metadata: Vec::new(),
+                    }
+                })
+                .collect();
+            (ContainerKind::Bambu3mf, plates)
+        } else if has("3D/model.gcode") {
+            let thumbnail = "Metadata/thumbnail.png";
+            let plate = Plate {
+                number: 1,
+                gcode: "3D/model.gcode".to_string(),
+                thumbnail: has(thumbnail).then_some(thumbnail.to_string()),
+                metadata: Vec::new(),
+            };
+            (ContainerKind::Ufp, vec![plate])
+        } else {
+            let plates = names
+                .iter()
+                .filter(|name| name.to_ascii_lowercase().ends_with(".gcode"))
+                .enumerate()
+                .map(|(i, name)| Plate {
+                    number: i + 1,
+                    gcode: name.clone(),
+                    thumbnail: None,
+                    metadata: Vec::new(),
+                })
+                .collect();
+            (ContainerKind::Zip, plates)
+        };
+        if plates.is_empty() {
+            return Err(ContainerError::NoGcode);
+        }
+
+        if kind == ContainerKind::Bambu3mf && has("Metadata/slice_info.config") {
+            let config = read_entry(&mut archive, "Metadata/slice_info.config")?;
+            let config = String::from_utf8_lossy(&config);
+            for (number, metadata) in slice_info(&config) {
+                if let Some(plate) = plates.iter_mut().find(|p| p.number == number) {
+                    plate.metadata = metadata;
+                }
+            }
+        }
+        Ok(Container {
+            kind,
+            plates,
+            archive,
+        })
+    }
+
+    /// The plate `number`, or the first one.
+    pub fn plate(&self, number: Option<usize>) -> Result<&Plate, ContainerError> {
+        match number {
+            None => Ok(&self.plates[0]),
+            Some(number) => self
+                .plates
+                .iter()
+                .find(|p| p.number == number)
+                .ok_or_else(|| ContainerError::Plate {
+                    plate: number,
+                    plates: self.plates.iter().map(|p| p.number).collect(),
+                }),
+        }
+    }
+
+    /// The G-code of a plate, byte for byte, so that its line numbers are those of the entry.
+    /// The metadata of the plate is in [`Plate::metadata`] and its preview in
+    /// [`Container::read_preview`].
+    pub fn read_plate(&mut self, number: Option<usize>) -> Result<Vec<u8>, ContainerError> {
+        let plate = self.plate(number)?.gcode.clone();
+        read_entry(&mut self.archive, &plate)
+    }
+
+    /// The PNG preview of a plate stored next to its G-code, as a thumbnail that isn't on any
+    /// line of the G-code.
+    pub fn read_preview(
+        &mut self,
+        number: Option<usize>,
+    ) -> Result<Option<Thumbnail>, ContainerError> {
+        let Some(entry) = self.plate(number)?.thumbnail.clone() else {
+            return Ok(None);
+        };
+        let png = read_entry(&mut self.archive, &entry)?;
+        Ok(png_size(&png).map(|(width, height)| {
+            let base64 = base64::encode(&png);
+            Thumbnail {
+                format: ThumbnailFormat::Png,
+                width,
+                height,
+                lines: 0..0,
+                length: base64.len(),
+                base64,
+            }
+        }))
+    }
+}
+
+fn read_entry(
+    archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+    name: &str,
+) -> Result<Vec<u8>, ContainerError> {
+    let mut entry = archive.by_name(name)?;
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry
+        .read_to_end(&mut data)
+        .map_err(|err| ContainerError::Zip(ZipError::Io(err)))?;
+    Ok(data)
+}
+
+/// The size in the IHDR chunk of a PNG.
+fn png_size(png: &[u8]) -> Option<(u32, u32)> {
+    if !png.starts_with(b"\x89PNG") || png.len() < 24 {
+        return None;
+    }
+    let number = |at: usize| u32::from_be_bytes([png[at], png[at + 1], png[at + 2], png[at + 3]]);
+    Some((number(16), number(20)))
+}
+
+/// The value of the attribute `name` of an XML element.
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let start = element.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = element[start..].find('"')?;
+    Some(&element[start..start + end])
+}
+
+/// The metadata of each plate of Bambu's `slice_info.config`, by plate number.
+///
+/// Plates are `<plate>` elements with `<metadata key=".." value=".."/>` children, one of them
+/// the `index`, and a `<filament>` element per filament used.
+fn slice_info(config: &str) -> Vec<(usize, Vec<(String, String)>)> {
+    let mut plates = Vec::new();
+    for plate in config.split("<plate>").skip(1) {
+        let plate = plate.split("</plate>").next().unwrap_or(plate);
+        let mut number = None;
+        let mut metadata = Vec::new();
+        for element in plate.split('<').skip(1) {
+            if element.starts_with("metadata ") {
+                let (Some(key), Some(value)) =
+                    (attribute(element, "key"), attribute(element, "value"))
+                else {
+                    continue;
+                };
+                if key == "index" {
+                    number = value.parse().ok();
+                } else {
+                    metadata.push((key.to_string(), value.to_string()));
+                }
+            } else if element.starts_with("filament ") {
+                let id = attribute(element, "id").unwrap_or("?");
+                for name in ["type", "color", "used_m", "used_g"] {
+                    if let Some(value) = attribute(element, name) {
+                        metadata.push((format!("filament {} {}", id, name), value.to_string()));
+                    }
+                }
+            }
+        }
+        if let Some(number) = number {
+            plates.push((number, metadata));
+        }
+    }
+    plates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::FileOptions;
+
+    #[test]
+    fn should_read_bambu_plates() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let entries = [
+            ("Metadata/plate_1.gcode", "G28\n"),
+            ("Metadata/plate_2.gcode", "G1 X1\n"),
+            (
+                "Metadata/slice_info.config",
+                "<config>\n<plate>\n<metadata key=\"index\" value=\"2\"/>\n\
+                 <metadata key=\"prediction\" value=\"5863\"/>\n\
+                 <filament id=\"1\" type=\"PLA\" color=\"#FFFFFF\" used_g=\"17.45\" />\n\
+                 </plate>\n</config>\n",
+            ),
+        ];
+        for (name, content) in entries {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let data = zip.finish().unwrap().into_inner();
+        assert!(is_zip(&data));
+
+        let mut container = Container::open(data).unwrap();
+        assert_eq!(container.kind, ContainerKind::Bambu3mf);
+        assert_eq!(container.plates.len(), 2);
+        assert_eq!(container.read_plate(None).unwrap(), b"G28\n");
+        assert_eq!(container.read_plate(Some(2)).unwrap(), b"G1 X1\n");
+        assert_eq!(
+            container.plate(Some(2)).unwrap().metadata,
+            [
+                ("prediction", "5863"),
+                ("filament 1 type", "PLA"),
+                ("filament 1 color", "#FFFFFF"),
+                ("filament 1 used_g", "17.45")
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(container.read_preview(Some(2)).unwrap(), None);
+        assert!(matches!(
+            container.read_plate(Some(3)),
+            Err(ContainerError::Plate { plate: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn should_read_ufp_packages() {
+        let png = crate::render::raster::Image::new(4, 3, crate::render::Rgb(0, 0, 0xff))
+            .encode_png()
+            .unwrap();
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("3D/model.gcode", FileOptions::default())
+            .unwrap();
+        zip.write_all(b";FLAVOR:Griffin\r\nG28\r\n; 20\xb0\r\n")
+            .unwrap();
+        zip.start_file("Metadata/thumbnail.png", FileOptions::default())
+            .unwrap();
+        zip.write_all(&png).unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let mut container = Container::open(data).unwrap();
+        assert_eq!(container.kind, ContainerKind::Ufp);
+        assert_eq!(container.plates.len(), 1);
+        // the G-code is the entry as is, so line 2 is line 2 of the entry
+        assert_eq!(
+            container.read_plate(None).unwrap(),
+            b";FLAVOR:Griffin\r\nG28\r\n; 20\xb0\r\n"
+        );
+        let preview = container.read_preview(None).unwrap().unwrap();
+        assert_eq!((preview.width, preview.height), (4, 3));
+        assert!(preview.lines.is_empty());
+        assert_eq!(preview.data().unwrap(), png);
+        assert!(matches!(
+            container.read_plate(Some(2)),
+            Err(ContainerError::Plate { plate: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,542 @@
+//! Volumetric flow and print speed of the extruding moves, compared with what the hotend of
+//! a [`MachineProfile`] can do.
+//!
+//! The volumetric flow of a move is the filament it pushes per second times the
+//! cross-section of the filament, see [`Move::volumetric_flow`]. A hotend only melts so much
+//! plastic per second; above that it under-extrudes or skips, whatever the slicer asked for.
+
+use crate::gcode::machine::{Machine, Move, Observer, Toolpath};
+use crate::gcode::profile::MachineProfile;
+use crate::gcode::stats::NO_FEATURE;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// The values a histogram counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantity {
+    /// Volumetric flow in mm³/s
+    Flow,
+    /// Speed of the nozzle in mm/s
+    Speed,
+}
+
+impl Quantity {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Quantity::Flow => "mm³/s",
+            Quantity::Speed => "mm/s",
+        }
+    }
+
+    /// The value of the extruding move `m`.
+    pub fn of(&self, m: &Move, filament_area: f64) -> f64 {
+        match self {
+            Quantity::Flow => m.volumetric_flow(filament_area),
+            Quantity::Speed => m.speed(),
+        }
+    }
+}
+
+impl Move {
+    /// Volume of plastic extruded per second in mm³/s, for a filament with a cross-section
+    /// of `filament_area` mm².
+    pub fn volumetric_flow(&self, filament_area: f64) -> f64 {
+        match self.duration() {
+            duration if duration > 0.0 && self.extrusion > 0.0 => {
+                self.extrusion * filament_area / duration
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// The limits moves are checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Limits {
+    /// Filament diameter in mm
+    pub filament_diameter: f64,
+    /// Maximum volumetric flow in mm³/s
+    pub max_flow: f64,
+    /// Maximum print speed in mm/s
+    pub max_speed: f64,
+}
+
+impl Limits {
+    pub fn limit(&self, quantity: Quantity) -> f64 {
+        match quantity {
+            Quantity::Flow => self.max_flow,
+            Quantity::Speed => self.max_speed,
+        }
+    }
+
+    fn filament_area(&self) -> f64 {
+        std::f64::consts::PI * (self.filament_diameter / 2.0).powi(2)
+    }
+}
+
+impl From<&MachineProfile> for Limits {
+    fn from(profile: &MachineProfile) -> Self {
+        Limits {
+            filament_diameter: profile.filament_diameter,
+            max_flow: profile.max_volumetric_flow,
+            max_speed: profile.max_print_speed,
+        }
+    }
+}
+
+/// A bin of a [`Histogram`], the values from `start` up to the start of the next bin.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Bin {
+    pub start: f64,
+    pub moves: usize,
+    /// Estimated seconds spent by the moves
+    pub time: f64,
+}
+
+/// How the values of a run of moves are distributed, in bins of equal width.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub bin_width: f64,
+    /// The bins holding moves, from the lowest value to the highest
+    pub bins: Vec<Bin>,
+    pub max: f64,
+}
+
+impl Histogram {
+    /// The histogram of `(value, seconds)` pairs, in bins `bin_width` wide starting at 0.
+    pub fn new(values: impl IntoIterator<Item = (f64, f64)>, bin_width: f64) -> Self {
+        let mut bins = Bins::default();
+        for (value, time) in values {
+            bins.add(value, time, bin_width);
+        }
+        bins.finish(bin_width)
+    }
+
+    /// The start and end of `bin`, with as many decimals as the width of the bins has.
+    pub fn edges(&self, bin: &Bin) -> [String; 2] {
+        let decimals = decimals(self.bin_width);
+        [bin.start, bin.start + self.bin_width].map(|edge| format!("{:.*}", decimals, edge))
+    }
+}
+
+/// The decimals of `width`, up to 6, e.g. 1 for `0.1` and 0 for `10`.
+fn decimals(width: f64) -> usize {
+    (0..6)
+        .find(|&decimals| {
+            let scaled = width * 10f64.powi(decimals as i32);
+            (scaled - scaled.round()).abs() < 1e-9 * scaled.max(1.0)
+        })
+        .unwrap_or(6)
+}
+
+/// `index` times `width`, rounded to the decimals of `width`, so that bins `0.1` wide start at
+/// `2.3` rather than `2.3000000000000003`.
+fn bin_start(index: u64, width: f64) -> f64 {
+    let scale = 10f64.powi(decimals(width) as i32);
+    (index as f64 * width * scale).round() / scale
+}
+
+/// A [`Histogram`] being filled, by the index of its bins.
+#[derive(Debug, Clone, Default)]
+struct Bins {
+    // sparse, a tiny move can extrude at an absurd rate far above all the others
+    bins: BTreeMap<u64, Bin>,
+    max: f64,
+}
+
+impl Bins {
+    fn add(&mut self, value: f64, time: f64, width: f64) {
+        let index = (value / width).floor().max(0.0) as u64;
+        let bin = self.bins.entry(index).or_insert_with(|| Bin {
+            start: bin_start(index, width),
+            ..Bin::default()
+        });
+        bin.moves += 1;
+        bin.time += time;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(mut self, next: Bins) -> Bins {
+        for (index, bin) in next.bins {
+            let into = self.bins.entry(index).or_insert_with(|| Bin {
+                start: bin.start,
+                ..Bin::default()
+            });
+            into.moves += bin.moves;
+            into.time += bin.time;
+        }
+        self.max = self.max.max(next.max);
+        self
+    }
+
+    fn finish(self, bin_width: f64) -> Histogram {
+        Histogram {
+            bin_width,
+            bins: self.bins.into_values().collect(),
+            max: self.max,
+        }
+    }
+}
+
+/// The histograms of the extruding moves of one feature type.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeatureFlow {
+    pub feature: String,
+    pub flow: Histogram,
+    pub speed: Histogram,
+}
+
+/// Consecutive extruding moves going over a limit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub quantity: Quantity,
+    /// Lines of the file, starting at 1
+    pub lines: Range<usize>,
+    pub layer: usize,
+    pub feature: String,
+    pub moves: usize,
+    /// Estimated seconds spent over the limit
+    pub time: f64,
+    /// Highest value of the moves
+    pub peak: f64,
+    pub limit: f64,
+}
+
+/// Flow and speed of a whole file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlowReport {
+    pub limits: Limits,
+    /// All extruding moves
+    pub total: FeatureFlow,
+    /// Per feature, sorted by name
+    pub features: Vec<FeatureFlow>,
+    /// In the order of the file, volumetric flow and speed mixed
+    pub violations: Vec<Violation>,
+}
+
+/// Bin widths of the histograms, in the units of [`Quantity::unit`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinWidths {
+    pub flow: f64,
+    pub speed: f64,
+}
+
+impl Default for BinWidths {
+    fn default() -> Self {
+        Self {
+            flow: 1.0,
+            speed: 10.0,
+        }
+    }
+}
+
+/// The histograms of one feature type being filled.
+#[derive(Debug, Clone, Default)]
+struct FeatureBins {
+    flow: Bins,
+    speed: Bins,
+}
+
+impl FeatureBins {
+    fn merge(self, next: FeatureBins) -> FeatureBins {
+        FeatureBins {
+            flow: self.flow.merge(next.flow),
+            speed: self.speed.merge(next.speed),
+        }
+    }
+
+    fn finish(self, feature: &str, widths: BinWidths) -> FeatureFlow {
+        FeatureFlow {
+            feature: feature.to_string(),
+            flow: self.flow.finish(widths.flow),
+            speed: self.speed.finish(widths.speed),
+        }
+    }
+}
+
+/// The runs of moves going over the limit of a quantity, see [`Violation`].
+#[derive(Debug, Clone)]
+struct Runs {
+    quantity: Quantity,
+    limit: f64,
+    /// In the order of the file
+    runs: Vec<Violation>,
+    /// Whether the first extruding move went over the limit, `None` before any
+    starts_over: Option<bool>,
+    /// Whether the last extruding move went over the limit, so the last run may go on
+    open: bool,
+}
+
+impl Runs {
+    fn new(quantity: Quantity, limit: f64) -> Self {
+        Runs {
+            quantity,
+            limit,
+            runs: vec![],
+            starts_over: None,
+            open: false,
+        }
+    }
+
+    /// Adds the extruding move `m` and its `value`.
+    fn add(&mut self, m: &Move, value: f64) {
+        // a run ends at the first move within the limit or of another feature or layer,
+        // travel and retractions in between don't count
+        let over = value > self.limit;
+        self.starts_over.get_or_insert(over);
+        if !over {
+            self.open = false;
+            return;
+        }
+        let feature = m.feature.as_deref().unwrap_or(NO_FEATURE);
+        let run = Violation {
+            quantity: self.quantity,
+            lines: m.line + 1..m.line + 2,
+            layer: m.layer,
+            feature: feature.to_string(),
+            moves: 1,
+            time: m.duration(),
+            peak: value,
+            limit: self.limit,
+        };
+        self.push(run);
+        self.open = true;
+    }
+
+    /// Adds `run`, continuing the last run if it is open and of the same feature and layer.
+    fn push(&mut self, run: Violation) {
+        match self.runs.last_mut() {
+            Some(last) if self.open && last.feature == run.feature && last.layer == run.layer => {
+                last.lines.end = run.lines.end;
+                last.moves += run.moves;
+                last.time += run.time;
+                last.peak = last.peak.max(run.peak);
+            }
+            _ => self.runs.push(run),
+        }
+    }
+
+    /// Adds the runs of the moves after those of `self`.
+    fn merge(mut self, next: Runs) -> Runs {
+        let Some(starts_over) = next.starts_over else {
+            return self;
+        };
+        self.starts_over.get_or_insert(starts_over);
+        let mut runs = next.runs.into_iter();
+        if !starts_over {
+            self.open = false;
+        }
+        if let Some(first) = runs.next() {
+            self.push(first);
+        }
+        self.runs.extend(runs);
+        self.open = next.open;
+        self
+    }
+}
+
+/// The histograms and runs over the limits of the extruding moves of a file, filled a chunk
+/// at a time, see [`flow_report`].
+#[derive(Debug, Clone)]
+pub struct FlowTotals {
+    limits: Limits,
+    widths: BinWidths,
+    total: FeatureBins,
+    features: BTreeMap<String, FeatureBins>,
+    flow: Runs,
+    speed: Runs,
+}
+
+impl FlowTotals {
+    pub fn new(limits: Limits, widths: BinWidths) -> Self {
+        FlowTotals {
+            limits,
+            widths,
+            total: FeatureBins::default(),
+            features: BTreeMap::new(),
+            flow: Runs::new(Quantity::Flow, limits.max_flow),
+            speed: Runs::new(Quantity::Speed, limits.max_speed),
+        }
+    }
+
+    fn add(&mut self, m: &Move) {
+        if !m.is_extrusion() {
+            return;
+        }
+        let flow = Quantity::Flow.of(m, self.limits.filament_area());
+        let speed = Quantity::Speed.of(m, self.limits.filament_area());
+        let feature = m.feature.as_deref().unwrap_or(NO_FEATURE);
+        if !self.features.contains_key(feature) {
+            self.features
+                .insert(feature.to_string(), FeatureBins::default());
+        }
+        let widths = self.widths;
+        for bins in [&mut self.total, self.features.get_mut(feature).unwrap()] {
+            bins.flow.add(flow, m.duration(), widths.flow);
+            bins.speed.add(speed, m.duration(), widths.speed);
+        }
+        self.flow.add(m, flow);
+        self.speed.add(m, speed);
+    }
+
+    /// Adds what was found in the moves after those of `self`.
+    pub fn merge(mut self, next: FlowTotals) -> FlowTotals {
+        self.total = self.total.merge(next.total);
+        for (feature, bins) in next.features {
+            let into = self.features.entry(feature).or_default();
+            *into = std::mem::take(into).merge(bins);
+        }
+        self.flow = self.flow.merge(next.flow);
+        self.speed = self.speed.merge(next.speed);
+        self
+    }
+
+    pub fn finish(self) -> FlowReport {
+        let widths = self.widths;
+        let mut violations = self.flow.runs;
+        violations.extend(self.speed.runs);
+        violations.sort_by_key(|v| (v.lines.start, v.quantity == Quantity::Speed));
+        FlowReport {
+            limits: self.limits,
+            total: self.total.finish("all", widths),
+            features: self
+                .features
+                .into_iter()
+                .map(|(feature, bins)| bins.finish(&feature, widths))
+                .collect(),
+            violations,
+        }
+    }
+}
+
+impl Observer for FlowTotals {
+    fn observe(&mut self, _: &Machine, moves: Vec<Move>) {
+        moves.iter().for_each(|m| self.add(m));
+    }
+}
+
+/// Builds the histograms of the extruding moves of `toolpath` and finds the runs of moves
+/// going over `limits`.
+pub fn flow_report(toolpath: &Toolpath, limits: Limits, widths: BinWidths) -> FlowReport {
+    let mut totals = FlowTotals::new(limits, widths);
+    toolpath.moves.iter().for_each(|m| totals.add(m));
+    totals.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::{parse_lines, LineIterator};
+    use crate::gcode::machine::simulate;
+    use crate::gcode::parallel::split;
+
+    #[test]
+    fn should_find_runs_over_the_limits() {
+        // 0.1 mm of filament per 10 mm at 60 mm/s is 0.6 mm/s of filament, 1.44 mm³/s
+        let src = "M83\n;TYPE:WALL-OUTER\nG1 X10 E0.1 F3600\nG1 X20 E0.1\n;TYPE:FILL\n\
+                   G1 X30 E1 F6000\nG1 X40 E1\nG0 X0\nG1 X10 E0.1 F3600\n";
+        let toolpath = simulate(LineIterator::new(parse_lines(src)));
+        let limits = Limits {
+            filament_diameter: 1.75,
+            max_flow: 12.0,
+            max_speed: 80.0,
+        };
+        let report = flow_report(&toolpath, limits, BinWidths::default());
+
+        assert_eq!(report.features.len(), 2);
+        let wall = &report.features[1];
+        assert_eq!(wall.feature, "WALL-OUTER");
+        assert_eq!(wall.flow.bins.len(), 1);
+        assert_eq!((wall.flow.bins[0].start, wall.flow.bins[0].moves), (1.0, 2));
+        assert_eq!(wall.speed.bins[0].start, 60.0);
+        let starts: Vec<f64> = report.total.flow.bins.iter().map(|bin| bin.start).collect();
+        assert_eq!(starts, vec![1.0, 24.0]);
+
+        let found: Vec<(Quantity, Range<usize>, &str, usize)> = report
+            .violations
+            .iter()
+            .map(|v| (v.quantity, v.lines.clone(), v.feature.as_str(), v.moves))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Quantity::Flow, 6..8, "FILL", 2),
+                (Quantity::Speed, 6..8, "FILL", 2)
+            ]
+        );
+        assert!((report.violations[0].peak - 24.05).abs() < 0.01);
+    }
+
+    #[test]
+    fn should_bin_a_tiny_move_extruding_at_an_absurd_rate() {
+        let src = "G1 X10 F1800\nG1 X10.000001 E5 F6000\n";
+        let toolpath = simulate(LineIterator::new(parse_lines(src)));
+        let limits = Limits {
+            filament_diameter: 1.75,
+            max_flow: 12.0,
+            max_speed: 80.0,
+        };
+        let report = flow_report(&toolpath, limits, BinWidths::default());
+
+        assert_eq!(report.total.flow.bins.len(), 1);
+        assert!(report.total.flow.max > 1e8, "{}", report.total.flow.max);
+        assert_eq!(report.violations[0].quantity, Quantity::Flow);
+    }
+
+    #[test]
+    fn should_join_the_runs_of_the_chunks_of_a_file() {
+        let mut src = String::from("M83\n");
+        for i in 0..30 {
+            src.push_str(match i % 3 {
+                0 => ";TYPE:WALL\nG1 X10 E0.1 F3600\nG1 X30 E1 F6000\nG0 X0\n",
+                1 => "G1 X10 E1\nG1 Y10 E1\n;TYPE:FILL\nG1 X30 E2\n",
+                _ => "G1 X10 E0.1 F3600\n;LAYER_CHANGE\nG1 Z0.2\nG1 X30 E2 F6000\n",
+            });
+        }
+        let toolpath = simulate(LineIterator::new(parse_lines(&src)));
+        let limits = Limits {
+            filament_diameter: 1.75,
+            max_flow: 12.0,
+            max_speed: 80.0,
+        };
+        let expected = flow_report(&toolpath, limits, BinWidths::default());
+        assert!(expected.violations.iter().any(|v| v.moves > 2));
+
+        // a chunk per line, runs go on over several chunks
+        let mut totals = FlowTotals::new(limits, BinWidths::default());
+        crate::gcode::parallel::fold(
+            split(&src, 1).into_iter().map(Ok::<_, ()>),
+            |_, _| FlowTotals::new(limits, BinWidths::default()),
+            |next| {
+                totals = totals.clone().merge(next);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(totals.finish(), expected);
+    }
+
+    #[test]
+    fn should_show_the_edges_of_bins_of_any_width() {
+        let histogram = Histogram::new([(2.35, 1.0), (0.05, 1.0), (17.0, 1.0)], 0.1);
+        let starts: Vec<f64> = histogram.bins.iter().map(|bin| bin.start).collect();
+        assert_eq!(starts, vec![0.0, 2.3, 17.0]);
+        let edges: Vec<[String; 2]> = histogram
+            .bins
+            .iter()
+            .map(|bin| histogram.edges(bin))
+            .collect();
+        assert_eq!(
+            edges,
+            [["0.0", "0.1"], ["2.3", "2.4"], ["17.0", "17.1"]].map(|e| e.map(String::from))
+        );
+
+        let histogram = Histogram::new([(23.0, 1.0)], 2.5);
+        assert_eq!(histogram.edges(&histogram.bins[0]), ["22.5", "25.0"]);
+        let histogram = Histogram::new([(23.0, 1.0)], 10.0);
+        assert_eq!(histogram.edges(&histogram.bins[0]), ["20", "30"]);
+        assert_eq!(decimals(0.05), 2);
+    }
+}
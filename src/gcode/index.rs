@@ -0,0 +1,333 @@
+//! An index of the lines and layers of a G-code file, so that large files can be shown and
+//! searched without splitting them into lines first.
+//!
+//! The index keeps the byte offset of every [`STRIDE`]th line, a line is found by scanning
+//! from the closest one. Building it takes a while for files of several hundred megabytes, so
+//! [`LineIndex::spawn`] builds it on a thread and the index can be used while it grows.
+//!
+//! Chunks of the file (see [`crate::gcode::parallel`]) are parsed on the rayon thread pool.
+//! Whether a line starts a layer depends on the state of the machine, so the parsed lines are
+//! executed in order by a [`Machine`], which splits layers at layer comments or else where
+//! the extrusion goes higher up. Layer N of the index is layer N of the
+//! [`crate::gcode::machine::Toolpath`] of the file.
+
+use crate::gcode::lines::DisplayLine;
+use crate::gcode::machine::Machine;
+use crate::gcode::parallel::{split_bytes, Chunk, CHUNK_SIZE};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Number of lines between two offsets kept by the index.
+pub const STRIDE: usize = 64;
+
+/// The line index of a file.
+#[derive(Debug, Default)]
+pub struct LineIndex {
+    state: RwLock<IndexState>,
+    /// Bytes indexed so far
+    indexed: AtomicUsize,
+    /// Bytes of the file
+    size: usize,
+    complete: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    /// Byte offset of lines 0, STRIDE, 2 * STRIDE...
+    checkpoints: Vec<usize>,
+    /// Number of lines indexed
+    lines: usize,
+    /// First line of each layer
+    layers: Vec<usize>,
+    /// How often each command is used, e.g. `G1`
+    commands: HashMap<String, usize>,
+}
+
+/// The index of a chunk.
+#[derive(Debug, Default)]
+struct ChunkIndex {
+    lines: usize,
+    /// Byte offset of the lines of the chunk whose number is a multiple of [`STRIDE`]
+    checkpoints: Vec<usize>,
+    commands: HashMap<String, usize>,
+}
+
+impl ChunkIndex {
+    /// Indexes `chunk`, whose lines are `parsed`.
+    fn new(chunk: &Chunk<[u8]>, parsed: &[DisplayLine]) -> Self {
+        let mut index = ChunkIndex::default();
+        let mut offset = chunk.offset;
+        for (number, line) in (chunk.first_line..).zip(chunk.text.split_inclusive(|&b| b == b'\n'))
+        {
+            if number.is_multiple_of(STRIDE) {
+                index.checkpoints.push(offset);
+            }
+            offset += line.len();
+            index.lines += 1;
+        }
+        for line in parsed {
+            if let DisplayLine::GCode(name, _) = line {
+                *index.commands.entry(name.clone()).or_default() += 1;
+            }
+        }
+        index
+    }
+}
+
+/// The text of `chunk`, copied before it is checked to be UTF-8 since the file may be mapped.
+fn chunk_text(chunk: &Chunk<[u8]>) -> String {
+    String::from_utf8(chunk.text.to_vec())
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
+
+/// Finds the layers by executing the lines of the chunks in order, like a [`Simulation`]
+/// without keeping the moves.
+///
+/// [`Simulation`]: crate::gcode::machine::Simulation
+#[derive(Debug, Default)]
+struct Layers {
+    machine: Machine,
+}
+
+impl Layers {
+    /// The first line of each layer starting in `lines`.
+    fn starts(&mut self, lines: &[DisplayLine]) -> Vec<usize> {
+        let mut starts = Vec::new();
+        for line in lines {
+            let layer = self.machine.state().layer;
+            self.machine.execute(line);
+            if self.machine.state().layer > layer {
+                starts.push(self.machine.line());
+            }
+        }
+        starts
+    }
+}
+
+impl LineIndex {
+    /// Indexes `text` on the current thread.
+    pub fn build(text: &[u8]) -> LineIndex {
+        let index = LineIndex {
+            size: text.len(),
+            ..LineIndex::default()
+        };
+        index.index(text, CHUNK_SIZE);
+        index
+    }
+
+    /// Indexes `text` on a new thread, the index grows while the thread runs.
+    pub fn spawn<T>(text: Arc<T>) -> Arc<LineIndex>
+    where
+        T: AsRef<[u8]> + Send + Sync + ?Sized + 'static,
+    {
+        let index = Arc::new(LineIndex {
+            size: text.as_ref().as_ref().len(),
+            ..LineIndex::default()
+        });
+        let shared = Arc::clone(&index);
+        std::thread::spawn(move || shared.index(text.as_ref().as_ref(), CHUNK_SIZE));
+        index
+    }
+
+    fn index(&self, text: &[u8], chunk_size: usize) {
+        let chunks = split_bytes(text, chunk_size);
+        let mut layers = Layers::default();
+        // the first layer starts at the top of the file, like in a Toolpath
+        self.write().layers.push(0);
+        for batch in chunks.chunks(rayon::current_num_threads()) {
+            let texts: Vec<String> = batch.par_iter().map(chunk_text).collect();
+            let parsed: Vec<(ChunkIndex, Vec<DisplayLine>)> = batch
+                .par_iter()
+                .zip(texts.par_iter())
+                .map(|(chunk, text)| {
+                    let lines = Chunk {
+                        text: text.as_str(),
+                        offset: chunk.offset,
+                        first_line: chunk.first_line,
+                    }
+                    .parse();
+                    (ChunkIndex::new(chunk, &lines), lines)
+                })
+                .collect();
+            for (chunk, (index, lines)) in batch.iter().zip(parsed) {
+                self.publish(chunk, index, layers.starts(&lines));
+            }
+        }
+        self.complete.store(true, Ordering::Release);
+    }
+
+    /// Adds the index of `chunk` to the shared index, with the layers starting in it.
+    fn publish(&self, chunk: &Chunk<[u8]>, mut index: ChunkIndex, layers: Vec<usize>) {
+        let mut state = self.write();
+        state.lines = chunk.first_line + index.lines;
+        state.checkpoints.append(&mut index.checkpoints);
+        state.layers.extend(layers);
+        for (command, count) in index.commands {
+            *state.commands.entry(command).or_default() += count;
+        }
+        self.indexed
+            .store(chunk.offset + chunk.text.len(), Ordering::Release);
+    }
+
+    /// Whether the whole file is indexed.
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::Acquire)
+    }
+
+    /// Share of the file indexed, between 0 and 1.
+    pub fn progress(&self) -> f64 {
+        if self.is_complete() || self.size == 0 {
+            return 1.0;
+        }
+        self.indexed.load(Ordering::Acquire) as f64 / self.size as f64
+    }
+
+    /// Number of lines indexed so far.
+    pub fn lines(&self) -> usize {
+        self.read().lines
+    }
+
+    /// The first line of each layer indexed so far.
+    pub fn layers(&self) -> Vec<usize> {
+        self.read().layers.clone()
+    }
+
+    /// The layer `line` belongs to, `None` until indexing starts.
+    pub fn layer_of(&self, line: usize) -> Option<usize> {
+        self.read()
+            .layers
+            .partition_point(|&start| start <= line)
+            .checked_sub(1)
+    }
+
+    /// How often each command is used in the lines indexed so far.
+    pub fn commands(&self) -> HashMap<String, usize> {
+        self.read().commands.clone()
+    }
+
+    /// The bytes of `line`, without its line feed, if it is indexed.
+    pub fn line_range(&self, text: &[u8], line: usize) -> Option<Range<usize>> {
+        let start = {
+            let state = self.read();
+            if line >= state.lines {
+                return None;
+            }
+            state.checkpoints[line / STRIDE]
+        };
+        let mut lines = text[start..].split_inclusive(|&b| b == b'\n');
+        let mut start = start;
+        for _ in 0..line % STRIDE {
+            start += lines.next()?.len();
+        }
+        let line = lines.next()?;
+        let len = line
+            .iter()
+            .rposition(|&b| b != b'\r' && b != b'\n')
+            .map_or(0, |last| last + 1);
+        Some(start..start + len)
+    }
+
+    /// The bytes of `line`, without its line feed, if it is indexed.
+    pub fn line<'a>(&self, text: &'a [u8], line: usize) -> Option<&'a [u8]> {
+        self.line_range(text, line).map(|range| &text[range])
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, IndexState> {
+        self.state.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, IndexState> {
+        self.state.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_index_lines_and_layers() {
+        let mut src = String::from("; header\nG28\nG1 Z0.2 F600\n");
+        for layer in 0..3 {
+            if layer > 0 {
+                // a z-hop doesn't start a layer
+                src.push_str("G1 Z1.0\nG0 X0 Y0\n");
+                src.push_str(&format!("G1 Z{:.1}\n", 0.2 + layer as f64 * 0.2));
+            }
+            for i in 0..100 {
+                src.push_str(&format!("G1 X{} Y{} E{}\n", i, i, layer * 100 + i + 1));
+            }
+        }
+
+        let index = LineIndex::build(src.as_bytes());
+        assert!(index.is_complete());
+        // chunks cutting through layers find the same layers
+        let chunked = LineIndex {
+            size: src.len(),
+            ..LineIndex::default()
+        };
+        chunked.index(src.as_bytes(), 100);
+        assert_eq!(chunked.lines(), index.lines());
+        // layers start at the first move extruding higher up, the first one of each layer
+        // only pushes filament
+        assert_eq!(chunked.layers(), vec![0, 107, 210]);
+        assert_eq!(chunked.commands(), index.commands());
+        assert_eq!(index.lines(), src.lines().count());
+        assert_eq!(index.layers(), vec![0, 107, 210]);
+        assert_eq!(index.layer_of(1), Some(0));
+        assert_eq!(index.layer_of(150), Some(1));
+        assert_eq!(index.commands()["G1"], 305);
+        for (number, line) in src.lines().enumerate() {
+            assert_eq!(index.line(src.as_bytes(), number), Some(line.as_bytes()));
+            assert_eq!(chunked.line(src.as_bytes(), number), Some(line.as_bytes()));
+        }
+        assert_eq!(index.line(src.as_bytes(), src.lines().count()), None);
+
+        let spawned = LineIndex::spawn(Arc::new(src.clone()));
+        while !spawned.is_complete() {
+            std::thread::yield_now();
+        }
+        assert_eq!(spawned.layers(), index.layers());
+    }
+
+    #[test]
+    fn should_find_the_layers_of_the_toolpath() {
+        let layers = |src: &str| {
+            let chunked = LineIndex {
+                size: src.len(),
+                ..LineIndex::default()
+            };
+            chunked.index(src.as_bytes(), 64);
+            let toolpath = crate::gcode::parallel::simulate(src);
+            let expected: Vec<usize> = toolpath.layers.iter().map(|l| l.lines.start).collect();
+            assert_eq!(chunked.layers(), expected);
+            expected
+        };
+
+        // layer comments, with the Z moves relative
+        let mut src = String::from("G28\nG1 Z0.3 E5 ; purge\nG91\n");
+        for layer in 0..4 {
+            src.push_str(&format!(
+                ";LAYER:{}\nG1 Z0.2\nG1 X10 E1\nG1 Y10 E1\n",
+                layer
+            ));
+        }
+        assert_eq!(layers(&src), vec![0, 7, 11, 15]);
+
+        // vase mode raises Z on every line, the comments still tell the layers
+        let mut src = String::from(";LAYER_CHANGE\nG1 Z0.2\n");
+        for layer in 0..3 {
+            if layer > 0 {
+                src.push_str(";LAYER_CHANGE\n");
+            }
+            for i in 0..10 {
+                let z = 0.2 + (layer * 10 + i) as f64 * 0.02;
+                src.push_str(&format!("G1 X{} Y{} Z{:.2} E{}\n", i, i % 2, z, i + 1));
+            }
+        }
+        assert_eq!(layers(&src), vec![0, 12, 23]);
+    }
+}
@@ -1,10 +1,176 @@
 use crate::helpers::PopIf;
+use serde::ser::{Serialize, SerializeMap, Serializer};
 
 pub enum DisplayLine<'a> {
     Comment(gcode::Comment<'a>),
     GCode(String, gcode::GCode),
 }
 
+/// Serializes as `{"comment": "; text"}` or `{"opcode": "G1", "parameters": {"X": 10.0}}`, the
+/// parameters in the order of the line.
+impl Serialize for DisplayLine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DisplayLine::Comment(comment) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("comment", comment.value)?;
+                map.end()
+            }
+            DisplayLine::GCode(opcode, gcode) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("opcode", opcode)?;
+                map.serialize_entry("parameters", &Parameters(gcode.arguments()))?;
+                map.end()
+            }
+        }
+    }
+}
+
+struct Parameters<'a>(&'a [gcode::Word]);
+
+impl Serialize for Parameters<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for word in self.0.iter() {
+            map.serialize_entry(&word.letter, &word.value)?;
+        }
+        map.end()
+    }
+}
+
+/// The name of the command of `gcode` used to look it up in the docs, e.g. `G1` or `M600.1`.
+pub fn opcode_name(gcode: &gcode::GCode) -> String {
+    match (gcode.mnemonic(), gcode.major_number(), gcode.minor_number()) {
+        (m, major, 0) => format!("{}{}", m, major),
+        (m, major, minor) => format!("{}{}.{}", m, major, minor),
+    }
+}
+
+/// Commands taking a free-form string argument, e.g. `M117 Hello world`.
+///
+/// The gcode parser would otherwise read the words of the message as arguments.
+pub const STRING_COMMANDS: [&str; 8] = ["M23", "M28", "M30", "M32", "M33", "M117", "M118", "M928"];
+
+/// Commands taking regular arguments followed by an optional message, e.g. `M0 S5 Resume?`.
+pub const MESSAGE_COMMANDS: [&str; 2] = ["M0", "M1"];
+
+/// Parses `src` one physical line at a time.
+///
+/// Unlike [`gcode::full_parse_with_callbacks`], a command never swallows words from the next
+/// line and string arguments (see [`STRING_COMMANDS`]) are not parsed as words. There is
+/// exactly one [`gcode::Line`] per line of `src`, with spans relative to `src`.
+pub fn parse_lines(src: &str) -> impl Iterator<Item = gcode::Line<'_>> {
+    parse_lines_at(src, 0, 0)
+}
+
+/// Like [`parse_lines`] for a part of a file starting at byte `offset` and line `first_line`.
+pub fn parse_lines_at(
+    src: &str,
+    offset: usize,
+    first_line: usize,
+) -> impl Iterator<Item = gcode::Line<'_>> {
+    let mut offset = offset;
+    src.split_inclusive('\n')
+        .enumerate()
+        .map(move |(number, raw)| {
+            let start = offset;
+            offset += raw.len();
+            parse_line(
+                raw.trim_end_matches(['\r', '\n']),
+                start,
+                first_line + number,
+            )
+        })
+}
+
+/// Parses a single line of text located at byte `offset` and line `number` of its file.
+pub fn parse_line(text: &str, offset: usize, number: usize) -> gcode::Line<'_> {
+    let blanked = blank_string_argument(text);
+    let shift =
+        |span: gcode::Span| gcode::Span::new(span.start + offset, span.end + offset, number);
+
+    let mut line = gcode::Line::default();
+    for parsed in gcode::full_parse_with_callbacks(&blanked, gcode::Nop) {
+        for g in parsed.gcodes() {
+            let command_number = g.major_number() as f32 + g.minor_number() as f32 / 10.0;
+            let mut gcode = gcode::GCode::new(g.mnemonic(), command_number, shift(g.span()));
+            for word in g.arguments() {
+                let _ = gcode.push_argument(gcode::Word::new(
+                    word.letter,
+                    word.value,
+                    shift(word.span),
+                ));
+            }
+            let _ = line.push_gcode(gcode);
+        }
+        for c in parsed.comments() {
+            // blanking keeps byte offsets, so the comment can borrow from the original text
+            let _ = line.push_comment(gcode::Comment {
+                value: &text[c.span.start..c.span.end],
+                span: shift(c.span),
+            });
+        }
+    }
+    line
+}
+
+/// Replaces the string argument of a [`STRING_COMMANDS`] or [`MESSAGE_COMMANDS`] command with
+/// spaces, keeping the byte offsets of everything else intact.
+fn blank_string_argument(text: &str) -> std::borrow::Cow<'_, str> {
+    let code_end = text.find(';').unwrap_or(text.len());
+    let mut tokens = text[..code_end]
+        .char_indices()
+        .filter(|(i, c)| {
+            !c.is_whitespace() && (*i == 0 || text[..*i].ends_with(char::is_whitespace))
+        })
+        .map(|(i, _)| i);
+
+    // skip an optional line number
+    let mut command_start = match tokens.next() {
+        Some(start) => start,
+        None => return text.into(),
+    };
+    if text[command_start..].starts_with(['N', 'n']) {
+        command_start = match tokens.next() {
+            Some(start) => start,
+            None => return text.into(),
+        };
+    }
+    let command_end = text[command_start..code_end]
+        .find(char::is_whitespace)
+        .map_or(code_end, |end| command_start + end);
+    let command = text[command_start..command_end].to_uppercase();
+
+    let argument_start = if STRING_COMMANDS.contains(&command.as_str()) {
+        Some(command_end)
+    } else if MESSAGE_COMMANDS.contains(&command.as_str()) {
+        // the message starts at the first token that isn't a word like `S5`
+        tokens.find(|start| !is_word(&text[*start..code_end]))
+    } else {
+        None
+    };
+
+    match argument_start {
+        Some(start) if start < code_end => {
+            let blanked: String = text[start..code_end]
+                .chars()
+                .map(|c| " ".repeat(c.len_utf8()))
+                .collect();
+            format!("{}{}{}", &text[..start], blanked, &text[code_end..]).into()
+        }
+        _ => text.into(),
+    }
+}
+
+/// True if `token` starts with a word, a letter followed by a number like `S5` or `X-1.5`.
+fn is_word(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars
+            .next()
+            .is_some_and(|c| c.is_ascii_digit() || c == '-' || c == '.' || c == '+')
+}
+
 pub struct LineIterator<'input, I>
 where
     I: Iterator<Item = gcode::Line<'input>>,
@@ -19,7 +185,7 @@ impl<'input, I> LineIterator<'input, I>
 where
     I: Iterator<Item = gcode::Line<'input>>,
 {
-    pub fn new(mut lines: I) -> Self {
+    pub fn new(lines: I) -> Self {
         let mut res = LineIterator {
             s: lines,
             current_line: None,
@@ -47,25 +213,22 @@ where
     type Item = DisplayLine<'input>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(l) = &self.current_line {
+        while self.current_line.is_some() {
             let first_gcode_line: usize = self
                 .gcodes
-                .get(0)
-                .and_then(|x| Some(x.span().line))
-                .unwrap_or(0);
+                .first()
+                .map(|x| x.span().line)
+                .unwrap_or(usize::MAX);
 
             // if there are still comments for previous lines, emit
-            if let Some(x) = self.comments.pop_if(|c| c.span.line <= first_gcode_line) {
-                return Some(DisplayLine::Comment(x.clone()));
+            if let Some(x) = PopIf::pop_if(&mut self.comments, |c| c.span.line < first_gcode_line) {
+                return Some(DisplayLine::Comment(x));
             }
 
-            // emit all gcodes buffered up
-            if let Some(i) = self.gcodes.pop() {
-                let opcode = match (i.mnemonic(), i.major_number(), i.minor_number()) {
-                    (m, major, 0) => format!("{}{}", m, major),
-                    (m, major, minor) => format!("{}{}.{}", m, major, minor),
-                };
-                return Some(DisplayLine::GCode(opcode, i.clone()));
+            // emit all gcodes buffered up, in order
+            if !self.gcodes.is_empty() {
+                let i = self.gcodes.remove(0);
+                return Some(DisplayLine::GCode(opcode_name(&i), i));
             }
 
             // all gcodes emitted, get next line
@@ -75,3 +238,61 @@ where
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(src: &str) -> Vec<String> {
+        LineIterator::new(parse_lines(src))
+            .map(|line| match line {
+                DisplayLine::Comment(c) => format!("{} {}", c.span.line, c.value),
+                DisplayLine::GCode(name, g) => {
+                    let args: String = g.arguments().iter().map(|w| w.letter).collect();
+                    format!("{} {} {}", g.span().line, name, args)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_keep_comments_in_order() {
+        // several comments can be buffered at once, they must come out first to last
+        assert_eq!(
+            display("(first) (second) ; third\nG1 X1 (fourth)\n"),
+            vec![
+                "0 (first)",
+                "0 (second)",
+                "0 ; third",
+                "1 G1 X",
+                "1 (fourth)"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_keep_commands_on_their_line() {
+        assert_eq!(
+            display("; start\nG28 X\nG1 X10 Y5 ; move\nM117 Hello X1 world\nG92 E0 G1 E2\n"),
+            vec![
+                "0 ; start",
+                "1 G28 ",
+                "2 G1 XY",
+                "2 ; move",
+                "3 M117 ",
+                "4 G92 E",
+                "4 G1 E",
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_message_arguments() {
+        let line = parse_line("M0 S5 Press X1 to resume", 10, 3);
+        let gcode = &line.gcodes()[0];
+        assert_eq!(opcode_name(gcode), "M0");
+        assert_eq!(gcode.arguments().len(), 1);
+        assert_eq!(gcode.span().start, 10);
+        assert_eq!(gcode.span().line, 3);
+    }
+}
@@ -0,0 +1,766 @@
+//! Simulates the printer executing a G-code file.
+//!
+//! The [`Machine`] tracks the state implied by the commands seen so far: positions and
+//! coordinate modes, feedrate, temperatures, fan, speed and flow factors, the current tool,
+//! the layer and the feature type announced by slicer comments. Each motion command yields
+//! one or more [`Move`]s, [`simulate`] collects them into a [`Toolpath`].
+//!
+//! Print times are estimated from the feedrate alone, ignoring acceleration, so they are
+//! shorter than what the printer will actually take.
+
+use crate::gcode::lines::DisplayLine;
+use serde::Serialize;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Axis indices into the positions of [`MachineState`].
+pub const X: usize = 0;
+pub const Y: usize = 1;
+pub const Z: usize = 2;
+pub const E: usize = 3;
+
+const AXES: [char; 4] = ['X', 'Y', 'Z', 'E'];
+
+/// Length of the segments arcs (`G2`/`G3`) are split into, in mm.
+const ARC_SEGMENT_LENGTH: f64 = 1.0;
+
+/// The state of the printer after executing a line.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MachineState {
+    /// Logical position of X, Y, Z and E in mm, as seen by the G-code
+    pub position: [f64; 4],
+    /// Physical minus logical position, changed by `G92`
+    pub offset: [f64; 4],
+    /// `G90`/`G91`
+    pub absolute: bool,
+    /// `M82`/`M83`
+    pub absolute_e: bool,
+    /// `G20`/`G21`
+    pub inches: bool,
+    /// Feedrate in mm/min
+    pub feedrate: f64,
+    /// Speed factor in percent, `M220`
+    pub speed_factor: f64,
+    /// Flow factor in percent, `M221`
+    pub flow: f64,
+    /// Hotend target temperature in °C, `M104`/`M109`
+    pub hotend_temperature: f64,
+    /// Bed target temperature in °C, `M140`/`M190`
+    pub bed_temperature: f64,
+    /// Part cooling fan speed from 0 to 255, `M106`/`M107`
+    pub fan_speed: f64,
+    pub tool: u32,
+    /// The feature type of the last `;TYPE:` comment, e.g. `WALL-OUTER` or `Perimeter`
+    pub feature: Option<Arc<str>>,
+    /// Index of the current layer, starting at 0
+    pub layer: usize,
+    /// Estimated seconds since the start of the file
+    pub time: f64,
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 4],
+            offset: [0.0; 4],
+            absolute: true,
+            absolute_e: true,
+            inches: false,
+            feedrate: 1500.0,
+            speed_factor: 100.0,
+            flow: 100.0,
+            hotend_temperature: 0.0,
+            bed_temperature: 0.0,
+            fan_speed: 0.0,
+            tool: 0,
+            feature: None,
+            layer: 0,
+            time: 0.0,
+        }
+    }
+}
+
+impl MachineState {
+    /// The physical position of `axis`, the logical one plus the `G92` offset.
+    pub fn physical(&self, axis: usize) -> f64 {
+        self.position[axis] + self.offset[axis]
+    }
+}
+
+/// Widens a parsed value, keeping its decimal representation.
+///
+/// The gcode parser stores values as `f32`, so `0.4` would otherwise become
+/// `0.4000000059604645`.
+fn to_f64(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(f64::from(value))
+}
+
+/// The text of a `;` or `( )` comment.
+fn comment_text(comment: &str) -> &str {
+    comment
+        .trim_start_matches([';', '('])
+        .trim_end_matches(')')
+        .trim()
+}
+
+/// Whether `comment` announces a new layer. Cura writes `;LAYER:3`, PrusaSlicer and its forks
+/// `;LAYER_CHANGE`.
+pub fn is_layer_change(comment: &str) -> bool {
+    let text = comment_text(comment);
+    text == "LAYER_CHANGE"
+        || text
+            .strip_prefix("LAYER:")
+            .is_some_and(|n| n.trim().parse::<i64>().is_ok())
+}
+
+/// What a [`Move`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+    /// Moving without extruding
+    Travel,
+    /// Moving while extruding
+    Extrusion,
+    /// Pulling the filament back without moving
+    Retraction,
+    /// Pushing the filament forward without moving
+    Unretraction,
+}
+
+/// A straight segment of the toolpath.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    /// Line of the command in the file, starting at 0
+    pub line: usize,
+    /// Physical start and end position of X, Y and Z in mm
+    pub from: [f64; 3],
+    pub to: [f64; 3],
+    /// Filament pushed through the nozzle in mm including the flow factor, negative when
+    /// retracting
+    pub extrusion: f64,
+    /// Feedrate in mm/min including the speed factor
+    pub feedrate: f64,
+    pub layer: usize,
+    pub feature: Option<Arc<str>>,
+    pub fan_speed: f64,
+    pub tool: u32,
+}
+
+impl Move {
+    /// Distance travelled by the nozzle in mm.
+    pub fn length(&self) -> f64 {
+        (0..3)
+            .map(|i| (self.to[i] - self.from[i]).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// Distance travelled in the XY plane in mm.
+    pub fn xy_length(&self) -> f64 {
+        (self.to[X] - self.from[X]).hypot(self.to[Y] - self.from[Y])
+    }
+
+    /// Speed of the nozzle in mm/s.
+    pub fn speed(&self) -> f64 {
+        self.feedrate / 60.0
+    }
+
+    /// Estimated duration in seconds.
+    pub fn duration(&self) -> f64 {
+        let distance = match self.length() {
+            length if length > 0.0 => length,
+            _ => self.extrusion.abs(),
+        };
+        if self.feedrate > 0.0 {
+            distance / self.speed()
+        } else {
+            0.0
+        }
+    }
+
+    pub fn kind(&self) -> MoveKind {
+        let moving = self.length() > 0.0;
+        match (moving, self.extrusion) {
+            (true, e) if e > 0.0 => MoveKind::Extrusion,
+            (true, _) => MoveKind::Travel,
+            (false, e) if e < 0.0 => MoveKind::Retraction,
+            (false, e) if e > 0.0 => MoveKind::Unretraction,
+            (false, _) => MoveKind::Travel,
+        }
+    }
+
+    pub fn is_extrusion(&self) -> bool {
+        self.kind() == MoveKind::Extrusion
+    }
+
+    /// Width of the extruded line in mm, `None` when not extruding.
+    ///
+    /// Uses the model of the slicers, a rectangle of `layer_height` with semicircles on both
+    /// ends: `area = (width - height) * height + π * (height / 2)²`.
+    pub fn extrusion_width(&self, filament_area: f64, layer_height: f64) -> Option<f64> {
+        let length = self.xy_length();
+        if !self.is_extrusion() || length <= 0.0 || layer_height <= 0.0 {
+            return None;
+        }
+        let area = self.extrusion * filament_area / length;
+        Some(area / layer_height + layer_height * (1.0 - std::f64::consts::FRAC_PI_4))
+    }
+}
+
+/// Executes G-code lines one at a time.
+#[derive(Debug, Default, Clone)]
+pub struct Machine {
+    state: MachineState,
+    /// Whether the file announces layers with comments, Z changes are ignored then
+    layer_comments: bool,
+    /// Whether the current layer extruded anything yet
+    layer_extruded: bool,
+    /// Z of the first extrusion of the current layer
+    layer_z: Option<f64>,
+    /// Z of the first extrusion of the last layer below the current one that extruded
+    below_z: Option<f64>,
+    /// Line of the last executed command
+    line: usize,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+
+    /// Line of the last executed command.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Z of the first extrusion of the current layer.
+    pub fn layer_z(&self) -> Option<f64> {
+        self.layer_z
+    }
+
+    /// Z of the first extrusion of the last layer below the current one that extruded.
+    pub fn below_z(&self) -> Option<f64> {
+        self.below_z
+    }
+
+    /// Executes `line`, returning the moves it makes.
+    pub fn execute(&mut self, line: &DisplayLine) -> Vec<Move> {
+        match line {
+            DisplayLine::Comment(comment) => {
+                self.line = comment.span.line;
+                self.comment(comment.value);
+                vec![]
+            }
+            DisplayLine::GCode(name, gcode) => {
+                self.line = gcode.span().line;
+                self.command(name, gcode)
+            }
+        }
+    }
+
+    fn comment(&mut self, value: &str) {
+        if let Some(feature) = comment_text(value).strip_prefix("TYPE:") {
+            self.state.feature = Some(feature.trim().into());
+        } else if is_layer_change(value) {
+            if self.layer_comments && self.layer_extruded {
+                self.next_layer();
+            }
+            self.layer_comments = true;
+        }
+    }
+
+    fn next_layer(&mut self) {
+        self.state.layer += 1;
+        self.layer_extruded = false;
+        self.below_z = self.layer_z.take().or(self.below_z);
+    }
+
+    fn command(&mut self, name: &str, gcode: &gcode::GCode) -> Vec<Move> {
+        let value = |letter: char| gcode.value_for(letter).map(to_f64);
+        let state = &mut self.state;
+        match name {
+            "G0" | "G1" => return self.linear_move(gcode),
+            "G2" | "G3" => return self.arc_move(gcode, name == "G2"),
+            "G4" => {
+                state.time += value('P').map_or(0.0, |ms| ms / 1000.0) + value('S').unwrap_or(0.0);
+            }
+            "G20" => state.inches = true,
+            "G21" => state.inches = false,
+            "G28" => {
+                // flags like `G28 X` are dropped by the parser, so this always homes all axes
+                for axis in [X, Y, Z] {
+                    state.position[axis] = 0.0;
+                    state.offset[axis] = 0.0;
+                }
+            }
+            "G90" => {
+                state.absolute = true;
+                state.absolute_e = true;
+            }
+            "G91" => {
+                state.absolute = false;
+                state.absolute_e = false;
+            }
+            "G92" => {
+                let given: Vec<(usize, f64)> = AXES
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(axis, letter)| Some((axis, value(*letter)?)))
+                    .collect();
+                let given = if given.is_empty() {
+                    (0..4).map(|axis| (axis, 0.0)).collect()
+                } else {
+                    given
+                };
+                let scale = if state.inches { 25.4 } else { 1.0 };
+                for (axis, v) in given {
+                    let physical = state.physical(axis);
+                    state.position[axis] = v * scale;
+                    state.offset[axis] = physical - v * scale;
+                }
+            }
+            "M82" => state.absolute_e = true,
+            "M83" => state.absolute_e = false,
+            "M104" | "M109" => {
+                if let Some(s) = value('S').or_else(|| value('R')) {
+                    state.hotend_temperature = s;
+                }
+            }
+            "M140" | "M190" => {
+                if let Some(s) = value('S').or_else(|| value('R')) {
+                    state.bed_temperature = s;
+                }
+            }
+            "M106" => state.fan_speed = value('S').unwrap_or(255.0).clamp(0.0, 255.0),
+            "M107" => state.fan_speed = 0.0,
+            "M220" => {
+                if let Some(s) = value('S') {
+                    state.speed_factor = s;
+                }
+            }
+            "M221" => {
+                if let Some(s) = value('S') {
+                    state.flow = s;
+                }
+            }
+            _ if gcode.mnemonic() == gcode::Mnemonic::ToolChange => {
+                state.tool = gcode.major_number();
+            }
+            _ => {}
+        }
+        vec![]
+    }
+
+    /// The logical target of a move, `None` for axes the command doesn't mention.
+    fn target(&self, gcode: &gcode::GCode) -> [Option<f64>; 4] {
+        let scale = if self.state.inches { 25.4 } else { 1.0 };
+        let mut target = [None; 4];
+        for (axis, letter) in AXES.iter().enumerate() {
+            if let Some(v) = gcode.value_for(*letter) {
+                let v = to_f64(v) * scale;
+                let absolute = if axis == E {
+                    self.state.absolute_e
+                } else {
+                    self.state.absolute
+                };
+                target[axis] = Some(if absolute {
+                    v
+                } else {
+                    self.state.position[axis] + v
+                });
+            }
+        }
+        target
+    }
+
+    fn update_feedrate(&mut self, gcode: &gcode::GCode) {
+        if let Some(f) = gcode.value_for('F') {
+            let scale = if self.state.inches { 25.4 } else { 1.0 };
+            self.state.feedrate = to_f64(f) * scale;
+        }
+    }
+
+    fn linear_move(&mut self, gcode: &gcode::GCode) -> Vec<Move> {
+        self.update_feedrate(gcode);
+        let target = self.target(gcode);
+        let end: [f64; 4] =
+            std::array::from_fn(|axis| target[axis].unwrap_or(self.state.position[axis]));
+        match self.segment(end) {
+            Some(m) => vec![m],
+            None => vec![],
+        }
+    }
+
+    /// Splits an arc into straight segments. Arcs given with `R` are drawn as straight lines.
+    fn arc_move(&mut self, gcode: &gcode::GCode, clockwise: bool) -> Vec<Move> {
+        self.update_feedrate(gcode);
+        let target = self.target(gcode);
+        let start = self.state.position;
+        let end: [f64; 4] = std::array::from_fn(|axis| target[axis].unwrap_or(start[axis]));
+        let scale = if self.state.inches { 25.4 } else { 1.0 };
+        let (i, j) = match (gcode.value_for('I'), gcode.value_for('J')) {
+            (None, None) => return self.segment(end).into_iter().collect(),
+            (i, j) => (
+                to_f64(i.unwrap_or(0.0)) * scale,
+                to_f64(j.unwrap_or(0.0)) * scale,
+            ),
+        };
+
+        let center = [start[X] + i, start[Y] + j];
+        let radius = i.hypot(j);
+        let start_angle = (start[Y] - center[1]).atan2(start[X] - center[0]);
+        let end_angle = (end[Y] - center[1]).atan2(end[X] - center[0]);
+        let mut sweep = end_angle - start_angle;
+        if clockwise && sweep >= 0.0 {
+            sweep -= std::f64::consts::TAU;
+        } else if !clockwise && sweep <= 0.0 {
+            sweep += std::f64::consts::TAU;
+        }
+        let segments = ((sweep.abs() * radius) / ARC_SEGMENT_LENGTH)
+            .ceil()
+            .max(1.0) as usize;
+
+        let mut moves = vec![];
+        for n in 1..=segments {
+            let t = n as f64 / segments as f64;
+            let point = if n == segments {
+                end
+            } else {
+                let angle = start_angle + sweep * t;
+                [
+                    center[0] + radius * angle.cos(),
+                    center[1] + radius * angle.sin(),
+                    start[Z] + (end[Z] - start[Z]) * t,
+                    start[E] + (end[E] - start[E]) * t,
+                ]
+            };
+            moves.extend(self.segment(point));
+        }
+        moves
+    }
+
+    /// Moves to the logical position `end`, returning the move unless nothing changes.
+    fn segment(&mut self, end: [f64; 4]) -> Option<Move> {
+        let state = &self.state;
+        let from = [state.physical(X), state.physical(Y), state.physical(Z)];
+        let to = [
+            end[X] + state.offset[X],
+            end[Y] + state.offset[Y],
+            end[Z] + state.offset[Z],
+        ];
+        let extrusion = (end[E] - state.position[E]) * state.flow / 100.0;
+        self.state.position = end;
+        if from == to && extrusion == 0.0 {
+            return None;
+        }
+
+        let mut m = Move {
+            line: self.line,
+            from,
+            to,
+            extrusion,
+            feedrate: self.state.feedrate * self.state.speed_factor / 100.0,
+            layer: self.state.layer,
+            feature: self.state.feature.clone(),
+            fan_speed: self.state.fan_speed,
+            tool: self.state.tool,
+        };
+        if m.is_extrusion() {
+            // without layer comments, extruding higher up starts a new layer
+            if !self.layer_comments
+                && self.layer_extruded
+                && self.layer_z.is_some_and(|z| to[Z] > z + 1e-4)
+            {
+                self.next_layer();
+                m.layer = self.state.layer;
+            }
+            self.layer_extruded = true;
+            self.layer_z.get_or_insert(to[Z]);
+        }
+        self.state.time += m.duration();
+        Some(m)
+    }
+}
+
+/// A layer of a [`Toolpath`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub index: usize,
+    /// Z of the first extrusion of the layer, `None` if it doesn't extrude
+    pub z: Option<f64>,
+    /// Indices of the moves of the layer in [`Toolpath::moves`]
+    pub moves: Range<usize>,
+    /// Lines of the file belonging to the layer
+    pub lines: Range<usize>,
+    /// Estimated seconds since the start of the file when the layer starts
+    pub start_time: f64,
+}
+
+/// A change of the temperature targets or the fan speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemperatureChange {
+    pub line: usize,
+    /// Estimated seconds since the start of the file
+    pub time: f64,
+    pub hotend: f64,
+    pub bed: f64,
+    pub fan_speed: f64,
+}
+
+/// Everything a file makes the printer do.
+#[derive(Debug, Clone, Default)]
+pub struct Toolpath {
+    pub moves: Vec<Move>,
+    pub layers: Vec<Layer>,
+    pub temperatures: Vec<TemperatureChange>,
+    /// The state after the last line
+    pub final_state: MachineState,
+    /// Number of lines of the file
+    pub lines: usize,
+}
+
+impl Toolpath {
+    /// The moves of `layer`.
+    pub fn layer_moves(&self, layer: &Layer) -> &[Move] {
+        &self.moves[layer.moves.clone()]
+    }
+
+    /// Height of the layer `index` in mm, the distance to the Z of the previous layer that
+    /// extrudes, or to the bed for the first one.
+    pub fn layer_height(&self, index: usize) -> Option<f64> {
+        let z = self.layers.get(index)?.z?;
+        let below = self.layers[..index]
+            .iter()
+            .rev()
+            .find_map(|layer| layer.z)
+            .unwrap_or(0.0);
+        Some(z - below).filter(|height| *height > 0.0)
+    }
+
+    /// The first layer extruding at or above `z`.
+    pub fn layer_at(&self, z: f64) -> Option<&Layer> {
+        self.layers
+            .iter()
+            .find(|layer| layer.z.is_some_and(|layer_z| layer_z > z - 1e-4))
+    }
+}
+
+/// Receives what a [`Machine`] does, line by line.
+///
+/// The chunks of a large file are simulated in parallel, each by an observer of its own
+/// starting from the state the lines before leave the machine in, see
+/// [`crate::gcode::parallel::fold`].
+pub trait Observer {
+    /// Called after `machine` executed a line, with the moves it made.
+    fn observe(&mut self, machine: &Machine, moves: Vec<Move>);
+}
+
+impl Machine {
+    /// Executes `line`, handing the moves it makes to `observer`.
+    pub fn run<O: Observer>(&mut self, line: &DisplayLine, observer: &mut O) {
+        let moves = self.execute(line);
+        observer.observe(self, moves);
+    }
+}
+
+/// Builds a [`Toolpath`] one line at a time.
+///
+/// A simulation can start in the middle of a file and be appended to the simulation of the
+/// lines before, so the chunks of a file can be simulated in parallel.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    toolpath: Toolpath,
+    last_temperature: (f64, f64, f64),
+    /// The current layer and the estimated time before the line being observed
+    layer: usize,
+    time: f64,
+    /// First line, first move and start time of each layer started since the simulation
+    /// started
+    starts: Vec<(usize, usize, f64)>,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            starts: vec![(0, 0, 0.0)],
+            ..Self::starting_at(&Machine::new())
+        }
+    }
+}
+
+impl Observer for Simulation {
+    fn observe(&mut self, machine: &Machine, moves: Vec<Move>) {
+        let toolpath = &mut self.toolpath;
+        let state = machine.state();
+        while self.layer < state.layer {
+            self.layer += 1;
+            let first_move = toolpath.moves.len()
+                + moves
+                    .iter()
+                    .position(|m| m.layer >= self.layer)
+                    .unwrap_or(moves.len());
+            self.starts.push((machine.line(), first_move, self.time));
+        }
+        toolpath.moves.extend(moves);
+        self.time = state.time;
+
+        let temperature = (
+            state.hotend_temperature,
+            state.bed_temperature,
+            state.fan_speed,
+        );
+        if temperature != self.last_temperature {
+            toolpath.temperatures.push(TemperatureChange {
+                line: machine.line(),
+                time: state.time,
+                hotend: temperature.0,
+                bed: temperature.1,
+                fan_speed: temperature.2,
+            });
+            self.last_temperature = temperature;
+        }
+    }
+}
+
+impl Simulation {
+    /// A simulation of a file from its first line.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A simulation of the lines following those `machine` executed.
+    pub fn starting_at(machine: &Machine) -> Self {
+        let state = machine.state();
+        Self {
+            toolpath: Toolpath::default(),
+            last_temperature: (
+                state.hotend_temperature,
+                state.bed_temperature,
+                state.fan_speed,
+            ),
+            layer: state.layer,
+            time: state.time,
+            starts: vec![],
+        }
+    }
+
+    /// Adds the simulation of the lines following these, started from the state these leave
+    /// the machine in.
+    pub fn append(&mut self, next: Simulation) {
+        let moves = self.toolpath.moves.len();
+        self.starts.extend(
+            next.starts
+                .into_iter()
+                .map(|(line, first_move, time)| (line, moves + first_move, time)),
+        );
+        self.toolpath.moves.extend(next.toolpath.moves);
+        self.toolpath
+            .temperatures
+            .extend(next.toolpath.temperatures);
+        self.last_temperature = next.last_temperature;
+        self.layer = next.layer;
+        self.time = next.time;
+    }
+
+    /// The toolpath of the lines executed by `machine`.
+    pub fn finish(self, machine: &Machine) -> Toolpath {
+        let Simulation {
+            mut toolpath,
+            starts,
+            ..
+        } = self;
+        toolpath.lines = machine.line() + 1;
+        let end = (toolpath.lines, toolpath.moves.len());
+        for (index, (line, first_move, start_time)) in starts.iter().copied().enumerate() {
+            let (next_line, next_move) = starts.get(index + 1).map_or(end, |s| (s.0, s.1));
+            let moves = first_move..next_move;
+            toolpath.layers.push(Layer {
+                index,
+                z: toolpath.moves[moves.clone()]
+                    .iter()
+                    .find(|m| m.is_extrusion())
+                    .map(|m| m.to[Z]),
+                moves,
+                lines: line..next_line,
+                start_time,
+            });
+        }
+        toolpath.final_state = machine.state().clone();
+        toolpath
+    }
+}
+
+/// Simulates all `lines`, see [`Machine`].
+pub fn simulate<'a, I>(lines: I) -> Toolpath
+where
+    I: IntoIterator<Item = DisplayLine<'a>>,
+{
+    let mut machine = Machine::new();
+    let mut simulation = Simulation::new();
+    for line in lines {
+        machine.run(&line, &mut simulation);
+    }
+    simulation.finish(&machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::{parse_lines, LineIterator};
+
+    fn run(src: &str) -> Toolpath {
+        simulate(LineIterator::new(parse_lines(src)))
+    }
+
+    #[test]
+    fn should_track_positions_and_modes() {
+        let toolpath = run(
+            "G21\nG90\nM83\nG1 X10 Y0 F3000\nG1 X20 E1.5\nG91\nG1 X-5 E-0.8\nG92 X0\nG90\nG1 X1\n",
+        );
+        let ends: Vec<[f64; 3]> = toolpath.moves.iter().map(|m| m.to).collect();
+        assert_eq!(
+            ends,
+            vec![
+                [10.0, 0.0, 0.0],
+                [20.0, 0.0, 0.0],
+                [15.0, 0.0, 0.0],
+                [16.0, 0.0, 0.0]
+            ]
+        );
+        assert_eq!(toolpath.moves[1].kind(), MoveKind::Extrusion);
+        assert_eq!(toolpath.moves[2].extrusion, -0.8);
+        assert_eq!(toolpath.moves[0].duration(), 0.2);
+        assert_eq!(toolpath.final_state.position[X], 1.0);
+    }
+
+    #[test]
+    fn should_split_layers() {
+        let by_comment = run(
+            ";LAYER:0\n;TYPE:WALL-OUTER\nG1 Z0.2\nG1 X10 E1\n;LAYER:1\nM106 S255\nG1 Z0.4\nG1 X0 E2\n",
+        );
+        assert_eq!(by_comment.layers.len(), 2);
+        assert_eq!(by_comment.layers[1].lines, 4..8);
+        assert_eq!(by_comment.layers[1].z, Some(0.4));
+        assert_eq!(by_comment.moves[1].feature.as_deref(), Some("WALL-OUTER"));
+        assert_eq!(by_comment.temperatures[0].fan_speed, 255.0);
+
+        let by_z = run("G1 Z0.2\nG1 X10 E1\nG1 Z0.4\nG1 X0 E2\nG1 X5 E3\n");
+        let layers: Vec<(Option<f64>, Range<usize>)> =
+            by_z.layers.iter().map(|l| (l.z, l.moves.clone())).collect();
+        assert_eq!(layers, vec![(Some(0.2), 0..3), (Some(0.4), 3..5)]);
+    }
+
+    #[test]
+    fn should_split_arcs() {
+        let toolpath = run("G1 X10 Y0\nG3 X0 Y10 I-10 J0\n");
+        let arc = &toolpath.moves[1..];
+        assert!(arc.len() > 10);
+        assert!(arc
+            .iter()
+            .all(|m| (m.to[X].hypot(m.to[Y]) - 10.0).abs() < 1e-9));
+        assert_eq!(arc.last().unwrap().to, [0.0, 10.0, 0.0]);
+    }
+}
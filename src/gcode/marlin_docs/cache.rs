@@ -0,0 +1,242 @@
+//! An on-disk cache of the parsed documentation, so that the markdown files don't have to be
+//! parsed again on every start.
+//!
+//! There is one cache file per documentation directory. It stores a fingerprint of the
+//! directory (file names, sizes and modification times) and is rebuilt whenever the
+//! fingerprint doesn't match anymore.
+
+use super::{
+    index_description, parse_marlin_docs, unique_descriptions, DocsError, DocsLoadReport,
+    OpcodeDescription, Opcodes,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Bump when the cache layout changes.
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CachedDocs {
+    version: u32,
+    crate_version: String,
+    fingerprint: u64,
+    loaded: Vec<PathBuf>,
+    skipped: Vec<PathBuf>,
+    descriptions: Vec<OpcodeDescription>,
+}
+
+/// The directory the cache files are written to, `$XDG_CACHE_HOME/gcode-viz` on Linux.
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("gcode-viz"))
+}
+
+/// Like [`parse_marlin_docs`], but goes through the on-disk cache in [`cache_dir`].
+///
+/// The cache is only written if every file could be loaded, so that a broken checkout keeps
+/// reporting its failures. Problems reading or writing the cache are logged and otherwise
+/// ignored.
+pub fn load_cached_marlin_docs<P: AsRef<Path>>(
+    gcode_docs_dir: P,
+) -> Result<(Opcodes, DocsLoadReport), DocsError> {
+    let dir = gcode_docs_dir.as_ref();
+    let cache_file = cache_dir().map(|cache_dir| cache_dir.join(cache_file_name(dir)));
+    load_through_cache(dir, cache_file)
+}
+
+/// Loads the documentation in `dir` from `cache_file` if it is up to date, or parses it and
+/// writes `cache_file`.
+fn load_through_cache(
+    dir: &Path,
+    cache_file: Option<PathBuf>,
+) -> Result<(Opcodes, DocsLoadReport), DocsError> {
+    let fingerprint = fingerprint(dir).map_err(|source| DocsError::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+
+    if let Some(cached) = cache_file.as_deref().and_then(read_cache) {
+        if cached.version == CACHE_VERSION
+            && cached.crate_version == env!("CARGO_PKG_VERSION")
+            && cached.fingerprint == fingerprint
+        {
+            let opcodes = cached
+                .descriptions
+                .into_iter()
+                .flat_map(|od| index_description(Arc::new(od)))
+                .collect();
+            let report = DocsLoadReport {
+                loaded: cached.loaded,
+                skipped: cached.skipped,
+                failures: vec![],
+            };
+            return Ok((opcodes, report));
+        }
+    }
+
+    let (opcodes, report) = parse_marlin_docs(dir)?;
+    if let (Some(cache_file), true) = (cache_file, report.is_ok()) {
+        let cached = CachedDocs {
+            version: CACHE_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            fingerprint,
+            loaded: report.loaded.clone(),
+            skipped: report.skipped.clone(),
+            descriptions: unique_descriptions(&opcodes)
+                .iter()
+                .map(|od| (**od).clone())
+                .collect(),
+        };
+        if let Err(err) = write_cache(&cache_file, &cached) {
+            warn!(
+                "Could not write docs cache {}: {}",
+                cache_file.display(),
+                err
+            );
+        }
+    }
+
+    Ok((opcodes, report))
+}
+
+fn read_cache(cache_file: &Path) -> Option<CachedDocs> {
+    let data = std::fs::read(cache_file).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(cached) => Some(cached),
+        Err(err) => {
+            warn!(
+                "Ignoring corrupt docs cache {}: {}",
+                cache_file.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+fn write_cache(cache_file: &Path, cached: &CachedDocs) -> std::io::Result<()> {
+    if let Some(parent) = cache_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // write to a temporary file first so concurrent runs never see a partial cache
+    let tmp = cache_file.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp, serde_json::to_vec(cached)?)?;
+    std::fs::rename(tmp, cache_file)
+}
+
+fn cache_file_name(dir: &Path) -> String {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    format!(
+        "docs-{:016x}.json",
+        fnv1a(dir.to_string_lossy().as_bytes(), FNV_OFFSET)
+    )
+}
+
+/// Hashes the names, sizes and modification times of all entries in `dir`.
+fn fingerprint(dir: &Path) -> std::io::Result<u64> {
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        entries.push((entry.file_name(), metadata.len(), mtime));
+    }
+    entries.sort();
+
+    Ok(entries.iter().fold(FNV_OFFSET, |hash, (name, len, mtime)| {
+        let hash = fnv1a(name.to_string_lossy().as_bytes(), hash);
+        let hash = fnv1a(&len.to_le_bytes(), hash);
+        fnv1a(&mtime.to_le_bytes(), hash)
+    }))
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a, used because the std hashers are not guaranteed to be stable across releases.
+fn fnv1a(bytes: &[u8], hash: u64) -> u64 {
+    bytes.iter().fold(hash, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    const M104: &str =
+        "---\ntag: m0104\ntitle: Set Hotend Temperature\nbrief: Set temp\ncodes: [ M104 ]\n---\n";
+
+    /// A documentation directory with one file, and a cache file next to it.
+    fn setup(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("gcode-viz-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("_gcode");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("M104.md"), M104).unwrap();
+        let cache_file = root.join("cache").join("docs.json");
+        (root, dir, cache_file)
+    }
+
+    fn title(dir: &Path, cache_file: &Path) -> String {
+        let (opcodes, report) = load_through_cache(dir, Some(cache_file.to_path_buf())).unwrap();
+        assert!(report.is_ok());
+        opcodes["M104"].title.clone()
+    }
+
+    /// Changes the title stored in the cache, to tell a cache hit from a parse.
+    fn tamper(cache_file: &Path) {
+        let mut cached = read_cache(cache_file).unwrap();
+        cached.descriptions[0].title = "From the cache".to_string();
+        write_cache(cache_file, &cached).unwrap();
+    }
+
+    #[test]
+    fn should_use_the_cache_until_a_file_changes() {
+        let (root, dir, cache_file) = setup("hit");
+        assert_eq!(title(&dir, &cache_file), "Set Hotend Temperature");
+        tamper(&cache_file);
+        assert_eq!(title(&dir, &cache_file), "From the cache");
+
+        // same size, newer modification time
+        let file = std::fs::File::options()
+            .write(true)
+            .open(dir.join("M104.md"))
+            .unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(title(&dir, &cache_file), "Set Hotend Temperature");
+
+        // other size
+        tamper(&cache_file);
+        std::fs::write(
+            dir.join("M104.md"),
+            M104.replace("Set temp", "Set the temp"),
+        )
+        .unwrap();
+        assert_eq!(title(&dir, &cache_file), "Set Hotend Temperature");
+        assert_eq!(title(&dir, &cache_file), "Set Hotend Temperature");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_rebuild_a_corrupt_cache() {
+        let (root, dir, cache_file) = setup("corrupt");
+        std::fs::create_dir_all(cache_file.parent().unwrap()).unwrap();
+        std::fs::write(&cache_file, "{\"version\": 1, \"trunc").unwrap();
+
+        assert_eq!(title(&dir, &cache_file), "Set Hotend Temperature");
+        let cached = read_cache(&cache_file).unwrap();
+        assert_eq!(cached.fingerprint, fingerprint(&dir).unwrap());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! The opcode database embedded into the binary: the descriptions of a MarlinDocumentation
+//! `_gcode` directory as deflated JSON.
+//!
+//! This module is also compiled into `build.rs`, which writes the database, so it must only
+//! depend on external crates and the [`super::model`] module.
+
+use super::model::OpcodeDescription;
+use gray_matter::engine::YAML;
+use gray_matter::Matter;
+use std::path::{Path, PathBuf};
+
+/// Parses a markdown file, keeping its body and file stem.
+fn parse_doc(path: &Path) -> Result<OpcodeDescription, String> {
+    let s = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let result = Matter::<YAML>::new().parse(s.as_str());
+    let data = result.data.ok_or("missing front matter")?;
+    let mut od: OpcodeDescription = data.deserialize().map_err(|e| e.to_string())?;
+    od.body = result.content;
+    od.source = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(od)
+}
+
+/// Parses the markdown files of `gcode_docs_dir`, sorted by path. Only failing to read the
+/// directory is an error, the files that can't be parsed come with the reason.
+#[allow(unused)]
+pub fn read_docs(
+    gcode_docs_dir: &Path,
+) -> std::io::Result<Vec<(PathBuf, Result<OpcodeDescription, String>)>> {
+    let mut paths = std::fs::read_dir(gcode_docs_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let od = parse_doc(&path);
+            (path, od)
+        })
+        .collect())
+}
+
+/// Encodes the database.
+#[allow(unused)]
+pub fn encode(descriptions: &[OpcodeDescription]) -> Vec<u8> {
+    let json = serde_json::to_vec(descriptions).unwrap();
+    miniz_oxide::deflate::compress_to_vec(&json, 9)
+}
+
+/// Decodes a database written by [`encode`].
+#[allow(unused)]
+pub fn decode(data: &[u8]) -> Result<Vec<OpcodeDescription>, String> {
+    let json = miniz_oxide::inflate::decompress_to_vec(data)
+        .map_err(|err| format!("not valid deflate data: {:?}", err))?;
+    serde_json::from_slice(&json).map_err(|err| err.to_string())
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong while loading a Marlin documentation file.
+#[derive(Debug)]
+pub enum DocsError {
+    /// A file or directory could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The markdown file doesn't start with a `---` delimited front matter block.
+    MissingFrontMatter { path: PathBuf },
+    /// The front matter is not valid YAML.
+    ///
+    /// `line` and `column` are 1-based and relative to the start of the markdown file.
+    Yaml {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The front matter is valid YAML, but doesn't match the [`super::OpcodeDescription`] schema.
+    Schema { path: PathBuf, message: String },
+}
+
+impl DocsError {
+    /// The file (or directory) the error occured in.
+    pub fn path(&self) -> &Path {
+        match self {
+            DocsError::Io { path, .. }
+            | DocsError::MissingFrontMatter { path }
+            | DocsError::Yaml { path, .. }
+            | DocsError::Schema { path, .. } => path,
+        }
+    }
+
+    /// A short description of the error, without the path.
+    pub fn reason(&self) -> String {
+        match self {
+            DocsError::Io { source, .. } => format!("I/O error: {}", source),
+            DocsError::MissingFrontMatter { .. } => "missing front matter".to_string(),
+            DocsError::Yaml {
+                line,
+                column,
+                message,
+                ..
+            } => format!(
+                "YAML error at line {}, column {}: {}",
+                line, column, message
+            ),
+            DocsError::Schema { message, .. } => format!("schema mismatch: {}", message),
+        }
+    }
+}
+
+impl Display for DocsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path().display(), self.reason())
+    }
+}
+
+impl std::error::Error for DocsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DocsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of loading a documentation directory.
+///
+/// Loading never stops at the first broken file, instead every failure is collected here
+/// so that a docs checkout can be fixed in one go.
+#[derive(Debug, Default)]
+pub struct DocsLoadReport {
+    /// Markdown files that were parsed successfully.
+    pub loaded: Vec<PathBuf>,
+    /// Entries that were ignored because they are not markdown files.
+    pub skipped: Vec<PathBuf>,
+    /// Markdown files that could not be parsed, along with the reason.
+    pub failures: Vec<DocsError>,
+}
+
+impl DocsLoadReport {
+    /// True if every markdown file was loaded.
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
@@ -0,0 +1,199 @@
+//! Checks the `examples` of the documentation against our own parser.
+//!
+//! Each example line is parsed with [`parse_line`], its commands must be listed in the `codes`
+//! of the same file and its parameters in the `parameters`. Mismatches are either mistakes in
+//! the documentation or in our parser, either way they are worth a look.
+
+use super::{unique_descriptions, OpcodeDescription, Opcodes};
+use crate::gcode::lines::{opcode_name, parse_line, MESSAGE_COMMANDS, STRING_COMMANDS};
+use std::fmt;
+use std::fmt::Display;
+
+/// What is wrong with an example line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExampleIssueKind {
+    /// The line is neither a comment nor does it contain a command.
+    Unparsable,
+    /// The command is not in the `codes` of the file documenting the example.
+    UndocumentedCommand { code: String },
+    /// The command uses a parameter missing from `parameters`.
+    UnknownParameter { code: String, parameter: char },
+}
+
+/// A mismatch found in an example.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExampleIssue {
+    /// The documentation file the example is from, e.g. `M104`
+    pub source: String,
+    /// Index of the example in the file
+    pub example: usize,
+    /// The offending example line
+    pub line: String,
+    pub kind: ExampleIssueKind,
+}
+
+impl Display for ExampleIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} example {}: ", self.source, self.example + 1)?;
+        match &self.kind {
+            ExampleIssueKind::Unparsable => write!(f, "no command found")?,
+            ExampleIssueKind::UndocumentedCommand { code } => {
+                write!(f, "{} is not documented in this file", code)?
+            }
+            ExampleIssueKind::UnknownParameter { code, parameter } => {
+                write!(f, "{} has no parameter {}", code, parameter)?
+            }
+        }
+        write!(f, " in `{}`", self.line)
+    }
+}
+
+/// The result of checking all examples.
+#[derive(Debug, Default)]
+pub struct ExampleReport {
+    /// Number of example blocks checked
+    pub examples: usize,
+    /// Number of example lines checked
+    pub lines: usize,
+    pub issues: Vec<ExampleIssue>,
+}
+
+/// Checks the examples of all descriptions in `opcodes`.
+pub fn verify_examples(opcodes: &Opcodes) -> ExampleReport {
+    let mut report = ExampleReport::default();
+    for od in unique_descriptions(opcodes) {
+        report.examples += od.examples().len();
+        report.lines += example_lines(&od).count();
+        report.issues.extend(verify_description(&od));
+    }
+    report
+}
+
+/// All code lines of the examples of `od`, with the index of their example.
+pub fn example_lines(od: &OpcodeDescription) -> impl Iterator<Item = (usize, &str)> {
+    od.examples().iter().enumerate().flat_map(|(i, example)| {
+        example
+            .code()
+            .iter()
+            .flat_map(|code| code.lines())
+            .map(move |line| (i, line))
+            .filter(|(_, line)| !line.trim().is_empty())
+    })
+}
+
+/// Checks the examples of a single description.
+pub fn verify_description(od: &OpcodeDescription) -> Vec<ExampleIssue> {
+    let mut issues = vec![];
+    let parameters: Vec<char> = od
+        .parameters()
+        .iter()
+        .flat_map(|p| p.tag.chars().filter(|c| c.is_ascii_alphabetic()))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    for (example, text) in example_lines(od) {
+        let issue = |kind| ExampleIssue {
+            source: od.source.clone(),
+            example,
+            line: text.to_string(),
+            kind,
+        };
+
+        let line = parse_line(text, 0, 0);
+        if line.gcodes().is_empty() {
+            if line.comments().is_empty() {
+                issues.push(issue(ExampleIssueKind::Unparsable));
+            }
+            continue;
+        }
+
+        for (i, gcode) in line.gcodes().iter().enumerate() {
+            let code = opcode_name(gcode);
+            if !od.codes.iter().any(|c| c.eq_ignore_ascii_case(&code)) {
+                issues.push(issue(ExampleIssueKind::UndocumentedCommand { code }));
+                continue;
+            }
+            if STRING_COMMANDS.contains(&code.as_str()) {
+                continue;
+            }
+            // the span ends at the last parsed word, dropped flags may follow it, except for
+            // the message of commands like `M0 S5 Resume?`
+            let start = gcode.span().start;
+            let end = line.gcodes()[i + 1..]
+                .iter()
+                .map(|g| g.span().start)
+                .chain(line.comments().iter().map(|c| c.span.start))
+                .filter(|&s| s > start)
+                .min()
+                .unwrap_or(text.len());
+            let end = if MESSAGE_COMMANDS.contains(&code.as_str()) {
+                gcode.span().end
+            } else {
+                end
+            };
+            for parameter in command_parameters(&text[start..end]) {
+                if !parameters.contains(&parameter) {
+                    issues.push(issue(ExampleIssueKind::UnknownParameter {
+                        code: code.clone(),
+                        parameter,
+                    }));
+                }
+            }
+        }
+    }
+    issues
+}
+
+/// The parameter letters of a single command, e.g. `XE` for `G1 X10 E2`.
+///
+/// The gcode parser drops flags without a value like the `X` in `G28 X`, so the letters are
+/// taken from the source text instead of the parsed words.
+fn command_parameters(command: &str) -> Vec<char> {
+    command
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|word| word.chars().next())
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_undocumented_commands_and_parameters() {
+        let od: OpcodeDescription = serde_json::from_str(
+            r#"{
+                "tag": "g028", "title": "Auto Home", "brief": "Home", "codes": ["G28"],
+                "parameters": [{"tag": "X"}, {"tag": "Y"}],
+                "examples": [
+                    {"code": ["G28 ; all", "G28 X Z", "M117 Homing X1"]},
+                    {"code": "G28 Y\nG29"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let issues: Vec<ExampleIssueKind> = verify_description(&od)
+            .into_iter()
+            .map(|i| i.kind)
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ExampleIssueKind::UnknownParameter {
+                    code: "G28".to_string(),
+                    parameter: 'Z'
+                },
+                ExampleIssueKind::UndocumentedCommand {
+                    code: "M117".to_string()
+                },
+                ExampleIssueKind::UndocumentedCommand {
+                    code: "G29".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! Exports the documentation for offline reading, as a static HTML site, markdown files or
+//! man pages.
+//!
+//! Every format writes one page per description plus an index of the groups. Links between
+//! pages are rewritten to point to the exported files, links to the rest of the Marlin
+//! website are made absolute.
+
+use super::groups::group_index;
+use super::links::{cross_references, link_destinations, resolve_link, CrossReferences};
+use super::render::body_to_text;
+use super::{unique_descriptions, DocsError, OpcodeDescription, Opcodes};
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// The output formats of [`export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `index.html` and one `<page>.html` per description
+    Html,
+    /// `gcode-viz-docs.7` and one `gcode-<page>.7` per description
+    Man,
+    /// `index.md` and one `<page>.md` per description
+    Markdown,
+}
+
+const MARLIN_WEBSITE: &str = "https://marlinfw.org";
+
+/// Writes the documentation in `opcodes` to `dir` in `format`, creating `dir` if needed.
+///
+/// Returns the paths of the written files, the index first.
+pub fn export(
+    opcodes: &Opcodes,
+    format: ExportFormat,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, DocsError> {
+    std::fs::create_dir_all(dir).map_err(|source| DocsError::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    let xrefs = cross_references(opcodes);
+
+    let mut pages = vec![(index_file(format), index(opcodes, format))];
+    for od in unique_descriptions(opcodes) {
+        let content = match format {
+            ExportFormat::Html => html_page(opcodes, &xrefs, &od),
+            ExportFormat::Man => man_page(&xrefs, &od),
+            ExportFormat::Markdown => markdown_page(opcodes, &xrefs, &od),
+        };
+        pages.push((page_file(&od, format), content));
+    }
+
+    let mut written = vec![];
+    for (file, content) in pages {
+        let path = dir.join(file);
+        std::fs::write(&path, content).map_err(|source| DocsError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// The name of the exported page of `od`, the name of its source file, e.g. `G000-G001`.
+pub fn page_name(od: &OpcodeDescription) -> &str {
+    if od.source.is_empty() {
+        &od.tag
+    } else {
+        &od.source
+    }
+}
+
+/// The name of the man page of `od`, e.g. `gcode-M500`.
+fn man_name(od: &OpcodeDescription) -> String {
+    format!("gcode-{}", page_name(od))
+}
+
+fn index_file(format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Html => "index.html".to_string(),
+        ExportFormat::Man => "gcode-viz-docs.7".to_string(),
+        ExportFormat::Markdown => "index.md".to_string(),
+    }
+}
+
+fn page_file(od: &OpcodeDescription, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Html => format!("{}.html", page_name(od)),
+        ExportFormat::Man => format!("{}.7", man_name(od)),
+        ExportFormat::Markdown => format!("{}.md", page_name(od)),
+    }
+}
+
+/// Where a link of the documentation points to in the export.
+fn export_link(opcodes: &Opcodes, destination: &str, format: ExportFormat) -> String {
+    match resolve_link(opcodes, destination) {
+        Some(target) => page_file(&target, format),
+        None if destination.starts_with('/') => format!("{}{}", MARLIN_WEBSITE, destination),
+        None => destination.to_string(),
+    }
+}
+
+/// The index of all groups and their commands.
+fn index(opcodes: &Opcodes, format: ExportFormat) -> String {
+    let groups = group_index(opcodes);
+    let mut out = String::new();
+    match format {
+        ExportFormat::Html => {
+            out.push_str(&html_header("Marlin G-code"));
+            out.push_str("<h1>Marlin G-code</h1>\n<ul class=\"groups\">\n");
+            for group in groups.keys() {
+                let _ = writeln!(
+                    out,
+                    "<li><a href=\"#group-{0}\">{0}</a></li>",
+                    escape_html(group)
+                );
+            }
+            out.push_str("</ul>\n");
+            for (group, descriptions) in groups.iter() {
+                let _ = writeln!(
+                    out,
+                    "<h2 id=\"group-{0}\">{0}</h2>\n<dl>",
+                    escape_html(group)
+                );
+                for od in descriptions {
+                    let _ = writeln!(
+                        out,
+                        "<dt><a href=\"{}\">{}</a> {}</dt><dd>{}</dd>",
+                        page_file(od, format),
+                        escape_html(&od.codes.join(", ")),
+                        escape_html(&od.title),
+                        escape_html(&od.brief)
+                    );
+                }
+                out.push_str("</dl>\n");
+            }
+            out.push_str("</body>\n</html>\n");
+        }
+        ExportFormat::Markdown => {
+            out.push_str("# Marlin G-code\n");
+            for (group, descriptions) in groups.iter() {
+                let _ = writeln!(out, "\n## {}\n", group);
+                for od in descriptions {
+                    let _ = writeln!(
+                        out,
+                        "- [{}]({}) {} - {}",
+                        od.codes.join(", "),
+                        page_file(od, format),
+                        od.title,
+                        od.brief
+                    );
+                }
+            }
+        }
+        ExportFormat::Man => {
+            out.push_str(".TH GCODE-VIZ-DOCS 7 \"\" \"gcode-viz\" \"Marlin G-code\"\n");
+            out.push_str(".SH NAME\ngcode-viz-docs \\- index of the Marlin G-code commands\n");
+            for (group, descriptions) in groups.iter() {
+                let _ = writeln!(out, ".SH {}", escape_man(&group.to_uppercase()));
+                for od in descriptions {
+                    let _ = writeln!(
+                        out,
+                        ".TP\n.BR {} (7)\n{} \\- {}",
+                        escape_man(&man_name(od)),
+                        escape_man(&od.codes.join(", ")),
+                        escape_man(&od.title)
+                    );
+                }
+            }
+        }
+    }
+    out
+}
+
+fn html_header(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\nbody {{ font-family: sans-serif; max-width: 50em; margin: auto; }}\n\
+         pre, code {{ background: #f4f4f4; }}\ntable {{ border-collapse: collapse; }}\n\
+         td, th {{ border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }}\n\
+         </style>\n</head>\n<body>\n",
+        escape_html(title)
+    )
+}
+
+/// Renders markdown to HTML, rewriting the links to the exported pages.
+fn markdown_to_html(opcodes: &Opcodes, markdown: &str) -> String {
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::Start(Tag::Link(kind, destination, title)) => {
+            let destination = export_link(opcodes, &destination, ExportFormat::Html);
+            Event::Start(Tag::Link(kind, CowStr::from(destination), title))
+        }
+        event => event,
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+fn html_page(opcodes: &Opcodes, xrefs: &CrossReferences, od: &OpcodeDescription) -> String {
+    let mut out = html_header(&format!("{} - {}", od.codes.join(", "), od.title));
+    let _ = writeln!(
+        out,
+        "<p><a href=\"index.html\">Index</a></p>\n<h1>{} - {}</h1>\n<p>{}</p>",
+        escape_html(&od.codes.join(", ")),
+        escape_html(&od.title),
+        escape_html(&od.brief)
+    );
+    if let Some(requires) = &od.requires {
+        let _ = writeln!(
+            out,
+            "<p>Requires: <code>{}</code></p>",
+            escape_html(requires)
+        );
+    }
+
+    if !od.parameters().is_empty() {
+        out.push_str("<h2>Parameters</h2>\n<table>\n");
+        for p in od.parameters() {
+            let tag = if p.optional {
+                format!("[{}]", p.tag)
+            } else {
+                p.tag.clone()
+            };
+            let description = p.description.as_deref().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "<tr><th><code>{}</code></th><td>{}</td></tr>",
+                escape_html(&tag),
+                markdown_to_html(opcodes, description).trim()
+            );
+        }
+        out.push_str("</table>\n");
+    }
+
+    if !od.notes().is_empty() {
+        out.push_str("<h2>Notes</h2>\n<ul>\n");
+        for note in od.notes() {
+            let _ = writeln!(out, "<li>{}</li>", markdown_to_html(opcodes, note).trim());
+        }
+        out.push_str("</ul>\n");
+    }
+
+    if !od.examples().is_empty() {
+        out.push_str("<h2>Examples</h2>\n");
+        for example in od.examples() {
+            for pre in example.pre() {
+                out.push_str(&markdown_to_html(opcodes, pre));
+            }
+            for code in example.code() {
+                let _ = writeln!(out, "<pre><code>{}</code></pre>", escape_html(code));
+            }
+            for post in example.post() {
+                out.push_str(&markdown_to_html(opcodes, post));
+            }
+        }
+    }
+
+    out.push_str(&markdown_to_html(opcodes, &od.body));
+
+    for (heading, descriptions) in [
+        ("See also", xrefs.references(od)),
+        ("Referenced by", xrefs.referenced_by(od)),
+    ] {
+        if descriptions.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "<h2>{}</h2>\n<ul>", heading);
+        for target in descriptions {
+            let _ = writeln!(
+                out,
+                "<li><a href=\"{}\">{}</a> {}</li>",
+                page_file(target, ExportFormat::Html),
+                escape_html(&target.codes.join(", ")),
+                escape_html(&target.title)
+            );
+        }
+        out.push_str("</ul>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Rewrites the link destinations in `markdown` to the exported pages.
+fn rewrite_markdown_links(opcodes: &Opcodes, markdown: &str) -> String {
+    let mut out = markdown.to_string();
+    for destination in link_destinations(markdown) {
+        let target = export_link(opcodes, &destination, ExportFormat::Markdown);
+        out = out.replace(&format!("]({})", destination), &format!("]({})", target));
+    }
+    out
+}
+
+fn markdown_page(opcodes: &Opcodes, xrefs: &CrossReferences, od: &OpcodeDescription) -> String {
+    let mut out = format!(
+        "[Index](index.md)\n\n# {} - {}\n\n{}\n",
+        od.codes.join(", "),
+        od.title,
+        od.brief
+    );
+    if let Some(requires) = &od.requires {
+        let _ = writeln!(out, "\nRequires: `{}`", requires);
+    }
+
+    if !od.parameters().is_empty() {
+        out.push_str("\n## Parameters\n\n| Parameter | Description |\n| --- | --- |\n");
+        for p in od.parameters() {
+            let tag = if p.optional {
+                format!("[{}]", p.tag)
+            } else {
+                p.tag.clone()
+            };
+            let description = p.description.as_deref().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "| `{}` | {} |",
+                tag,
+                rewrite_markdown_links(opcodes, description).replace('\n', " ")
+            );
+        }
+    }
+
+    if !od.notes().is_empty() {
+        out.push_str("\n## Notes\n\n");
+        for note in od.notes() {
+            let _ = writeln!(out, "- {}", rewrite_markdown_links(opcodes, note));
+        }
+    }
+
+    if !od.examples().is_empty() {
+        out.push_str("\n## Examples\n");
+        for example in od.examples() {
+            for pre in example.pre() {
+                let _ = writeln!(out, "\n{}", rewrite_markdown_links(opcodes, pre));
+            }
+            for code in example.code() {
+                let _ = writeln!(out, "\n```gcode\n{}\n```", code.trim_end());
+            }
+            for post in example.post() {
+                let _ = writeln!(out, "\n{}", rewrite_markdown_links(opcodes, post));
+            }
+        }
+    }
+
+    let _ = write!(
+        out,
+        "\n{}",
+        rewrite_markdown_links(opcodes, od.body.trim_end())
+    );
+    out.push('\n');
+
+    for (heading, descriptions) in [
+        ("See also", xrefs.references(od)),
+        ("Referenced by", xrefs.referenced_by(od)),
+    ] {
+        if descriptions.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n## {}\n", heading);
+        for target in descriptions {
+            let _ = writeln!(
+                out,
+                "- [{}]({}) {}",
+                target.codes.join(", "),
+                page_file(target, ExportFormat::Markdown),
+                target.title
+            );
+        }
+    }
+    out
+}
+
+fn man_page(xrefs: &CrossReferences, od: &OpcodeDescription) -> String {
+    let mut out = format!(
+        ".TH GCODE-{} 7 \"\" \"gcode-viz\" \"Marlin G-code\"\n.SH NAME\n{} \\- {}\n",
+        escape_man(&page_name(od).to_uppercase()),
+        escape_man(&od.codes.join(", ")),
+        escape_man(&od.title)
+    );
+
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    for code in od.codes.iter() {
+        let mut synopsis = format!(".B {}", escape_man(code));
+        for p in od.parameters() {
+            if p.optional {
+                let _ = write!(synopsis, " [{}]", escape_man(&p.tag));
+            } else {
+                let _ = write!(synopsis, " {}", escape_man(&p.tag));
+            }
+        }
+        let _ = writeln!(out, "{}\n.br", synopsis);
+    }
+
+    let _ = writeln!(out, ".SH DESCRIPTION\n{}", escape_man(&od.brief));
+    if let Some(requires) = &od.requires {
+        let _ = writeln!(out, ".PP\nRequires: {}", escape_man(requires));
+    }
+    man_text(&mut out, &body_to_text(&od.body));
+
+    if !od.parameters().is_empty() {
+        out.push_str(".SH PARAMETERS\n");
+        for p in od.parameters() {
+            let description = p.description.as_deref().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                ".TP\n.B {}\n{}",
+                escape_man(&p.tag),
+                escape_man(&body_to_text(description).join(" "))
+            );
+        }
+    }
+
+    if !od.notes().is_empty() {
+        out.push_str(".SH NOTES\n");
+        for note in od.notes() {
+            let _ = writeln!(
+                out,
+                ".IP \\(bu 2\n{}",
+                escape_man(&body_to_text(note).join(" "))
+            );
+        }
+    }
+
+    if !od.examples().is_empty() {
+        out.push_str(".SH EXAMPLES\n");
+        for example in od.examples() {
+            for pre in example.pre() {
+                man_text(&mut out, &body_to_text(pre));
+            }
+            for code in example.code() {
+                out.push_str(".PP\n.nf\n.RS\n");
+                for line in code.lines() {
+                    let _ = writeln!(out, "{}", escape_man(line));
+                }
+                out.push_str(".RE\n.fi\n");
+            }
+            for post in example.post() {
+                man_text(&mut out, &body_to_text(post));
+            }
+        }
+    }
+
+    let references: Vec<String> = xrefs
+        .references(od)
+        .iter()
+        .chain(xrefs.referenced_by(od))
+        .map(|target| format!(".BR {} (7)", escape_man(&man_name(target))))
+        .chain(std::iter::once(".BR gcode\\-viz\\-docs (7)".to_string()))
+        .collect();
+    let _ = writeln!(out, ".SH SEE ALSO\n{}", references.join(" ,\n"));
+    out
+}
+
+/// Appends plain text lines, empty lines start a new paragraph.
+fn man_text(out: &mut String, lines: &[String]) {
+    out.push_str(".PP\n");
+    for line in lines {
+        if line.is_empty() {
+            out.push_str(".PP\n");
+        } else {
+            let _ = writeln!(out, "{}", escape_man(line));
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escapes backslashes and the control characters `.` and `'` at the start of a line.
+fn escape_man(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn should_link_exported_pages() {
+        let descriptions: Vec<OpcodeDescription> = serde_json::from_str(
+            r#"[
+                {"tag": "m0500", "title": "Save Settings", "brief": "Save", "codes": ["M500"], "source": "M500",
+                 "group": "eeprom", "body": "Restore with [M501](/docs/gcode/M501.html), see [config](/docs/configuration/configuration.html)."},
+                {"tag": "m0501", "title": "Restore Settings", "brief": "Restore", "codes": ["M501"], "source": "M501",
+                 "group": "eeprom", "examples": [{"code": "M501 ; <restore>"}]}
+            ]"#,
+        )
+        .unwrap();
+        let opcodes: Opcodes = descriptions
+            .into_iter()
+            .map(|od| (od.codes[0].clone(), Arc::new(od)))
+            .collect();
+        let xrefs = cross_references(&opcodes);
+
+        let html = html_page(&opcodes, &xrefs, &opcodes["M500"]);
+        assert!(html.contains("<a href=\"M501.html\">M501</a>"));
+        assert!(
+            html.contains("href=\"https://marlinfw.org/docs/configuration/configuration.html\"")
+        );
+        assert!(html_page(&opcodes, &xrefs, &opcodes["M501"]).contains("M501 ; &lt;restore&gt;"));
+        assert!(index(&opcodes, ExportFormat::Html).contains("<h2 id=\"group-eeprom\">eeprom</h2>"));
+
+        let markdown = markdown_page(&opcodes, &xrefs, &opcodes["M500"]);
+        assert!(markdown.contains("[M501](M501.md)"));
+
+        let man = man_page(&xrefs, &opcodes["M501"]);
+        assert!(man.contains(".BR gcode\\-M500 (7)"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Groups the documentation by the `group` front matter field (motion, thermal, eeprom, ...).
+
+use super::{unique_descriptions, OpcodeDescription, Opcodes};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The group of descriptions that don't declare one.
+pub const UNGROUPED: &str = "other";
+
+/// Maps each group name to its descriptions, both sorted.
+pub type GroupIndex = BTreeMap<String, Vec<Arc<OpcodeDescription>>>;
+
+/// Builds the group index of `opcodes`.
+///
+/// A description listing several groups appears in each of them.
+pub fn group_index(opcodes: &Opcodes) -> GroupIndex {
+    let mut index = GroupIndex::new();
+    for od in unique_descriptions(opcodes) {
+        let groups = match od.groups() {
+            [] => vec![UNGROUPED.to_string()],
+            groups => groups.iter().map(|g| g.trim().to_lowercase()).collect(),
+        };
+        for group in groups {
+            index.entry(group).or_default().push(od.clone());
+        }
+    }
+    index
+}
+
+/// How often a G-code file uses the commands of a group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupUsage {
+    pub group: String,
+    /// Number of commands in the file belonging to the group
+    pub count: usize,
+    /// Number of uses of each command of the group
+    pub commands: BTreeMap<String, usize>,
+}
+
+/// The group of commands without documentation in [`group_usage`].
+pub const UNDOCUMENTED: &str = "undocumented";
+
+/// Counts which groups the `codes` (e.g. `G1`, `M104`) of a file belong to.
+///
+/// Codes that aren't documented are counted in [`UNDOCUMENTED`]. The result is sorted by
+/// descending count.
+pub fn group_usage<I, S>(opcodes: &Opcodes, codes: I) -> Vec<GroupUsage>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut usage: BTreeMap<String, GroupUsage> = BTreeMap::new();
+    for code in codes {
+        let code = code.as_ref();
+        let groups = match opcodes.get(code).map(|od| od.groups()) {
+            None => vec![UNDOCUMENTED.to_string()],
+            Some([]) => vec![UNGROUPED.to_string()],
+            Some(groups) => groups.iter().map(|g| g.trim().to_lowercase()).collect(),
+        };
+        for group in groups {
+            let entry = usage.entry(group.clone()).or_insert_with(|| GroupUsage {
+                group,
+                count: 0,
+                commands: BTreeMap::new(),
+            });
+            entry.count += 1;
+            *entry.commands.entry(code.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut usage: Vec<GroupUsage> = usage.into_values().collect();
+    usage.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.group.cmp(&b.group)));
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opcodes() -> Opcodes {
+        let descriptions: Vec<OpcodeDescription> = serde_json::from_str(
+            r#"[
+                {"tag": "g000-g001", "title": "Linear Move", "brief": "Move in a line", "codes": ["G0", "G1"], "group": "Motion", "source": "G000-G001"},
+                {"tag": "m0104", "title": "Set Hotend Temperature", "brief": "Set a new target", "codes": ["M104"], "group": ["thermal", " Motion "], "source": "M104"},
+                {"tag": "m0117", "title": "Set LCD Message", "brief": "Show a message", "codes": ["M117"], "source": "M117"}
+            ]"#,
+        )
+        .unwrap();
+        let mut opcodes = Opcodes::new();
+        for od in descriptions {
+            let od = Arc::new(od);
+            for code in od.codes.iter() {
+                opcodes.insert(code.clone(), od.clone());
+            }
+        }
+        opcodes
+    }
+
+    #[test]
+    fn should_group_each_description_once_per_group() {
+        let index = group_index(&opcodes());
+        let tags: Vec<(&str, Vec<&str>)> = index
+            .iter()
+            .map(|(group, ods)| {
+                (
+                    group.as_str(),
+                    ods.iter().map(|od| od.tag.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("motion", vec!["g000-g001", "m0104"]),
+                ("other", vec!["m0117"]),
+                ("thermal", vec!["m0104"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_count_the_commands_of_each_group() {
+        let usage = group_usage(&opcodes(), ["G1", "G1", "G0", "M104", "M117", "M9999"]);
+        let counts: Vec<(&str, usize)> =
+            usage.iter().map(|u| (u.group.as_str(), u.count)).collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("motion", 4),
+                ("other", 1),
+                ("thermal", 1),
+                (UNDOCUMENTED, 1)
+            ]
+        );
+        let motion: Vec<(&str, usize)> = usage[0]
+            .commands
+            .iter()
+            .map(|(code, count)| (code.as_str(), *count))
+            .collect();
+        assert_eq!(motion, vec![("G0", 1), ("G1", 2), ("M104", 1)]);
+    }
+}
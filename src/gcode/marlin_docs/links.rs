@@ -0,0 +1,172 @@
+//! Cross-references between the documentation pages.
+//!
+//! The markdown of the docs links to other commands like `[M500](/docs/gcode/M500.html)`.
+//! These links are resolved to the [`OpcodeDescription`] they point to, and inverted into a
+//! "referenced by" index.
+
+use super::{unique_descriptions, OpcodeDescription, Opcodes};
+use pulldown_cmark::{Event, Parser, Tag};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The destinations of all links in `markdown`, in order.
+pub fn link_destinations(markdown: &str) -> Vec<String> {
+    Parser::new(markdown)
+        .filter_map(|event| match event {
+            Event::Start(Tag::Link(_, destination, _)) => Some(destination.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The page name a link points to, e.g. `M500` for `/docs/gcode/M500.html#notes`.
+///
+/// `None` for links leaving the G-code documentation, like other parts of the Marlin website
+/// or external sites.
+pub fn link_page(destination: &str) -> Option<&str> {
+    let path = destination.split(['#', '?']).next()?;
+    let path = path
+        .strip_prefix("https://marlinfw.org")
+        .or_else(|| path.strip_prefix("http://marlinfw.org"))
+        .unwrap_or(path);
+    if path.contains("://") || path.starts_with("mailto:") {
+        return None;
+    }
+    // absolute links must stay in the G-code docs, relative ones are siblings
+    if path.starts_with('/') && !path.starts_with("/docs/gcode/") {
+        return None;
+    }
+
+    let page = path.rsplit('/').next()?;
+    let page = page
+        .strip_suffix(".html")
+        .or_else(|| page.strip_suffix(".md"))
+        .unwrap_or(page);
+    (!page.is_empty()).then_some(page)
+}
+
+/// Resolves a link destination to the description it points to.
+///
+/// The page is matched against the source file names first (`G000-G001`), then against the
+/// codes (`G0`).
+pub fn resolve_link(opcodes: &Opcodes, destination: &str) -> Option<Arc<OpcodeDescription>> {
+    let page = link_page(destination)?;
+    opcodes
+        .values()
+        .find(|od| od.source.eq_ignore_ascii_case(page))
+        .or_else(|| opcodes.get(&page.to_uppercase()))
+        .cloned()
+}
+
+/// The links between all descriptions, in both directions.
+///
+/// Descriptions are identified by their `source`.
+#[derive(Debug, Default, Clone)]
+pub struct CrossReferences {
+    references: HashMap<String, Vec<Arc<OpcodeDescription>>>,
+    referenced_by: HashMap<String, Vec<Arc<OpcodeDescription>>>,
+}
+
+impl CrossReferences {
+    /// The descriptions `od` links to, in the order of the links.
+    pub fn references(&self, od: &OpcodeDescription) -> &[Arc<OpcodeDescription>] {
+        self.references.get(&od.source).map_or(&[], Vec::as_slice)
+    }
+
+    /// The descriptions linking to `od`, sorted by source.
+    pub fn referenced_by(&self, od: &OpcodeDescription) -> &[Arc<OpcodeDescription>] {
+        self.referenced_by
+            .get(&od.source)
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Resolves the links of every description in `opcodes`.
+///
+/// Links are collected from the notes, the parameter descriptions and the body. Links to the
+/// page itself and links that don't resolve are ignored.
+pub fn cross_references(opcodes: &Opcodes) -> CrossReferences {
+    let mut xrefs = CrossReferences::default();
+    for od in unique_descriptions(opcodes) {
+        let markdown = od
+            .notes()
+            .iter()
+            .chain(
+                od.parameters()
+                    .iter()
+                    .filter_map(|p| p.description.as_ref()),
+            )
+            .chain(std::iter::once(&od.body));
+
+        let mut references: Vec<Arc<OpcodeDescription>> = vec![];
+        for destination in markdown.flat_map(|text| link_destinations(text)) {
+            match resolve_link(opcodes, &destination) {
+                Some(target)
+                    if !Arc::ptr_eq(&target, &od)
+                        && !references.iter().any(|r| Arc::ptr_eq(r, &target)) =>
+                {
+                    references.push(target)
+                }
+                _ => {}
+            }
+        }
+
+        for target in references.iter() {
+            xrefs
+                .referenced_by
+                .entry(target.source.clone())
+                .or_default()
+                .push(od.clone());
+        }
+        if !references.is_empty() {
+            xrefs.references.insert(od.source.clone(), references);
+        }
+    }
+    xrefs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_resolve_links_both_ways() {
+        let descriptions: Vec<OpcodeDescription> = serde_json::from_str(
+            r#"[
+                {"tag": "g000", "title": "Linear Move", "brief": "Move", "codes": ["G0", "G1"], "source": "G000-G001",
+                 "body": "See [M500](/docs/gcode/M500.html) and [G0](G000-G001.html)."},
+                {"tag": "m0500", "title": "Save Settings", "brief": "Save", "codes": ["M500"], "source": "M500",
+                 "notes": ["Reverts with [M502](/docs/gcode/M502.html), moves with [G1](/docs/gcode/G1.html#notes)."]},
+                {"tag": "m0502", "title": "Factory Reset", "brief": "Reset", "codes": ["M502"], "source": "M502",
+                 "body": "[Configuration](/docs/configuration/configuration.html), [forum](https://example.com/M500.html)"}
+            ]"#,
+        )
+        .unwrap();
+        let opcodes: Opcodes = descriptions
+            .into_iter()
+            .flat_map(|od| {
+                let od = Arc::new(od);
+                od.codes
+                    .clone()
+                    .into_iter()
+                    .map(move |code| (code, od.clone()))
+            })
+            .collect();
+
+        let xrefs = cross_references(&opcodes);
+        let sources = |ods: &[Arc<OpcodeDescription>]| {
+            ods.iter().map(|od| od.source.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(sources(xrefs.references(&opcodes["G0"])), vec!["M500"]);
+        assert_eq!(
+            sources(xrefs.references(&opcodes["M500"])),
+            vec!["M502", "G000-G001"]
+        );
+        assert!(xrefs.references(&opcodes["M502"]).is_empty());
+        assert_eq!(
+            sources(xrefs.referenced_by(&opcodes["M500"])),
+            vec!["G000-G001"]
+        );
+        assert_eq!(sources(xrefs.referenced_by(&opcodes["M502"])), vec!["M500"]);
+    }
+}
@@ -0,0 +1,283 @@
+use gray_matter::engine::YAML;
+use gray_matter::{Matter, Pod};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use yaml_rust::YamlLoader;
+
+mod cache;
+mod embed;
+mod error;
+pub mod examples;
+pub mod export;
+pub mod groups;
+pub mod links;
+mod model;
+pub mod render;
+mod search;
+
+pub use cache::{cache_dir, load_cached_marlin_docs};
+pub use error::{DocsError, DocsLoadReport};
+pub use model::{Example, OpcodeDescription, Parameter, ParameterValue};
+pub use search::{search, SearchHit};
+
+/// Maps each opcode (e.g. `G1`) to its documentation.
+///
+/// Descriptions covering several codes are shared between them.
+pub type Opcodes = HashMap<String, Arc<OpcodeDescription>>;
+
+/// Parses all the markdown files in `gcode_docs_dir` (usually `_gcode` in a MarlinDocumentation
+/// checkout).
+///
+/// Only failing to read the directory itself is an error. Files that can't be parsed are
+/// collected in the returned [`DocsLoadReport`].
+pub fn parse_marlin_docs<P: AsRef<Path>>(
+    gcode_docs_dir: P,
+) -> Result<(Opcodes, DocsLoadReport), DocsError> {
+    let dir = gcode_docs_dir.as_ref();
+    let io_error = |source| DocsError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    // read_dir order is platform dependent, keep reports stable
+    entries.sort();
+
+    let mut report = DocsLoadReport::default();
+    let mut opcodes = HashMap::new();
+    for path in entries {
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "md") {
+            report.skipped.push(path);
+            continue;
+        }
+
+        match parse_opcode_md(path.clone()) {
+            Ok(codes) => {
+                opcodes.extend(codes);
+                report.loaded.push(path);
+            }
+            Err(err) => report.failures.push(err),
+        }
+    }
+
+    Ok((opcodes, report))
+}
+
+pub fn parse_opcode_md(doc: PathBuf) -> Result<Vec<(String, Arc<OpcodeDescription>)>, DocsError> {
+    let matter = Matter::<YAML>::new();
+    let s = std::fs::read_to_string(&doc).map_err(|source| DocsError::Io {
+        path: doc.clone(),
+        source,
+    })?;
+    let result = matter.parse(s.as_str());
+
+    let data = match result.data {
+        None => return Err(DocsError::MissingFrontMatter { path: doc }),
+        // gray_matter swallows YAML errors and returns Null, parse again to find out why
+        Some(Pod::Null) => return Err(yaml_error(doc, &s, &result.matter)),
+        Some(data) => data,
+    };
+    let mut od: OpcodeDescription = data.deserialize().map_err(|err| DocsError::Schema {
+        path: doc.clone(),
+        message: err.to_string(),
+    })?;
+    od.body = result.content;
+    od.source = doc
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(index_description(Arc::new(od)).collect())
+}
+
+/// Maps every code of `od` to `od`.
+fn index_description(
+    od: Arc<OpcodeDescription>,
+) -> impl Iterator<Item = (String, Arc<OpcodeDescription>)> {
+    (0..od.codes.len()).map(move |i| (od.codes[i].clone(), od.clone()))
+}
+
+/// Every description in `opcodes` once, sorted by source file.
+pub fn unique_descriptions(opcodes: &Opcodes) -> Vec<Arc<OpcodeDescription>> {
+    let mut descriptions: Vec<&Arc<OpcodeDescription>> = opcodes.values().collect();
+    descriptions.sort_by(|a, b| a.source.cmp(&b.source).then(a.tag.cmp(&b.tag)));
+    descriptions.dedup_by(|a, b| Arc::ptr_eq(a, b));
+    descriptions.into_iter().cloned().collect()
+}
+
+/// The opcode database built from `vendor/MarlinDocumentation` by `build.rs`.
+static EMBEDDED_DOCS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/marlin_docs.json.deflate"));
+
+/// Decodes the opcode database embedded into the binary.
+///
+/// This is empty if the documentation wasn't available at build time.
+pub fn embedded_descriptions() -> Vec<OpcodeDescription> {
+    // the data is produced by our own build script, failing to decode it is a build bug
+    embed::decode(EMBEDDED_DOCS)
+        .unwrap_or_else(|err| panic!("embedded marlin docs can't be decoded: {}", err))
+}
+
+/// Number of descriptions embedded into the binary, 0 if the documentation wasn't available
+/// at build time.
+pub fn embedded_count() -> usize {
+    env!("GCODE_VIZ_EMBEDDED_DOCS").parse().unwrap_or(0)
+}
+
+/// Loads the opcode documentation.
+///
+/// If `marlin_docs_dir` is given, the markdown files in its `_gcode` subdirectory are loaded
+/// through the on-disk cache, otherwise the database embedded at build time is used.
+pub fn load_marlin_docs(
+    marlin_docs_dir: Option<&Path>,
+) -> Result<(Opcodes, DocsLoadReport), DocsError> {
+    match marlin_docs_dir {
+        Some(dir) => load_cached_marlin_docs(dir.join("_gcode")),
+        None => {
+            let opcodes = embedded_descriptions()
+                .into_iter()
+                .flat_map(|od| index_description(Arc::new(od)))
+                .collect();
+            Ok((opcodes, DocsLoadReport::default()))
+        }
+    }
+}
+
+/// Builds the error for front matter that gray_matter couldn't parse.
+///
+/// `matter` is the trimmed front matter as returned by gray_matter, `s` the whole file.
+fn yaml_error(path: PathBuf, s: &str, matter: &str) -> DocsError {
+    let err = match YamlLoader::load_from_str(matter) {
+        Err(err) => err,
+        Ok(_) => {
+            return DocsError::Schema {
+                path,
+                message: "front matter is empty".to_string(),
+            }
+        }
+    };
+
+    // yaml-rust markers are relative to the trimmed front matter
+    let lines_before = s
+        .find(matter)
+        .map_or(1, |start| s[..start].matches('\n').count());
+    let marker = err.marker();
+    // Display appends the (trimmed) position, we only want the message
+    #[allow(deprecated)]
+    let message = std::error::Error::description(&err).to_string();
+    DocsError::Yaml {
+        path,
+        line: lines_before + marker.line(),
+        column: marker.col() + 1,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_docs(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gcode-viz-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn should_report_every_failed_file() {
+        let dir = write_docs(
+            "report",
+            &[
+                (
+                    "M104.md",
+                    "---\ntag: m0104\ntitle: Set Hotend Temperature\nbrief: Set temp\ncodes: [ M104 ]\n---\nBody\n",
+                ),
+                ("Makefile", "all:\n"),
+                ("nofront.md", "just a body\n"),
+                ("yaml.md", "---\ntag: m1\ntitle: [oops\ncodes: [ M1 ]\n---\n"),
+                ("schema.md", "---\ntag: m2\nbrief: no title\ncodes: [ M2 ]\n---\n"),
+            ],
+        );
+
+        let (opcodes, report) = parse_marlin_docs(&dir).unwrap();
+        assert!(opcodes.contains_key("M104"));
+        assert_eq!(report.loaded.len(), 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.failures.len(), 3);
+        assert!(matches!(
+            report.failures[0],
+            DocsError::MissingFrontMatter { .. }
+        ));
+        assert!(matches!(report.failures[1], DocsError::Schema { .. }));
+        assert!(matches!(
+            report.failures[2],
+            DocsError::Yaml { line: 4, .. }
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_embed_a_docs_directory() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/marlin_docs/_gcode");
+        let docs = embed::read_docs(&dir).unwrap();
+        let descriptions: Vec<OpcodeDescription> = docs
+            .into_iter()
+            .map(|(path, od)| od.unwrap_or_else(|err| panic!("{}: {}", path.display(), err)))
+            .collect();
+        let decoded = embed::decode(&embed::encode(&descriptions)).unwrap();
+
+        let sources: Vec<&str> = decoded.iter().map(|od| od.source.as_str()).collect();
+        assert_eq!(sources, ["G000-G001", "M104"]);
+        let moves = &decoded[0];
+        assert_eq!(moves.codes, ["G0", "G1"]);
+        assert_eq!(moves.title, "Linear Move");
+        assert_eq!(moves.groups(), ["motion"]);
+        assert_eq!(moves.parameters()[0].tag, "X");
+        assert_eq!(moves.examples()[0].code(), ["G1 X10 F3000"]);
+        assert!(moves.body.contains("straight line"), "{}", moves.body);
+        assert_eq!(decoded[1].parameters()[0].since.as_deref(), Some("1.1"));
+        assert!(embed::decode(b"not deflate").is_err());
+    }
+
+    #[test]
+    fn should_keep_versions_through_the_embedded_format() {
+        // YAML reads 2.1 as a number, the database stores it as a string
+        let dir = write_docs(
+            "versions",
+            &[(
+                "M900.md",
+                "---\ntag: m0900\ntitle: Linear Advance Factor\nbrief: K\ncodes: [ M900 ]\n\
+                 since: 1.1\nparameters:\n  - tag: K\n    since: 2.0.9\n    values:\n\
+                 \x20     - tag: 0\n        type: float\n---\n",
+            )],
+        );
+        let (opcodes, _) = parse_marlin_docs(&dir).unwrap();
+        let embedded = embed::decode(&embed::encode(&[(*opcodes["M900"]).clone()])).unwrap();
+
+        let od = &embedded[0];
+        assert_eq!(od.since.as_deref(), Some("1.1"));
+        assert_eq!(od.parameters()[0].since.as_deref(), Some("2.0.9"));
+        let values = od.parameters()[0].values.as_ref().unwrap();
+        assert_eq!(
+            (values[0].tag.as_deref(), values[0].type_.as_deref()),
+            (Some("0"), Some("float"))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_fail_on_missing_directory() {
+        let result = parse_marlin_docs("/nonexistent/gcode-viz/_gcode");
+        assert!(matches!(result, Err(DocsError::Io { .. })));
+    }
+}
@@ -0,0 +1,135 @@
+//! The front matter schema of the MarlinDocumentation `_gcode` markdown files.
+//!
+//! This module is also compiled into `build.rs` to pre-build the embedded opcode database,
+//! so it must only depend on external crates.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_either::SingleOrVec;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[allow(unused)]
+pub struct ParameterValue {
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub tag: Option<String>,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[allow(unused)]
+pub struct Parameter {
+    pub tag: String,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub since: Option<String>,
+    pub description: Option<String>,
+    pub requires: Option<String>,
+    pub values: Option<Vec<ParameterValue>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[allow(unused)]
+pub struct Example {
+    pub pre: Option<SingleOrVec<String>>,
+    pub code: SingleOrVec<String>,
+    pub post: Option<SingleOrVec<String>>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[allow(unused)]
+pub struct OpcodeDescription {
+    pub tag: String,
+    pub title: String,
+    pub brief: String,
+    pub author: Option<String>,
+
+    pub experimental: Option<bool>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub since: Option<String>,
+    pub requires: Option<String>,
+
+    pub parameters: Option<SingleOrVec<Parameter>>,
+
+    pub videos: Option<Vec<String>>,
+
+    pub group: Option<SingleOrVec<String>>,
+    pub codes: Vec<String>,
+    pub notes: Option<SingleOrVec<String>>,
+    pub examples: Option<SingleOrVec<Example>>,
+
+    /// The markdown body following the front matter.
+    #[serde(default)]
+    pub body: String,
+    /// The file stem of the markdown file, e.g. `G000-G001`.
+    #[serde(default)]
+    pub source: String,
+}
+
+#[allow(unused)]
+impl OpcodeDescription {
+    pub fn parameters(&self) -> &[Parameter] {
+        as_slice(&self.parameters)
+    }
+
+    pub fn groups(&self) -> &[String] {
+        as_slice(&self.group)
+    }
+
+    pub fn notes(&self) -> &[String] {
+        as_slice(&self.notes)
+    }
+
+    pub fn examples(&self) -> &[Example] {
+        as_slice(&self.examples)
+    }
+}
+
+#[allow(unused)]
+impl Example {
+    pub fn pre(&self) -> &[String] {
+        as_slice(&self.pre)
+    }
+
+    pub fn code(&self) -> &[String] {
+        match &self.code {
+            SingleOrVec::Single(code) => std::slice::from_ref(code),
+            SingleOrVec::Vec(code) => code,
+        }
+    }
+
+    pub fn post(&self) -> &[String] {
+        as_slice(&self.post)
+    }
+}
+
+/// Most list fields can be omitted, given as a single value or as a list.
+fn as_slice<T>(value: &Option<SingleOrVec<T>>) -> &[T] {
+    match value {
+        None => &[],
+        Some(SingleOrVec::Single(v)) => std::slice::from_ref(v),
+        Some(SingleOrVec::Vec(v)) => v,
+    }
+}
+
+/// YAML turns versions like `1.1` into floats, accept those as well as strings.
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Integer(i64),
+        Float(f64),
+    }
+
+    Ok(
+        Option::<StringOrNumber>::deserialize(deserializer)?.map(|v| match v {
+            StringOrNumber::String(s) => s,
+            StringOrNumber::Integer(i) => i.to_string(),
+            StringOrNumber::Float(f) => f.to_string(),
+        }),
+    )
+}
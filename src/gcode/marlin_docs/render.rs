@@ -0,0 +1,91 @@
+//! Renders the markdown bodies of the documentation for display outside of a browser.
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag};
+
+/// Renders a markdown body as plain text, one entry per line.
+///
+/// Headings are underlined, list items get a bullet and code blocks are indented. Long lines
+/// are not wrapped, that is left to the caller.
+pub fn body_to_text(markdown: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+    let mut list_depth = 0usize;
+    let mut in_code_block = false;
+
+    let flush = |lines: &mut Vec<String>, current: &mut String| {
+        if !current.trim().is_empty() {
+            lines.push(std::mem::take(current));
+        }
+        current.clear();
+    };
+    let blank = |lines: &mut Vec<String>| {
+        if lines.last().is_some_and(|l| !l.is_empty()) {
+            lines.push(String::new());
+        }
+    };
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Item) => {
+                flush(&mut lines, &mut current);
+                current.push_str(&"  ".repeat(list_depth.saturating_sub(1)));
+                current.push_str("- ");
+            }
+            Event::Start(Tag::List(_)) => {
+                flush(&mut lines, &mut current);
+                list_depth += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                flush(&mut lines, &mut current);
+                list_depth -= 1;
+                if list_depth == 0 {
+                    blank(&mut lines);
+                }
+            }
+            Event::End(Tag::Item) => flush(&mut lines, &mut current),
+            Event::Start(Tag::CodeBlock(_)) => {
+                flush(&mut lines, &mut current);
+                in_code_block = true;
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                in_code_block = false;
+                blank(&mut lines);
+            }
+            Event::End(Tag::Heading(level, _, _)) => {
+                let underline = if level == HeadingLevel::H1 { '=' } else { '-' };
+                let width = current.chars().count();
+                flush(&mut lines, &mut current);
+                lines.push(underline.to_string().repeat(width));
+                blank(&mut lines);
+            }
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::BlockQuote)
+            | Event::End(Tag::Table(_)) => {
+                flush(&mut lines, &mut current);
+                if list_depth == 0 {
+                    blank(&mut lines);
+                }
+            }
+            Event::End(Tag::TableRow) | Event::End(Tag::TableHead) => {
+                flush(&mut lines, &mut current)
+            }
+            Event::End(Tag::TableCell) => current.push_str(" | "),
+            Event::Text(text) if in_code_block => {
+                for line in text.lines() {
+                    lines.push(format!("    {}", line));
+                }
+            }
+            Event::Text(text) | Event::Code(text) => current.push_str(&text),
+            Event::Html(html) => current.push_str(html.trim()),
+            Event::SoftBreak => current.push(' '),
+            Event::HardBreak | Event::Rule => flush(&mut lines, &mut current),
+            _ => {}
+        }
+    }
+    flush(&mut lines, &mut current);
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
@@ -0,0 +1,170 @@
+//! Fuzzy full-text search over the documentation.
+//!
+//! Every query term is matched against the words of each field of an [`OpcodeDescription`].
+//! Exact words score highest, followed by prefixes, substrings and finally words within a
+//! small edit distance, so that typos like "linaer advance" still find `M900`. The per-term
+//! scores are weighted by field, a description only matches if every term is found somewhere.
+
+use super::{unique_descriptions, OpcodeDescription, Opcodes};
+use std::sync::Arc;
+
+/// A description matching a search query.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub description: Arc<OpcodeDescription>,
+    pub score: f64,
+}
+
+const CODE_WEIGHT: f64 = 20.0;
+const TITLE_WEIGHT: f64 = 10.0;
+const BRIEF_WEIGHT: f64 = 6.0;
+const NOTES_WEIGHT: f64 = 3.0;
+const PARAMETER_WEIGHT: f64 = 2.0;
+const BODY_WEIGHT: f64 = 1.0;
+
+/// Minimum normalized Damerau-Levenshtein similarity for a word to count as a fuzzy match.
+const FUZZY_THRESHOLD: f64 = 0.75;
+
+/// Searches `opcodes` for `query`, returning at most `limit` hits, best first.
+pub fn search(opcodes: &Opcodes, query: &str, limit: usize) -> Vec<SearchHit> {
+    let terms = words(query);
+    if terms.is_empty() {
+        return vec![];
+    }
+    let phrase = query.trim().to_lowercase();
+
+    let mut hits: Vec<SearchHit> = unique_descriptions(opcodes)
+        .into_iter()
+        .filter_map(|od| {
+            let score = score(&od, &terms, &phrase)?;
+            Some(SearchHit {
+                description: od,
+                score,
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.description.source.cmp(&b.description.source))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+/// Scores a single description, `None` if any of the terms doesn't match.
+fn score(od: &OpcodeDescription, terms: &[String], phrase: &str) -> Option<f64> {
+    let fields: Vec<(f64, String)> = vec![
+        (CODE_WEIGHT, od.codes.join(" ")),
+        (TITLE_WEIGHT, od.title.clone()),
+        (BRIEF_WEIGHT, od.brief.clone()),
+        (NOTES_WEIGHT, od.notes().join(" ")),
+        (
+            PARAMETER_WEIGHT,
+            od.parameters()
+                .iter()
+                .filter_map(|p| p.description.as_deref())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        (BODY_WEIGHT, od.body.clone()),
+    ];
+
+    let mut total = 0.0;
+    for term in terms {
+        let best = fields
+            .iter()
+            .map(|(weight, text)| weight * best_match(term, &words(text)))
+            .fold(0.0, f64::max);
+        if best == 0.0 {
+            return None;
+        }
+        total += best;
+    }
+
+    // reward descriptions containing the query verbatim
+    if terms.len() > 1 {
+        for (weight, text) in fields.iter() {
+            if text.to_lowercase().contains(phrase) {
+                total += weight;
+            }
+        }
+    }
+
+    Some(total)
+}
+
+/// How well `term` matches the best of `words`, between 0 and 1.
+fn best_match(term: &str, words: &[String]) -> f64 {
+    words
+        .iter()
+        .map(|word| {
+            if word == term {
+                1.0
+            } else if term.len() >= 2 && word.starts_with(term) {
+                0.8
+            } else if term.len() >= 3 && word.contains(term) {
+                0.5
+            } else if term.len() >= 4 && !term.chars().any(|c| c.is_ascii_digit()) {
+                // codes and numbers only differ by a digit, never match them fuzzily
+                let similarity = strsim::normalized_damerau_levenshtein(term, word);
+                if similarity >= FUZZY_THRESHOLD {
+                    0.6 * similarity
+                } else {
+                    0.0
+                }
+            } else {
+                0.0
+            }
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Lowercase alphanumeric words of `text`.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opcodes() -> Opcodes {
+        let descriptions: Vec<OpcodeDescription> = serde_json::from_str(
+            r#"[
+                {"tag": "m0900", "title": "Linear Advance Factor", "brief": "Get and set Linear Advance K value", "codes": ["M900"]},
+                {"tag": "m0412", "title": "Filament Runout", "brief": "Get/set filament runout detection parameters", "codes": ["M412"]},
+                {"tag": "m0500", "title": "Save Settings", "brief": "Save settings to EEPROM.", "codes": ["M500"], "body": "Also see M900."}
+            ]"#,
+        )
+        .unwrap();
+        descriptions
+            .into_iter()
+            .map(|od| (od.codes[0].clone(), Arc::new(od)))
+            .collect()
+    }
+
+    fn codes(hits: Vec<SearchHit>) -> Vec<String> {
+        hits.iter()
+            .map(|h| h.description.codes[0].clone())
+            .collect()
+    }
+
+    #[test]
+    fn should_find_by_title_with_typos() {
+        let opcodes = opcodes();
+        assert_eq!(codes(search(&opcodes, "linear advance", 10)), vec!["M900"]);
+        assert_eq!(codes(search(&opcodes, "linaer advnce", 10)), vec!["M900"]);
+        assert_eq!(codes(search(&opcodes, "runout", 10)), vec!["M412"]);
+    }
+
+    #[test]
+    fn should_rank_codes_above_mentions() {
+        let opcodes = opcodes();
+        assert_eq!(codes(search(&opcodes, "M900", 10)), vec!["M900", "M500"]);
+        assert!(search(&opcodes, "", 10).is_empty());
+    }
+}
@@ -0,0 +1,534 @@
+//! MeatPack, the packing of G-code into 4 bits per character used by Marlin's
+//! `MEATPACK_ON_SERIAL_PORT` and by the G-code blocks of binary G-code.
+//!
+//! The 15 most common characters are packed two per byte, the first one in the low nibble.
+//! A nibble of `0b1111` means the character didn't fit and follows as a full byte. Two
+//! `0xFF` bytes followed by a [`Command`] switch packing and the no-spaces mode on and off; in
+//! no-spaces mode the code of the space stands for `E` instead.
+//!
+//! [`pack`], [`unpack`] and [`restore_spaces`] encode and decode the G-code blocks of
+//! [`crate::gcode::bgcode`]; the rest reads and measures serial streams.
+
+use crate::gcode::lines::{MESSAGE_COMMANDS, STRING_COMMANDS};
+
+/// The byte that, twice in a row, announces a [`Command`].
+pub const SIGNAL_BYTE: u8 = 0xff;
+
+/// The packed characters, by code. Code 15 marks a character sent as a full byte.
+const TABLE: [u8; 15] = *b"0123456789. \nGX";
+
+/// The code of the space, which stands for `E` in no-spaces mode.
+const SPACE_CODE: u8 = 11;
+
+/// The nibble of a character sent as a full byte.
+const LITERAL: u8 = 0b1111;
+
+/// The commands following two [`SIGNAL_BYTE`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    EnablePacking = 251,
+    DisablePacking = 250,
+    ResetAll = 249,
+    QueryConfig = 248,
+    EnableNoSpaces = 247,
+    DisableNoSpaces = 246,
+}
+
+impl Command {
+    pub fn from_byte(byte: u8) -> Option<Command> {
+        Some(match byte {
+            251 => Command::EnablePacking,
+            250 => Command::DisablePacking,
+            249 => Command::ResetAll,
+            248 => Command::QueryConfig,
+            247 => Command::EnableNoSpaces,
+            246 => Command::DisableNoSpaces,
+            _ => return None,
+        })
+    }
+
+    /// The bytes sending the command.
+    pub fn signal(self) -> [u8; 3] {
+        [SIGNAL_BYTE, SIGNAL_BYTE, self as u8]
+    }
+}
+
+/// The code of `c`, `None` if it has to be sent as a full byte.
+fn code(c: u8, no_spaces: bool) -> Option<u8> {
+    match c {
+        b'E' if no_spaces => Some(SPACE_CODE),
+        b' ' if no_spaces => None,
+        _ => TABLE.iter().position(|&t| t == c).map(|i| i as u8),
+    }
+}
+
+/// The character of `code`.
+fn character(code: u8, no_spaces: bool) -> u8 {
+    if no_spaces && code == SPACE_CODE {
+        b'E'
+    } else {
+        TABLE[code as usize]
+    }
+}
+
+/// How to pack G-code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PackOptions {
+    /// Drop the spaces between the words of commands and pack `E` instead
+    pub no_spaces: bool,
+    /// Keep comments and empty lines, Marlin ignores them but they make the stream readable
+    pub comments: bool,
+}
+
+/// Packs `text` as a complete stream: packing is switched on at the start, with the
+/// no-spaces mode if requested, and everything is reset at the end.
+pub fn pack(text: &str, options: PackOptions) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len() / 2);
+    out.extend(Command::EnablePacking.signal());
+    if options.no_spaces {
+        out.extend(Command::EnableNoSpaces.signal());
+    }
+    for line in text.lines() {
+        pack_line(line, options, &mut out);
+    }
+    out.extend(Command::ResetAll.signal());
+    out
+}
+
+/// Packs `line` and its line feed into `out`, assuming the stream is already packing.
+///
+/// Lines are packed separately so that a line feed never starts a byte with another
+/// character behind it: Marlin drops the character following a line feed in the same byte.
+pub fn pack_line(line: &str, options: PackOptions, out: &mut Vec<u8>) {
+    let line = line.trim_end_matches('\r');
+    let (code_part, comment) = match line.find(';') {
+        Some(i) if !options.comments => (line[..i].trim_end(), ""),
+        Some(i) => line.split_at(i),
+        None if !options.comments => (line.trim_end(), ""),
+        None => (line, ""),
+    };
+    let mut bytes: Vec<u8> = if options.no_spaces && !keeps_spaces(code_part) {
+        drop_spaces(code_part)
+    } else {
+        code_part.as_bytes().to_vec()
+    };
+    bytes.extend_from_slice(comment.as_bytes());
+    if !options.comments && bytes.is_empty() {
+        return;
+    }
+    bytes.push(b'\n');
+
+    for pair in bytes.chunks(2) {
+        let first = pair[0];
+        let second = pair.get(1).copied().unwrap_or(b'\n');
+        let (low, high) = (
+            code(first, options.no_spaces),
+            code(second, options.no_spaces),
+        );
+        out.push(high.unwrap_or(LITERAL) << 4 | low.unwrap_or(LITERAL));
+        if low.is_none() {
+            out.push(first);
+        }
+        if high.is_none() {
+            out.push(second);
+        }
+    }
+}
+
+/// Whether the spaces of the command are part of its argument, like the message of `M117`.
+fn keeps_spaces(code_part: &str) -> bool {
+    let word = code_part
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_ascii_uppercase();
+    STRING_COMMANDS
+        .iter()
+        .chain(MESSAGE_COMMANDS.iter())
+        .any(|command| *command == word)
+}
+
+/// Whether [`restore_spaces`] puts a space between `previous` and the letter `next`.
+fn restores_space(previous: u8, next: u8) -> bool {
+    previous != b' ' && !previous.is_ascii_alphabetic() && next.is_ascii_alphabetic()
+}
+
+/// `code_part` without the spaces [`restore_spaces`] puts back: single spaces between a
+/// number and the letter of the next word. Other spaces, like runs of spaces or those of a
+/// quoted argument such as `M862.3 P "MK4S"`, are sent as full bytes.
+fn drop_spaces(code_part: &str) -> Vec<u8> {
+    let code = code_part.as_bytes();
+    let mut out = Vec::with_capacity(code.len());
+    let mut quoted = false;
+    for (i, &c) in code.iter().enumerate() {
+        if c == b'"' {
+            quoted = !quoted;
+        }
+        let restored = c == b' '
+            && !quoted
+            && i > 0
+            && code
+                .get(i + 1)
+                .is_some_and(|&next| restores_space(code[i - 1], next));
+        if !restored {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Decodes a MeatPack stream one byte at a time, the way Marlin does.
+#[derive(Debug, Clone, Default)]
+pub struct Unpacker {
+    active: bool,
+    no_spaces: bool,
+    /// Number of `0xFF` bytes received in a row, up to 2
+    signal_bytes: u8,
+    /// Number of full bytes still expected
+    literals: u8,
+    /// The packed second character, to output after the literal first one
+    second: Option<u8>,
+    /// The commands received, in order
+    commands: Vec<Command>,
+}
+
+impl Unpacker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn is_no_spaces(&self) -> bool {
+        self.no_spaces
+    }
+
+    /// The commands received so far.
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Decodes `byte`, appending the characters it completes to `out`.
+    pub fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if self.signal_bytes == 2 {
+            self.signal_bytes = 0;
+            if let Some(command) = Command::from_byte(byte) {
+                self.handle(command);
+            }
+            return;
+        }
+        if byte == SIGNAL_BYTE {
+            self.signal_bytes += 1;
+            return;
+        }
+        if self.signal_bytes == 1 {
+            // a single 0xFF is a byte with two full characters
+            self.signal_bytes = 0;
+            self.unpack(SIGNAL_BYTE, out);
+        }
+        self.unpack(byte, out);
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::EnablePacking => self.active = true,
+            Command::DisablePacking => self.active = false,
+            Command::ResetAll => {
+                self.active = false;
+                self.no_spaces = false;
+            }
+            Command::QueryConfig => {}
+            Command::EnableNoSpaces => self.no_spaces = true,
+            Command::DisableNoSpaces => self.no_spaces = false,
+        }
+        self.commands.push(command);
+    }
+
+    fn unpack(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if !self.active {
+            out.push(byte);
+            return;
+        }
+        if self.literals > 0 {
+            out.push(byte);
+            out.extend(self.second.take());
+            self.literals -= 1;
+            return;
+        }
+        let (low, high) = (byte & 0xf, byte >> 4);
+        if low == LITERAL {
+            self.literals = 1;
+            if high == LITERAL {
+                self.literals = 2;
+            } else {
+                self.second = Some(character(high, self.no_spaces));
+            }
+            return;
+        }
+        let first = character(low, self.no_spaces);
+        out.push(first);
+        // the rest of a byte starting with a line feed is padding
+        if first != b'\n' {
+            if high == LITERAL {
+                self.literals = 1;
+            } else {
+                out.push(character(high, self.no_spaces));
+            }
+        }
+    }
+}
+
+/// Decodes a complete MeatPack stream.
+pub fn unpack(data: &[u8]) -> Vec<u8> {
+    let mut unpacker = Unpacker::new();
+    let mut out = Vec::with_capacity(data.len() * 2);
+    for &byte in data {
+        unpacker.push(byte, &mut out);
+    }
+    out
+}
+
+/// What a captured stream contains, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamItem {
+    /// A signal switching the mode of the stream
+    Signal(Command),
+    /// A line, without its line feed
+    Line {
+        text: String,
+        /// Whether the line was packed in no-spaces mode, [`restore_spaces`] makes it readable
+        no_spaces: bool,
+        /// Bytes of the stream the line took
+        bytes: usize,
+    },
+}
+
+/// Splits a captured stream into its signals and lines.
+pub fn decode_stream(data: &[u8]) -> Vec<StreamItem> {
+    let mut unpacker = Unpacker::new();
+    let mut items = Vec::new();
+    let mut line = Vec::new();
+    let mut bytes = 0usize;
+    for &byte in data {
+        let commands = unpacker.commands().len();
+        unpacker.push(byte, &mut line);
+        bytes += 1;
+        if let Some(&command) = unpacker.commands().get(commands) {
+            items.push(StreamItem::Signal(command));
+            // the signal doesn't count towards the line it interrupts
+            bytes = bytes.saturating_sub(3);
+        }
+        if let Some(end) = line.iter().position(|&c| c == b'\n') {
+            let rest = line.split_off(end + 1);
+            line.pop();
+            items.push(StreamItem::Line {
+                text: String::from_utf8_lossy(&line).into_owned(),
+                no_spaces: unpacker.is_no_spaces(),
+                bytes,
+            });
+            // a literal following a packed line feed belongs to the next line
+            line = rest;
+            bytes = 0;
+        }
+    }
+    if !line.is_empty() {
+        items.push(StreamItem::Line {
+            text: String::from_utf8_lossy(&line).into_owned(),
+            no_spaces: unpacker.is_no_spaces(),
+            bytes,
+        });
+    }
+    items
+}
+
+/// How many bytes sending a file over a serial port takes, without and with packing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bandwidth {
+    /// Lines sent, comments and empty lines are not
+    pub lines: usize,
+    /// Bytes of the commands as text, with their line feeds
+    pub text: usize,
+    /// Bytes of the packed commands, including the signals
+    pub packed: usize,
+}
+
+impl Bandwidth {
+    /// The packed size relative to the text size.
+    pub fn ratio(&self) -> f64 {
+        self.packed as f64 / self.text.max(1) as f64
+    }
+
+    /// Seconds it takes to send `bytes` at `baud` with 8N1 framing, 10 bits per byte.
+    pub fn seconds(bytes: usize, baud: u32) -> f64 {
+        bytes as f64 * 10.0 / baud as f64
+    }
+}
+
+/// Measures what packing `text` saves, the way a host sends it: comments are stripped and
+/// every command is a line of its own.
+pub fn bandwidth(text: &str, no_spaces: bool) -> Bandwidth {
+    let options = PackOptions {
+        no_spaces,
+        comments: false,
+    };
+    // the signals switching packing and the no-spaces mode on, then off
+    let signals = if no_spaces { 3 } else { 2 };
+    let mut result = Bandwidth {
+        packed: signals * 3,
+        ..Bandwidth::default()
+    };
+    let mut packed = Vec::new();
+    for line in text.lines() {
+        let code = line.split(';').next().unwrap_or("").trim();
+        if !code.is_empty() {
+            result.lines += 1;
+            result.text += code.len() + 1;
+        }
+        pack_line(line, options, &mut packed);
+        result.packed += packed.len();
+        packed.clear();
+    }
+    result
+}
+
+/// Puts back the spaces between the words of commands packed in no-spaces mode, e.g.
+/// `G1X10Y5E.3` becomes `G1 X10 Y5 E.3`.
+///
+/// Together with [`pack_line`] this gives back the original line as long as its words were
+/// separated by spaces. The text of quoted arguments and the comment are left as they are.
+pub fn restore_spaces(line: &str) -> String {
+    let (code_part, comment) = line.split_at(line.find(';').unwrap_or(line.len()));
+    if keeps_spaces(code_part) {
+        return line.to_string();
+    }
+    let mut out = String::with_capacity(line.len() + 8);
+    let mut previous = b' ';
+    let mut quoted = false;
+    for c in code_part.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if !quoted && c.is_ascii() && restores_space(previous, c as u8) {
+            out.push(' ');
+        }
+        out.push(c);
+        previous = if c.is_ascii() { c as u8 } else { b'?' };
+    }
+    out.push_str(comment);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_round_trip() {
+        let text = "G1 X10.5 Y20 E0.35\nM117 Hello world\n; layer 2\nG28\n\nG1 Z0.2\n";
+        let with_comments = PackOptions {
+            no_spaces: false,
+            comments: true,
+        };
+        let packed = pack(text, with_comments);
+        assert!(packed.len() < text.len() + 6);
+        assert_eq!(String::from_utf8(unpack(&packed)).unwrap(), text);
+
+        let no_spaces = PackOptions {
+            no_spaces: true,
+            comments: false,
+        };
+        let unpacked = String::from_utf8(unpack(&pack(text, no_spaces))).unwrap();
+        assert_eq!(unpacked, "G1X10.5Y20E0.35\nM117 Hello world\nG28\nG1Z0.2\n");
+        let restored: Vec<String> = unpacked.lines().map(restore_spaces).collect();
+        assert_eq!(restored[0], "G1 X10.5 Y20 E0.35");
+        assert_eq!(restored[1], "M117 Hello world");
+
+        let items = decode_stream(&pack(text, no_spaces));
+        assert_eq!(items[0], StreamItem::Signal(Command::EnablePacking));
+        assert_eq!(items[1], StreamItem::Signal(Command::EnableNoSpaces));
+        assert_eq!(
+            items[2],
+            StreamItem::Line {
+                text: "G1X10.5Y20E0.35".to_string(),
+                no_spaces: true,
+                bytes: 9,
+            }
+        );
+        assert_eq!(items.last(), Some(&StreamItem::Signal(Command::ResetAll)));
+
+        let bandwidth = bandwidth(&text.repeat(10), true);
+        assert_eq!(bandwidth.lines, 40);
+        assert!(bandwidth.ratio() < 0.85, "{:?}", bandwidth);
+        assert_eq!(bandwidth.packed, pack(&text.repeat(10), no_spaces).len());
+    }
+
+    /// Streams laid out by hand the way OctoPrint's MeatPack plugin sends them, not by
+    /// [`pack`]: a file packed in no-spaces mode and ended with a reset, and a stream that
+    /// switches the no-spaces mode, then packing, off again.
+    const NO_SPACES_STREAM: &[u8] = include_bytes!("../../testdata/meatpack/no_spaces.mpk");
+    const SIGNALS_STREAM: &[u8] = include_bytes!("../../testdata/meatpack/signals.mpk");
+
+    #[test]
+    fn should_match_streams_of_the_host_packer() {
+        let text = "; generated by a test\nG28 ; home\n\nG1 Z0.2 F720\n\
+                    G1 X10.5 Y20 E0.35 F1200\nM104 S215\nG92 E0\nM117 Layer 2\n";
+        let no_spaces = PackOptions {
+            no_spaces: true,
+            comments: false,
+        };
+        assert_eq!(
+            String::from_utf8(unpack(NO_SPACES_STREAM)).unwrap(),
+            "G28\nG1Z0.2F720\nG1X10.5Y20E0.35F1200\nM104S215\nG92E0\nM117 Layer 2\n"
+        );
+        assert_eq!(pack(text, no_spaces), NO_SPACES_STREAM);
+
+        let line = |text: &str, no_spaces, bytes| StreamItem::Line {
+            text: text.to_string(),
+            no_spaces,
+            bytes,
+        };
+        assert_eq!(
+            decode_stream(SIGNALS_STREAM),
+            [
+                StreamItem::Signal(Command::EnablePacking),
+                StreamItem::Signal(Command::EnableNoSpaces),
+                line("G1X1E2", true, 4),
+                StreamItem::Signal(Command::DisableNoSpaces),
+                line("G1 X5 Y5", false, 6),
+                StreamItem::Signal(Command::DisablePacking),
+                line("M117 Done", false, 10),
+            ]
+        );
+        let mut packed = Command::EnablePacking.signal().to_vec();
+        packed.extend(Command::EnableNoSpaces.signal());
+        pack_line("G1 X1 E2", no_spaces, &mut packed);
+        packed.extend(Command::DisableNoSpaces.signal());
+        pack_line("G1 X5 Y5", PackOptions::default(), &mut packed);
+        packed.extend(Command::DisablePacking.signal());
+        packed.extend(b"M117 Done\n");
+        assert_eq!(packed, SIGNALS_STREAM);
+    }
+
+    #[test]
+    fn should_keep_quoted_arguments_and_odd_spacing_in_no_spaces_mode() {
+        let text = "M862.3 P \"MK4S\" ; printer\nG1  X10 Y5 ; two spaces\n\
+                    G1 X1 E2;no space\nM862.5 P2\n  G1 X2 \n";
+        let options = PackOptions {
+            no_spaces: true,
+            comments: true,
+        };
+        let unpacked = String::from_utf8(unpack(&pack(text, options))).unwrap();
+        assert!(
+            unpacked.starts_with("M862.3P \"MK4S\" ; printer\n"),
+            "{}",
+            unpacked
+        );
+        let restored: String = unpacked
+            .lines()
+            .map(|line| restore_spaces(line) + "\n")
+            .collect();
+        assert_eq!(restored, text);
+    }
+}
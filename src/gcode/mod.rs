@@ -1,2 +1,17 @@
+pub mod annotate;
+pub mod bgcode;
+pub mod container;
+pub mod flow;
+pub mod index;
 pub mod lines;
+pub mod machine;
 pub mod marlin_docs;
+pub mod meatpack;
+pub mod modify;
+pub mod parallel;
+pub mod profile;
+pub mod resume;
+pub mod stats;
+pub mod table;
+pub mod thumbnails;
+pub mod width;
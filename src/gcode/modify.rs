@@ -0,0 +1,337 @@
+//! Post-processing of a file: filament changes, pauses, temperature, fan and flow changes and
+//! custom snippets inserted at layer changes.
+//!
+//! Modifications only insert lines, every line of the original file is written back byte for
+//! byte, with its own line ending. Inserted lines use the line ending of the first line.
+
+use crate::gcode::machine::{is_layer_change, Toolpath, Z};
+use std::fmt::{self, Display};
+
+/// The layer a modification applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum At {
+    /// A layer, starting at 0
+    Layer(usize),
+    /// The first layer printed at or above this height in mm
+    Z(f64),
+}
+
+impl Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            At::Layer(layer) => write!(f, "layer {}", layer),
+            At::Z(z) => write!(f, "Z {}", z),
+        }
+    }
+}
+
+/// What to insert at a layer change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// `M600`
+    FilamentChange,
+    /// A pause with the given command, e.g. `M0` or `M25`
+    Pause(String),
+    /// Hotend target temperature in °C, without waiting for it
+    Temperature(f64),
+    /// Part cooling fan speed in percent
+    Fan(f64),
+    /// Flow factor in percent, `M221`
+    Flow(f64),
+}
+
+impl Change {
+    /// The commands making the change.
+    pub fn gcode(&self) -> String {
+        match self {
+            Change::FilamentChange => "M600".to_string(),
+            Change::Pause(command) => command.clone(),
+            Change::Temperature(temperature) => format!("M104 S{}", temperature),
+            Change::Fan(percent) if *percent <= 0.0 => "M107".to_string(),
+            Change::Fan(percent) => {
+                format!("M106 S{}", (percent * 255.0 / 100.0).round().min(255.0))
+            }
+            Change::Flow(percent) => format!("M221 S{}", percent),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::FilamentChange => write!(f, "filament change"),
+            Change::Pause(_) => write!(f, "pause"),
+            Change::Temperature(temperature) => write!(f, "hotend at {}°C", temperature),
+            Change::Fan(percent) => write!(f, "fan at {}%", percent),
+            Change::Flow(percent) => write!(f, "flow at {}%", percent),
+        }
+    }
+}
+
+/// A change from a layer on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modification {
+    pub at: At,
+    pub change: Change,
+}
+
+/// G-code inserted at every layer change, where `{layer}` is replaced by the index of the layer
+/// and `{z}` by its height.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snippets {
+    /// Inserted before the first line of the layer
+    pub before_layer: Vec<String>,
+    /// Inserted after the move to the height of the layer
+    pub after_layer: Vec<String>,
+}
+
+/// Lines inserted into a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insertion {
+    /// The line the text is inserted before, starting at 0
+    pub line: usize,
+    pub layer: usize,
+    /// What the text does, also written as a comment above it
+    pub description: String,
+    /// Lines separated by `\n`
+    pub text: String,
+}
+
+/// A modification at a layer the file doesn't have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModifyError {
+    pub at: At,
+    pub layers: usize,
+}
+
+impl Display for ModifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.at {
+            At::Layer(layer) => write!(
+                f,
+                "no layer {}, the file has layers 0 to {}",
+                layer,
+                self.layers.saturating_sub(1)
+            ),
+            At::Z(z) => write!(f, "no layer at or above Z {}", z),
+        }
+    }
+}
+
+impl std::error::Error for ModifyError {}
+
+/// The lines before and after the layer change of `layer`: its first line, and the line after
+/// the move to its height, or its first extrusion when it starts without moving Z.
+fn layer_change(text: &str, toolpath: &Toolpath, layer: usize) -> (usize, usize) {
+    let lines = &toolpath.layers[layer].lines;
+    let first_extrusion = toolpath
+        .layer_moves(&toolpath.layers[layer])
+        .iter()
+        .find(|m| m.is_extrusion())
+        .map_or(lines.end, |m| m.line);
+    // the first layer also holds the start G-code and maybe a purge line, it starts at its
+    // layer comment if any
+    let before = if layer == 0 {
+        text.lines()
+            .take(lines.end)
+            .position(|line| line.trim_start().starts_with(';') && is_layer_change(line.trim()))
+            .unwrap_or(first_extrusion)
+    } else {
+        lines.start
+    };
+    let first_move = toolpath.moves.partition_point(|m| m.line < before);
+    let after = toolpath.moves[first_move..]
+        .iter()
+        .find(|m| m.from[Z] != m.to[Z] || m.is_extrusion())
+        // after the Z move, before the extrusion
+        .map_or(before, |m| m.line + usize::from(!m.is_extrusion()));
+    (before, after.max(before))
+}
+
+/// The lines to insert into `text`, whose toolpath is `toolpath`, in the order they are
+/// inserted.
+pub fn plan(
+    text: &str,
+    toolpath: &Toolpath,
+    modifications: &[Modification],
+    snippets: &Snippets,
+) -> Result<Vec<Insertion>, ModifyError> {
+    let z = |layer: usize| toolpath.layers[layer].z.map_or(0.0, |z| z);
+    let snippet = |gcode: &str, layer: usize| {
+        gcode
+            .replace("{layer}", &layer.to_string())
+            .replace("{z}", &z(layer).to_string())
+    };
+
+    let mut insertions = vec![];
+    let changes: Vec<(usize, usize)> = (0..toolpath.layers.len())
+        .map(|layer| layer_change(text, toolpath, layer))
+        .collect();
+    for (layer, (before, _)) in changes.iter().enumerate() {
+        for gcode in snippets.before_layer.iter() {
+            insertions.push(Insertion {
+                line: *before,
+                layer,
+                description: format!("before layer {}", layer),
+                text: snippet(gcode, layer),
+            });
+        }
+    }
+    for modification in modifications {
+        let layer = match modification.at {
+            At::Layer(layer) if layer < toolpath.layers.len() => Some(layer),
+            At::Layer(_) => None,
+            At::Z(z) => toolpath.layer_at(z).map(|layer| layer.index),
+        }
+        .ok_or(ModifyError {
+            at: modification.at,
+            layers: toolpath.layers.len(),
+        })?;
+        insertions.push(Insertion {
+            line: changes[layer].0,
+            layer,
+            description: format!("{} at layer {}, Z {}", modification.change, layer, z(layer)),
+            text: modification.change.gcode(),
+        });
+    }
+    for (layer, (_, after)) in changes.iter().enumerate() {
+        for gcode in snippets.after_layer.iter() {
+            insertions.push(Insertion {
+                line: *after,
+                layer,
+                description: format!("after layer change {}", layer),
+                text: snippet(gcode, layer),
+            });
+        }
+    }
+    insertions.sort_by_key(|insertion| insertion.line);
+    Ok(insertions)
+}
+
+/// `text` with the `insertions` made, each preceded by a comment with its description.
+///
+/// `text` is spliced as bytes, so lines that aren't UTF-8, like Latin-1 comments, are written
+/// back as they were.
+pub fn apply(text: &[u8], insertions: &[Insertion]) -> Vec<u8> {
+    let newline: &[u8] = match text.iter().position(|&b| b == b'\n') {
+        Some(end) if text[..end].ends_with(b"\r") => b"\r\n",
+        _ => b"\n",
+    };
+    let mut out = Vec::with_capacity(text.len());
+    let insert = |out: &mut Vec<u8>, insertion: &Insertion| {
+        for line in std::iter::once(format!("; {}", insertion.description))
+            .chain(insertion.text.lines().map(str::to_string))
+        {
+            out.extend_from_slice(line.as_bytes());
+            out.extend_from_slice(newline);
+        }
+    };
+
+    let mut pending = insertions.iter().peekable();
+    for (index, line) in text.split_inclusive(|&b| b == b'\n').enumerate() {
+        while let Some(insertion) = pending.next_if(|insertion| insertion.line <= index) {
+            insert(&mut out, insertion);
+        }
+        out.extend_from_slice(line);
+    }
+    if pending.peek().is_some() && !out.is_empty() && !out.ends_with(b"\n") {
+        out.extend_from_slice(newline);
+    }
+    for insertion in pending {
+        insert(&mut out, insertion);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::parallel;
+
+    #[test]
+    fn should_leave_the_other_lines_untouched() {
+        // CRLF, odd spacing, a layer change mid file, a Latin-1 comment and no newline at the
+        // end
+        let data: &[u8] = b"M104 S200\r\nG28\r\nG1 Z5 E-1 ; lift\r\n;LAYER:0\r\nG1  Z0.2\r\n\
+                   G1 X10 E1\r\n\r\n;LAYER:1\r\nG1 Z0.4\r\nG1 X0 E2 ; 20\xb0 overhang\r\n\
+                   ;LAYER:2\r\nG1 Z0.6\r\nG1 X10 E3";
+        let src = &String::from_utf8_lossy(data);
+        let toolpath = parallel::simulate(src);
+        assert_eq!(apply(data, &[]), data);
+
+        let modifications = [
+            Modification {
+                at: At::Layer(1),
+                change: Change::FilamentChange,
+            },
+            Modification {
+                at: At::Z(0.5),
+                change: Change::Temperature(215.0),
+            },
+            Modification {
+                at: At::Layer(2),
+                change: Change::Fan(50.0),
+            },
+        ];
+        let snippets = Snippets {
+            before_layer: vec![],
+            after_layer: vec!["M117 Layer {layer}".to_string()],
+        };
+        let insertions = plan(src, &toolpath, &modifications, &snippets).unwrap();
+        let placed: Vec<(usize, &str)> = insertions
+            .iter()
+            .map(|insertion| (insertion.line, insertion.text.as_str()))
+            .collect();
+        assert_eq!(
+            placed,
+            vec![
+                (5, "M117 Layer 0"),
+                (7, "M600"),
+                (9, "M117 Layer 1"),
+                (10, "M104 S215"),
+                (10, "M106 S128"),
+                (12, "M117 Layer 2"),
+            ]
+        );
+
+        let modified = apply(data, &insertions);
+        let text = String::from_utf8_lossy(&modified);
+        assert!(text.contains("G1 Z0.4\r\n; after layer change 1\r\nM117 Layer 1\r\nG1 X0"));
+        assert!(text.ends_with("G1 Z0.6\r\n; after layer change 2\r\nM117 Layer 2\r\nG1 X10 E3"));
+        let inserted: Vec<String> = insertions
+            .iter()
+            .flat_map(|insertion| {
+                [
+                    format!("; {}", insertion.description),
+                    insertion.text.clone(),
+                ]
+            })
+            .collect();
+        let untouched: Vec<u8> = modified
+            .split_inclusive(|&b| b == b'\n')
+            .filter(|line| {
+                !inserted
+                    .iter()
+                    .any(|text| line.trim_ascii_end() == text.as_bytes())
+            })
+            .flatten()
+            .copied()
+            .collect();
+        assert_eq!(untouched, data);
+
+        assert_eq!(
+            plan(
+                src,
+                &toolpath,
+                &[Modification {
+                    at: At::Layer(3),
+                    change: Change::FilamentChange
+                }],
+                &Snippets::default()
+            )
+            .unwrap_err()
+            .to_string(),
+            "no layer 3, the file has layers 0 to 2"
+        );
+    }
+}
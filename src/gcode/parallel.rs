@@ -0,0 +1,423 @@
+//! Parsing and simulating large files on all cores.
+//!
+//! A file is split into [`Chunk`]s at line boundaries, preferably at layer change comments so
+//! that a chunk holds whole layers. Lines are parsed independently of each other, so chunks
+//! are parsed on the rayon thread pool. Executing them is another matter: every move depends
+//! on the position, modes, feedrate and temperatures left by the lines before it. So a
+//! [`Machine`] first runs through the parsed lines of a batch of chunks in order, keeping
+//! nothing but its state at the start of each chunk. That pass is cheap next to parsing and
+//! analysing the moves. The chunks are then simulated again in parallel, each from its own
+//! copy of that state, and the results are merged in the order of the file, see [`fold`].
+//! Only a batch of chunks is held at a time.
+
+use crate::gcode::lines::{parse_lines_at, DisplayLine, LineIterator};
+use crate::gcode::machine::{Machine, Observer, Simulation, Toolpath};
+use rayon::prelude::*;
+use std::convert::Infallible;
+use std::io::{self, Read};
+
+/// Bytes of a chunk, give or take a line or a layer.
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// Comments announcing a layer change, see [`crate::gcode::machine::Machine`].
+const LAYER_MARKERS: [&str; 2] = [";LAYER_CHANGE", ";LAYER:"];
+
+/// A part of a file starting at the beginning of a line, as text or, for files that may not
+/// be UTF-8, as bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Chunk<'a, T: ?Sized = str> {
+    pub text: &'a T,
+    /// Byte offset of the chunk in the file
+    pub offset: usize,
+    /// Line of the file the chunk starts at
+    pub first_line: usize,
+}
+
+impl<T: ?Sized> Clone for Chunk<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Chunk<'_, T> {}
+
+/// A chunk, borrowed or held.
+pub trait AsChunk: Sync {
+    fn as_chunk(&self) -> Chunk<'_>;
+}
+
+impl AsChunk for Chunk<'_> {
+    fn as_chunk(&self) -> Chunk<'_> {
+        *self
+    }
+}
+
+impl<'a> Chunk<'a> {
+    /// Parses the lines of the chunk, with spans relative to the file.
+    pub fn parse(&self) -> Vec<DisplayLine<'a>> {
+        LineIterator::new(parse_lines_at(self.text, self.offset, self.first_line)).collect()
+    }
+}
+
+/// The start of the line after the one containing byte `at`, or the end of `text`.
+fn next_line(text: &[u8], at: usize) -> usize {
+    text[at..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(text.len(), |n| at + n + 1)
+}
+
+/// Splits `text` into chunks of about `size` bytes.
+///
+/// A chunk ends at the first layer change in the quarter of `size` following its nominal end,
+/// or else at the end of the line.
+pub fn split(text: &str, size: usize) -> Vec<Chunk<'_>> {
+    split_bytes(text.as_bytes(), size)
+        .into_iter()
+        .map(|chunk| Chunk {
+            // chunks start after a line feed, so at a character boundary
+            text: &text[chunk.offset..chunk.offset + chunk.text.len()],
+            offset: chunk.offset,
+            first_line: chunk.first_line,
+        })
+        .collect()
+}
+
+/// The end of the chunk at the start of `text`, see [`split`]; `None` if `text` may stop too
+/// early to tell and isn't `complete`.
+fn chunk_end(text: &[u8], size: usize, complete: bool) -> Option<usize> {
+    if text.len() <= size {
+        return complete.then_some(text.len());
+    }
+    let end = next_line(text, size - 1);
+    let window = end + size / 4;
+    let longest = LAYER_MARKERS.iter().map(|m| m.len()).max().unwrap_or(0);
+    if !complete && (end == text.len() || window + longest > text.len()) {
+        return None;
+    }
+    let window = window.min(text.len());
+    let mut line = end;
+    while line < window
+        && !LAYER_MARKERS
+            .iter()
+            .any(|m| text[line..].starts_with(m.as_bytes()))
+    {
+        line = next_line(text, line);
+    }
+    Some(if line < window { line } else { end })
+}
+
+/// Splits `text` like [`split`], without requiring it to be UTF-8.
+pub fn split_bytes(text: &[u8], size: usize) -> Vec<Chunk<'_, [u8]>> {
+    let size = size.max(1);
+    let mut bounds = vec![0];
+    let mut start = 0;
+    loop {
+        start += chunk_end(&text[start..], size, true).unwrap_or(text.len() - start);
+        bounds.push(start);
+        if start >= text.len() {
+            break;
+        }
+    }
+
+    let texts: Vec<&[u8]> = bounds.windows(2).map(|b| &text[b[0]..b[1]]).collect();
+    let lines: Vec<usize> = texts
+        .par_iter()
+        .map(|text| text.iter().filter(|&&b| b == b'\n').count())
+        .collect();
+    let mut first_line = 0;
+    texts
+        .into_iter()
+        .zip(bounds)
+        .zip(lines)
+        .map(|((text, offset), lines)| {
+            let chunk = Chunk {
+                text,
+                offset,
+                first_line,
+            };
+            first_line += lines;
+            chunk
+        })
+        .collect()
+}
+
+/// A chunk read into memory, see [`read_chunks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkBuf {
+    pub text: String,
+    /// Byte offset of the chunk in the text of the file
+    pub offset: usize,
+    /// Line of the file the chunk starts at
+    pub first_line: usize,
+}
+
+impl AsChunk for ChunkBuf {
+    fn as_chunk(&self) -> Chunk<'_> {
+        Chunk {
+            text: &self.text,
+            offset: self.offset,
+            first_line: self.first_line,
+        }
+    }
+}
+
+/// Reads the chunks of a file, see [`read_chunks`].
+#[derive(Debug)]
+pub struct ChunkReader<R> {
+    reader: R,
+    size: usize,
+    /// What was read past the chunks already handed out
+    buffer: Vec<u8>,
+    /// What is read at once
+    block: Vec<u8>,
+    done: bool,
+    offset: usize,
+    first_line: usize,
+}
+
+/// Reads the text of `reader` a chunk at a time, split like [`split`] would split all of it,
+/// holding a chunk and a quarter at most.
+///
+/// Text that isn't UTF-8 gets replacement characters, and the offsets of the chunks count
+/// the bytes of the text with them.
+pub fn read_chunks<R: Read>(reader: R, size: usize) -> ChunkReader<R> {
+    ChunkReader {
+        reader,
+        size: size.max(1),
+        buffer: Vec::new(),
+        block: vec![0; READ_SIZE],
+        done: false,
+        offset: 0,
+        first_line: 0,
+    }
+}
+
+/// Bytes read at once by a [`ChunkReader`].
+const READ_SIZE: usize = 1 << 16;
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = io::Result<ChunkBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let end = loop {
+            if let Some(end) = chunk_end(&self.buffer, self.size, self.done) {
+                break end;
+            }
+            match self.reader.read(&mut self.block) {
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&self.block[..n]);
+                    self.done = n == 0;
+                }
+                Err(err) => {
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                }
+            }
+        };
+        if end == 0 {
+            return None;
+        }
+        let bytes: Vec<u8> = self.buffer.drain(..end).collect();
+        let lines = bytes.iter().filter(|&&b| b == b'\n').count();
+        let text = String::from_utf8(bytes)
+            .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned());
+        let chunk = ChunkBuf {
+            offset: self.offset,
+            first_line: self.first_line,
+            text,
+        };
+        self.offset += chunk.text.len();
+        self.first_line += lines;
+        Some(Ok(chunk))
+    }
+}
+
+/// Simulates the chunks of a file on all cores and hands the observer of each chunk to `each`,
+/// in the order of the file. Returns the machine after the last line.
+///
+/// `start` makes the observer of a chunk from the machine in the state the lines before the
+/// chunk leave it in. `each` runs on the calling thread between batches of chunks, so it can
+/// merge or write out what the observers found before the next batch is read.
+pub fn fold<C, E, O>(
+    chunks: impl IntoIterator<Item = Result<C, E>>,
+    start: impl Fn(Chunk, &Machine) -> O + Sync,
+    mut each: impl FnMut(O) -> Result<(), E>,
+) -> Result<Machine, E>
+where
+    C: AsChunk + Send,
+    O: Observer + Send,
+{
+    let mut chunks = chunks.into_iter();
+    let mut machine = Machine::new();
+    loop {
+        let batch = chunks
+            .by_ref()
+            .take(rayon::current_num_threads() * 2)
+            .collect::<Result<Vec<C>, E>>()?;
+        if batch.is_empty() {
+            return Ok(machine);
+        }
+        let parsed: Vec<Vec<DisplayLine>> = batch
+            .par_iter()
+            .map(|chunk| chunk.as_chunk().parse())
+            .collect();
+        // the state handed from each chunk to the next
+        let seeds: Vec<Machine> = parsed
+            .iter()
+            .map(|lines| {
+                let seed = machine.clone();
+                for line in lines {
+                    machine.execute(line);
+                }
+                seed
+            })
+            .collect();
+        let observers: Vec<O> = batch
+            .par_iter()
+            .zip(parsed.par_iter())
+            .zip(seeds.into_par_iter())
+            .map(|((chunk, lines), mut machine)| {
+                let mut observer = start(chunk.as_chunk(), &machine);
+                for line in lines {
+                    machine.run(line, &mut observer);
+                }
+                observer
+            })
+            .collect();
+        observers.into_iter().try_for_each(&mut each)?;
+    }
+}
+
+/// Simulates `text` like [`crate::gcode::machine::simulate`], on all cores.
+pub fn simulate(text: &str) -> Toolpath {
+    simulate_chunks(split(text, CHUNK_SIZE))
+}
+
+fn simulate_chunks(chunks: Vec<Chunk>) -> Toolpath {
+    let mut simulation = Simulation::new();
+    let machine = fold(
+        chunks.into_iter().map(Ok::<_, Infallible>),
+        |_, machine| Simulation::starting_at(machine),
+        |next| {
+            simulation.append(next);
+            Ok(())
+        },
+    );
+    let Ok(machine) = machine;
+    simulation.finish(&machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::parse_lines;
+    use crate::gcode::machine::simulate as simulate_lines;
+
+    #[test]
+    fn should_simulate_chunks_like_the_whole_file() {
+        let mut src = String::from("; header\nM104 S210\nG28\nG91\nG1 Z0.2\nG90\nM83\n");
+        for layer in 0..20 {
+            src.push_str(&format!(
+                ";LAYER:{}\nG1 Z{:.1} F600\n",
+                layer,
+                0.2 * (layer + 1) as f64
+            ));
+            for i in 0..30 {
+                src.push_str(&format!("G1 X{} Y{} E0.5 F{}\n", i, 30 - i, 1200 + i));
+            }
+            src.push_str("G2 X0 Y0 I-5 J5 E1\nM106 S128\n");
+        }
+
+        let by_layer = split(&src, 3000);
+        // the last chunk starts where the file runs out of layers
+        let (last, by_layer) = by_layer.split_last().unwrap();
+        assert!(by_layer[1..].iter().all(|c| c.text.starts_with(";LAYER:")));
+        assert!(last.text.starts_with("G1 "));
+
+        let chunks = split(&src, 200);
+        assert!(chunks.len() > 20);
+        assert_eq!(chunks.iter().map(|c| c.text).collect::<String>(), src);
+        for chunk in chunks.iter() {
+            assert_eq!(src[..chunk.offset].lines().count(), chunk.first_line);
+        }
+
+        let expected = simulate_lines(LineIterator::new(parse_lines(&src)));
+        let toolpath = simulate_chunks(chunks);
+        assert_same(&toolpath, &expected);
+    }
+
+    fn assert_same(toolpath: &Toolpath, expected: &Toolpath) {
+        assert_eq!(toolpath.moves, expected.moves);
+        assert_eq!(toolpath.layers, expected.layers);
+        assert_eq!(toolpath.temperatures, expected.temperatures);
+        assert_eq!(toolpath.lines, expected.lines);
+        assert_eq!(toolpath.final_state, expected.final_state);
+    }
+
+    #[test]
+    fn should_hand_the_modes_over_to_the_next_chunk() {
+        let mut src = String::from("M140 S60\nM104 S210\nT1\nG1 Z0.2 F1200\n");
+        for i in 0..100 {
+            src.push_str(match i % 4 {
+                0 => "G91\nM83\nG1 X1 Y2 E0.5\nG1 Z0.2 F900\n",
+                1 => "G90\nM82\nG92 E0\nG1 X10 Y10 E1\nG2 X20 Y10 I5 J0 E2\n",
+                2 => "G20\nG1 X0.5 E2.1 F60\nM104 S205\nT0\n",
+                _ => "G21\nM221 S90\nM220 S150\nM106 S200\nG1 X30 E3\n;LAYER_CHANGE\n",
+            });
+        }
+        // a chunk per line, so every mode change is on one side of a boundary and the moves
+        // it affects on the other
+        let chunks = split(&src, 1);
+        assert_eq!(chunks.len(), src.lines().count());
+        assert!(chunks.len() > rayon::current_num_threads() * 2);
+
+        let expected = simulate_lines(LineIterator::new(parse_lines(&src)));
+        assert!(expected.layers.len() > 20);
+        assert_same(&simulate_chunks(chunks), &expected);
+    }
+
+    /// Reads a byte at a time.
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn should_read_the_chunks_split_would_make() {
+        let mut src = String::from("; header\n");
+        for layer in 0..200 {
+            src.push_str(&format!(";LAYER_CHANGE\nG1 Z{}\n", layer));
+            for i in 0..layer % 13 {
+                src.push_str(&format!("G1 X{} E0.1 ; ümlaut\n", i));
+            }
+        }
+        src.push_str("M84"); // no line break at the end
+        for size in [1, 7, 100, 1000, 1 << 20] {
+            let expected: Vec<ChunkBuf> = split(&src, size)
+                .into_iter()
+                .map(|chunk| ChunkBuf {
+                    text: chunk.text.to_string(),
+                    offset: chunk.offset,
+                    first_line: chunk.first_line,
+                })
+                .filter(|chunk| !chunk.text.is_empty())
+                .collect();
+            // a byte at a time, to stop in the middle of lines and characters
+            let reader = OneByte(src.as_bytes());
+            let chunks: Vec<ChunkBuf> = read_chunks(reader, size).map(Result::unwrap).collect();
+            assert_eq!(chunks, expected, "size {}", size);
+        }
+        assert_eq!(read_chunks(&b""[..], 10).count(), 0);
+    }
+}
@@ -0,0 +1,199 @@
+//! Machine profiles: the bed, nozzle and filament of a printer and the limits of its hotend.
+//!
+//! Profiles are TOML files with the fields of [`MachineProfile`], any field left out keeps
+//! the value of the [`MachineProfile::default`] profile:
+//!
+//! ```toml
+//! name = "My Ender"
+//! bed_size = [235.0, 235.0]
+//! max_volumetric_flow = 12.0
+//! ```
+//!
+//! A few common printers are built in, see [`BUILTIN_PROFILES`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MachineProfile {
+    pub name: String,
+    /// Size of the bed in X and Y in mm
+    pub bed_size: [f64; 2],
+    /// Whether X0 Y0 is the centre of the bed, like on delta printers, instead of the front
+    /// left corner
+    pub origin_center: bool,
+    /// Maximum print height in mm
+    pub max_height: f64,
+    /// Nozzle diameter in mm
+    pub nozzle_diameter: f64,
+    /// Filament diameter in mm
+    pub filament_diameter: f64,
+    /// Maximum volumetric flow the hotend can melt, in mm³/s, checked by
+    /// [`crate::gcode::flow`]
+    pub max_volumetric_flow: f64,
+    /// Maximum print speed in mm/s, checked by [`crate::gcode::flow`]
+    pub max_print_speed: f64,
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        Self {
+            name: "generic".to_string(),
+            bed_size: [220.0, 220.0],
+            origin_center: false,
+            max_height: 250.0,
+            nozzle_diameter: 0.4,
+            filament_diameter: 1.75,
+            max_volumetric_flow: 12.0,
+            max_print_speed: 200.0,
+        }
+    }
+}
+
+impl MachineProfile {
+    /// The printable area as `(min, max)` corners in mm.
+    pub fn bed_area(&self) -> ([f64; 2], [f64; 2]) {
+        let [x, y] = self.bed_size;
+        if self.origin_center {
+            ([-x / 2.0, -y / 2.0], [x / 2.0, y / 2.0])
+        } else {
+            ([0.0, 0.0], [x, y])
+        }
+    }
+
+    /// Cross-section of the filament in mm².
+    pub fn filament_area(&self) -> f64 {
+        std::f64::consts::PI * (self.filament_diameter / 2.0).powi(2)
+    }
+}
+
+/// Names of the profiles built into the binary.
+pub const BUILTIN_PROFILES: [&str; 5] =
+    ["generic", "ender3", "prusa-mk3s", "prusa-mini", "voron-350"];
+
+/// A built-in profile by name.
+pub fn builtin_profile(name: &str) -> Option<MachineProfile> {
+    let generic = MachineProfile::default();
+    let profile = match name {
+        "generic" => generic,
+        "ender3" => MachineProfile {
+            name: name.to_string(),
+            bed_size: [235.0, 235.0],
+            max_volumetric_flow: 10.0,
+            max_print_speed: 150.0,
+            ..generic
+        },
+        "prusa-mk3s" => MachineProfile {
+            name: name.to_string(),
+            bed_size: [250.0, 210.0],
+            max_height: 210.0,
+            max_volumetric_flow: 15.0,
+            ..generic
+        },
+        "prusa-mini" => MachineProfile {
+            name: name.to_string(),
+            bed_size: [180.0, 180.0],
+            max_height: 180.0,
+            max_volumetric_flow: 12.0,
+            ..generic
+        },
+        "voron-350" => MachineProfile {
+            name: name.to_string(),
+            bed_size: [350.0, 350.0],
+            max_height: 340.0,
+            max_volumetric_flow: 24.0,
+            max_print_speed: 500.0,
+            ..generic
+        },
+        _ => return None,
+    };
+    Some(profile)
+}
+
+/// Everything that can go wrong while loading a profile.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The profile file could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The profile file is not valid TOML or doesn't match [`MachineProfile`].
+    Toml { path: PathBuf, message: String },
+    /// Neither a file nor a built-in profile.
+    Unknown { name: String },
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io { path, source } => {
+                write!(f, "{}: I/O error: {}", path.display(), source)
+            }
+            ProfileError::Toml { path, message } => write!(f, "{}: {}", path.display(), message),
+            ProfileError::Unknown { name } => write!(
+                f,
+                "no profile file or built-in profile {}, the built-in profiles are {}",
+                name,
+                BUILTIN_PROFILES.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProfileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Loads the profile `name`, either the path of a TOML file or the name of a built-in profile.
+pub fn load_profile(name: &str) -> Result<MachineProfile, ProfileError> {
+    let path = Path::new(name);
+    if path.is_file() || name.ends_with(".toml") {
+        let s = std::fs::read_to_string(path).map_err(|source| ProfileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut profile: MachineProfile = toml::from_str(&s).map_err(|err| ProfileError::Toml {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        })?;
+        if profile.name == MachineProfile::default().name {
+            profile.name = path
+                .file_stem()
+                .map_or(name.into(), |stem| stem.to_string_lossy())
+                .into_owned();
+        }
+        return Ok(profile);
+    }
+    builtin_profile(name).ok_or_else(|| ProfileError::Unknown {
+        name: name.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_fill_missing_fields_from_defaults() {
+        let profile: MachineProfile =
+            toml::from_str("bed_size = [300.0, 200.0]\norigin_center = true\n").unwrap();
+        assert_eq!(profile.bed_area(), ([-150.0, -100.0], [150.0, 100.0]));
+        assert_eq!(profile.filament_diameter, 1.75);
+        assert!(matches!(
+            load_profile("nonexistent"),
+            Err(ProfileError::Unknown { .. })
+        ));
+        assert!(BUILTIN_PROFILES
+            .iter()
+            .all(|name| builtin_profile(name).is_some()));
+    }
+}
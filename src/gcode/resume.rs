@@ -0,0 +1,285 @@
+//! Resumes a failed print from a line, a layer or a height.
+//!
+//! The state of the printer at any line follows from the lines before it, see
+//! [`crate::gcode::machine`]. A resumed file starts with a preamble bringing a freshly
+//! started printer back into that state, followed by the rest of the original file as is.
+//!
+//! The nozzle can't be homed in Z over a print, so the preamble assumes it was left at the
+//! height the print stopped at and declares that height with `G92`.
+
+use crate::gcode::machine::{MachineState, Toolpath, E, X, Y, Z};
+use crate::gcode::parallel;
+use std::fmt::{self, Display, Write};
+
+/// Feedrate of the travel to the resume position, in mm/min.
+const TRAVEL_FEEDRATE: f64 = 3000.0;
+
+/// Feedrate of the Z moves of the preamble, in mm/min.
+const Z_FEEDRATE: f64 = 600.0;
+
+/// Where to resume a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumePoint {
+    /// A line of the file, starting at 1
+    Line(usize),
+    /// The first line of a layer, starting at 0
+    Layer(usize),
+    /// The first line of the first layer printed at or above this height in mm
+    Z(f64),
+}
+
+/// A resume point outside the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResumeError {
+    Line { line: usize, lines: usize },
+    Layer { layer: usize, layers: usize },
+    Z { z: f64, max: Option<f64> },
+}
+
+impl Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResumeError::Line { line, lines } => {
+                write!(f, "no line {}, the file has lines 1 to {}", line, lines)
+            }
+            ResumeError::Layer { layer, layers } => {
+                write!(
+                    f,
+                    "no layer {}, the file has layers 0 to {}",
+                    layer,
+                    layers - 1
+                )
+            }
+            ResumeError::Z { z, max: Some(max) } => {
+                write!(f, "no layer at Z {}, the highest is at {}", z, max)
+            }
+            ResumeError::Z { z, max: None } => {
+                write!(f, "no layer at Z {}, the file doesn't extrude", z)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// A file cut at a resume point.
+#[derive(Debug, Clone)]
+pub struct Resume<'a> {
+    /// Line the print resumes at, starting at 0
+    pub line: usize,
+    pub layer: usize,
+    /// The state of the printer before the line
+    pub state: MachineState,
+    /// The lines from the resume point to the end of the file, as they are in the file
+    pub rest: &'a [u8],
+}
+
+/// Byte offset of the start of `line` in `text`, `None` past the last line.
+fn line_offset(text: &[u8], line: usize) -> Option<usize> {
+    if line == 0 {
+        return Some(0);
+    }
+    text.iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b'\n')
+        .nth(line - 1)
+        .map(|(offset, _)| offset + 1)
+        .filter(|offset| *offset < text.len())
+}
+
+/// Finds `point` in the file `text`, whose toolpath is `toolpath`, and the state of the
+/// printer there.
+///
+/// `text` is cut as bytes, so that the rest of a file that isn't UTF-8 is written back as it
+/// was.
+pub fn resume<'a>(
+    text: &'a [u8],
+    toolpath: &Toolpath,
+    point: ResumePoint,
+) -> Result<Resume<'a>, ResumeError> {
+    let line = match point {
+        ResumePoint::Line(line) => line.checked_sub(1).ok_or(ResumeError::Line {
+            line,
+            lines: toolpath.lines,
+        })?,
+        ResumePoint::Layer(layer) => {
+            toolpath
+                .layers
+                .get(layer)
+                .ok_or(ResumeError::Layer {
+                    layer,
+                    layers: toolpath.layers.len(),
+                })?
+                .lines
+                .start
+        }
+        ResumePoint::Z(z) => {
+            toolpath
+                .layer_at(z)
+                .ok_or(ResumeError::Z {
+                    z,
+                    max: toolpath
+                        .layers
+                        .iter()
+                        .filter_map(|layer| layer.z)
+                        .reduce(f64::max),
+                })?
+                .lines
+                .start
+        }
+    };
+    let offset = line_offset(text, line).ok_or(ResumeError::Line {
+        line: line + 1,
+        lines: toolpath.lines,
+    })?;
+    Ok(Resume {
+        line,
+        layer: toolpath
+            .layers
+            .partition_point(|layer| layer.lines.end <= line),
+        state: parallel::simulate(&String::from_utf8_lossy(&text[..offset])).final_state,
+        rest: &text[offset..],
+    })
+}
+
+/// Formats a coordinate with at most 5 decimals.
+fn number(value: f64) -> String {
+    let s = format!("{:.5}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" | "" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+impl Resume<'_> {
+    /// The commands bringing a freshly started printer into [`Resume::state`]: heating, homing
+    /// X and Y after lifting the nozzle by `lift` mm, moving back and restoring the modes, the
+    /// extruder position, the fan, the feedrate and the speed and flow factors.
+    pub fn preamble(&self, lift: f64) -> String {
+        let state = &self.state;
+        let position = [state.physical(X), state.physical(Y), state.physical(Z)];
+        let (hotend, bed) = (state.hotend_temperature, state.bed_temperature);
+        let mut out = String::new();
+        // writing to a String can't fail
+        let mut line = |text: String| {
+            let _ = writeln!(out, "{}", text);
+        };
+
+        line(format!(
+            "; resumed at line {}, layer {}, Z {}",
+            self.line + 1,
+            self.layer,
+            number(position[Z])
+        ));
+        line("G21 ; millimetres".to_string());
+        if bed > 0.0 {
+            line(format!("M140 S{}", number(bed)));
+        }
+        if hotend > 0.0 {
+            line(format!("M104 S{}", number(hotend)));
+        }
+        line(format!(
+            "G92 Z{} ; the nozzle is where the print stopped",
+            number(position[Z])
+        ));
+        line("G90".to_string());
+        line(format!(
+            "G1 Z{} F{}",
+            number(position[Z] + lift),
+            number(Z_FEEDRATE)
+        ));
+        line("G28 X Y".to_string());
+        if bed > 0.0 {
+            line(format!("M190 S{}", number(bed)));
+        }
+        if hotend > 0.0 {
+            line(format!("M109 S{}", number(hotend)));
+        }
+        // the tool change macro may move and purge, so only with X and Y homed and hot
+        if state.tool != 0 {
+            line(format!("T{}", state.tool));
+        }
+        line(format!(
+            "G1 X{} Y{} F{}",
+            number(position[X]),
+            number(position[Y]),
+            number(TRAVEL_FEEDRATE)
+        ));
+        line(format!(
+            "G1 Z{} F{}",
+            number(position[Z]),
+            number(Z_FEEDRATE)
+        ));
+
+        // back to the coordinates of the file, shifted by its own G92s
+        let mut g92 = String::from("G92");
+        for (axis, letter) in [(X, 'X'), (Y, 'Y'), (Z, 'Z')] {
+            if state.offset[axis] != 0.0 {
+                let _ = write!(g92, " {}{}", letter, number(state.position[axis]));
+            }
+        }
+        let _ = write!(g92, " E{}", number(state.position[E]));
+        line(g92);
+        line(format!("G1 F{}", number(state.feedrate)));
+        // G91 also makes E relative
+        line(if state.absolute { "G90" } else { "G91" }.to_string());
+        line(if state.absolute_e { "M82" } else { "M83" }.to_string());
+        if state.fan_speed > 0.0 {
+            line(format!("M106 S{}", number(state.fan_speed)));
+        } else {
+            line("M107".to_string());
+        }
+        line(format!("M220 S{}", number(state.speed_factor)));
+        line(format!("M221 S{}", number(state.flow)));
+        if state.inches {
+            line("G20".to_string());
+        }
+        line("; end of the resume preamble".to_string());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_restore_the_state_before_the_layer() {
+        let data: &[u8] = b"M140 S60\nM104 S200\nG28\nT1\nM83\n;LAYER:0\nG1 Z0.2 F600\n\
+                   G1 X10 Y5 E1 F1200\nM106 S128\n;LAYER:1\nM104 S210 ; 210\xb0C\nG1 Z0.4\nG1 X0 E1\n";
+        let toolpath = parallel::simulate(&String::from_utf8_lossy(data));
+
+        let resume = resume(data, &toolpath, ResumePoint::Layer(1)).unwrap();
+        assert_eq!((resume.line, resume.layer), (9, 1));
+        assert_eq!(
+            resume.rest,
+            b";LAYER:1\nM104 S210 ; 210\xb0C\nG1 Z0.4\nG1 X0 E1\n"
+        );
+        let preamble = resume.preamble(5.0);
+        for expected in [
+            "G28 X Y\nM190 S60\nM109 S200\nT1\nG1 X10",
+            "G92 Z0.2 ;",
+            "G1 Z5.2 F600\n",
+            "G1 X10 Y5 F3000\n",
+            "G92 E1\nG1 F1200\nG90\nM83\nM106 S128\n",
+        ] {
+            assert!(preamble.contains(expected), "{}", preamble);
+        }
+
+        let by_z = super::resume(data, &toolpath, ResumePoint::Z(0.3)).unwrap();
+        assert_eq!(by_z.line, 9);
+        let by_line = super::resume(data, &toolpath, ResumePoint::Line(12)).unwrap();
+        assert_eq!(
+            (by_line.layer, by_line.state.hotend_temperature),
+            (1, 210.0)
+        );
+        assert_eq!(
+            super::resume(data, &toolpath, ResumePoint::Line(14)).unwrap_err(),
+            ResumeError::Line {
+                line: 14,
+                lines: 13
+            }
+        );
+    }
+}
@@ -0,0 +1,514 @@
+//! Summary statistics of a simulated [`Toolpath`] and of the text of a file.
+
+use crate::gcode::lines::{opcode_name, parse_lines_at};
+use crate::gcode::machine::{Machine, Move, MoveKind, Observer, Toolpath, X, Y, Z};
+use crate::gcode::parallel::{split, Chunk, CHUNK_SIZE};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The feature of moves outside of any `;TYPE:` section.
+pub const NO_FEATURE: &str = "none";
+
+/// Totals of the moves of one feature type.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FeatureStats {
+    pub feature: String,
+    /// Number of extruding moves
+    pub moves: usize,
+    /// Distance extruded along in mm
+    pub distance: f64,
+    /// Filament used in mm
+    pub filament: f64,
+    /// Estimated time in seconds, including travel within the feature
+    pub time: f64,
+}
+
+/// Totals of one layer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LayerStats {
+    pub index: usize,
+    pub z: Option<f64>,
+    /// Distance to the layer below in mm, see [`Toolpath::layer_height`]
+    pub height: Option<f64>,
+    pub moves: usize,
+    /// Filament used in mm
+    pub filament: f64,
+    /// Estimated time in seconds
+    pub time: f64,
+}
+
+/// Totals of a whole file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub lines: usize,
+    pub moves: usize,
+    /// Distance travelled without extruding, in mm
+    pub travel_distance: f64,
+    /// Distance travelled while extruding, in mm
+    pub extrusion_distance: f64,
+    /// Filament used in mm, retractions that are undone cancel out
+    pub filament: f64,
+    pub retractions: usize,
+    /// Estimated print time in seconds, ignoring acceleration and heating
+    pub time: f64,
+    /// Smallest and largest X, Y and Z of the extruding moves
+    pub bounds: Option<([f64; 3], [f64; 3])>,
+    /// Highest and lowest targets in °C, heaters turned off are left out of the lowest
+    pub max_hotend_temperature: f64,
+    pub min_hotend_temperature: f64,
+    pub max_bed_temperature: f64,
+    pub min_bed_temperature: f64,
+    /// Estimated seconds of moves with the part cooling fan on
+    pub fan_time: f64,
+    /// Highest part cooling fan speed of the moves, from 0 to 255
+    pub max_fan_speed: f64,
+    /// Tools that move, in order of their first move
+    pub tools: Vec<u32>,
+    /// Number of times a move uses another tool than the previous one
+    pub tool_changes: usize,
+    /// Per feature, sorted by name
+    pub features: Vec<FeatureStats>,
+    pub layers: Vec<LayerStats>,
+}
+
+/// Moves added up by a task of the thread pool. The split doesn't depend on the number of
+/// threads, so neither do the rounding errors of the sums.
+const MOVES_PER_TASK: usize = 1 << 16;
+
+/// The lower of two temperature targets, leaving out heaters turned off.
+fn lowest(lowest: f64, target: f64) -> f64 {
+    match (lowest, target) {
+        (_, t) if t <= 0.0 => lowest,
+        (l, t) if l <= 0.0 => t,
+        (l, t) => l.min(t),
+    }
+}
+
+/// The [`Stats`] of a run of moves, added up as a [`Machine`] makes them.
+///
+/// The chunks of a file each have their own totals, merged in the order of the file.
+#[derive(Debug, Clone, Default)]
+pub struct Totals {
+    stats: Stats,
+    features: BTreeMap<String, FeatureStats>,
+    /// Tools of the first and the last move
+    tools: Option<(u32, u32)>,
+}
+
+impl Observer for Totals {
+    fn observe(&mut self, machine: &Machine, moves: Vec<Move>) {
+        let state = machine.state();
+        self.reach_layer(state.layer);
+        for m in moves.iter() {
+            self.add(m);
+        }
+        self.temperatures(state.hotend_temperature, state.bed_temperature);
+    }
+}
+
+impl Totals {
+    /// The totals of the lines following those `machine` executed.
+    pub fn starting_at(machine: &Machine) -> Self {
+        let mut totals = Totals::default();
+        totals.stats.layers.push(LayerStats {
+            index: machine.state().layer,
+            ..LayerStats::default()
+        });
+        totals
+    }
+
+    fn of(moves: &[Move]) -> Self {
+        let mut totals = Totals::default();
+        for m in moves.iter() {
+            totals.add(m);
+        }
+        totals
+    }
+
+    /// Adds empty layers up to `index`.
+    fn reach_layer(&mut self, index: usize) {
+        let layers = &mut self.stats.layers;
+        let next = layers.last().map_or(0, |layer| layer.index + 1);
+        layers.extend((next..=index).map(|index| LayerStats {
+            index,
+            ..LayerStats::default()
+        }));
+    }
+
+    fn add(&mut self, m: &Move) {
+        let kind = m.kind();
+        let feature = m.feature.as_deref().unwrap_or(NO_FEATURE);
+        if !self.features.contains_key(feature) {
+            self.features.insert(
+                feature.to_string(),
+                FeatureStats {
+                    feature: feature.to_string(),
+                    ..FeatureStats::default()
+                },
+            );
+        }
+        self.reach_layer(m.layer);
+        let entry = self.features.get_mut(feature).unwrap();
+        let stats = &mut self.stats;
+        let layer = stats.layers.last_mut().unwrap();
+        stats.moves += 1;
+        layer.moves += 1;
+        layer.filament += m.extrusion;
+        layer.time += m.duration();
+        entry.time += m.duration();
+        stats.time += m.duration();
+        stats.filament += m.extrusion;
+        if m.fan_speed > 0.0 {
+            stats.fan_time += m.duration();
+            stats.max_fan_speed = stats.max_fan_speed.max(m.fan_speed);
+        }
+
+        match kind {
+            MoveKind::Extrusion => {
+                entry.moves += 1;
+                entry.distance += m.length();
+                entry.filament += m.extrusion;
+                stats.extrusion_distance += m.length();
+                layer.z.get_or_insert(m.to[Z]);
+                let (min, max) = stats.bounds.get_or_insert((m.from, m.from));
+                for point in [m.from, m.to] {
+                    for axis in [X, Y, Z] {
+                        min[axis] = min[axis].min(point[axis]);
+                        max[axis] = max[axis].max(point[axis]);
+                    }
+                }
+            }
+            MoveKind::Travel => stats.travel_distance += m.length(),
+            MoveKind::Retraction => stats.retractions += 1,
+            MoveKind::Unretraction => {}
+        }
+
+        if let Some((_, last)) = self.tools {
+            if last != m.tool {
+                stats.tool_changes += 1;
+            }
+        }
+        if !stats.tools.contains(&m.tool) {
+            stats.tools.push(m.tool);
+        }
+        self.tools = Some((self.tools.map_or(m.tool, |(first, _)| first), m.tool));
+    }
+
+    /// Takes the temperature targets in °C into account.
+    fn temperatures(&mut self, hotend: f64, bed: f64) {
+        let stats = &mut self.stats;
+        stats.max_hotend_temperature = stats.max_hotend_temperature.max(hotend);
+        stats.max_bed_temperature = stats.max_bed_temperature.max(bed);
+        stats.min_hotend_temperature = lowest(stats.min_hotend_temperature, hotend);
+        stats.min_bed_temperature = lowest(stats.min_bed_temperature, bed);
+    }
+
+    /// Adds the totals of the moves following these.
+    pub fn merge(mut self, other: Totals) -> Self {
+        let (stats, next) = (&mut self.stats, other.stats);
+        stats.moves += next.moves;
+        stats.time += next.time;
+        stats.filament += next.filament;
+        stats.extrusion_distance += next.extrusion_distance;
+        stats.travel_distance += next.travel_distance;
+        stats.retractions += next.retractions;
+        stats.fan_time += next.fan_time;
+        stats.max_fan_speed = stats.max_fan_speed.max(next.max_fan_speed);
+        stats.bounds = match (stats.bounds, next.bounds) {
+            (Some((min, max)), Some((next_min, next_max))) => Some((
+                std::array::from_fn(|axis| min[axis].min(next_min[axis])),
+                std::array::from_fn(|axis| max[axis].max(next_max[axis])),
+            )),
+            (bounds, next) => bounds.or(next),
+        };
+        stats.max_hotend_temperature = stats
+            .max_hotend_temperature
+            .max(next.max_hotend_temperature);
+        stats.max_bed_temperature = stats.max_bed_temperature.max(next.max_bed_temperature);
+        stats.min_hotend_temperature =
+            lowest(stats.min_hotend_temperature, next.min_hotend_temperature);
+        stats.min_bed_temperature = lowest(stats.min_bed_temperature, next.min_bed_temperature);
+        stats.tool_changes += next.tool_changes;
+        if let (Some((_, last)), Some((first, _))) = (self.tools, other.tools) {
+            if last != first {
+                stats.tool_changes += 1;
+            }
+        }
+        for tool in next.tools {
+            if !stats.tools.contains(&tool) {
+                stats.tools.push(tool);
+            }
+        }
+        self.tools = match (self.tools, other.tools) {
+            (Some((first, _)), Some((_, last))) => Some((first, last)),
+            (tools, next) => tools.or(next),
+        };
+
+        let mut layers = next.layers.into_iter().peekable();
+        if let Some(layer) = layers.next_if(|layer| {
+            self.stats
+                .layers
+                .last()
+                .is_some_and(|last| last.index == layer.index)
+        }) {
+            let last = self.stats.layers.last_mut().unwrap();
+            last.z = last.z.or(layer.z);
+            last.moves += layer.moves;
+            last.filament += layer.filament;
+            last.time += layer.time;
+        }
+        if let Some(below) = layers.peek().and_then(|layer| layer.index.checked_sub(1)) {
+            self.reach_layer(below);
+        }
+        self.stats.layers.extend(layers);
+
+        for (feature, next) in other.features {
+            let entry = self.features.entry(feature).or_default();
+            entry.feature = next.feature;
+            entry.moves += next.moves;
+            entry.distance += next.distance;
+            entry.filament += next.filament;
+            entry.time += next.time;
+        }
+        self
+    }
+
+    /// The statistics of a file of `lines` lines.
+    pub fn finish(self, lines: usize) -> Stats {
+        let Totals {
+            mut stats,
+            mut features,
+            ..
+        } = self;
+        stats.lines = lines;
+        // moves that only exist to travel between features clutter the table
+        features.retain(|_, f| f.moves > 0);
+        stats.features = features.into_values().collect();
+        // like Toolpath::layer_height, from the layer below that extrudes
+        let mut below = None;
+        for layer in stats.layers.iter_mut() {
+            layer.height = layer
+                .z
+                .map(|z| z - below.unwrap_or(0.0))
+                .filter(|height| *height > 0.0);
+            below = layer.z.or(below);
+        }
+        stats
+    }
+}
+
+/// Computes the [`Stats`] of `toolpath`, on all cores.
+pub fn stats(toolpath: &Toolpath) -> Stats {
+    let totals: Vec<Totals> = toolpath
+        .moves
+        .par_chunks(MOVES_PER_TASK)
+        .map(Totals::of)
+        .collect();
+    let mut totals = totals.into_iter().fold(Totals::default(), Totals::merge);
+    if let Some(last) = toolpath.layers.last() {
+        totals.reach_layer(last.index);
+    }
+    for change in toolpath.temperatures.iter() {
+        totals.temperatures(change.hotend, change.bed);
+    }
+    totals.finish(toolpath.lines)
+}
+
+/// What the lines of a file hold, regardless of what they make the printer do.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LineCounts {
+    pub lines: usize,
+    /// Lines without commands or comments
+    pub blank_lines: usize,
+    /// Lines with comments but no commands
+    pub comment_lines: usize,
+    pub commands: usize,
+    /// Comments, including those following a command
+    pub comments: usize,
+    /// Number of times each opcode is used, e.g. `G1` or `M104`
+    pub opcodes: BTreeMap<String, usize>,
+}
+
+impl LineCounts {
+    /// Adds the counts of the lines following these.
+    pub fn merge(mut self, other: LineCounts) -> Self {
+        self.lines += other.lines;
+        self.blank_lines += other.blank_lines;
+        self.comment_lines += other.comment_lines;
+        self.commands += other.commands;
+        self.comments += other.comments;
+        for (opcode, count) in other.opcodes {
+            *self.opcodes.entry(opcode).or_default() += count;
+        }
+        self
+    }
+}
+
+/// Counts the lines, commands, comments and opcodes of `chunk`.
+pub fn count_lines_of(chunk: Chunk) -> LineCounts {
+    let mut counts = LineCounts::default();
+    for line in parse_lines_at(chunk.text, chunk.offset, chunk.first_line) {
+        counts.lines += 1;
+        let (commands, comments) = (line.gcodes().len(), line.comments().len());
+        counts.commands += commands;
+        counts.comments += comments;
+        match (commands, comments) {
+            (0, 0) => counts.blank_lines += 1,
+            (0, _) => counts.comment_lines += 1,
+            _ => {}
+        }
+        for gcode in line.gcodes() {
+            *counts.opcodes.entry(opcode_name(gcode)).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Counts the lines, commands, comments and opcodes of `text`, on all cores.
+pub fn count_lines(text: &str) -> LineCounts {
+    split(text, CHUNK_SIZE)
+        .into_par_iter()
+        .map(count_lines_of)
+        .reduce(LineCounts::default, LineCounts::merge)
+}
+
+/// Formats seconds as `1h 02m 03s`.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::{parse_lines, LineIterator};
+    use crate::gcode::machine::simulate;
+    use std::sync::Arc;
+
+    const SRC: &str = "; generated\nM140 S60\nM104 S210\n\nG1 Z0.3 F600\nG1 X10 E1 ; skirt\n\
+                       M104 S195\nM106 S128\nG1 Z0.5\nG1 X0 E2\nT1\nG1 X5 E3\nM104 S0\n";
+
+    #[test]
+    fn should_count_lines() {
+        let counts = count_lines(SRC);
+        assert_eq!(counts.lines, 13);
+        assert_eq!(counts.blank_lines, 1);
+        assert_eq!(counts.comment_lines, 1);
+        assert_eq!(counts.commands, 11);
+        assert_eq!(counts.comments, 2);
+        assert_eq!(counts.opcodes["G1"], 5);
+        assert_eq!(counts.opcodes["M104"], 3);
+        assert_eq!(counts.opcodes["T1"], 1);
+    }
+
+    #[test]
+    fn should_total_temperatures_fan_and_tools() {
+        let stats = stats(&simulate(LineIterator::new(parse_lines(SRC))));
+        assert_eq!(
+            (stats.min_hotend_temperature, stats.max_hotend_temperature),
+            (195.0, 210.0)
+        );
+        assert_eq!(
+            (stats.min_bed_temperature, stats.max_bed_temperature),
+            (60.0, 60.0)
+        );
+        assert_eq!(stats.max_fan_speed, 128.0);
+        assert!(stats.fan_time > 0.0 && stats.fan_time < stats.time);
+        assert_eq!((stats.tools.clone(), stats.tool_changes), (vec![0, 1], 1));
+        let heights: Vec<Option<f64>> = stats.layers.iter().map(|l| l.height).collect();
+        assert_eq!(heights.len(), 2);
+        assert!((heights[1].unwrap() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_merge_the_totals_of_several_tasks() {
+        let features: [Option<Arc<str>>; 3] = [None, Some("WALL".into()), Some("FILL".into())];
+        let moves: Vec<Move> = (0..MOVES_PER_TASK * 3 + 100)
+            .map(|i| {
+                let x = (i % 100) as f64;
+                let z = 0.2 + (i / 1000) as f64 * 0.2;
+                Move {
+                    line: i,
+                    from: [x, 0.0, z],
+                    to: [x + 1.0, (i % 7) as f64, z],
+                    // one move in 55 retracts, the other multiples of 5 travel
+                    extrusion: match i % 55 {
+                        0 => -0.8,
+                        n if n % 5 == 0 => 0.0,
+                        _ => 0.05,
+                    },
+                    feedrate: 1200.0 + (i % 3) as f64 * 600.0,
+                    layer: i / 1000,
+                    feature: features[(i / 5000) % 3].clone(),
+                    fan_speed: if i > MOVES_PER_TASK * 2 { 255.0 } else { 0.0 },
+                    tool: 0,
+                }
+            })
+            .collect();
+        let toolpath = Toolpath {
+            moves,
+            ..Toolpath::default()
+        };
+
+        let merged = stats(&toolpath);
+        let Totals {
+            stats: single,
+            features,
+            ..
+        } = Totals::of(&toolpath.moves);
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * b.abs().max(1.0);
+        assert_eq!(merged.moves, toolpath.moves.len());
+        assert_eq!(merged.retractions, single.retractions);
+        assert_eq!(merged.bounds, single.bounds);
+        assert_eq!(merged.max_fan_speed, 255.0);
+        for (a, b) in [
+            (merged.time, single.time),
+            (merged.filament, single.filament),
+            (merged.extrusion_distance, single.extrusion_distance),
+            (merged.travel_distance, single.travel_distance),
+            (merged.fan_time, single.fan_time),
+        ] {
+            assert!(close(a, b), "{} != {}", a, b);
+        }
+        let features: Vec<FeatureStats> = features.into_values().collect();
+        assert_eq!(merged.features.len(), 3);
+        for (a, b) in merged.features.iter().zip(features.iter()) {
+            assert_eq!((&a.feature, a.moves), (&b.feature, b.moves));
+            assert!(close(a.distance, b.distance) && close(a.time, b.time));
+            assert!(close(a.filament, b.filament));
+        }
+    }
+
+    #[test]
+    fn should_merge_the_totals_of_the_chunks_of_a_file() {
+        let mut src = String::from(SRC);
+        for i in 0..50 {
+            src.push_str(match i % 3 {
+                0 => ";TYPE:WALL\nG91\nM83\nG1 X1 Y2 E0.5\nG1 Z0.2\nG1 X-1 E0.2\nM104 S215\n",
+                1 => ";TYPE:FILL\nG90\nM82\nG92 E0\nG1 X10 Y10 E1\nT0\nG1 X12 E-0.5\n",
+                _ => "G20\nG1 X0.5 E2.1 F60\nG21\nM106 S0\nT1\nG1 Y20 E3\n;LAYER_CHANGE\n",
+            });
+        }
+        let toolpath = simulate(LineIterator::new(parse_lines(&src)));
+        let expected = stats(&toolpath);
+        assert!(expected.layers.len() > 10 && expected.tool_changes > 30);
+
+        // a chunk per line, moves are added up in the same order, so the sums are the same
+        let mut totals = Totals::default();
+        let machine = crate::gcode::parallel::fold(
+            split(&src, 1).into_iter().map(Ok::<_, ()>),
+            |_, machine| Totals::starting_at(machine),
+            |next| {
+                totals = std::mem::take(&mut totals).merge(next);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(totals.finish(machine.line() + 1), expected);
+    }
+}
@@ -0,0 +1,301 @@
+//! A flat table of the moves of a [`Toolpath`], one row per move, for spreadsheets and data
+//! frames.
+//!
+//! The rows carry everything needed to analyse a print without knowing G-code: positions are
+//! physical, extrusion is relative and includes the flow factor, and the temperatures are
+//! the targets in effect when the move starts.
+
+use crate::gcode::machine::{Machine, Move, MoveKind, Observer, Toolpath, X, Y, Z};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Names of the columns, in the order [`write_csv`] writes them.
+pub const COLUMNS: [&str; 19] = [
+    "line",
+    "layer",
+    "z_layer",
+    "feature",
+    "kind",
+    "tool",
+    "x_start",
+    "y_start",
+    "z_start",
+    "x_end",
+    "y_end",
+    "z_end",
+    "distance",
+    "extrusion",
+    "feedrate",
+    "duration",
+    "hotend_temperature",
+    "bed_temperature",
+    "fan_speed",
+];
+
+/// Digits kept after the decimal point, enough for microns and microseconds.
+const DECIMALS: i32 = 6;
+
+/// A row of the table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MoveRow<'a> {
+    /// Line of the file, starting at 1
+    pub line: usize,
+    /// Index of the layer, starting at 0
+    pub layer: usize,
+    /// Z of the first extrusion of the layer
+    pub z_layer: Option<f64>,
+    /// The feature type announced by the slicer, e.g. `WALL-OUTER`
+    pub feature: Option<&'a str>,
+    /// `travel`, `extrusion`, `retraction` or `unretraction`
+    pub kind: &'static str,
+    pub tool: u32,
+    /// Physical start and end position in mm
+    pub x_start: f64,
+    pub y_start: f64,
+    pub z_start: f64,
+    pub x_end: f64,
+    pub y_end: f64,
+    pub z_end: f64,
+    /// Distance travelled by the nozzle in mm
+    pub distance: f64,
+    /// Filament pushed through the nozzle in mm, negative when retracting
+    pub extrusion: f64,
+    /// Feedrate in mm/min including the speed factor
+    pub feedrate: f64,
+    /// Estimated seconds, ignoring acceleration
+    pub duration: f64,
+    /// Target temperatures in °C
+    pub hotend_temperature: f64,
+    pub bed_temperature: f64,
+    /// Part cooling fan speed from 0 to 255
+    pub fan_speed: f64,
+}
+
+fn round(value: f64) -> f64 {
+    let scale = 10f64.powi(DECIMALS);
+    // adding zero turns `-0` into `0`
+    (value * scale).round() / scale + 0.0
+}
+
+fn kind_name(kind: MoveKind) -> &'static str {
+    match kind {
+        MoveKind::Travel => "travel",
+        MoveKind::Extrusion => "extrusion",
+        MoveKind::Retraction => "retraction",
+        MoveKind::Unretraction => "unretraction",
+    }
+}
+
+impl<'a> MoveRow<'a> {
+    /// The row of `m`, with the temperature targets `(hotend, bed)` in effect.
+    pub fn new(m: &'a Move, z_layer: Option<f64>, temperatures: (f64, f64)) -> Self {
+        Self {
+            line: m.line + 1,
+            layer: m.layer,
+            z_layer: z_layer.map(round),
+            feature: m.feature.as_deref(),
+            kind: kind_name(m.kind()),
+            tool: m.tool,
+            x_start: round(m.from[X]),
+            y_start: round(m.from[Y]),
+            z_start: round(m.from[Z]),
+            x_end: round(m.to[X]),
+            y_end: round(m.to[Y]),
+            z_end: round(m.to[Z]),
+            distance: round(m.length()),
+            extrusion: round(m.extrusion),
+            feedrate: round(m.feedrate),
+            duration: round(m.duration()),
+            hotend_temperature: temperatures.0,
+            bed_temperature: temperatures.1,
+            fan_speed: m.fan_speed,
+        }
+    }
+}
+
+/// The rows of all moves of `toolpath`, in order.
+pub fn rows(toolpath: &Toolpath) -> impl Iterator<Item = MoveRow<'_>> {
+    let mut changes = toolpath.temperatures.iter().peekable();
+    let mut temperatures = (0.0, 0.0);
+    toolpath.moves.iter().map(move |m| {
+        // changes are recorded on the line setting them, which never moves
+        while let Some(change) = changes.next_if(|change| change.line < m.line) {
+            temperatures = (change.hotend, change.bed);
+        }
+        let z_layer = toolpath.layers.get(m.layer).and_then(|layer| layer.z);
+        MoveRow::new(m, z_layer, temperatures)
+    })
+}
+
+/// Quotes `field` if it holds a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_number(value: Option<f64>) -> String {
+    value.map_or_else(String::new, |v| v.to_string())
+}
+
+/// Writes `row` as a line of CSV, see [`write_csv`].
+pub fn write_csv_row<W: Write>(out: &mut W, row: &MoveRow) -> io::Result<()> {
+    writeln!(
+        out,
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        row.line,
+        row.layer,
+        csv_number(row.z_layer),
+        row.feature.map(csv_field).unwrap_or_default(),
+        row.kind,
+        row.tool,
+        row.x_start,
+        row.y_start,
+        row.z_start,
+        row.x_end,
+        row.y_end,
+        row.z_end,
+        row.distance,
+        row.extrusion,
+        row.feedrate,
+        row.duration,
+        row.hotend_temperature,
+        row.bed_temperature,
+        row.fan_speed,
+    )
+}
+
+/// Writes the table of `toolpath` as CSV with a header row. Missing values are empty fields.
+pub fn write_csv<W: Write>(out: &mut W, toolpath: &Toolpath) -> io::Result<()> {
+    writeln!(out, "{}", COLUMNS.join(","))?;
+    for row in rows(toolpath) {
+        write_csv_row(out, &row)?;
+    }
+    Ok(())
+}
+
+/// The moves of a chunk of a file, with the temperature targets `(hotend, bed)` in effect.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMoves(pub Vec<(Move, (f64, f64))>);
+
+impl Observer for ChunkMoves {
+    fn observe(&mut self, machine: &Machine, moves: Vec<Move>) {
+        // lines setting temperatures never move, the targets are those of the lines before
+        let state = machine.state();
+        let temperatures = (state.hotend_temperature, state.bed_temperature);
+        self.0.extend(moves.into_iter().map(|m| (m, temperatures)));
+    }
+}
+
+/// Makes the rows of the moves of a file as they come, like [`rows`] without the toolpath.
+///
+/// The moves of a layer are held back until its first extrusion gives the Z of the layer.
+#[derive(Debug, Clone, Default)]
+pub struct Rows {
+    layer: usize,
+    z: Option<f64>,
+    held: Vec<(Move, (f64, f64))>,
+}
+
+impl Rows {
+    /// Hands the rows of the moves before `m` and of `m` itself to `write`, as soon as their
+    /// layer is known.
+    pub fn push(
+        &mut self,
+        m: Move,
+        temperatures: (f64, f64),
+        write: &mut impl FnMut(MoveRow) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if m.layer != self.layer {
+            self.flush(write)?;
+            self.layer = m.layer;
+            self.z = None;
+        }
+        if self.z.is_none() && m.is_extrusion() {
+            self.z = Some(m.to[Z]);
+        }
+        self.held.push((m, temperatures));
+        if self.z.is_some() {
+            self.flush(write)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, write: &mut impl FnMut(MoveRow) -> io::Result<()>) -> io::Result<()> {
+        for (m, temperatures) in self.held.drain(..) {
+            write(MoveRow::new(&m, self.z, temperatures))?;
+        }
+        Ok(())
+    }
+
+    /// Hands the rows of the moves held back to `write`, after the last move of the file.
+    pub fn finish(mut self, write: &mut impl FnMut(MoveRow) -> io::Result<()>) -> io::Result<()> {
+        self.flush(write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::{parse_lines, LineIterator};
+    use crate::gcode::machine::simulate;
+    use crate::gcode::parallel::{fold, split};
+
+    #[test]
+    fn should_write_a_row_per_move() {
+        let src = "M140 S60\nM104 S200\n;TYPE:Skirt, brim\nG1 Z0.2 F600\nM104 S210\n\
+                   G1 X10 E0.5 F1200\nG1 E-0.8\n";
+        let toolpath = simulate(LineIterator::new(parse_lines(src)));
+        let mut out = vec![];
+        write_csv(&mut out, &toolpath).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].split(',').count(), COLUMNS.len());
+        assert_eq!(
+            lines[1],
+            "4,0,0.2,\"Skirt, brim\",travel,0,0,0,0,0,0,0.2,0.2,0,600,0.02,200,60,0"
+        );
+        assert_eq!(
+            lines[2],
+            "6,0,0.2,\"Skirt, brim\",extrusion,0,0,0,0.2,10,0,0.2,10,0.5,1200,0.5,210,60,0"
+        );
+        assert!(lines[3].contains(",retraction,"));
+        assert!(lines[3].contains(",-1.3,"));
+    }
+
+    #[test]
+    fn should_make_the_rows_of_the_chunks_of_a_file_like_those_of_the_toolpath() {
+        let src = "M140 S60\nG1 Z5 F600\nG1 X5\nM104 S200\n;LAYER_CHANGE\nG1 Z0.2\nG1 X0\n\
+                   G1 X10 E0.5\nM104 S210\nG1 E-0.8\n;LAYER_CHANGE\nG1 Z0.4\nG1 E0.8\n\
+                   G1 X0 Y5 E0.5\n;LAYER_CHANGE\nG1 Z10\nG1 X0 Y0\n";
+        let toolpath = simulate(LineIterator::new(parse_lines(src)));
+        let mut expected = vec![];
+        for row in rows(&toolpath) {
+            write_csv_row(&mut expected, &row).unwrap();
+        }
+
+        // a chunk per line, the moves before the first extrusion of a layer wait for its Z
+        let mut out = vec![];
+        let mut write = |row: MoveRow| write_csv_row(&mut out, &row);
+        let mut table = Rows::default();
+        fold(
+            split(src, 1).into_iter().map(Ok),
+            |_, _| ChunkMoves::default(),
+            |chunk| {
+                chunk
+                    .0
+                    .into_iter()
+                    .try_for_each(|(m, temperatures)| table.push(m, temperatures, &mut write))
+            },
+        )
+        .unwrap();
+        table.finish(&mut write).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Preview images that slicers embed in comments.
+//!
+//! PrusaSlicer, SuperSlicer and Cura write them as base64 between two comments, one line of
+//! base64 per comment:
+//!
+//! ```text
+//! ; thumbnail begin 16x16 1234
+//! ; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf8/9hAAAAAXNSR0IArs4c6QAAAARnQU1BAACx
+//! ; ...
+//! ; thumbnail end
+//! ```
+//!
+//! `thumbnail` blocks are PNG, `thumbnail_JPG` and `thumbnail_QOI` blocks are JPEG and QOI.
+
+use crate::render::raster::Image;
+use crate::render::Rgb;
+use std::fmt;
+use std::fmt::Display;
+use std::ops::Range;
+
+/// The encoding of a thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    /// The format of the `thumbnail_XXX` comment suffix, `None` for unknown ones.
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix.to_ascii_uppercase().as_str() {
+            "" | "PNG" => Some(ThumbnailFormat::Png),
+            "JPG" | "JPEG" => Some(ThumbnailFormat::Jpg),
+            "QOI" => Some(ThumbnailFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// The file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Jpg => "jpg",
+            ThumbnailFormat::Qoi => "qoi",
+        }
+    }
+}
+
+impl Display for ThumbnailFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ThumbnailFormat::Png => "PNG",
+            ThumbnailFormat::Jpg => "JPG",
+            ThumbnailFormat::Qoi => "QOI",
+        })
+    }
+}
+
+/// A thumbnail block of a G-code file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub format: ThumbnailFormat,
+    /// Size declared by the begin comment
+    pub width: u32,
+    pub height: u32,
+    /// Lines of the block including the begin and end comments, starting at 0
+    pub lines: Range<usize>,
+    /// Length of the base64 declared by the begin comment
+    pub length: usize,
+    /// The base64 of the image, without the comment markers
+    pub base64: String,
+}
+
+/// Everything that can go wrong while decoding a thumbnail.
+#[derive(Debug)]
+pub enum ThumbnailError {
+    /// The base64 is invalid, usually because the block was cut short.
+    Base64(base64::DecodeError),
+    Png(png::DecodingError),
+    Jpg(jpeg_decoder::Error),
+    Qoi(qoi::Error),
+    /// The image uses a colour type we don't convert.
+    Unsupported {
+        format: ThumbnailFormat,
+    },
+}
+
+impl Display for ThumbnailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbnailError::Base64(err) => write!(f, "invalid base64: {}", err),
+            ThumbnailError::Png(err) => write!(f, "invalid PNG: {}", err),
+            ThumbnailError::Jpg(err) => write!(f, "invalid JPG: {}", err),
+            ThumbnailError::Qoi(err) => write!(f, "invalid QOI: {}", err),
+            ThumbnailError::Unsupported { format } => {
+                write!(f, "unsupported {} colour type", format)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ThumbnailError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ThumbnailError::Base64(err) => Some(err),
+            ThumbnailError::Png(err) => Some(err),
+            ThumbnailError::Jpg(err) => Some(err),
+            ThumbnailError::Qoi(err) => Some(err),
+            ThumbnailError::Unsupported { .. } => None,
+        }
+    }
+}
+
+impl Thumbnail {
+    /// Whether the block has as much base64 as its begin comment announced.
+    pub fn is_complete(&self) -> bool {
+        self.base64.len() == self.length
+    }
+
+    /// The encoded image.
+    pub fn data(&self) -> Result<Vec<u8>, ThumbnailError> {
+        base64::decode(&self.base64).map_err(ThumbnailError::Base64)
+    }
+
+    /// Decodes the image, blending transparent pixels with `background`.
+    pub fn decode(&self, background: Rgb) -> Result<Image, ThumbnailError> {
+        let data = self.data()?;
+        // slicers have mislabelled blocks before, trust the signature over the comment
+        let format = if data.starts_with(b"\x89PNG") {
+            ThumbnailFormat::Png
+        } else if data.starts_with(&[0xff, 0xd8]) {
+            ThumbnailFormat::Jpg
+        } else if data.starts_with(b"qoif") {
+            ThumbnailFormat::Qoi
+        } else {
+            self.format
+        };
+        let (width, height, channels, pixels) = match format {
+            ThumbnailFormat::Png => decode_png(&data)?,
+            ThumbnailFormat::Jpg => {
+                let mut decoder = jpeg_decoder::Decoder::new(data.as_slice());
+                let pixels = decoder.decode().map_err(ThumbnailError::Jpg)?;
+                let info = decoder
+                    .info()
+                    .ok_or(ThumbnailError::Unsupported { format })?;
+                let channels = match info.pixel_format {
+                    jpeg_decoder::PixelFormat::L8 => 1,
+                    jpeg_decoder::PixelFormat::RGB24 => 3,
+                    _ => return Err(ThumbnailError::Unsupported { format }),
+                };
+                (info.width as u32, info.height as u32, channels, pixels)
+            }
+            ThumbnailFormat::Qoi => {
+                let (header, pixels) = qoi::decode_to_vec(&data).map_err(ThumbnailError::Qoi)?;
+                (
+                    header.width,
+                    header.height,
+                    header.channels.as_u8() as usize,
+                    pixels,
+                )
+            }
+        };
+        Ok(blend(width, height, channels, &pixels, background))
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<(u32, u32, usize, Vec<u8>), ThumbnailError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(ThumbnailError::Png)?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .map_err(ThumbnailError::Png)?;
+    pixels.truncate(info.buffer_size());
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err(ThumbnailError::Unsupported {
+                format: ThumbnailFormat::Png,
+            })
+        }
+    };
+    Ok((info.width, info.height, channels, pixels))
+}
+
+/// Converts grey, grey and alpha, RGB or RGBA `pixels` to an RGB image.
+fn blend(width: u32, height: u32, channels: usize, pixels: &[u8], background: Rgb) -> Image {
+    let mut image = Image::new(width, height, background);
+    let background = [background.0, background.1, background.2];
+    for (out, pixel) in image
+        .pixels
+        .chunks_exact_mut(3)
+        .zip(pixels.chunks_exact(channels))
+    {
+        let (color, alpha) = match *pixel {
+            [l] => ([l, l, l], 255),
+            [l, a] => ([l, l, l], a),
+            [r, g, b] => ([r, g, b], 255),
+            [r, g, b, a] => ([r, g, b], a),
+            _ => continue,
+        };
+        for i in 0..3 {
+            let value =
+                color[i] as u32 * alpha as u32 + background[i] as u32 * (255 - alpha as u32);
+            out[i] = (value / 255) as u8;
+        }
+    }
+    image
+}
+
+/// Parses a begin comment like `thumbnail_JPG begin 300x300 12345` into the format, size and
+/// base64 length.
+fn parse_begin(comment: &str) -> Option<(ThumbnailFormat, u32, u32, usize)> {
+    let mut words = comment.split_whitespace();
+    let format = ThumbnailFormat::from_suffix(
+        words
+            .next()?
+            .strip_prefix("thumbnail")?
+            .trim_start_matches('_'),
+    )?;
+    if words.next()? != "begin" {
+        return None;
+    }
+    let (width, height) = words.next()?.split_once('x')?;
+    let length = words.next()?.parse().ok()?;
+    Some((format, width.parse().ok()?, height.parse().ok()?, length))
+}
+
+/// Finds the thumbnail blocks of `src`.
+///
+/// A block without end comment stops at the first line that isn't a comment.
+pub fn find_thumbnails(src: &str) -> Vec<Thumbnail> {
+    let mut thumbnails = Vec::new();
+    let mut current: Option<Thumbnail> = None;
+
+    for (number, line) in src.lines().enumerate() {
+        let comment = line.trim().strip_prefix(';').map(str::trim);
+        if let Some(thumbnail) = current.as_mut() {
+            match comment {
+                Some(comment) if comment.starts_with("thumbnail") && comment.ends_with(" end") => {
+                    thumbnail.lines.end = number + 1;
+                    thumbnails.extend(current.take());
+                }
+                Some(comment) => {
+                    thumbnail.base64.push_str(comment);
+                    thumbnail.lines.end = number + 1;
+                }
+                None => thumbnails.extend(current.take()),
+            }
+            if current.is_some() {
+                continue;
+            }
+        }
+        if let Some((format, width, height, length)) = comment.and_then(parse_begin) {
+            current = Some(Thumbnail {
+                format,
+                width,
+                height,
+                lines: number..number + 1,
+                length,
+                base64: String::with_capacity(length),
+            });
+        }
+    }
+    thumbnails.extend(current);
+    thumbnails
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_and_decode_thumbnails() {
+        let red = Rgb(0xff, 0, 0);
+        let png = base64::encode(Image::new(3, 2, red).encode_png().unwrap());
+        let mut src = format!(
+            "; generated by test\n;\n; thumbnail begin 3x2 {}\n",
+            png.len()
+        );
+        for chunk in png.as_bytes().chunks(20) {
+            src.push_str(&format!("; {}\n", std::str::from_utf8(chunk).unwrap()));
+        }
+        src.push_str("; thumbnail end\n;\n; thumbnail_QOI begin 8x8 100\n; cXVvaWY=\nG28\n");
+
+        let thumbnails = find_thumbnails(&src);
+        assert_eq!(thumbnails.len(), 2);
+
+        let first = &thumbnails[0];
+        let lines = src.lines().count() - 4;
+        assert_eq!(first.lines, 2..lines);
+        assert!(first.is_complete());
+        let image = first.decode(Rgb(0, 0, 0)).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixel(2, 1), red);
+
+        // the second block is cut short by a command
+        let second = &thumbnails[1];
+        assert_eq!(second.format, ThumbnailFormat::Qoi);
+        assert_eq!(second.lines, lines + 1..lines + 3);
+        assert!(!second.is_complete());
+        assert!(second.decode(Rgb(0, 0, 0)).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+//! Extrusion widths inferred from what the moves extrude, compared with the other moves of
+//! the same feature.
+//!
+//! Slicers state the line widths they plan in their settings, but the printer only sees
+//! lengths of filament. Given the filament diameter and the height of the layer, the width of
+//! a line follows from the filament it uses per mm, see [`Move::extrusion_width`]. Within a
+//! feature type the widths should barely vary, so moves far from the median of their feature
+//! are over- or under-extruded.
+
+use crate::gcode::machine::{Machine, Move, Observer, Toolpath, Z};
+use crate::gcode::stats::NO_FEATURE;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Deviation from the median of the feature beyond which a move is an outlier, as a fraction.
+pub const DEFAULT_TOLERANCE: f64 = 0.2;
+
+/// The widths of the moves of a toolpath and the median of each feature.
+#[derive(Debug, Clone, Default)]
+pub struct WidthModel {
+    filament_area: f64,
+    /// Z of the first extrusion of each layer
+    layer_z: Vec<Option<f64>>,
+    layer_heights: Vec<Option<f64>>,
+    medians: HashMap<String, f64>,
+}
+
+/// Widths are counted in steps of 0.1 µm, so that the medians don't need every width.
+const WIDTH_STEP: f64 = 1e-4;
+
+/// The median of the values counted by `counts`, in steps of [`WIDTH_STEP`].
+fn median(counts: &BTreeMap<i64, usize>) -> Option<f64> {
+    let total: usize = counts.values().sum();
+    if total == 0 {
+        return None;
+    }
+    // the values at the positions (total - 1) / 2 and total / 2 of the sorted values
+    let nth = |position: usize| {
+        let mut seen = 0;
+        counts
+            .iter()
+            .find(|(_, count)| {
+                seen += **count;
+                seen > position
+            })
+            .map(|(value, _)| *value as f64 * WIDTH_STEP)
+    };
+    Some((nth((total - 1) / 2)? + nth(total / 2)?) / 2.0)
+}
+
+/// Cross-section of a filament of `diameter` mm, in mm².
+pub fn filament_area(diameter: f64) -> f64 {
+    std::f64::consts::PI * (diameter / 2.0).powi(2)
+}
+
+/// The height of the layer of each move of a chunk, from the Z of the first extrusion of
+/// the layers, see [`Toolpath::layer_height`].
+#[derive(Debug, Clone, Default)]
+struct Heights {
+    layer: usize,
+    z: Option<f64>,
+    below: f64,
+}
+
+impl Heights {
+    fn starting_at(machine: &Machine) -> Self {
+        Heights {
+            layer: machine.state().layer,
+            z: machine.layer_z(),
+            below: machine.below_z().unwrap_or(0.0),
+        }
+    }
+
+    /// The height of the layer of `m`, which follows the moves already seen.
+    fn of(&mut self, m: &Move) -> Option<f64> {
+        if m.layer > self.layer {
+            self.layer = m.layer;
+            if let Some(z) = self.z.take() {
+                self.below = z;
+            }
+        }
+        if m.is_extrusion() {
+            self.z.get_or_insert(m.to[Z]);
+        }
+        Some(self.z? - self.below).filter(|height| *height > 0.0)
+    }
+}
+
+/// The widths of the moves of a file counted a chunk at a time, to make a [`WidthModel`].
+#[derive(Debug, Clone, Default)]
+pub struct WidthSamples {
+    filament_area: f64,
+    heights: Heights,
+    /// Z of the first extrusion of the layers starting in the chunk
+    layer_z: BTreeMap<usize, f64>,
+    /// Per feature, how many moves are of each width
+    widths: HashMap<String, BTreeMap<i64, usize>>,
+}
+
+impl WidthSamples {
+    /// The widths of a file from its first line, for a filament of `filament_area` mm².
+    pub fn new(filament_area: f64) -> Self {
+        WidthSamples {
+            filament_area,
+            ..Self::default()
+        }
+    }
+
+    /// The widths of the lines following those `machine` executed.
+    pub fn starting_at(machine: &Machine, filament_area: f64) -> Self {
+        WidthSamples {
+            filament_area,
+            heights: Heights::starting_at(machine),
+            ..Self::default()
+        }
+    }
+
+    fn add(&mut self, m: &Move) {
+        let height = self.heights.of(m);
+        if m.is_extrusion() {
+            self.layer_z.entry(m.layer).or_insert(m.to[Z]);
+        }
+        let Some(width) = height.and_then(|height| m.extrusion_width(self.filament_area, height))
+        else {
+            return;
+        };
+        if !self.widths.contains_key(feature_name(m)) {
+            self.widths
+                .insert(feature_name(m).to_string(), BTreeMap::new());
+        }
+        let counts = self.widths.get_mut(feature_name(m)).unwrap();
+        *counts
+            .entry((width / WIDTH_STEP).round() as i64)
+            .or_default() += 1;
+    }
+
+    /// Adds the widths of the moves after those of `self`.
+    pub fn merge(mut self, next: WidthSamples) -> WidthSamples {
+        for (layer, z) in next.layer_z {
+            self.layer_z.entry(layer).or_insert(z);
+        }
+        for (feature, counts) in next.widths {
+            let into = self.widths.entry(feature).or_default();
+            for (width, count) in counts {
+                *into.entry(width).or_default() += count;
+            }
+        }
+        self
+    }
+
+    /// The model of a file of `layers` layers.
+    pub fn finish(self, layers: usize) -> WidthModel {
+        let layer_z: Vec<Option<f64>> = (0..layers)
+            .map(|index| self.layer_z.get(&index).copied())
+            .collect();
+        let layer_heights = (0..layers)
+            .map(|index| {
+                let z = layer_z[index]?;
+                let below = layer_z[..index]
+                    .iter()
+                    .rev()
+                    .find_map(|z| *z)
+                    .unwrap_or(0.0);
+                Some(z - below).filter(|height| *height > 0.0)
+            })
+            .collect();
+        WidthModel {
+            filament_area: self.filament_area,
+            layer_z,
+            layer_heights,
+            medians: self
+                .widths
+                .iter()
+                .filter_map(|(feature, counts)| Some((feature.clone(), median(counts)?)))
+                .collect(),
+        }
+    }
+}
+
+impl Observer for WidthSamples {
+    fn observe(&mut self, _: &Machine, moves: Vec<Move>) {
+        moves.iter().for_each(|m| self.add(m));
+    }
+}
+
+impl WidthModel {
+    /// Infers the widths of the moves of `toolpath` for a filament of `filament_area` mm².
+    ///
+    /// Medians are rounded to 0.1 µm.
+    pub fn new(toolpath: &Toolpath, filament_area: f64) -> Self {
+        let mut samples = WidthSamples::new(filament_area);
+        toolpath.moves.iter().for_each(|m| samples.add(m));
+        samples.finish(toolpath.layers.len())
+    }
+
+    /// Width of the line extruded by `m` in mm, `None` for moves that don't extrude or
+    /// layers of unknown height.
+    pub fn width(&self, m: &Move) -> Option<f64> {
+        let height = self.layer_heights.get(m.layer).copied().flatten()?;
+        m.extrusion_width(self.filament_area, height)
+    }
+
+    /// Median width of the moves of `feature`, [`NO_FEATURE`] for moves outside any.
+    pub fn median(&self, feature: &str) -> Option<f64> {
+        self.medians.get(feature).copied()
+    }
+
+    /// Width of `m` relative to the median of its feature, `0.1` for 10% wider.
+    pub fn deviation(&self, m: &Move) -> Option<f64> {
+        let median = self
+            .median(feature_name(m))
+            .filter(|median| *median > 0.0)?;
+        Some(self.width(m)? / median - 1.0)
+    }
+
+    /// Height of the layer `index` in mm, see [`Toolpath::layer_height`].
+    pub fn layer_height(&self, index: usize) -> Option<f64> {
+        self.layer_heights.get(index).copied().flatten()
+    }
+}
+
+fn feature_name(m: &Move) -> &str {
+    m.feature.as_deref().unwrap_or(NO_FEATURE)
+}
+
+/// The widths of one feature type.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeatureWidth {
+    pub feature: String,
+    /// Extruding moves of known width
+    pub moves: usize,
+    /// Widths in mm
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Moves wider or narrower than the median by more than the tolerance
+    pub over: usize,
+    pub under: usize,
+}
+
+/// The height of a layer, inferred from the Z of its first extrusion and of the layer below.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerHeight {
+    pub index: usize,
+    pub z: Option<f64>,
+    pub height: Option<f64>,
+}
+
+/// An extruding move far from the median width of its feature.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WidthOutlier {
+    /// Line of the file, starting at 1
+    pub line: usize,
+    pub layer: usize,
+    pub feature: String,
+    /// Width of the move and median of the feature, in mm
+    pub width: f64,
+    pub median: f64,
+    /// `0.25` for 25% wider than the median, negative when narrower
+    pub deviation: f64,
+}
+
+/// The widths of a whole file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WidthReport {
+    pub filament_diameter: f64,
+    pub tolerance: f64,
+    /// Per feature, sorted by name
+    pub features: Vec<FeatureWidth>,
+    pub layers: Vec<LayerHeight>,
+    /// In the order of the file
+    pub outliers: Vec<WidthOutlier>,
+}
+
+/// The widths of the moves of a file compared with a [`WidthModel`] of it, a chunk at a time.
+#[derive(Debug, Clone)]
+pub struct WidthTotals<'a> {
+    model: &'a WidthModel,
+    tolerance: f64,
+    features: BTreeMap<String, FeatureWidth>,
+    /// In the order of the file
+    outliers: Vec<WidthOutlier>,
+}
+
+impl<'a> WidthTotals<'a> {
+    /// Moves deviating from the median of their feature in `model` by more than `tolerance`
+    /// are outliers.
+    pub fn new(model: &'a WidthModel, tolerance: f64) -> Self {
+        WidthTotals {
+            model,
+            tolerance,
+            features: BTreeMap::new(),
+            outliers: vec![],
+        }
+    }
+
+    fn add(&mut self, m: &Move) {
+        let model = self.model;
+        let (Some(width), Some(deviation)) = (model.width(m), model.deviation(m)) else {
+            return;
+        };
+        let feature = feature_name(m);
+        let median = model.median(feature).unwrap_or(width);
+        if !self.features.contains_key(feature) {
+            self.features.insert(
+                feature.to_string(),
+                FeatureWidth {
+                    feature: feature.to_string(),
+                    moves: 0,
+                    median,
+                    min: width,
+                    max: width,
+                    over: 0,
+                    under: 0,
+                },
+            );
+        }
+        let entry = self.features.get_mut(feature).unwrap();
+        entry.moves += 1;
+        entry.min = entry.min.min(width);
+        entry.max = entry.max.max(width);
+        if deviation.abs() > self.tolerance {
+            if deviation > 0.0 {
+                entry.over += 1;
+            } else {
+                entry.under += 1;
+            }
+            self.outliers.push(WidthOutlier {
+                line: m.line + 1,
+                layer: m.layer,
+                feature: feature.to_string(),
+                width,
+                median,
+                deviation,
+            });
+        }
+    }
+
+    /// Adds what was found in the moves after those of `self`.
+    pub fn merge(mut self, next: WidthTotals) -> Self {
+        for (feature, width) in next.features {
+            match self.features.get_mut(&feature) {
+                Some(into) => {
+                    into.moves += width.moves;
+                    into.min = into.min.min(width.min);
+                    into.max = into.max.max(width.max);
+                    into.over += width.over;
+                    into.under += width.under;
+                }
+                None => {
+                    self.features.insert(feature, width);
+                }
+            }
+        }
+        self.outliers.extend(next.outliers);
+        self
+    }
+
+    /// The report of a filament of `filament_diameter` mm.
+    pub fn finish(self, filament_diameter: f64) -> WidthReport {
+        let model = self.model;
+        WidthReport {
+            filament_diameter,
+            tolerance: self.tolerance,
+            features: self.features.into_values().collect(),
+            layers: (0..model.layer_z.len())
+                .map(|index| LayerHeight {
+                    index,
+                    z: model.layer_z[index],
+                    height: model.layer_height(index),
+                })
+                .collect(),
+            outliers: self.outliers,
+        }
+    }
+}
+
+impl Observer for WidthTotals<'_> {
+    fn observe(&mut self, _: &Machine, moves: Vec<Move>) {
+        moves.iter().for_each(|m| self.add(m));
+    }
+}
+
+/// Infers the widths of the moves of `toolpath` for a filament of `filament_diameter` mm, and
+/// finds the moves deviating from the median of their feature by more than `tolerance`.
+pub fn width_report(toolpath: &Toolpath, filament_diameter: f64, tolerance: f64) -> WidthReport {
+    let model = WidthModel::new(toolpath, filament_area(filament_diameter));
+    let mut totals = WidthTotals::new(&model, tolerance);
+    toolpath.moves.iter().for_each(|m| totals.add(m));
+    totals.finish(filament_diameter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::lines::{parse_lines, LineIterator};
+    use crate::gcode::machine::simulate;
+    use crate::gcode::parallel::{fold, split};
+
+    #[test]
+    fn should_find_outliers_relative_to_the_feature_median() {
+        // 0.2 mm layers of 0.45 mm lines take 0.0333 mm of 1.75 mm filament per mm
+        let src = "M83\n;TYPE:WALL-OUTER\nG1 Z0.2\nG1 X10 E0.333\nG1 Y10 E0.333\nG1 X0 E0.333\n\
+                   G1 Y0 E0.5\nG1 X10 E0.2\n;TYPE:FILL\nG1 X20 E0.4\n";
+        let toolpath = simulate(LineIterator::new(parse_lines(src)));
+        let report = width_report(&toolpath, 1.75, DEFAULT_TOLERANCE);
+
+        let wall = &report.features[1];
+        assert_eq!((wall.feature.as_str(), wall.moves), ("WALL-OUTER", 5));
+        assert!((wall.median - 0.45).abs() < 0.01, "{}", wall.median);
+        assert_eq!((wall.over, wall.under), (1, 1));
+        assert_eq!(report.features[0].over + report.features[0].under, 0);
+
+        let outliers: Vec<(usize, bool)> = report
+            .outliers
+            .iter()
+            .map(|o| (o.line, o.deviation > 0.0))
+            .collect();
+        assert_eq!(outliers, vec![(7, true), (8, false)]);
+        assert_eq!(report.layers[0].height, Some(0.2));
+        let counts = BTreeMap::from([(4, 1), (1, 1), (3, 1), (2, 1)]);
+        assert!((median(&counts).unwrap() - 2.5 * WIDTH_STEP).abs() < 1e-12);
+    }
+
+    #[test]
+    fn should_measure_the_chunks_of_a_file_like_the_whole_file() {
+        let mut src = String::from("M83\n");
+        for i in 0..20 {
+            let z = 0.2 * (i + 1) as f64;
+            src.push_str(&format!(
+                ";LAYER_CHANGE\nG1 Z{z:.1}\n;TYPE:WALL\nG1 X10 E0.33\nG1 Y10 E0.{}\n\
+                 ;TYPE:FILL\nG1 X0 E0.4\nG1 Y0 E0.2\n",
+                30 + i % 7
+            ));
+        }
+        let toolpath = simulate(LineIterator::new(parse_lines(&src)));
+        let expected = width_report(&toolpath, 1.75, DEFAULT_TOLERANCE);
+        assert_eq!(expected.layers.len(), 20);
+
+        // a chunk per line, the heights of the layers are handed over from the machine
+        let area = filament_area(1.75);
+        let mut samples = WidthSamples::new(area);
+        let machine = fold(
+            split(&src, 1).into_iter().map(Ok::<_, ()>),
+            |_, machine| WidthSamples::starting_at(machine, area),
+            |next| {
+                samples = std::mem::take(&mut samples).merge(next);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let model = samples.finish(machine.state().layer + 1);
+        let mut totals = WidthTotals::new(&model, DEFAULT_TOLERANCE);
+        fold(
+            split(&src, 1).into_iter().map(Ok::<_, ()>),
+            |_, _| WidthTotals::new(&model, DEFAULT_TOLERANCE),
+            |next| {
+                totals = totals.clone().merge(next);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(totals.finish(1.75), expected);
+    }
+}
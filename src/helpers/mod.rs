@@ -8,14 +8,19 @@ pub trait PopIf {
     ///
     /// # Examples
     ///
+    /// Note that `Vec` has an inherent `pop_if` (which looks at the last element) that shadows
+    /// this method, so call it through the trait.
+    ///
     /// ```
+    /// use gcode_viz::helpers::PopIf;
+    ///
     /// let mut x: Vec<u32> = vec![0, 1, 2, 3];
     ///
-    /// let e = x.pop_if(|x| *x < 2u32);
+    /// let e = PopIf::pop_if(&mut x, |x| *x < 2u32);
     /// assert_eq!(e, Some(0));
-    /// let e = x.pop_if(|x| *x < 2u32);
+    /// let e = PopIf::pop_if(&mut x, |x| *x < 2u32);
     /// assert_eq!(e, Some(1));
-    /// let e = x.pop_if(|x| *x < 2u32);
+    /// let e = PopIf::pop_if(&mut x, |x| *x < 2u32);
     /// assert_eq!(e, None);
     /// ```
     fn pop_if<F>(&mut self, f: F) -> Option<Self::Item>
@@ -40,7 +45,7 @@ impl<A> PopIf for Vec<A> {
     where
         F: FnOnce(&Self::Item) -> bool,
     {
-        self.get(0).filter(|x| f(*x))?;
-        self.pop()
+        self.first().filter(|x| f(*x))?;
+        Some(self.remove(0))
     }
 }
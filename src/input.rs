@@ -0,0 +1,771 @@
+//! Reading G-code files whatever their encoding.
+//!
+//! Binary G-code (`.bgcode`), zip containers (`.gcode.3mf`, `.ufp`) and gzip, zstd or xz
+//! compressed files are recognised by their magic bytes rather than their extension and
+//! converted to text, so that every command works on all of them. The file `-` is the standard
+//! input.
+
+use crate::gcode::bgcode::{is_bgcode, Bgcode, BgcodeError};
+use crate::gcode::container::{is_zip, Container, ContainerError, Plate};
+use crate::gcode::parallel::{read_chunks, ChunkBuf, CHUNK_SIZE};
+use crate::gcode::thumbnails::Thumbnail;
+use memmap2::Mmap;
+use ruzstd::frame_decoder::FrameDecoder;
+use ruzstd::streaming_decoder::StreamingDecoder;
+use std::borrow::Cow;
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+/// Everything that can go wrong while reading a G-code file.
+#[derive(Debug)]
+pub enum InputError {
+    /// The file could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file is binary G-code, but broken.
+    Bgcode { path: PathBuf, source: BgcodeError },
+    /// The file is a zip container, but broken or without the requested plate.
+    Container {
+        path: PathBuf,
+        source: ContainerError,
+    },
+    /// The file is compressed, but broken.
+    Decompress {
+        path: PathBuf,
+        compression: Compression,
+        source: std::io::Error,
+    },
+    /// The file is binary, but none of the formats above.
+    NotGcode { path: PathBuf },
+    /// A plate was asked of a file that isn't a container.
+    NotContainer { path: PathBuf, plate: usize },
+}
+
+/// The compression formats of archived G-code files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        })
+    }
+}
+
+impl Compression {
+    /// The compression of `data`, from its magic bytes.
+    pub fn detect(data: &[u8]) -> Option<Compression> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if data.starts_with(b"\xfd7zXZ\0") {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Decompresses all the streams or frames `input` reads, as they are read.
+    ///
+    /// xz is decompressed a block at a time on a thread of its own, and `xz` writes a single
+    /// block unless it compresses on several threads, so those files end up held whole.
+    pub fn reader<R: Read + Send + 'static>(
+        self,
+        input: R,
+    ) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::new(ZstdFrames::new(BufReader::new(input))?),
+            Compression::Xz => Box::new(xz_reader(input)),
+        })
+    }
+}
+
+fn invalid(err: &dyn Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// The frames of a zstd stream one after the other, as `cat a.zst b.zst` writes them.
+struct ZstdFrames<R: BufRead> {
+    frame: Option<StreamingDecoder<R, FrameDecoder>>,
+}
+
+impl<R: BufRead> ZstdFrames<R> {
+    fn new(input: R) -> std::io::Result<Self> {
+        let frame = StreamingDecoder::new(input).map_err(|err| invalid(&err))?;
+        Ok(ZstdFrames { frame: Some(frame) })
+    }
+}
+
+impl<R: BufRead> Read for ZstdFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(frame) = &mut self.frame {
+            let read = frame.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            let mut input = self.frame.take().map(StreamingDecoder::into_inner).unwrap();
+            if !input.fill_buf()?.is_empty() {
+                self.frame = Some(StreamingDecoder::new(input).map_err(|err| invalid(&err))?);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Blocks of decompressed bytes the xz thread hands over at once, and how many can wait.
+const PIPE_BLOCK: usize = 1 << 16;
+const PIPE_DEPTH: usize = 4;
+
+/// Decompresses the xz stream `input` reads on a thread, see [`Compression::reader`].
+fn xz_reader<R: Read + Send + 'static>(input: R) -> Pipe {
+    let (sender, receiver) = mpsc::sync_channel(PIPE_DEPTH);
+    std::thread::spawn(move || {
+        let mut pipe = PipeWriter(sender.clone());
+        if let Err(err) = lzma_rs::xz_decompress(&mut BufReader::new(input), &mut pipe) {
+            // nobody listens any more if the reader was dropped
+            let _ = sender.send(Err(invalid(&format!("{:?}", err))));
+        }
+    });
+    Pipe {
+        receiver,
+        block: Cursor::new(Vec::new()),
+    }
+}
+
+/// The end of a [`Pipe`] written by the thread.
+struct PipeWriter(SyncSender<std::io::Result<Vec<u8>>>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for block in buf.chunks(PIPE_BLOCK) {
+            self.0
+                .send(Ok(block.to_vec()))
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Bytes written by another thread, read as they come.
+struct Pipe {
+    receiver: Receiver<std::io::Result<Vec<u8>>>,
+    block: Cursor<Vec<u8>>,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.block.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.receiver.recv() {
+                Ok(block) => self.block = Cursor::new(block?),
+                // the thread is done
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// How to read G-code files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputOptions {
+    /// The plate of containers with several, starting at 1, the first one by default
+    pub plate: Option<usize>,
+}
+
+impl InputError {
+    /// The file the error occured in.
+    pub fn path(&self) -> &Path {
+        match self {
+            InputError::Io { path, .. }
+            | InputError::Bgcode { path, .. }
+            | InputError::Container { path, .. }
+            | InputError::Decompress { path, .. }
+            | InputError::NotGcode { path }
+            | InputError::NotContainer { path, .. } => path,
+        }
+    }
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Io { source, .. } => {
+                write!(f, "{}: I/O error: {}", self.path().display(), source)
+            }
+            InputError::Bgcode { source, .. } => {
+                write!(
+                    f,
+                    "{}: invalid binary G-code: {}",
+                    self.path().display(),
+                    source
+                )
+            }
+            InputError::Container { source, .. } => {
+                write!(f, "{}: {}", self.path().display(), source)
+            }
+            InputError::Decompress {
+                compression,
+                source,
+                ..
+            } => write!(
+                f,
+                "{}: invalid {} data: {}",
+                self.path().display(),
+                compression,
+                source
+            ),
+            InputError::NotGcode { .. } => {
+                write!(f, "{}: not a G-code file", self.path().display())
+            }
+            InputError::NotContainer { plate, .. } => write!(
+                f,
+                "{}: no plate {}, only containers have plates",
+                self.path().display(),
+                plate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InputError::Io { source, .. } => Some(source),
+            InputError::Bgcode { source, .. } => Some(source),
+            InputError::Container { source, .. } => Some(source),
+            InputError::Decompress { source, .. } => Some(source),
+            InputError::NotGcode { .. } | InputError::NotContainer { .. } => None,
+        }
+    }
+}
+
+/// The text of a G-code file, see [`map_gcode`].
+///
+/// Text files are mapped into memory rather than read, so that the browser doesn't hold all
+/// of a large file: the system drops the pages already read when memory runs short. Another
+/// program may change a mapped file while we read it, so the mapping is only handed out as
+/// bytes, and text is copied out of it and checked to be UTF-8 as it is needed.
+pub enum GcodeText {
+    Mapped(Mmap),
+    /// Text decoded from binary G-code, a container or a compressed file
+    Owned(String),
+}
+
+impl GcodeText {
+    /// The bytes of the file, not necessarily UTF-8.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            GcodeText::Mapped(mmap) => mmap,
+            GcodeText::Owned(text) => text.as_bytes(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The text of the bytes `range`, with replacement characters for what isn't UTF-8.
+    pub fn slice(&self, range: Range<usize>) -> Cow<'_, str> {
+        match self {
+            GcodeText::Mapped(mmap) => Cow::Owned(into_text(mmap[range].to_vec())),
+            GcodeText::Owned(text) => String::from_utf8_lossy(&text.as_bytes()[range]),
+        }
+    }
+
+    /// All of the text, copied out of a mapped file.
+    pub fn text(&self) -> Cow<'_, str> {
+        self.slice(0..self.len())
+    }
+}
+
+impl AsRef<[u8]> for GcodeText {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// `data` as text, with replacement characters for what isn't UTF-8, e.g. Latin-1 comments.
+fn into_text(data: Vec<u8>) -> String {
+    String::from_utf8(data)
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
+
+/// Whether `path` stands for the standard input.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// The file to write next to `file`, with `extension` instead of its own.
+///
+/// The extension of a compression goes as well, `part.gcode.gz` becomes `part.<extension>`.
+/// Files made from the standard input are `stdin.<extension>`.
+pub fn output_path(file: &Path, extension: &str) -> PathBuf {
+    if is_stdin(file) {
+        return PathBuf::from("stdin").with_extension(extension);
+    }
+    let file = match file.extension().and_then(|ext| ext.to_str()) {
+        Some("gz" | "zst" | "xz") => file.with_extension(""),
+        _ => file.to_path_buf(),
+    };
+    file.with_extension(extension)
+}
+
+/// Reads all of `path`, or of the standard input for `-`.
+pub fn read_bytes(path: &Path) -> Result<Vec<u8>, InputError> {
+    let io_error = |source| InputError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    if is_stdin(path) {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .map_err(io_error)?;
+        return Ok(data);
+    }
+    std::fs::read(path).map_err(io_error)
+}
+
+/// Reads `path` as text G-code, converting binary G-code, extracting it from containers and
+/// decompressing it.
+pub fn open_gcode<P: AsRef<Path>>(path: P, options: &InputOptions) -> Result<String, InputError> {
+    open_stream(path, options)?.text()
+}
+
+/// Reads `path` like [`open_gcode`], keeping text files byte for byte even where they aren't
+/// UTF-8, for commands writing the file back.
+pub fn open_gcode_bytes<P: AsRef<Path>>(
+    path: P,
+    options: &InputOptions,
+) -> Result<Vec<u8>, InputError> {
+    open_stream(path, options)?.bytes()
+}
+
+/// Reads `path` like [`open_gcode`], along with the preview stored next to the G-code of
+/// packages, which isn't part of the text.
+pub fn open_gcode_with_preview<P: AsRef<Path>>(
+    path: P,
+    options: &InputOptions,
+) -> Result<(String, Option<Thumbnail>), InputError> {
+    let path = path.as_ref();
+    let data = read_bytes(path)?;
+    let preview = if is_zip(&data) {
+        Container::open(data.clone())
+            .and_then(|mut container| container.read_preview(options.plate))
+            .map_err(|source| InputError::Container {
+                path: path.to_path_buf(),
+                source,
+            })?
+    } else {
+        None
+    };
+    Ok((decode_gcode(path, data, options)?, preview))
+}
+
+/// The text of a G-code file read as it is needed, see [`open_stream`].
+pub struct GcodeStream {
+    path: PathBuf,
+    reader: Box<dyn Read + Send>,
+    /// The compression of the file, which read errors come from
+    compression: Option<Compression>,
+    metadata: Vec<(String, String)>,
+}
+
+/// Turns the errors of reading `path` into [`InputError`]s, decompression errors if the file
+/// has a `compression`.
+fn read_error(
+    path: &Path,
+    compression: Option<Compression>,
+) -> impl Fn(std::io::Error) -> InputError {
+    let path = path.to_path_buf();
+    move |source| match compression {
+        Some(compression) => InputError::Decompress {
+            path: path.clone(),
+            compression,
+            source,
+        },
+        None => InputError::Io {
+            path: path.clone(),
+            source,
+        },
+    }
+}
+
+impl GcodeStream {
+    /// The text, a chunk at a time, split like [`split`](crate::gcode::parallel::split).
+    pub fn chunks(self) -> impl Iterator<Item = Result<ChunkBuf, InputError>> {
+        let error = read_error(&self.path, self.compression);
+        read_chunks(self.reader, CHUNK_SIZE).map(move |chunk| chunk.map_err(&error))
+    }
+
+    /// The metadata of the plate read from a container, e.g. the print estimates of Bambu
+    /// Studio, see [`Plate::metadata`](crate::gcode::container::Plate::metadata). Empty for
+    /// other files.
+    pub fn metadata(&self) -> &[(String, String)] {
+        &self.metadata
+    }
+
+    /// All of the text, with replacement characters for what isn't UTF-8.
+    pub fn text(self) -> Result<String, InputError> {
+        self.bytes().map(into_text)
+    }
+
+    /// All of the text, left as bytes.
+    pub fn bytes(mut self) -> Result<Vec<u8>, InputError> {
+        let mut data = Vec::new();
+        self.reader
+            .read_to_end(&mut data)
+            .map_err(read_error(&self.path, self.compression))?;
+        Ok(data)
+    }
+}
+
+/// Opens `path` like [`open_gcode`], reading and decompressing text files as their text is
+/// needed rather than all at once, so that commands going through a file once hold a few
+/// chunks of it at most.
+///
+/// Binary G-code and containers are still decoded into memory.
+pub fn open_stream<P: AsRef<Path>>(
+    path: P,
+    options: &InputOptions,
+) -> Result<GcodeStream, InputError> {
+    let path = path.as_ref();
+    let reader: Box<dyn Read + Send> = if is_stdin(path) {
+        Box::new(std::io::stdin())
+    } else {
+        let file = File::open(path).map_err(|source| InputError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Box::new(file)
+    };
+    sniff(
+        GcodeStream {
+            path: path.to_path_buf(),
+            reader,
+            compression: None,
+            metadata: Vec::new(),
+        },
+        options,
+    )
+}
+
+/// Tells what `stream` reads from its first bytes, and decodes it into text.
+fn sniff(mut stream: GcodeStream, options: &InputOptions) -> Result<GcodeStream, InputError> {
+    let mut head = Vec::with_capacity(8192);
+    (&mut stream.reader)
+        .take(8192)
+        .read_to_end(&mut head)
+        .map_err(read_error(&stream.path, stream.compression))?;
+    let mut reader = Cursor::new(head).chain(stream.reader);
+    let head = reader.get_ref().0.get_ref();
+    if let Some(compression) = Compression::detect(head) {
+        let error = |source| InputError::Decompress {
+            path: stream.path.clone(),
+            compression,
+            source,
+        };
+        let decompressed = GcodeStream {
+            reader: compression.reader(reader).map_err(error)?,
+            compression: Some(compression),
+            ..stream
+        };
+        return sniff(decompressed, options);
+    }
+    if is_bgcode(head) || is_zip(head) {
+        let zip = is_zip(head);
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(read_error(&stream.path, stream.compression))?;
+        let (text, metadata) = if zip {
+            let (plate, gcode) = read_container(&stream.path, data, options)?;
+            (gcode, plate.metadata)
+        } else {
+            (decode_bytes(&stream.path, data, options)?, Vec::new())
+        };
+        return Ok(GcodeStream {
+            reader: Box::new(Cursor::new(text)),
+            compression: None,
+            metadata,
+            ..stream
+        });
+    }
+    no_plate(&stream.path, options)?;
+    // text doesn't hold NUL bytes, unlike images or executables
+    if head.contains(&0) {
+        return Err(InputError::NotGcode { path: stream.path });
+    }
+    Ok(GcodeStream {
+        reader: Box::new(reader),
+        ..stream
+    })
+}
+
+/// The plate of the container `data` read from `path` and its G-code, byte for byte.
+fn read_container(
+    path: &Path,
+    data: Vec<u8>,
+    options: &InputOptions,
+) -> Result<(Plate, Vec<u8>), InputError> {
+    let container_error = |source| InputError::Container {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut container = Container::open(data).map_err(container_error)?;
+    let plate = container
+        .plate(options.plate)
+        .map_err(container_error)?
+        .clone();
+    let gcode = container
+        .read_plate(Some(plate.number))
+        .map_err(container_error)?;
+    Ok((plate, gcode))
+}
+
+/// Fails if `options` ask for a plate of `path`, which isn't a container.
+fn no_plate(path: &Path, options: &InputOptions) -> Result<(), InputError> {
+    match options.plate {
+        Some(plate) => Err(InputError::NotContainer {
+            path: path.to_path_buf(),
+            plate,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Opens `path` like [`open_gcode`], mapping text files into memory instead of reading them.
+pub fn map_gcode<P: AsRef<Path>>(path: P, options: &InputOptions) -> Result<GcodeText, InputError> {
+    let path = path.as_ref();
+    let io_error = |source| InputError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    if is_stdin(path) {
+        return open_gcode(path, options).map(GcodeText::Owned);
+    }
+    let file = File::open(path).map_err(io_error)?;
+    if file.metadata().map_err(io_error)?.len() == 0 {
+        no_plate(path, options)?;
+        return Ok(GcodeText::Owned(String::new()));
+    }
+    // SAFETY: the mapping is read only and only read as bytes; like any reader, we see
+    // garbage if the file is truncated or rewritten while we read it
+    let mmap = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+    if is_bgcode(&mmap) || is_zip(&mmap) || Compression::detect(&mmap).is_some() {
+        drop(mmap);
+        return open_gcode(path, options).map(GcodeText::Owned);
+    }
+    no_plate(path, options)?;
+    if mmap[..mmap.len().min(8192)].contains(&0) {
+        return Err(InputError::NotGcode {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(GcodeText::Mapped(mmap))
+}
+
+/// Converts the content of the G-code file `path` to text.
+///
+/// Text that isn't valid UTF-8, e.g. Latin-1 comments, is read with replacement characters.
+pub fn decode_gcode(
+    path: &Path,
+    data: Vec<u8>,
+    options: &InputOptions,
+) -> Result<String, InputError> {
+    decode_bytes(path, data, options).map(into_text)
+}
+
+/// Converts the content of the G-code file `path` to text, left as bytes.
+fn decode_bytes(path: &Path, data: Vec<u8>, options: &InputOptions) -> Result<Vec<u8>, InputError> {
+    if is_zip(&data) {
+        return read_container(path, data, options).map(|(_, gcode)| gcode);
+    }
+    if Compression::detect(&data).is_some() {
+        let stream = GcodeStream {
+            path: path.to_path_buf(),
+            reader: Box::new(Cursor::new(data)),
+            compression: None,
+            metadata: Vec::new(),
+        };
+        return sniff(stream, options)?.bytes();
+    }
+    no_plate(path, options)?;
+    if is_bgcode(&data) {
+        return Bgcode::read(&data)
+            .map(|bgcode| bgcode.to_ascii().into_bytes())
+            .map_err(|source| InputError::Bgcode {
+                path: path.to_path_buf(),
+                source,
+            });
+    }
+    // text doesn't hold NUL bytes, unlike images or executables
+    if data[..data.len().min(8192)].contains(&0) {
+        return Err(InputError::NotGcode {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const GCODE: &str = "G28\nG1 X10 Y10 E1\n";
+
+    /// `GCODE` compressed by the zstd and xz command line tools.
+    const ZSTD: [u8; 31] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x12, 0x91, 0x00, 0x00, 0x47, 0x32, 0x38, 0x0a, 0x47, 0x31,
+        0x20, 0x58, 0x31, 0x30, 0x20, 0x59, 0x31, 0x30, 0x20, 0x45, 0x31, 0x0a, 0xa8, 0xfa, 0xa6,
+        0x6e,
+    ];
+    const XZ: [u8; 84] = [
+        0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x04, 0xc0, 0x16,
+        0x12, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa7, 0x51,
+        0x77, 0x9e, 0x01, 0x00, 0x11, 0x47, 0x32, 0x38, 0x0a, 0x47, 0x31, 0x20, 0x58, 0x31, 0x30,
+        0x20, 0x59, 0x31, 0x30, 0x20, 0x45, 0x31, 0x0a, 0x00, 0x00, 0x00, 0x84, 0x56, 0xe1, 0x95,
+        0x2c, 0x3a, 0x59, 0xa0, 0x00, 0x01, 0x32, 0x12, 0x12, 0x90, 0x4f, 0x3e, 0x1f, 0xb6, 0xf3,
+        0x7d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
+    ];
+
+    #[test]
+    fn should_only_read_plates_of_containers() {
+        let plate = |plate| InputOptions { plate: Some(plate) };
+        let err = decode_gcode(Path::new("x"), GCODE.into(), &plate(2)).unwrap_err();
+        assert!(
+            matches!(err, InputError::NotContainer { plate: 2, .. }),
+            "{}",
+            err
+        );
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let entries = [
+            ("Metadata/plate_1.gcode", GCODE),
+            ("Metadata/plate_2.gcode", "G28\n"),
+            (
+                "Metadata/slice_info.config",
+                "<plate><metadata key=\"index\" value=\"2\"/>\
+                 <metadata key=\"prediction\" value=\"60\"/></plate>",
+            ),
+        ];
+        for (name, content) in entries {
+            zip.start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let data = zip.finish().unwrap().into_inner();
+        let err = decode_gcode(Path::new("x"), data.clone(), &plate(3)).unwrap_err();
+        assert!(matches!(err, InputError::Container { .. }), "{}", err);
+
+        let stream = GcodeStream {
+            path: PathBuf::from("x"),
+            reader: Box::new(Cursor::new(data)),
+            compression: None,
+            metadata: Vec::new(),
+        };
+        let stream = sniff(stream, &plate(2)).unwrap();
+        assert_eq!(
+            stream.metadata(),
+            [("prediction".to_string(), "60".to_string())]
+        );
+        assert_eq!(stream.text().unwrap(), "G28\n");
+    }
+
+    #[test]
+    fn should_decompress_as_it_reads() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(GCODE.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        for (compression, data) in [
+            (Compression::Gzip, gzip),
+            (Compression::Zstd, [ZSTD, ZSTD].concat()),
+            (Compression::Xz, XZ.to_vec()),
+        ] {
+            let mut reader = compression.reader(Cursor::new(data)).unwrap();
+            let mut text = vec![];
+            let mut buf = [0; 3];
+            loop {
+                match reader.read(&mut buf).unwrap() {
+                    0 => break,
+                    read => text.extend_from_slice(&buf[..read]),
+                }
+            }
+            let expected = if compression == Compression::Zstd {
+                GCODE.repeat(2)
+            } else {
+                GCODE.to_string()
+            };
+            assert_eq!(
+                String::from_utf8(text).unwrap(),
+                expected,
+                "{}",
+                compression
+            );
+        }
+
+        let mut reader = Compression::Xz
+            .reader(Cursor::new(XZ[..40].to_vec()))
+            .unwrap();
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_decompress_by_content() {
+        let decode = |data: Vec<u8>| decode_gcode(Path::new("x"), data, &InputOptions::default());
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(GCODE.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(Compression::detect(&gzip), Some(Compression::Gzip));
+        // concatenated streams, as written by `cat a.gz b.gz`
+        assert_eq!(
+            decode([gzip.clone(), gzip].concat()).unwrap(),
+            GCODE.repeat(2)
+        );
+        assert_eq!(decode([ZSTD, ZSTD].concat()).unwrap(), GCODE.repeat(2));
+        assert_eq!(decode(XZ.to_vec()).unwrap(), GCODE);
+
+        assert!(matches!(
+            decode(ZSTD[..20].to_vec()),
+            Err(InputError::Decompress {
+                compression: Compression::Zstd,
+                ..
+            })
+        ));
+        assert!(matches!(
+            decode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec()),
+            Err(InputError::NotGcode { .. })
+        ));
+    }
+}
@@ -1,2 +1,5 @@
 pub mod gcode;
 pub mod helpers;
+pub mod input;
+pub mod render;
+pub mod report;
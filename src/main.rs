@@ -1,37 +1,168 @@
+use crate::commands::annotate::AnnotateArgs;
+use crate::commands::convert::ConvertArgs;
+use crate::commands::docs::DocsCommand;
+use crate::commands::flow::FlowArgs;
+use crate::commands::meatpack::MeatpackCommand;
+use crate::commands::modify::ModifyArgs;
+use crate::commands::moves::MovesArgs;
+use crate::commands::render::RenderArgs;
+use crate::commands::resume::ResumeArgs;
+use crate::commands::stats::StatsArgs;
+use crate::commands::thumbnails::ThumbnailsCommand;
+use crate::commands::widths::WidthsArgs;
 use crate::ui::app::App;
 use crate::ui::io::{IoAsyncHandler, IoEvent};
-use clap::Parser;
-use eyre::Result;
-use gcode::{Callbacks, Comment, Nop};
-use gcode_viz::gcode::lines::{DisplayLine, LineIterator};
+use crate::ui::listing::Listing;
+use clap::{Parser, Subcommand};
+use eyre::{eyre, Result};
+use gcode_viz::gcode::lines::{parse_lines, LineIterator};
 use gcode_viz::gcode::marlin_docs;
-use gcode_viz::helpers::PopIf;
+use gcode_viz::input::{map_gcode, open_gcode, InputOptions};
+use gcode_viz::render::Rgb;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+mod commands;
 mod ui;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(arg_required_else_help = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Name of the GCode file to parse
     #[clap(value_parser)]
-    file: String,
+    file: Option<String>,
+
+    /// Directory of a MarlinDocumentation checkout, overrides the built-in documentation
+    #[clap(short, long, value_parser, global = true)]
+    marlin_docs_dir: Option<PathBuf>,
+
+    /// Plate to read from Bambu `.gcode.3mf` files with several, starting at 1
+    #[clap(long, value_parser, global = true)]
+    plate: Option<usize>,
+
+    /// Number of threads parsing and analysing large files, defaults to the number of cores
+    #[clap(short, long, value_parser, global = true)]
+    jobs: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect the Marlin documentation
+    Docs {
+        #[clap(subcommand)]
+        command: DocsCommand,
+    },
+    /// Show which documentation groups the commands of a GCode file belong to
+    Groups {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+    },
+    /// Browse a GCode file along with its documentation in the terminal
+    Browse {
+        /// Name of the GCode file to browse
+        #[clap(value_parser)]
+        file: String,
+    },
+    /// Write a self-contained HTML report with statistics, layer previews and the listing
+    Report {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
 
-    /// Name of the directory with marlin documentation
-    #[clap(
-        short,
-        long,
-        value_parser,
-        default_value = "vendor/MarlinDocumentation"
-    )]
-    marlin_docs_dir: String,
+        /// The HTML file to write, defaults to the GCode file with an `.html` extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// List or extract the preview images slicers embed in GCode files
+    Thumbnails {
+        #[clap(subcommand)]
+        command: ThumbnailsCommand,
+    },
+    /// Draw layers or the whole toolpath as SVG, from above or isometric
+    Svg {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+
+        /// The SVG file to write, defaults to the GCode file with an `.svg` extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+
+        #[clap(flatten)]
+        render: RenderArgs,
+    },
+    /// Draw layers or the whole toolpath as PNG, from above or isometric
+    Png {
+        /// Name of the GCode file
+        #[clap(value_parser)]
+        file: String,
+
+        /// The PNG file to write, defaults to the GCode file with a `.png` extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+
+        #[clap(flatten)]
+        render: RenderArgs,
+
+        /// Height of the image in pixels, defaults to the proportions of the drawing
+        #[clap(long, value_parser)]
+        height: Option<u32>,
+
+        /// Background colour, e.g. `#ffffff`
+        #[clap(long, value_parser = commands::png::parse_color, default_value = "#ffffff")]
+        background: Rgb,
+    },
+    /// Convert between text GCode and binary GCode (`.bgcode`)
+    Convert(ConvertArgs),
+    /// Print each command with its documentation, as text, JSON or newline-delimited JSON
+    /// records holding the state of the printer around it
+    Annotate(AnnotateArgs),
+    /// Print the statistics of a GCode file: counts, opcodes, size, layers, distances, time,
+    /// temperatures, fan and tools, as text, JSON or Markdown
+    Stats(StatsArgs),
+    /// Show histograms of the volumetric flow and print speed per feature and the moves
+    /// going over what the hotend can do
+    Flow(FlowArgs),
+    /// Infer the extrusion width of every move and the height of every layer, and find the
+    /// moves far from the median width of their feature
+    Widths(WidthsArgs),
+    /// Write the rest of a failed print from a layer, a line or a height, after a preamble
+    /// heating, homing X and Y and restoring the state of the printer there
+    Resume(ResumeArgs),
+    /// Insert filament changes, pauses, temperature, fan and flow changes and G-code snippets
+    /// at layer changes, leaving the other lines as they are
+    Modify(ModifyArgs),
+    /// Export one row per move with its positions, extrusion, feedrate, layer, feature,
+    /// duration, tool and temperatures, as CSV or newline-delimited JSON
+    Moves(MovesArgs),
+    /// Measure, pack and decode MeatPack serial streams
+    Meatpack {
+        #[clap(subcommand)]
+        command: MeatpackCommand,
+    },
 }
 
-async fn run_ui() -> Result<(), eyre::Error> {
+async fn run_ui(
+    file: String,
+    marlin_docs_dir: Option<PathBuf>,
+    input: &InputOptions,
+) -> Result<(), eyre::Error> {
+    let (opcodes, _) = commands::load_docs(marlin_docs_dir.as_deref())?;
+    let listing = Listing::new(map_gcode(&file, input)?);
+
     let (sync_io_tx, mut sync_io_rx) = tokio::sync::mpsc::channel::<IoEvent>(100);
 
-    let app = Arc::new(tokio::sync::Mutex::new(App::new(sync_io_tx.clone())));
+    let app = Arc::new(tokio::sync::Mutex::new(App::new(
+        sync_io_tx.clone(),
+        Arc::new(opcodes),
+        file,
+        listing,
+    )));
     let app_clone = Arc::clone(&app);
 
     tokio::spawn(async move {
@@ -51,36 +182,87 @@ async fn main() -> Result<(), eyre::Error> {
     color_eyre::install()?;
 
     let args = Cli::parse();
+    let input = InputOptions { plate: args.plate };
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+
+    match args.command {
+        Some(Command::Docs { command }) => {
+            return commands::docs::run(&command, args.marlin_docs_dir.as_deref())
+        }
+        Some(Command::Groups { file }) => {
+            let (opcodes, _) = commands::load_docs(args.marlin_docs_dir.as_deref())?;
+            return commands::groups::run(&file, &opcodes, &input);
+        }
+        Some(Command::Browse { file }) => return run_ui(file, args.marlin_docs_dir, &input).await,
+        Some(Command::Report { file, output }) => {
+            let (opcodes, _) = commands::load_docs(args.marlin_docs_dir.as_deref())?;
+            return commands::report::run(&file, output.as_deref(), &opcodes, &input);
+        }
+        Some(Command::Thumbnails { command }) => {
+            return commands::thumbnails::run(&command, &input)
+        }
+        Some(Command::Svg {
+            file,
+            output,
+            render,
+        }) => return commands::svg::run(&file, output.as_deref(), &render, &input),
+        Some(Command::Png {
+            file,
+            output,
+            render,
+            height,
+            background,
+        }) => {
+            return commands::png::run(
+                &file,
+                output.as_deref(),
+                &render,
+                height,
+                background,
+                &input,
+            );
+        }
+        Some(Command::Convert(convert)) => return commands::convert::run(&convert, &input),
+        Some(Command::Annotate(annotate)) => {
+            let (opcodes, _) = commands::load_docs(args.marlin_docs_dir.as_deref())?;
+            return commands::annotate::run(&annotate, &opcodes, &input);
+        }
+        Some(Command::Stats(stats)) => {
+            return commands::stats::run(&stats, args.marlin_docs_dir.as_deref(), &input)
+        }
+        Some(Command::Flow(flow)) => return commands::flow::run(&flow, &input),
+        Some(Command::Widths(widths)) => return commands::widths::run(&widths, &input),
+        Some(Command::Resume(resume)) => return commands::resume::run(&resume, &input),
+        Some(Command::Modify(modify)) => return commands::modify::run(&modify, &input),
+        Some(Command::Moves(moves)) => return commands::moves::run(&moves, &input),
+        Some(Command::Meatpack { command }) => {
+            return commands::meatpack::run(&command, args.marlin_docs_dir.as_deref(), &input)
+        }
+        None => {}
+    }
+    let file = args.file.ok_or_else(|| eyre!("No file given"))?;
+
+    let (opcodes, report) = commands::load_docs(args.marlin_docs_dir.as_deref())?;
+    if !report.is_ok() {
+        eprintln!(
+            "{} documentation files failed to load, run `gcode-viz docs check` for details",
+            report.failures.len()
+        );
+    }
 
-    // append /_gcode to the marlin docs dir
-    let gcode_docs_dir = format!("{}/_gcode", args.marlin_docs_dir);
-    // get all files in doc_dir
-    let opcodes = marlin_docs::parse_marlin_docs(gcode_docs_dir);
-
-    let path = args.file.clone();
-    let s = std::fs::read_to_string(path).unwrap();
-    if args.file.ends_with(".gcode") {
-        println!("Parsing GCode file: {}", args.file);
-        let lines = gcode::full_parse_with_callbacks(s.as_str(), Nop);
-
-        let mut my_iterator = LineIterator::new(lines.take(32));
-        my_iterator.for_each(|line| match line {
-            DisplayLine::Comment(c) => println!("// {}", c.value),
-            DisplayLine::GCode(o, opcode) => {
-                let span = opcode.span();
-                let orig = s[span.start..span.end].to_string();
-                if let Some(od) = opcodes.get(o.as_str()) {
-                    println!("{}: {}", opcode, od.title);
-                } else {
-                    println!("{}: {}", opcode, "Unknown");
-                }
-            }
-        });
-    } else if args.file.ends_with(".md") {
-        let od = marlin_docs::parse_opcode_md(PathBuf::from(args.file)).unwrap();
+    if file.ends_with(".md") {
+        let od = marlin_docs::parse_opcode_md(PathBuf::from(file))?;
         println!("{:?}", od);
     } else {
-        println!("File is not a GCode file");
+        // the content tells the format, text, binary G-code, containers or compressed
+        let s = open_gcode(&file, &input)?;
+        println!("Parsing GCode file: {}", file);
+        let lines = LineIterator::new(parse_lines(&s).take(32));
+        commands::annotate::write_text(&mut std::io::stdout().lock(), lines, &opcodes)?;
     }
 
     Ok(())
@@ -0,0 +1,383 @@
+//! Renders simulated toolpaths to images.
+
+pub mod raster;
+pub mod svg;
+
+use crate::gcode::machine::{Layer, Move, Toolpath, X, Y, Z};
+use crate::gcode::profile::MachineProfile;
+use crate::gcode::stats::NO_FEATURE;
+use crate::gcode::width::{WidthModel, DEFAULT_TOLERANCE};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+/// An axis aligned rectangle in the XY plane, in mm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+}
+
+impl Bounds {
+    pub fn width(&self) -> f64 {
+        self.max[0] - self.min[0]
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max[1] - self.min[1]
+    }
+
+    /// Grows the rectangle by `margin` on every side.
+    pub fn expand(&self, margin: f64) -> Bounds {
+        Bounds {
+            min: [self.min[0] - margin, self.min[1] - margin],
+            max: [self.max[0] + margin, self.max[1] + margin],
+        }
+    }
+}
+
+/// The XY bounds of the extruding moves of `toolpath`, `None` if nothing is extruded.
+pub fn extrusion_bounds(toolpath: &Toolpath) -> Option<Bounds> {
+    let mut bounds: Option<Bounds> = None;
+    for m in toolpath.moves.iter().filter(|m| m.is_extrusion()) {
+        for point in [m.from, m.to] {
+            let b = bounds.get_or_insert(Bounds {
+                min: [point[X], point[Y]],
+                max: [point[X], point[Y]],
+            });
+            for axis in [X, Y] {
+                b.min[axis] = b.min[axis].min(point[axis]);
+                b.max[axis] = b.max[axis].max(point[axis]);
+            }
+        }
+    }
+    bounds
+}
+
+/// The direction the toolpath is looked at from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum View {
+    /// From above, X to the right and Y up
+    #[default]
+    Top,
+    /// From the front left corner, 30° above the bed
+    Isometric,
+}
+
+impl View {
+    /// The image coordinates of `point`, in mm with the second coordinate pointing up.
+    pub fn project(&self, point: [f64; 3]) -> [f64; 2] {
+        match self {
+            View::Top => [point[X], point[Y]],
+            View::Isometric => {
+                let (sin, cos) = std::f64::consts::FRAC_PI_6.sin_cos();
+                [
+                    (point[X] - point[Y]) * cos,
+                    (point[X] + point[Y]) * sin + point[Z],
+                ]
+            }
+        }
+    }
+}
+
+/// The layers of `toolpath` in `layers`, clamped to the layers it has.
+pub fn select_layers(toolpath: &Toolpath, layers: Range<usize>) -> &[Layer] {
+    let count = toolpath.layers.len();
+    &toolpath.layers[layers.start.min(count)..layers.end.min(count)]
+}
+
+/// The corners of the bed of `profile` seen from `view`.
+pub fn bed_outline(profile: &MachineProfile, view: View) -> [[f64; 2]; 4] {
+    let (min, max) = profile.bed_area();
+    [
+        [min[X], min[Y], 0.0],
+        [max[X], min[Y], 0.0],
+        [max[X], max[Y], 0.0],
+        [min[X], max[Y], 0.0],
+    ]
+    .map(|corner| view.project(corner))
+}
+
+/// The area covered by the moves of `layers` seen from `view`, and by the `bed` outline if
+/// given, with a small margin.
+pub fn view_bounds(
+    toolpath: &Toolpath,
+    layers: &[Layer],
+    view: View,
+    travel: bool,
+    bed: Option<&[[f64; 2]; 4]>,
+) -> Bounds {
+    let mut bounds: Option<Bounds> = None;
+    let points = layers
+        .iter()
+        .flat_map(|layer| toolpath.layer_moves(layer))
+        .filter(|m| travel || m.is_extrusion())
+        .flat_map(|m| [m.from, m.to])
+        .map(|point| view.project(point))
+        .chain(bed.into_iter().flatten().copied());
+    for point in points {
+        let b = bounds.get_or_insert(Bounds {
+            min: point,
+            max: point,
+        });
+        for axis in [X, Y] {
+            b.min[axis] = b.min[axis].min(point[axis]);
+            b.max[axis] = b.max[axis].max(point[axis]);
+        }
+    }
+    bounds
+        .unwrap_or(Bounds {
+            min: [0.0, 0.0],
+            max: [1.0, 1.0],
+        })
+        .expand(2.0)
+}
+
+/// An sRGB colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// The colour as a CSS hex string, e.g. `#ff8000`.
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// Colours of the feature types written by Cura and PrusaSlicer and its forks.
+const FEATURE_COLORS: [(&str, Rgb); 22] = [
+    ("WALL-OUTER", Rgb(0xe0, 0x40, 0x30)),
+    ("WALL-INNER", Rgb(0x40, 0xa0, 0x40)),
+    ("SKIN", Rgb(0xe0, 0xc0, 0x30)),
+    ("FILL", Rgb(0xc0, 0x60, 0x20)),
+    ("SUPPORT", Rgb(0x30, 0xa0, 0xc0)),
+    ("SUPPORT-INTERFACE", Rgb(0x30, 0x60, 0xc0)),
+    ("SKIRT", Rgb(0x90, 0x90, 0x90)),
+    ("PRIME-TOWER", Rgb(0xa0, 0x60, 0xc0)),
+    ("External perimeter", Rgb(0xe0, 0x40, 0x30)),
+    ("Perimeter", Rgb(0xf0, 0xa0, 0x30)),
+    ("Overhang perimeter", Rgb(0x30, 0x30, 0xe0)),
+    ("Internal infill", Rgb(0xc0, 0x60, 0x20)),
+    ("Solid infill", Rgb(0xa0, 0x30, 0xa0)),
+    ("Top solid infill", Rgb(0xe0, 0x30, 0x40)),
+    ("Bridge infill", Rgb(0x60, 0x80, 0xe0)),
+    ("Gap fill", Rgb(0xf0, 0xf0, 0xf0)),
+    ("Ironing", Rgb(0xff, 0x80, 0x80)),
+    ("Skirt/Brim", Rgb(0x90, 0x90, 0x90)),
+    ("Skirt", Rgb(0x90, 0x90, 0x90)),
+    ("Support material", Rgb(0x30, 0xa0, 0xc0)),
+    ("Support material interface", Rgb(0x30, 0x60, 0xc0)),
+    ("Wipe tower", Rgb(0xa0, 0x60, 0xc0)),
+];
+
+/// Colours for feature types we don't know, picked by hashing the name.
+const PALETTE: [Rgb; 8] = [
+    Rgb(0x1f, 0x77, 0xb4),
+    Rgb(0xff, 0x7f, 0x0e),
+    Rgb(0x2c, 0xa0, 0x2c),
+    Rgb(0xd6, 0x27, 0x28),
+    Rgb(0x94, 0x67, 0xbd),
+    Rgb(0x8c, 0x56, 0x4b),
+    Rgb(0xe3, 0x77, 0xc2),
+    Rgb(0xbc, 0xbd, 0x22),
+];
+
+/// The colour of travel moves.
+pub const TRAVEL_COLOR: Rgb = Rgb(0xb0, 0xb0, 0xb0);
+
+/// The colour of a feature type, stable across runs.
+pub fn feature_color(feature: Option<&str>) -> Rgb {
+    let feature = match feature {
+        Some(feature) => feature,
+        None => return Rgb(0x60, 0x60, 0x60),
+    };
+    if let Some((_, color)) = FEATURE_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(feature))
+    {
+        return *color;
+    }
+    let mut hasher = DefaultHasher::new();
+    feature.hash(&mut hasher);
+    PALETTE[hasher.finish() as usize % PALETTE.len()]
+}
+
+/// What decides the colour of the extruding moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorBy {
+    /// The feature type from the `;TYPE:` comments
+    #[default]
+    Feature,
+    /// Speed of the nozzle in mm/s
+    Speed,
+    /// Width of the extruded line in mm
+    Width,
+    /// Width of the extruded line relative to the median of its feature, in percent
+    WidthDeviation,
+    /// Volumetric flow in mm³/s
+    Flow,
+    /// Part cooling fan speed in percent
+    Fan,
+}
+
+/// Stops of the gradient used for values, from low to high.
+const GRADIENT: [Rgb; 5] = [
+    Rgb(0x30, 0x40, 0xc0),
+    Rgb(0x20, 0xa0, 0xd0),
+    Rgb(0x40, 0xc0, 0x40),
+    Rgb(0xf0, 0xc0, 0x20),
+    Rgb(0xd0, 0x30, 0x20),
+];
+
+/// The colour of `t` between 0 and 1 on the value gradient, clamped.
+pub fn gradient_color(t: f64) -> Rgb {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    let scaled = t * (GRADIENT.len() - 1) as f64;
+    let i = (scaled.floor() as usize).min(GRADIENT.len() - 2);
+    let f = scaled - i as f64;
+    let (a, b) = (GRADIENT[i], GRADIENT[i + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * f).round() as u8;
+    Rgb(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+/// Picks the colours of the moves of a toolpath, shared by all renderers so that the images
+/// and their legends agree.
+#[derive(Debug, Clone)]
+pub struct Colorizer {
+    pub by: ColorBy,
+    /// Smallest and largest value over the extruding moves, unused for [`ColorBy::Feature`]
+    pub range: (f64, f64),
+    /// The feature types of the extruding moves, sorted
+    pub features: Vec<String>,
+    filament_area: f64,
+    widths: WidthModel,
+}
+
+impl Colorizer {
+    /// Colours the moves of `toolpath` by `by`, using the filament of `profile` for widths.
+    pub fn new(toolpath: &Toolpath, by: ColorBy, profile: &MachineProfile) -> Self {
+        let mut colorizer = Colorizer {
+            by,
+            range: (0.0, 0.0),
+            features: Vec::new(),
+            filament_area: profile.filament_area(),
+            widths: match by {
+                ColorBy::Width | ColorBy::WidthDeviation => {
+                    WidthModel::new(toolpath, profile.filament_area())
+                }
+                _ => WidthModel::default(),
+            },
+        };
+        let mut features = BTreeSet::new();
+        let mut range: Option<(f64, f64)> = None;
+        for m in toolpath.moves.iter().filter(|m| m.is_extrusion()) {
+            features.insert(m.feature.as_deref().unwrap_or(NO_FEATURE));
+            if let Some(value) = colorizer.value(m) {
+                let (min, max) = range.get_or_insert((value, value));
+                *min = min.min(value);
+                *max = max.max(value);
+            }
+        }
+        colorizer.features = features.into_iter().map(str::to_string).collect();
+        colorizer.range = match by {
+            // centred on the median, so that green is right and the outliers stand out
+            ColorBy::WidthDeviation => (
+                100.0 - 200.0 * DEFAULT_TOLERANCE,
+                100.0 + 200.0 * DEFAULT_TOLERANCE,
+            ),
+            _ => range.unwrap_or_default(),
+        };
+        colorizer
+    }
+
+    /// Uses `range` instead of the values of the toolpath, so that the colours of several
+    /// files can be compared.
+    pub fn with_range(mut self, range: (f64, f64)) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// The widths of the moves, only inferred when colouring by width.
+    pub fn widths(&self) -> &WidthModel {
+        &self.widths
+    }
+
+    /// The value `m` is coloured by, `None` for features or when it has none.
+    pub fn value(&self, m: &Move) -> Option<f64> {
+        match self.by {
+            ColorBy::Feature => None,
+            ColorBy::Speed => Some(m.speed()),
+            ColorBy::Width => self.widths.width(m),
+            ColorBy::WidthDeviation => Some((1.0 + self.widths.deviation(m)?) * 100.0),
+            ColorBy::Flow => Some(m.volumetric_flow(self.filament_area)),
+            ColorBy::Fan => Some(m.fan_speed / 255.0 * 100.0),
+        }
+    }
+
+    /// The colour of the extruding move `m`.
+    pub fn color(&self, m: &Move) -> Rgb {
+        if self.by == ColorBy::Feature {
+            return feature_color(m.feature.as_deref());
+        }
+        match self.value(m) {
+            Some(value) => {
+                let (min, max) = self.range;
+                gradient_color(if max > min {
+                    (value - min) / (max - min)
+                } else {
+                    0.5
+                })
+            }
+            None => feature_color(None),
+        }
+    }
+
+    /// Unit of the values, empty for features.
+    pub fn unit(&self) -> &'static str {
+        match self.by {
+            ColorBy::Feature => "",
+            ColorBy::Speed => "mm/s",
+            ColorBy::Width => "mm",
+            ColorBy::WidthDeviation => "%",
+            ColorBy::Flow => "mm³/s",
+            ColorBy::Fan => "%",
+        }
+    }
+
+    /// Labels and colours explaining the image: the feature types, or evenly spaced values
+    /// from the smallest to the largest.
+    pub fn legend(&self) -> Vec<(String, Rgb)> {
+        if self.by == ColorBy::Feature {
+            return self
+                .features
+                .iter()
+                .map(|feature| {
+                    let color = feature_color(Some(feature.as_str()).filter(|f| *f != NO_FEATURE));
+                    (feature.clone(), color)
+                })
+                .collect();
+        }
+        let (min, max) = self.range;
+        let steps = if max > min { GRADIENT.len() } else { 1 };
+        (0..steps)
+            .map(|i| {
+                let t = if steps > 1 {
+                    i as f64 / (steps - 1) as f64
+                } else {
+                    0.5
+                };
+                let value = min + (max - min) * t;
+                let decimals = match self.by {
+                    ColorBy::Width => 2,
+                    ColorBy::Flow => 1,
+                    _ => 0,
+                };
+                (
+                    format!("{:.*} {}", decimals, value, self.unit()),
+                    gradient_color(t),
+                )
+            })
+            .collect()
+    }
+}
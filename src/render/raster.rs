@@ -0,0 +1,274 @@
+//! Renders layers of a [`Toolpath`] into pixels on the CPU, for thumbnails and for comparing
+//! the output of slicers on machines without a display.
+//!
+//! Lines are drawn as anti-aliased capsules, later moves over earlier ones. The isometric
+//! view shades the lines by their direction so that the walls of a part stand out.
+
+use crate::gcode::machine::{Move, Toolpath, X, Y};
+use crate::gcode::profile::MachineProfile;
+use crate::render::{
+    bed_outline, select_layers, view_bounds, Bounds, Colorizer, Rgb, View, TRAVEL_COLOR,
+};
+use std::ops::Range;
+
+/// How to draw layers.
+#[derive(Debug, Clone)]
+pub struct RasterOptions {
+    /// Width of the image in pixels
+    pub width: u32,
+    /// Height of the image in pixels, `None` to follow the proportions of the drawing
+    pub height: Option<u32>,
+    /// Width of the extrusion lines in mm, lines are at least one pixel wide
+    pub line_width: f64,
+    /// Whether to draw travel moves as thin lines
+    pub travel: bool,
+    pub view: View,
+    pub background: Rgb,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: None,
+            line_width: 0.4,
+            travel: false,
+            view: View::Top,
+            background: Rgb(0xff, 0xff, 0xff),
+        }
+    }
+}
+
+/// An RGB image with 8 bits per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from top to bottom, 3 bytes per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// An image filled with `background`.
+    pub fn new(width: u32, height: u32, background: Rgb) -> Self {
+        let pixels =
+            [background.0, background.1, background.2].repeat(width as usize * height as usize);
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Rgb {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        Rgb(self.pixels[i], self.pixels[i + 1], self.pixels[i + 2])
+    }
+
+    /// Mixes `color` into the pixel at `x`, `y`, with `alpha` between 0 and 1.
+    fn blend(&mut self, x: u32, y: u32, color: Rgb, alpha: f64) {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        for (channel, value) in [color.0, color.1, color.2].into_iter().enumerate() {
+            let old = self.pixels[i + channel] as f64;
+            self.pixels[i + channel] = (old + (value as f64 - old) * alpha).round() as u8;
+        }
+    }
+
+    /// Draws a line `width` pixels wide with round ends from `from` to `to`, in pixels.
+    pub fn draw_line(&mut self, from: [f64; 2], to: [f64; 2], width: f64, color: Rgb) {
+        let radius = width / 2.0;
+        let min_x = (from[X].min(to[X]) - radius - 1.0).floor().max(0.0);
+        let min_y = (from[Y].min(to[Y]) - radius - 1.0).floor().max(0.0);
+        let max_x = (from[X].max(to[X]) + radius + 1.0)
+            .ceil()
+            .min(self.width as f64 - 1.0);
+        let max_y = (from[Y].max(to[Y]) + radius + 1.0)
+            .ceil()
+            .min(self.height as f64 - 1.0);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        let (dx, dy) = (to[X] - from[X], to[Y] - from[Y]);
+        let length2 = dx * dx + dy * dy;
+        for y in min_y as u32..=max_y as u32 {
+            for x in min_x as u32..=max_x as u32 {
+                // distance from the centre of the pixel to the segment
+                let (px, py) = (x as f64 + 0.5 - from[X], y as f64 + 0.5 - from[Y]);
+                let t = if length2 > 0.0 {
+                    ((px * dx + py * dy) / length2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let distance = (px - t * dx).hypot(py - t * dy);
+                let coverage = (radius + 0.5 - distance).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    self.blend(x, y, color, coverage);
+                }
+            }
+        }
+    }
+
+    /// The image encoded as PNG.
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(data)
+    }
+}
+
+/// Renders the layers `layers` of `toolpath` on top of each other in the colours of
+/// `colorizer`, on the bed of `profile` if given.
+pub fn rasterize(
+    toolpath: &Toolpath,
+    layers: Range<usize>,
+    colorizer: &Colorizer,
+    profile: Option<&MachineProfile>,
+    options: &RasterOptions,
+) -> Image {
+    let layers = select_layers(toolpath, layers);
+    let bed = profile.map(|profile| bed_outline(profile, options.view));
+    let bounds = view_bounds(toolpath, layers, options.view, options.travel, bed.as_ref());
+
+    let width = options.width.max(1);
+    let height = options.height.unwrap_or_else(|| {
+        (width as f64 * bounds.height() / bounds.width())
+            .round()
+            .max(1.0) as u32
+    });
+    let transform = Transform::fit(bounds, width, height);
+    let mut image = Image::new(width, height, options.background);
+
+    if let Some(bed) = bed {
+        fill_polygon(&mut image, &bed.map(|corner| transform.apply(corner)));
+        for (i, corner) in bed.iter().enumerate() {
+            image.draw_line(
+                transform.apply(*corner),
+                transform.apply(bed[(i + 1) % bed.len()]),
+                1.0,
+                BED_OUTLINE,
+            );
+        }
+    }
+
+    let line_width = (options.line_width * transform.scale).max(1.0);
+    for m in layers.iter().flat_map(|layer| toolpath.layer_moves(layer)) {
+        let color = if m.is_extrusion() {
+            match options.view {
+                View::Top => colorizer.color(m),
+                View::Isometric => shade(colorizer.color(m), m),
+            }
+        } else if options.travel && m.xy_length() > 0.0 {
+            TRAVEL_COLOR
+        } else {
+            continue;
+        };
+        let width = if m.is_extrusion() { line_width } else { 1.0 };
+        image.draw_line(
+            transform.apply(options.view.project(m.from)),
+            transform.apply(options.view.project(m.to)),
+            width,
+            color,
+        );
+    }
+    image
+}
+
+const BED_FILL: Rgb = Rgb(0xf0, 0xf0, 0xf0);
+const BED_OUTLINE: Rgb = Rgb(0x88, 0x88, 0x88);
+
+/// Maps the projected coordinates in mm to pixels, keeping the proportions.
+struct Transform {
+    min: [f64; 2],
+    max_y: f64,
+    scale: f64,
+    offset: [f64; 2],
+}
+
+impl Transform {
+    /// Fits `bounds` into `width` by `height` pixels, centred.
+    fn fit(bounds: Bounds, width: u32, height: u32) -> Self {
+        let scale = (width as f64 / bounds.width()).min(height as f64 / bounds.height());
+        Transform {
+            min: bounds.min,
+            max_y: bounds.max[Y],
+            scale,
+            offset: [
+                (width as f64 - bounds.width() * scale) / 2.0,
+                (height as f64 - bounds.height() * scale) / 2.0,
+            ],
+        }
+    }
+
+    fn apply(&self, point: [f64; 2]) -> [f64; 2] {
+        [
+            (point[X] - self.min[X]) * self.scale + self.offset[X],
+            (self.max_y - point[Y]) * self.scale + self.offset[Y],
+        ]
+    }
+}
+
+/// Fills the convex polygon `corners` with the colour of the bed.
+fn fill_polygon(image: &mut Image, corners: &[[f64; 2]]) {
+    let inside = |x: f64, y: f64| {
+        let mut sign = 0.0;
+        for (i, a) in corners.iter().enumerate() {
+            let b = corners[(i + 1) % corners.len()];
+            let cross = (b[X] - a[X]) * (y - a[Y]) - (b[Y] - a[Y]) * (x - a[X]);
+            if cross * sign < 0.0 {
+                return false;
+            }
+            if cross != 0.0 {
+                sign = cross;
+            }
+        }
+        true
+    };
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if inside(x as f64 + 0.5, y as f64 + 0.5) {
+                image.blend(x, y, BED_FILL, 1.0);
+            }
+        }
+    }
+}
+
+/// Darkens `color` for lines that face away from the light, which shines from the front left.
+fn shade(color: Rgb, m: &Move) -> Rgb {
+    let (dx, dy) = (m.to[X] - m.from[X], m.to[Y] - m.from[Y]);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return color;
+    }
+    // the side of the line facing the light
+    let facing = ((dy - dx) / length * std::f64::consts::FRAC_1_SQRT_2).abs();
+    let factor = 0.6 + 0.4 * facing;
+    let scale = |c: u8| (c as f64 * factor).round() as u8;
+    Rgb(scale(color.0), scale(color.1), scale(color.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_draw_anti_aliased_lines() {
+        let white = Rgb(0xff, 0xff, 0xff);
+        let red = Rgb(0xff, 0, 0);
+        let mut image = Image::new(20, 10, white);
+        image.draw_line([2.0, 5.5], [18.0, 5.5], 2.0, red);
+
+        assert_eq!(image.pixel(10, 5), red);
+        assert_eq!(image.pixel(10, 0), white);
+        // the pixels on the edges are half covered
+        let edge = image.pixel(10, 6);
+        assert_eq!(edge, image.pixel(10, 4));
+        assert!(edge.1 > 0x40 && edge.1 < 0xc0, "{:?}", edge);
+
+        let png = image.encode_png().unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
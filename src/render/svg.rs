@@ -0,0 +1,421 @@
+//! Renders layers of a [`Toolpath`] as SVG.
+//!
+//! Coordinates are kept in mm, the Y axis is flipped so that the front of the bed is at the
+//! bottom of the image. The isometric view projects X, Y and Z onto the same plane first.
+
+use crate::gcode::machine::{Layer, Move, TemperatureChange, Toolpath, X, Y};
+use crate::gcode::profile::MachineProfile;
+use crate::gcode::stats::format_duration;
+use crate::render::{
+    bed_outline, feature_color, select_layers, view_bounds, Bounds, Colorizer, Rgb, View,
+    TRAVEL_COLOR,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// How to draw layers.
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// Width of the image in pixels, the height follows from the bounds
+    pub width: f64,
+    /// Width of the extrusion lines in mm
+    pub line_width: f64,
+    /// Whether to draw travel moves as thin dashed lines
+    pub travel: bool,
+    pub view: View,
+    /// Whether [`toolpath_svg`] adds a legend of the colours to the right of the image
+    pub legend: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            width: 600.0,
+            line_width: 0.4,
+            travel: false,
+            view: View::Top,
+            legend: false,
+        }
+    }
+}
+
+/// Renders `layer` of `toolpath` in the colours of `colorizer`, showing the area `bounds`.
+pub fn layer_svg(
+    toolpath: &Toolpath,
+    layer: &Layer,
+    bounds: Bounds,
+    colorizer: &Colorizer,
+    options: &SvgOptions,
+) -> String {
+    let mut out = svg_header(bounds, options.width);
+    draw_moves(&mut out, toolpath.layer_moves(layer), colorizer, options);
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Renders the layers `layers` of `toolpath` on top of each other, with the outline of the
+/// bed of `profile` if given.
+pub fn toolpath_svg(
+    toolpath: &Toolpath,
+    layers: Range<usize>,
+    colorizer: &Colorizer,
+    profile: Option<&MachineProfile>,
+    options: &SvgOptions,
+) -> String {
+    let layers = select_layers(toolpath, layers);
+    let bed = profile.map(|profile| bed_outline(profile, options.view));
+    let bounds = view_bounds(toolpath, layers, options.view, options.travel, bed.as_ref());
+
+    let legend = if options.legend {
+        colorizer.legend()
+    } else {
+        Vec::new()
+    };
+    let mut out = String::new();
+    if !legend.is_empty() {
+        const ROW: f64 = 18.0;
+        let height = options.width * bounds.height() / bounds.width();
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+            fmt(options.width + LEGEND_WIDTH),
+            fmt(height.max(ROW * legend.len() as f64 + 4.0))
+        );
+        for (i, (label, color)) in legend.iter().enumerate() {
+            legend_entry(
+                &mut out,
+                options.width + 10.0,
+                2.0 + ROW * i as f64,
+                12.0,
+                *color,
+                label,
+            );
+        }
+    }
+    out.push_str(&svg_header(bounds, options.width));
+    if let Some(bed) = bed {
+        let mut d = String::new();
+        for (i, corner) in bed.iter().enumerate() {
+            let _ = write!(
+                d,
+                "{}{} {}",
+                if i == 0 { "M" } else { "L" },
+                fmt(corner[X]),
+                fmt(-corner[Y])
+            );
+        }
+        let _ = writeln!(
+            out,
+            "<path d=\"{}Z\" fill=\"#f4f4f4\" stroke=\"#888\" stroke-width=\"{}\"/>",
+            d,
+            fmt(options.line_width)
+        );
+    }
+    // lower layers first, so that the upper ones cover them
+    for layer in layers {
+        draw_moves(&mut out, toolpath.layer_moves(layer), colorizer, options);
+    }
+    out.push_str("</svg>\n");
+    if !legend.is_empty() {
+        out.push_str("</svg>\n");
+    }
+    out
+}
+
+/// Width in pixels of the legend of [`toolpath_svg`].
+const LEGEND_WIDTH: f64 = 200.0;
+
+/// Draws `moves` with one path per colour, which keeps the file small.
+fn draw_moves(out: &mut String, moves: &[Move], colorizer: &Colorizer, options: &SvgOptions) {
+    if options.travel {
+        let travel: Vec<&Move> = moves.iter().filter(|m| !m.is_extrusion()).collect();
+        if !travel.is_empty() {
+            let _ = writeln!(
+                out,
+                "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-dasharray=\"{} {}\"/>",
+                path_data(&travel, options.view),
+                TRAVEL_COLOR.hex(),
+                fmt(options.line_width / 4.0),
+                fmt(options.line_width),
+                fmt(options.line_width)
+            );
+        }
+    }
+
+    let mut by_color: BTreeMap<String, Vec<&Move>> = BTreeMap::new();
+    for m in moves.iter().filter(|m| m.is_extrusion()) {
+        by_color
+            .entry(colorizer.color(m).hex())
+            .or_default()
+            .push(m);
+    }
+    for (color, moves) in by_color {
+        let _ = writeln!(
+            out,
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+            path_data(&moves, options.view),
+            color,
+            fmt(options.line_width)
+        );
+    }
+}
+
+/// The opening `<svg>` tag for `bounds` scaled to `width` pixels.
+pub(crate) fn svg_header(bounds: Bounds, width: f64) -> String {
+    let height = if bounds.width() > 0.0 {
+        width * bounds.height() / bounds.width()
+    } else {
+        width
+    };
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n",
+        fmt(width),
+        fmt(height),
+        fmt(bounds.min[0]),
+        fmt(-bounds.max[1]),
+        fmt(bounds.width()),
+        fmt(bounds.height())
+    )
+}
+
+/// SVG path data following `moves` seen from `view`, only starting a new subpath where they
+/// aren't connected.
+fn path_data(moves: &[&Move], view: View) -> String {
+    let mut d = String::new();
+    let mut last: Option<[f64; 3]> = None;
+    for m in moves {
+        if last != Some(m.from) {
+            let [x, y] = view.project(m.from);
+            let _ = write!(d, "M{} {}", fmt(x), fmt(-y));
+        }
+        let [x, y] = view.project(m.to);
+        let _ = write!(d, "L{} {}", fmt(x), fmt(-y));
+        last = Some(m.to);
+    }
+    d
+}
+
+/// A legend of the colours of `features`, one per row.
+pub fn feature_legend(features: &[&str]) -> String {
+    const ROW: f64 = 18.0;
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"240\" height=\"{}\">\n",
+        fmt(ROW * features.len() as f64 + 4.0)
+    );
+    for (i, feature) in features.iter().enumerate() {
+        legend_entry(
+            &mut out,
+            2.0,
+            2.0 + ROW * i as f64,
+            12.0,
+            feature_color(Some(feature)),
+            feature,
+        );
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Charts the hotend and bed targets and the fan speed over the estimated print time.
+pub fn temperature_chart(toolpath: &Toolpath, width: f64, height: f64) -> String {
+    const MARGIN: f64 = 40.0;
+    let end_time = toolpath.final_state.time.max(1.0);
+    let max_temperature = toolpath
+        .temperatures
+        .iter()
+        .map(|t| t.hotend.max(t.bed))
+        .fold(50.0_f64, f64::max);
+    let max_temperature = (max_temperature / 50.0).ceil() * 50.0;
+    let x = |time: f64| MARGIN + (width - 2.0 * MARGIN) * time / end_time;
+    let y = |value: f64, max: f64| height - MARGIN - (height - 2.0 * MARGIN) * value / max;
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+        fmt(width),
+        fmt(height)
+    );
+    // axes with a tick every 50°C and at the end of the print
+    let _ = writeln!(
+        out,
+        "<path d=\"M{0} {1}V{2}H{3}\" fill=\"none\" stroke=\"#444\"/>",
+        fmt(MARGIN),
+        fmt(MARGIN),
+        fmt(height - MARGIN),
+        fmt(width - MARGIN)
+    );
+    let mut tick = 0.0;
+    while tick <= max_temperature {
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}°C</text>",
+            fmt(MARGIN - 4.0),
+            fmt(y(tick, max_temperature) + 4.0),
+            tick
+        );
+        tick += 50.0;
+    }
+    let _ = writeln!(
+        out,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        fmt(width - MARGIN),
+        fmt(height - MARGIN + 16.0),
+        format_duration(end_time)
+    );
+
+    type Series = (
+        &'static str,
+        Rgb,
+        fn(&TemperatureChange) -> f64,
+        f64,
+        &'static str,
+    );
+    let series: [Series; 3] = [
+        (
+            "Hotend",
+            Rgb(0xd0, 0x30, 0x20),
+            |t| t.hotend,
+            max_temperature,
+            "",
+        ),
+        ("Bed", Rgb(0x20, 0x60, 0xd0), |t| t.bed, max_temperature, ""),
+        (
+            "Fan (0-255)",
+            Rgb(0x40, 0xa0, 0x40),
+            |t| t.fan_speed,
+            255.0,
+            "4 3",
+        ),
+    ];
+    for (i, (label, color, value, max, dash)) in series.iter().enumerate() {
+        // step line, the value holds until the next change
+        let mut d = format!("M{} {}", fmt(x(0.0)), fmt(y(0.0, *max)));
+        let mut last = 0.0;
+        for change in toolpath.temperatures.iter() {
+            if value(change) != last {
+                last = value(change);
+                let _ = write!(d, "H{}V{}", fmt(x(change.time)), fmt(y(last, *max)));
+            }
+        }
+        let _ = write!(d, "H{}", fmt(x(end_time)));
+        let _ = writeln!(
+            out,
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" stroke-dasharray=\"{}\"/>",
+            d,
+            color.hex(),
+            if dash.is_empty() { "none" } else { dash }
+        );
+        legend_entry(
+            &mut out,
+            MARGIN + 10.0 + 110.0 * i as f64,
+            6.0,
+            11.0,
+            *color,
+            label,
+        );
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+/// A legend entry, a colour swatch followed by a label.
+fn legend_entry(out: &mut String, x: f64, y: f64, size: f64, color: Rgb, label: &str) {
+    let _ = writeln!(
+        out,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/><text x=\"{}\" y=\"{}\" font-size=\"{}\" font-family=\"sans-serif\">{}</text>",
+        fmt(x),
+        fmt(y),
+        fmt(size),
+        fmt(size),
+        color.hex(),
+        fmt(x + size * 1.5),
+        fmt(y + size * 0.85),
+        fmt(size),
+        escape(label)
+    );
+}
+
+/// Formats a coordinate with at most 3 decimals.
+pub(crate) fn fmt(value: f64) -> String {
+    let s = format!("{:.3}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" | "" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// Escapes text for XML content and attributes.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::parallel::simulate;
+    use crate::render::{extrusion_bounds, ColorBy};
+
+    const SRC: &str = ";LAYER:0\nG1 Z0.2 F600\nG0 X10 Y10\n;TYPE:WALL-OUTER\nG1 X20 Y10 E1\n\
+                       G1 X20 Y20 E2\n;TYPE:FILL\nG1 X10 Y20 E3\n;LAYER:1\nG1 Z0.4\nG0 X10 Y10\n\
+                       ;TYPE:WALL-OUTER\nG1 X20 Y10 E4\n";
+
+    #[test]
+    fn should_draw_a_layer_with_one_path_per_colour() {
+        let toolpath = simulate(SRC);
+        let profile = MachineProfile::default();
+        let colorizer = Colorizer::new(&toolpath, ColorBy::Feature, &profile);
+        let bounds = extrusion_bounds(&toolpath).unwrap().expand(2.0);
+        let svg = layer_svg(
+            &toolpath,
+            &toolpath.layers[0],
+            bounds,
+            &colorizer,
+            &SvgOptions::default(),
+        );
+
+        // Y is flipped, so the top of the view box is at -max Y
+        assert!(svg.starts_with(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"600\" height=\"600\" \
+             viewBox=\"8 -22 14 14\">\n"
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<path").count(), 2);
+        assert!(svg.contains("<path d=\"M10 -10L20 -10L20 -20\" fill=\"none\" stroke=\"#e04030\""));
+        assert!(svg.contains("<path d=\"M20 -20L10 -20\" fill=\"none\" stroke=\"#c06020\""));
+        assert!(!svg.contains("stroke-dasharray"));
+    }
+
+    #[test]
+    fn should_draw_the_selected_layers_with_the_bed_travel_and_legend() {
+        let toolpath = simulate(SRC);
+        let profile = MachineProfile::default();
+        let colorizer = Colorizer::new(&toolpath, ColorBy::Feature, &profile);
+        let options = SvgOptions {
+            travel: true,
+            legend: true,
+            ..SvgOptions::default()
+        };
+
+        let svg = toolpath_svg(&toolpath, 1..5, &colorizer, Some(&profile), &options);
+        // the legend is an outer image holding the toolpath image
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800\""));
+        assert_eq!(svg.matches("</svg>").count(), 2);
+        assert!(svg.contains(">WALL-OUTER</text>"));
+        assert!(svg.contains("<path d=\"M0 0L220 0L220 -220L0 -220Z\" fill=\"#f4f4f4\""));
+        // only layer 1, its travel dashed
+        assert!(svg.contains("<path d=\"M10 -10L20 -10\" fill=\"none\" stroke=\"#e04030\""));
+        assert!(!svg.contains("stroke=\"#c06020\""));
+        assert!(svg.contains("stroke-dasharray=\"0.4 0.4\""));
+
+        let plain = toolpath_svg(&toolpath, 0..1, &colorizer, None, &SvgOptions::default());
+        assert_eq!(plain.matches("</svg>").count(), 1);
+        assert!(!plain.contains("#f4f4f4"));
+        assert!(plain.contains("#c06020"));
+        assert!(!plain.contains("M10 -10L20 -10\" fill=\"none\" stroke=\"#e04030\""));
+    }
+}
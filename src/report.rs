@@ -0,0 +1,377 @@
+//! A self-contained HTML report of a G-code file.
+//!
+//! The report has a summary of the statistics, per feature and per layer tables, a chart of
+//! the temperatures over time, an SVG of every layer and the listing of the file with the
+//! documentation of each command in a tooltip. Everything is inlined, so the page can be
+//! attached to a ticket or opened without network access.
+
+use crate::gcode::lines::{opcode_name, parse_lines};
+use crate::gcode::machine::Toolpath;
+use crate::gcode::marlin_docs::Opcodes;
+use crate::gcode::parallel::simulate;
+use crate::gcode::profile::MachineProfile;
+use crate::gcode::stats::{format_duration, stats, Stats};
+use crate::gcode::thumbnails::{find_thumbnails, Thumbnail, ThumbnailFormat};
+use crate::render::svg::{escape, feature_legend, layer_svg, temperature_chart, SvgOptions};
+use crate::render::{extrusion_bounds, ColorBy, Colorizer};
+use std::fmt::Write;
+
+/// What to include in the report.
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    /// Maximum number of lines of the listing, the rest is left out
+    pub max_listing_lines: usize,
+    /// Options for the layer images
+    pub svg: SvgOptions,
+    /// The metadata of the package the file was read from, see
+    /// [`Plate::metadata`](crate::gcode::container::Plate::metadata)
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self {
+            max_listing_lines: 20_000,
+            svg: SvgOptions::default(),
+            metadata: Vec::new(),
+        }
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 1em 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+pre.listing { line-height: 1.3; }
+pre.listing span[title] { text-decoration: underline dotted #999; }
+pre.listing .n { color: #999; user-select: none; }
+pre.listing .c { color: #080; }
+details { margin: 0.3em 0; }
+.layer svg { border: 1px solid #ddd; }
+";
+
+/// Generates the report of the G-code `src`, named `title`.
+pub fn html_report(title: &str, src: &str, opcodes: &Opcodes, options: &ReportOptions) -> String {
+    let toolpath = simulate(src);
+    let stats = stats(&toolpath);
+
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         <style>\n{1}</style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title),
+        STYLE
+    );
+
+    summary(&mut out, &stats);
+    package(&mut out, &options.metadata);
+    features(&mut out, &stats);
+
+    out.push_str("<h2>Temperatures</h2>\n");
+    out.push_str(&temperature_chart(&toolpath, 800.0, 260.0));
+
+    layers(&mut out, &toolpath, &stats, options);
+    listing(&mut out, src, opcodes, options.max_listing_lines);
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn summary(out: &mut String, stats: &Stats) {
+    let mut rows = vec![
+        ("Lines", stats.lines.to_string()),
+        ("Layers", stats.layers.len().to_string()),
+        ("Moves", stats.moves.to_string()),
+        ("Estimated time", format_duration(stats.time)),
+        ("Filament", format!("{:.2} m", stats.filament / 1000.0)),
+        (
+            "Extruding distance",
+            format!("{:.2} m", stats.extrusion_distance / 1000.0),
+        ),
+        (
+            "Travel distance",
+            format!("{:.2} m", stats.travel_distance / 1000.0),
+        ),
+        ("Retractions", stats.retractions.to_string()),
+        (
+            "Max hotend temperature",
+            format!("{}°C", stats.max_hotend_temperature),
+        ),
+        (
+            "Max bed temperature",
+            format!("{}°C", stats.max_bed_temperature),
+        ),
+    ];
+    if let Some((min, max)) = stats.bounds {
+        rows.push((
+            "Size",
+            format!(
+                "{:.1} × {:.1} × {:.1} mm",
+                max[0] - min[0],
+                max[1] - min[1],
+                max[2] - min[2]
+            ),
+        ));
+    }
+
+    out.push_str("<h2>Summary</h2>\n<table>\n");
+    for (label, value) in rows {
+        let _ = writeln!(
+            out,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            label,
+            escape(&value)
+        );
+    }
+    out.push_str("</table>\n<p>Times ignore acceleration and heating.</p>\n");
+}
+
+fn package(out: &mut String, metadata: &[(String, String)]) {
+    if metadata.is_empty() {
+        return;
+    }
+    out.push_str("<h2>Package</h2>\n<table>\n");
+    for (key, value) in metadata {
+        let _ = writeln!(
+            out,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(key),
+            escape(value)
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+fn features(out: &mut String, stats: &Stats) {
+    if stats.features.is_empty() {
+        return;
+    }
+    out.push_str(
+        "<h2>Features</h2>\n<table>\n<tr><th>Feature</th><th>Moves</th><th>Distance (m)</th>\
+         <th>Filament (m)</th><th>Time</th></tr>\n",
+    );
+    for f in stats.features.iter() {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{:.2}</td><td>{}</td></tr>",
+            escape(&f.feature),
+            f.moves,
+            f.distance / 1000.0,
+            f.filament / 1000.0,
+            format_duration(f.time)
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+fn layers(out: &mut String, toolpath: &Toolpath, stats: &Stats, options: &ReportOptions) {
+    let bounds = match extrusion_bounds(toolpath) {
+        Some(bounds) => bounds.expand(2.0),
+        None => return,
+    };
+    out.push_str("<h2>Layers</h2>\n");
+    let names: Vec<&str> = stats.features.iter().map(|f| f.feature.as_str()).collect();
+    out.push_str(&feature_legend(&names));
+    let colorizer = Colorizer::new(toolpath, ColorBy::Feature, &MachineProfile::default());
+
+    for (layer, layer_stats) in toolpath.layers.iter().zip(stats.layers.iter()) {
+        let z = layer.z.map_or("-".to_string(), |z| format!("{:.2} mm", z));
+        let _ = writeln!(
+            out,
+            "<details class=\"layer\"{}><summary>Layer {} at {}, {} moves, {}, \
+             <a href=\"#L{}\">line {}</a></summary>",
+            if layer.index == 0 { " open" } else { "" },
+            layer.index,
+            z,
+            layer_stats.moves,
+            format_duration(layer_stats.time),
+            layer.lines.start + 1,
+            layer.lines.start + 1
+        );
+        out.push_str(&layer_svg(
+            toolpath,
+            layer,
+            bounds,
+            &colorizer,
+            &options.svg,
+        ));
+        out.push_str("</details>\n");
+    }
+}
+
+/// A thumbnail block of the listing, as an image if browsers can show it.
+fn thumbnail_line(out: &mut String, thumbnail: &Thumbnail) {
+    let _ = writeln!(
+        out,
+        "<span class=\"n\" id=\"L{0}\">{0:>7} </span><span class=\"c\">; {1} thumbnail {2}x{3}, lines {0}-{4}</span>",
+        thumbnail.lines.start + 1,
+        thumbnail.format,
+        thumbnail.width,
+        thumbnail.height,
+        thumbnail.lines.end
+    );
+    let mime = match thumbnail.format {
+        ThumbnailFormat::Png => "image/png",
+        ThumbnailFormat::Jpg => "image/jpeg",
+        ThumbnailFormat::Qoi => return,
+    };
+    if thumbnail.data().is_ok() {
+        let _ = writeln!(
+            out,
+            "<img src=\"data:{};base64,{}\" width=\"{}\" height=\"{}\" alt=\"thumbnail\">",
+            mime, thumbnail.base64, thumbnail.width, thumbnail.height
+        );
+    }
+}
+
+/// The lines of `src`, with the documentation of their commands as tooltip.
+fn listing(out: &mut String, src: &str, opcodes: &Opcodes, max_lines: usize) {
+    out.push_str("<h2>Listing</h2>\n<pre class=\"listing\">");
+    let thumbnails = find_thumbnails(src);
+    let mut thumbnails = thumbnails.iter().peekable();
+    for (number, (text, line)) in src.lines().zip(parse_lines(src)).enumerate() {
+        // thumbnails are shown as images instead of their base64
+        thumbnails.next_if(|t| t.lines.end <= number);
+        if let Some(thumbnail) = thumbnails.peek().filter(|t| t.lines.contains(&number)) {
+            if number == thumbnail.lines.start && number < max_lines {
+                thumbnail_line(out, thumbnail);
+            }
+            continue;
+        }
+        if number >= max_lines {
+            let _ = writeln!(
+                out,
+                "<span class=\"n\">… {} more lines</span>",
+                src.lines().count() - max_lines
+            );
+            break;
+        }
+        let tooltip: Vec<String> = line
+            .gcodes()
+            .iter()
+            .filter_map(|gcode| {
+                let od = opcodes.get(&opcode_name(gcode))?;
+                Some(format!(
+                    "{} - {}: {}",
+                    od.codes.join(", "),
+                    od.title,
+                    od.brief
+                ))
+            })
+            .collect();
+        let _ = write!(
+            out,
+            "<span class=\"n\" id=\"L{0}\">{0:>7} </span>",
+            number + 1
+        );
+        let class = if line.gcodes().is_empty() && !line.comments().is_empty() {
+            " class=\"c\""
+        } else {
+            ""
+        };
+        if tooltip.is_empty() {
+            let _ = writeln!(out, "<span{}>{}</span>", class, escape(text));
+        } else {
+            let _ = writeln!(
+                out,
+                "<span title=\"{}\">{}</span>",
+                escape(&tooltip.join("\n")),
+                escape(text)
+            );
+        }
+    }
+    out.push_str("</pre>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::marlin_docs::OpcodeDescription;
+    use std::sync::Arc;
+
+    fn opcodes() -> Opcodes {
+        let descriptions: Vec<OpcodeDescription> = serde_json::from_str(
+            r#"[
+                {"tag": "g000-g001", "title": "Linear Move", "brief": "Add a straight line movement to the planner", "codes": ["G0", "G1"]}
+            ]"#,
+        )
+        .unwrap();
+        descriptions
+            .into_iter()
+            .flat_map(|od| {
+                let od = Arc::new(od);
+                od.codes
+                    .clone()
+                    .into_iter()
+                    .map(move |code| (code, od.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_escape_the_listing_and_document_its_commands() {
+        let src = "; <start> & \"go\"\nG28\n;LAYER:0\nG1 Z0.2 F600\nG1 X10 Y0 E1 F1200\n\
+                   G1 X10 Y10 E2\n;LAYER:1\nG1 Z0.4\nG1 X0 Y10 E3\n";
+        let report = html_report("a <b> & c", src, &opcodes(), &ReportOptions::default());
+
+        assert!(report.contains("<title>a &lt;b&gt; &amp; c</title>"));
+        assert!(report.contains("<span class=\"c\">; &lt;start&gt; &amp; &quot;go&quot;</span>"));
+        assert!(!report.contains("<start>"));
+        assert!(report.contains(
+            "<span title=\"G0, G1 - Linear Move: Add a straight line movement to the planner\">\
+             G1 X10 Y0 E1 F1200</span>"
+        ));
+        // G28 isn't documented here
+        assert!(report.contains("<span>G28</span>"));
+        assert!(report.contains("<span class=\"n\" id=\"L9\">      9 </span>"));
+
+        // one section with an image per layer, the first one open
+        let toolpath = simulate(src);
+        assert_eq!(
+            report.matches("<details class=\"layer\"").count(),
+            toolpath.layers.len()
+        );
+        assert!(report.contains("<details class=\"layer\" open><summary>Layer 0 at"));
+        let layer = report.split("<summary>Layer 1 at").nth(1).unwrap();
+        assert!(layer.contains(&format!(
+            "<a href=\"#L{0}\">line {0}</a></summary>\n<svg",
+            toolpath.layers[1].lines.start + 1
+        )));
+        assert!(layer[..layer.find("</details>").unwrap()].contains("<path"));
+    }
+
+    #[test]
+    fn should_cut_the_listing() {
+        let src = "G1 X1 E1\nG1 X2 E2\nG1 X3 E3\n";
+        let options = ReportOptions {
+            max_listing_lines: 2,
+            ..ReportOptions::default()
+        };
+        let report = html_report("cut", src, &Opcodes::new(), &options);
+        assert!(report.contains("<span>G1 X2 E2</span>"));
+        assert!(!report.contains("G1 X3 E3"));
+        assert!(report.contains("… 1 more lines"));
+    }
+
+    #[test]
+    fn should_show_the_metadata_of_the_package() {
+        let options = ReportOptions {
+            metadata: vec![
+                ("prediction".into(), "5863".into()),
+                ("note".into(), "<a>".into()),
+            ],
+            ..ReportOptions::default()
+        };
+        let report = html_report("plate", "G1 X1 E1\n", &Opcodes::new(), &options);
+        assert!(report.contains(
+            "<h2>Package</h2>\n<table>\n<tr><th>prediction</th><td>5863</td></tr>\n\
+             <tr><th>note</th><td>&lt;a&gt;</td></tr>\n</table>"
+        ));
+        let report = html_report(
+            "plain",
+            "G1 X1 E1\n",
+            &Opcodes::new(),
+            &ReportOptions::default(),
+        );
+        assert!(!report.contains("Package"));
+    }
+}
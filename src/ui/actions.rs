@@ -11,17 +11,61 @@ pub enum Action {
     Sleep,
     IncrementDelay,
     DecrementDelay,
+    /// Move the cursor in the listing
+    MoveUp,
+    MoveDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    /// Open the popup to go to a line or layer
+    GoTo,
+    /// Move the cursor to the start of the next or previous layer
+    NextLayer,
+    PreviousLayer,
+    /// Show the layer under the cursor coloured by extrusion width instead of the documentation
+    LayerView,
+    /// Open the documentation search popup
+    Search,
+    /// Go back to showing the documentation of the line under the cursor
+    ClearDoc,
+    /// Open the documentation group navigator
+    Groups,
+    /// Select the next link of the documentation panel
+    NextLink,
+    /// Show the documentation the selected link points to
+    FollowLink,
+    /// Move through the history of followed links
+    Back,
+    Forward,
 }
 
 impl Action {
     /// All available actions
     #[allow(unused)]
     pub fn iterator() -> Iter<'static, Action> {
-        static ACTIONS: [Action; 4] = [
+        static ACTIONS: [Action; 21] = [
             Action::Quit,
             Action::Sleep,
             Action::IncrementDelay,
             Action::DecrementDelay,
+            Action::MoveUp,
+            Action::MoveDown,
+            Action::PageUp,
+            Action::PageDown,
+            Action::Top,
+            Action::Bottom,
+            Action::GoTo,
+            Action::NextLayer,
+            Action::PreviousLayer,
+            Action::LayerView,
+            Action::Search,
+            Action::ClearDoc,
+            Action::Groups,
+            Action::NextLink,
+            Action::FollowLink,
+            Action::Back,
+            Action::Forward,
         ];
         ACTIONS.iter()
     }
@@ -33,6 +77,23 @@ impl Action {
             Action::Sleep => &[Key::Char('s')],
             Action::IncrementDelay => &[Key::Char('+')],
             Action::DecrementDelay => &[Key::Char('-')],
+            Action::MoveUp => &[Key::Up, Key::Char('k')],
+            Action::MoveDown => &[Key::Down, Key::Char('j')],
+            Action::PageUp => &[Key::PageUp],
+            Action::PageDown => &[Key::PageDown, Key::Char(' ')],
+            Action::Top => &[Key::Home],
+            Action::Bottom => &[Key::End],
+            Action::GoTo => &[Key::Char(':')],
+            Action::NextLayer => &[Key::Char(']')],
+            Action::PreviousLayer => &[Key::Char('[')],
+            Action::LayerView => &[Key::Char('v')],
+            Action::Search => &[Key::Char('/')],
+            Action::ClearDoc => &[Key::Esc],
+            Action::Groups => &[Key::Char('g')],
+            Action::NextLink => &[Key::Tab],
+            Action::FollowLink => &[Key::Enter],
+            Action::Back => &[Key::Backspace, Key::Left],
+            Action::Forward => &[Key::Right],
         }
     }
 }
@@ -45,6 +106,23 @@ impl Display for Action {
             Action::Sleep => "Sleep",
             Action::IncrementDelay => "Increment delay",
             Action::DecrementDelay => "Decrement delay",
+            Action::MoveUp => "Up",
+            Action::MoveDown => "Down",
+            Action::PageUp => "Page up",
+            Action::PageDown => "Page down",
+            Action::Top => "Top",
+            Action::Bottom => "Bottom",
+            Action::GoTo => "Go to line/layer",
+            Action::NextLayer => "Next layer",
+            Action::PreviousLayer => "Previous layer",
+            Action::LayerView => "Layer widths",
+            Action::Search => "Search docs",
+            Action::ClearDoc => "Docs under cursor",
+            Action::Groups => "Browse groups",
+            Action::NextLink => "Next link",
+            Action::FollowLink => "Follow link",
+            Action::Back => "Back",
+            Action::Forward => "Forward",
         };
         write!(f, "{}", str)
     }
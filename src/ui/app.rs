@@ -1,12 +1,24 @@
 use crate::ui::actions::{Action, Actions};
-use crate::ui::app::AppState::Initialized;
+use crate::ui::groups::{DocUsage, GroupTree};
 use crate::ui::io::IoEvent;
 use crate::ui::key::Key;
+use crate::ui::layer_view::LayerView;
+use crate::ui::listing::Listing;
+use gcode_viz::gcode::lines::opcode_name;
+use gcode_viz::gcode::marlin_docs::groups::group_index;
+use gcode_viz::gcode::marlin_docs::links::{cross_references, CrossReferences};
+use gcode_viz::gcode::marlin_docs::{self, OpcodeDescription, Opcodes, SearchHit};
+use gcode_viz::gcode::thumbnails::{Thumbnail, ThumbnailError};
+use gcode_viz::render::raster::Image;
+use gcode_viz::render::Rgb;
 use log::{error, warn};
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
+#[allow(unused)]
 pub enum AppState {
+    #[default]
     Init,
     Initialized {
         duration: Duration,
@@ -15,6 +27,7 @@ pub enum AppState {
     },
 }
 
+#[allow(unused)]
 impl AppState {
     pub fn initialized() -> Self {
         let duration = Duration::from_secs(1);
@@ -84,12 +97,76 @@ impl AppState {
     }
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self::Init
+/// A line of the file being browsed.
+pub struct ListingLine {
+    pub text: String,
+    /// The first command on the line, e.g. `G1`
+    pub opcode: Option<String>,
+    /// Line of the file, starting at 0
+    pub number: usize,
+    /// The thumbnail collapsed into this line
+    pub thumbnail: Option<Arc<ThumbnailPreview>>,
+}
+
+impl ListingLine {
+    pub fn new(number: usize, text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            opcode: gcode::parse(text).next().map(|g| opcode_name(&g)),
+            number,
+            thumbnail: None,
+        }
+    }
+
+    /// The line standing for the collapsed block of `preview`.
+    pub fn thumbnail(preview: Arc<ThumbnailPreview>) -> Self {
+        let thumbnail = &preview.thumbnail;
+        Self {
+            text: format!(
+                "; {} thumbnail {}x{} ({} lines)",
+                thumbnail.format,
+                thumbnail.width,
+                thumbnail.height,
+                thumbnail.lines.len()
+            ),
+            opcode: None,
+            number: thumbnail.lines.start,
+            thumbnail: Some(preview),
+        }
+    }
+}
+
+/// A thumbnail of the file and its pixels, decoded once.
+pub struct ThumbnailPreview {
+    pub thumbnail: Thumbnail,
+    pub image: Result<Image, ThumbnailError>,
+}
+
+impl ThumbnailPreview {
+    pub fn new(thumbnail: Thumbnail) -> Self {
+        let image = thumbnail.decode(Rgb(0, 0, 0));
+        Self { thumbnail, image }
     }
 }
 
+/// The documentation search popup.
+#[derive(Default)]
+pub struct SearchPopup {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+    pub selected: usize,
+}
+
+/// The documentation shown before following links, to go back and forth.
+#[derive(Default)]
+pub struct DocHistory {
+    back: Vec<Arc<OpcodeDescription>>,
+    forward: Vec<Arc<OpcodeDescription>>,
+}
+
+/// Number of search results shown in the popup.
+const SEARCH_LIMIT: usize = 50;
+
 #[allow(unused)]
 pub struct App {
     /// Contextual actions
@@ -98,6 +175,36 @@ pub struct App {
     state: AppState,
     io_tx: tokio::sync::mpsc::Sender<IoEvent>,
     is_loading: bool,
+    opcodes: Arc<Opcodes>,
+    /// Name of the file being browsed
+    title: String,
+    listing: Listing,
+    /// Row of the cursor
+    selected: usize,
+    /// First line of the listing shown on screen
+    offset: usize,
+    /// Number of listing lines that fit on screen, updated when drawing
+    page_size: usize,
+    /// Documentation picked in the search popup, shown instead of the one under the cursor
+    pinned_doc: Option<Arc<OpcodeDescription>>,
+    search: Option<SearchPopup>,
+    /// The group navigator, shown instead of the listing while open
+    groups: Option<GroupTree>,
+    /// How often the browsed file uses each description, updated while the file is indexed
+    doc_usage: DocUsage,
+    /// The line or layer typed in the go to popup
+    goto: Option<String>,
+    /// Whether the usage counts saw the whole file
+    indexed: bool,
+    /// The layers coloured by extrusion width, simulated when first shown
+    layer_view: Option<LayerView>,
+    /// Whether the layer view is shown instead of the documentation
+    show_layer: bool,
+    /// Links between the descriptions
+    xrefs: CrossReferences,
+    /// Index of the selected link in the references of the current doc
+    link: usize,
+    history: DocHistory,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -107,16 +214,80 @@ pub enum AppReturn {
 }
 
 impl App {
-    pub fn new(io_tx: tokio::sync::mpsc::Sender<IoEvent>) -> Self {
-        Self {
-            actions: vec![Action::Quit].into(),
+    pub fn new(
+        io_tx: tokio::sync::mpsc::Sender<IoEvent>,
+        opcodes: Arc<Opcodes>,
+        title: String,
+        listing: Listing,
+    ) -> Self {
+        let mut app = Self {
+            actions: vec![
+                Action::Quit,
+                Action::MoveUp,
+                Action::MoveDown,
+                Action::PageUp,
+                Action::PageDown,
+                Action::Top,
+                Action::Bottom,
+                Action::GoTo,
+                Action::PreviousLayer,
+                Action::NextLayer,
+                Action::LayerView,
+                Action::Search,
+                Action::ClearDoc,
+                Action::Groups,
+                Action::NextLink,
+                Action::FollowLink,
+                Action::Back,
+                Action::Forward,
+            ]
+            .into(),
             state: AppState::default(),
             io_tx,
             is_loading: false,
+            title,
+            listing,
+            selected: 0,
+            offset: 0,
+            page_size: 1,
+            pinned_doc: None,
+            search: None,
+            groups: None,
+            doc_usage: DocUsage::new(),
+            goto: None,
+            indexed: false,
+            layer_view: None,
+            show_layer: false,
+            xrefs: cross_references(&opcodes),
+            link: 0,
+            history: DocHistory::default(),
+            opcodes,
+        };
+        app.update_doc_usage();
+        app
+    }
+
+    /// Counts the uses of each description in the lines indexed so far.
+    fn update_doc_usage(&mut self) {
+        self.doc_usage.clear();
+        for (opcode, count) in self.listing.index().commands() {
+            if let Some(od) = self.opcodes.get(&opcode) {
+                *self.doc_usage.entry(od.tag.clone()).or_default() += count;
+            }
         }
     }
 
     pub async fn do_action(&mut self, key: Key) -> AppReturn {
+        if self.search.is_some() {
+            return self.do_search_action(key);
+        }
+        if self.groups.is_some() {
+            return self.do_groups_action(key);
+        }
+        if self.goto.is_some() {
+            return self.do_goto_action(key);
+        }
+
         if let Some(action) = self.actions.find(key) {
             match action {
                 Action::Quit => AppReturn::Exit,
@@ -134,6 +305,94 @@ impl App {
                     self.state.decrement_delay();
                     AppReturn::Continue
                 }
+                Action::MoveUp => self.move_cursor(-1),
+                Action::MoveDown => self.move_cursor(1),
+                Action::PageUp => self.move_cursor(-(self.page_size as isize)),
+                Action::PageDown => self.move_cursor(self.page_size as isize),
+                Action::Top => self.move_cursor(isize::MIN),
+                Action::Bottom => self.move_cursor(isize::MAX),
+                Action::GoTo => {
+                    self.goto = Some(String::new());
+                    AppReturn::Continue
+                }
+                Action::NextLayer => {
+                    let line = self.listing.line_of_row(self.selected);
+                    let layers = self.listing.index().layers();
+                    if let Some(&start) = layers.iter().find(|&&start| start > line) {
+                        self.go_to_line(start);
+                    }
+                    AppReturn::Continue
+                }
+                Action::PreviousLayer => {
+                    let line = self.listing.line_of_row(self.selected);
+                    let layers = self.listing.index().layers();
+                    if let Some(&start) = layers.iter().rev().find(|&&start| start < line) {
+                        self.go_to_line(start);
+                    }
+                    AppReturn::Continue
+                }
+                Action::LayerView => {
+                    self.show_layer = !self.show_layer;
+                    if self.layer_view.is_none() {
+                        let text = std::sync::Arc::clone(self.listing.text());
+                        self.layer_view = Some(LayerView::spawn(text));
+                    }
+                    AppReturn::Continue
+                }
+                Action::Search => {
+                    self.search = Some(SearchPopup::default());
+                    AppReturn::Continue
+                }
+                Action::ClearDoc => {
+                    self.pinned_doc = None;
+                    self.link = 0;
+                    AppReturn::Continue
+                }
+                Action::Groups => {
+                    self.groups = Some(GroupTree::new(group_index(&self.opcodes)));
+                    AppReturn::Continue
+                }
+                Action::NextLink => {
+                    let count = self
+                        .current_doc()
+                        .map_or(0, |od| self.xrefs.references(&od).len());
+                    self.link = if count == 0 {
+                        0
+                    } else {
+                        (self.link + 1) % count
+                    };
+                    AppReturn::Continue
+                }
+                Action::FollowLink => {
+                    if let (Some(current), Some(target)) =
+                        (self.current_doc(), self.selected_link())
+                    {
+                        self.history.back.push(current);
+                        self.history.forward.clear();
+                        self.show_doc(target);
+                    }
+                    AppReturn::Continue
+                }
+                Action::Back => {
+                    // only pop once there is a page to come back to, or the entry is lost
+                    if let Some(current) = self.current_doc() {
+                        if let Some(previous) = self.history.back.pop() {
+                            self.history.forward.push(current);
+                            self.show_doc(previous);
+                        }
+                    }
+                    AppReturn::Continue
+                }
+                Action::Forward => {
+                    // only pop once there is a page to come back to, or the entry is lost
+                    if let Some(current) = self.current_doc() {
+                        if let Some(next) = self.history.forward.pop() {
+                            self.history.back.push(current);
+                            self.show_doc(next);
+                        }
+                    }
+                    AppReturn::Continue
+                }
             }
         } else {
             warn!("No action associated with {}", key);
@@ -141,6 +400,229 @@ impl App {
         }
     }
 
+    /// Keys typed while the search popup is open edit the query instead of triggering actions.
+    fn do_search_action(&mut self, key: Key) -> AppReturn {
+        let Some(search) = self.search.as_mut() else {
+            return AppReturn::Continue;
+        };
+        match key {
+            Key::Ctrl('c') => return AppReturn::Exit,
+            Key::Esc => self.search = None,
+            Key::Enter => {
+                let hit = search
+                    .hits
+                    .get(search.selected)
+                    .map(|h| h.description.clone());
+                self.search = None;
+                if let Some(od) = hit {
+                    self.show_doc(od);
+                }
+            }
+            Key::Up => search.selected = search.selected.saturating_sub(1),
+            Key::Down => {
+                search.selected = (search.selected + 1).min(search.hits.len().saturating_sub(1))
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.update_search();
+            }
+            Key::Char(c) => {
+                search.query.push(c);
+                self.update_search();
+            }
+            _ => {}
+        }
+        AppReturn::Continue
+    }
+
+    /// Keys while the group navigator is open move through the tree.
+    fn do_groups_action(&mut self, key: Key) -> AppReturn {
+        let Some(groups) = self.groups.as_mut() else {
+            return AppReturn::Continue;
+        };
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return AppReturn::Exit,
+            Key::Esc | Key::Char('g') => self.groups = None,
+            Key::Up | Key::Char('k') => groups.move_cursor(-1),
+            Key::Down | Key::Char('j') => groups.move_cursor(1),
+            Key::PageUp => groups.move_cursor(-(self.page_size as isize)),
+            Key::PageDown => groups.move_cursor(self.page_size as isize),
+            Key::Right | Key::Char('l') => groups.set_expanded(Some(true)),
+            Key::Left | Key::Char('h') => groups.set_expanded(Some(false)),
+            Key::Enter => match groups.selected_doc() {
+                Some(od) => {
+                    self.groups = None;
+                    self.show_doc(od);
+                }
+                None => groups.set_expanded(None),
+            },
+            _ => {}
+        }
+        AppReturn::Continue
+    }
+
+    /// Keys while the go to popup is open edit the line or layer to go to.
+    fn do_goto_action(&mut self, key: Key) -> AppReturn {
+        let Some(goto) = self.goto.as_mut() else {
+            return AppReturn::Continue;
+        };
+        match key {
+            Key::Ctrl('c') => return AppReturn::Exit,
+            Key::Esc => self.goto = None,
+            Key::Backspace => {
+                goto.pop();
+            }
+            Key::Char(c) if c.is_ascii_digit() || (goto.is_empty() && (c == 'l' || c == 'L')) => {
+                goto.push(c)
+            }
+            Key::Enter => {
+                let target = self.goto.take().unwrap_or_default();
+                let line = match target.strip_prefix(['l', 'L']) {
+                    Some(layer) => layer
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|layer| self.listing.index().layers().get(layer).copied()),
+                    None => target
+                        .parse::<usize>()
+                        .ok()
+                        .map(|line| line.saturating_sub(1)),
+                };
+                if let Some(line) = line {
+                    self.go_to_line(line);
+                }
+            }
+            _ => {}
+        }
+        AppReturn::Continue
+    }
+
+    /// Moves the cursor to `line` of the file, or to the last line indexed so far.
+    fn go_to_line(&mut self, line: usize) {
+        self.link = 0;
+        let last = self.listing.len().saturating_sub(1);
+        self.selected = self.listing.row_of_line(line).min(last);
+        // show the line at the top of the screen
+        self.offset = self.selected;
+    }
+
+    fn update_search(&mut self) {
+        if let Some(search) = self.search.as_mut() {
+            search.hits = marlin_docs::search(&self.opcodes, &search.query, SEARCH_LIMIT);
+            search.selected = 0;
+        }
+    }
+
+    /// Pins `od` in the documentation panel with its first link selected.
+    fn show_doc(&mut self, od: Arc<OpcodeDescription>) {
+        self.pinned_doc = Some(od);
+        self.link = 0;
+    }
+
+    fn move_cursor(&mut self, delta: isize) -> AppReturn {
+        self.link = 0;
+        let last = self.listing.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        AppReturn::Continue
+    }
+
+    /// Keeps the cursor on screen for a listing `height` lines high.
+    pub fn scroll_to_cursor(&mut self, height: usize) {
+        self.page_size = height.max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.page_size {
+            self.offset = self.selected + 1 - self.page_size;
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    /// The rows shown on screen, for a listing `height` lines high.
+    pub fn visible_rows(&self, height: usize) -> Vec<ListingLine> {
+        self.listing.rows(self.offset, height)
+    }
+
+    /// The thumbnail under the cursor.
+    pub fn selected_thumbnail(&self) -> Option<Arc<ThumbnailPreview>> {
+        self.listing.row(self.selected)?.thumbnail
+    }
+
+    /// The layer view, while it is shown.
+    pub fn layer_view(&mut self) -> Option<&mut LayerView> {
+        self.layer_view.as_mut().filter(|_| self.show_layer)
+    }
+
+    /// The line of the file under the cursor.
+    pub fn selected_line(&self) -> usize {
+        self.listing.line_of_row(self.selected)
+    }
+
+    /// The line or layer typed in the go to popup, while it is open.
+    pub fn goto(&self) -> Option<&str> {
+        self.goto.as_deref()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn search(&self) -> Option<&SearchPopup> {
+        self.search.as_ref()
+    }
+
+    pub fn groups(&self) -> Option<&GroupTree> {
+        self.groups.as_ref()
+    }
+
+    /// How often the browsed file uses `od`.
+    pub fn doc_usage(&self, od: &OpcodeDescription) -> usize {
+        self.doc_usage.get(&od.tag).copied().unwrap_or(0)
+    }
+
+    pub fn xrefs(&self) -> &CrossReferences {
+        &self.xrefs
+    }
+
+    /// The description the selected link of the documentation panel points to.
+    ///
+    /// Links can't be followed while the group navigator is open.
+    pub fn selected_link(&self) -> Option<Arc<OpcodeDescription>> {
+        if self.groups.is_some() {
+            return None;
+        }
+        let od = self.current_doc()?;
+        self.xrefs.references(&od).get(self.link).cloned()
+    }
+
+    pub fn actions(&self) -> &Actions {
+        &self.actions
+    }
+
+    /// The documentation to show: the command selected in the group navigator, the one picked
+    /// in the search popup or in the navigator, or the one of the command under the cursor.
+    pub fn current_doc(&self) -> Option<Arc<OpcodeDescription>> {
+        if let Some(groups) = &self.groups {
+            return groups.selected_doc();
+        }
+        self.pinned_doc.clone().or_else(|| {
+            self.listing
+                .row(self.selected)
+                .and_then(|line| line.opcode)
+                .and_then(|opcode| self.opcodes.get(&opcode).cloned())
+        })
+    }
+
+    #[allow(unused)]
     pub fn is_loading(&self) -> bool {
         self.is_loading
     }
@@ -156,6 +638,11 @@ impl App {
 
     pub fn update_on_tick(&mut self) -> AppReturn {
         self.state.incr_tick();
+        if !self.indexed {
+            // read first, the last update has to see every line
+            self.indexed = self.listing.index().is_complete();
+            self.update_doc_usage();
+        }
         AppReturn::Continue
     }
 
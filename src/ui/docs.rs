@@ -0,0 +1,99 @@
+use gcode_viz::gcode::marlin_docs::links::CrossReferences;
+use gcode_viz::gcode::marlin_docs::render::body_to_text;
+use gcode_viz::gcode::marlin_docs::OpcodeDescription;
+use std::sync::Arc;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans, Text};
+
+/// Formats a description for the documentation panel.
+///
+/// The pages `od` links to are listed after the notes, with the `selected_link` highlighted,
+/// followed by the pages linking to `od`.
+pub fn doc_text(
+    od: &OpcodeDescription,
+    xrefs: &CrossReferences,
+    selected_link: Option<&Arc<OpcodeDescription>>,
+) -> Text<'static> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let heading = Style::default()
+        .fg(Color::LightCyan)
+        .add_modifier(Modifier::BOLD);
+
+    let mut lines = vec![
+        Spans::from(vec![
+            Span::styled(od.codes.join(", "), heading),
+            Span::raw(" - "),
+            Span::styled(od.title.clone(), bold),
+        ]),
+        Spans::from(od.brief.clone()),
+    ];
+    if let Some(requires) = &od.requires {
+        lines.push(Spans::from(Span::styled(
+            format!("Requires: {}", requires),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+
+    if !od.parameters().is_empty() {
+        lines.push(Spans::default());
+        lines.push(Spans::from(Span::styled("Parameters", heading)));
+        for p in od.parameters() {
+            let tag = if p.optional {
+                format!("[{}]", p.tag)
+            } else {
+                p.tag.clone()
+            };
+            lines.push(Spans::from(vec![
+                Span::styled(format!("  {:<6}", tag), bold),
+                Span::raw(p.description.clone().unwrap_or_default()),
+            ]));
+        }
+    }
+
+    if !od.notes().is_empty() {
+        lines.push(Spans::default());
+        lines.push(Spans::from(Span::styled("Notes", heading)));
+        for note in od.notes() {
+            lines.push(Spans::from(format!("  - {}", body_to_text(note).join(" "))));
+        }
+    }
+
+    let references = xrefs.references(od);
+    if !references.is_empty() {
+        lines.push(Spans::default());
+        lines.push(Spans::from(Span::styled("See also (Tab, Enter)", heading)));
+        for target in references {
+            let style = if selected_link.is_some_and(|link| Arc::ptr_eq(link, target)) {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default().fg(Color::LightBlue)
+            };
+            lines.push(Spans::from(vec![
+                Span::raw("  "),
+                Span::styled(
+                    format!("{} - {}", target.codes.join(", "), target.title),
+                    style,
+                ),
+            ]));
+        }
+    }
+
+    let referenced_by = xrefs.referenced_by(od);
+    if !referenced_by.is_empty() {
+        lines.push(Spans::default());
+        lines.push(Spans::from(Span::styled("Referenced by", heading)));
+        lines.push(Spans::from(format!(
+            "  {}",
+            referenced_by
+                .iter()
+                .map(|od| od.codes.join(", "))
+                .collect::<Vec<_>>()
+                .join("; ")
+        )));
+    }
+
+    lines.push(Spans::default());
+    lines.extend(body_to_text(&od.body).into_iter().map(Spans::from));
+
+    Text::from(lines)
+}
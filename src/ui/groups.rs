@@ -0,0 +1,93 @@
+use gcode_viz::gcode::marlin_docs::groups::GroupIndex;
+use gcode_viz::gcode::marlin_docs::OpcodeDescription;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A row of the group tree as shown on screen.
+pub enum TreeRow<'a> {
+    Group {
+        name: &'a str,
+        descriptions: &'a [Arc<OpcodeDescription>],
+        expanded: bool,
+    },
+    Command(&'a Arc<OpcodeDescription>),
+}
+
+/// The documentation group navigator: groups that expand into their commands.
+pub struct GroupTree {
+    groups: Vec<(String, Vec<Arc<OpcodeDescription>>)>,
+    expanded: Vec<bool>,
+    selected: usize,
+}
+
+impl GroupTree {
+    pub fn new(index: GroupIndex) -> Self {
+        let groups: Vec<_> = index.into_iter().collect();
+        let expanded = vec![false; groups.len()];
+        Self {
+            groups,
+            expanded,
+            selected: 0,
+        }
+    }
+
+    /// The visible rows, with the index of the group each one belongs to.
+    pub fn rows(&self) -> Vec<(usize, TreeRow<'_>)> {
+        let mut rows = vec![];
+        for (i, (name, descriptions)) in self.groups.iter().enumerate() {
+            rows.push((
+                i,
+                TreeRow::Group {
+                    name,
+                    descriptions,
+                    expanded: self.expanded[i],
+                },
+            ));
+            if self.expanded[i] {
+                rows.extend(descriptions.iter().map(|od| (i, TreeRow::Command(od))));
+            }
+        }
+        rows
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.rows().len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Expands or collapses the group under the cursor, `None` toggles it.
+    pub fn set_expanded(&mut self, expanded: Option<bool>) {
+        let rows = self.rows();
+        let Some((group, row)) = rows.get(self.selected) else {
+            return;
+        };
+        let group = *group;
+        let is_command = matches!(row, TreeRow::Command(_));
+        let expanded = expanded.unwrap_or(!self.expanded[group]);
+
+        self.expanded[group] = expanded;
+        if !expanded && is_command {
+            // collapsing from a command moves the cursor up to its group
+            self.selected = self
+                .rows()
+                .iter()
+                .position(|(g, _)| *g == group)
+                .unwrap_or(0);
+        }
+    }
+
+    /// The description under the cursor, if the cursor is on a command.
+    pub fn selected_doc(&self) -> Option<Arc<OpcodeDescription>> {
+        match self.rows().get(self.selected) {
+            Some((_, TreeRow::Command(od))) => Some((*od).clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Number of times each description is used in the browsed file, keyed by its tag.
+pub type DocUsage = HashMap<String, usize>;
@@ -1,7 +1,7 @@
 use crate::App;
+use log::info;
 use std::sync::Arc;
 use std::time::Duration;
-use log::info;
 
 #[derive(Debug, Clone)]
 pub enum IoEvent {
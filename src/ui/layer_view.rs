@@ -0,0 +1,113 @@
+use gcode_viz::gcode::machine::Toolpath;
+use gcode_viz::gcode::parallel;
+use gcode_viz::gcode::profile::MachineProfile;
+use gcode_viz::gcode::width::DEFAULT_TOLERANCE;
+use gcode_viz::input::GcodeText;
+use gcode_viz::render::raster::{rasterize, Image, RasterOptions};
+use gcode_viz::render::{ColorBy, Colorizer, Rgb};
+use std::sync::{Arc, OnceLock};
+
+/// The layers of the browsed file, coloured by the width of their lines relative to the
+/// median of their feature.
+///
+/// The file is simulated on a background thread the first time the view is opened, the view
+/// shows a message until then.
+pub struct LayerView {
+    simulated: Arc<OnceLock<Simulated>>,
+    /// The last image drawn, with its layer and size in cells
+    cache: Option<((usize, u16, u16), Arc<Image>)>,
+}
+
+struct Simulated {
+    toolpath: Toolpath,
+    colorizer: Colorizer,
+}
+
+/// Counts of the moves of a layer far from the median width of their feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Outliers {
+    pub over: usize,
+    pub under: usize,
+}
+
+impl LayerView {
+    /// Starts simulating `text` in the background.
+    pub fn spawn(text: Arc<GcodeText>) -> Self {
+        let simulated = Arc::new(OnceLock::new());
+        let result = Arc::clone(&simulated);
+        std::thread::spawn(move || {
+            let toolpath = parallel::simulate(&text.text());
+            let colorizer = Colorizer::new(
+                &toolpath,
+                ColorBy::WidthDeviation,
+                &MachineProfile::default(),
+            );
+            let _ = result.set(Simulated {
+                toolpath,
+                colorizer,
+            });
+        });
+        Self {
+            simulated,
+            cache: None,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.simulated.get().is_some()
+    }
+
+    /// The layer holding `line` of the file, once simulated.
+    pub fn layer_of(&self, line: usize) -> Option<usize> {
+        let layers = &self.simulated.get()?.toolpath.layers;
+        let index = layers.partition_point(|layer| layer.lines.start <= line);
+        Some(index.saturating_sub(1)).filter(|_| !layers.is_empty())
+    }
+
+    /// The moves of `layer` deviating from the median width of their feature by more than
+    /// [`DEFAULT_TOLERANCE`].
+    pub fn outliers(&self, layer: usize) -> Outliers {
+        let mut outliers = Outliers::default();
+        let Some(simulated) = self.simulated.get() else {
+            return outliers;
+        };
+        let Some(layer) = simulated.toolpath.layers.get(layer) else {
+            return outliers;
+        };
+        let widths = simulated.colorizer.widths();
+        for m in simulated.toolpath.layer_moves(layer) {
+            match widths.deviation(m) {
+                Some(d) if d > DEFAULT_TOLERANCE => outliers.over += 1,
+                Some(d) if d < -DEFAULT_TOLERANCE => outliers.under += 1,
+                _ => {}
+            }
+        }
+        outliers
+    }
+
+    /// `layer` drawn from above in `columns` by `rows` cells of two pixels each.
+    pub fn image(&mut self, layer: usize, columns: u16, rows: u16) -> Option<Arc<Image>> {
+        let key = (layer, columns, rows);
+        if let Some((cached, image)) = &self.cache {
+            if *cached == key {
+                return Some(Arc::clone(image));
+            }
+        }
+        let simulated = self.simulated.get()?;
+        let options = RasterOptions {
+            width: columns.max(1) as u32,
+            height: Some(rows.max(1) as u32 * 2),
+            background: Rgb(0, 0, 0),
+            ..RasterOptions::default()
+        };
+        let image = Arc::new(rasterize(
+            &simulated.toolpath,
+            layer..layer + 1,
+            &simulated.colorizer,
+            None,
+            &options,
+        ));
+        self.cache = Some((key, Arc::clone(&image)));
+        Some(image)
+    }
+}
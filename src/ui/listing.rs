@@ -0,0 +1,147 @@
+use crate::ui::app::{ListingLine, ThumbnailPreview};
+use gcode_viz::gcode::index::LineIndex;
+use gcode_viz::gcode::thumbnails::find_thumbnails;
+use gcode_viz::input::GcodeText;
+use std::sync::Arc;
+
+/// The rows of the browsed file, read from the file through its [`LineIndex`] when they are
+/// shown rather than all at once.
+///
+/// Rows are the lines of the file, except that each thumbnail block is collapsed into a single
+/// row. Thumbnails are only looked for in the comments before the first command, where slicers
+/// put them.
+pub struct Listing {
+    text: Arc<GcodeText>,
+    index: Arc<LineIndex>,
+    /// The collapsed thumbnail blocks, in order
+    thumbnails: Vec<Arc<ThumbnailPreview>>,
+}
+
+/// The length of the comments and empty lines at the start of `text`.
+fn header(text: &[u8]) -> usize {
+    let mut end = 0;
+    for line in text.split(|&b| b == b'\n') {
+        let trimmed = line.trim_ascii();
+        if !trimmed.is_empty() && !trimmed.starts_with(b";") {
+            break;
+        }
+        end = (end + line.len() + 1).min(text.len());
+    }
+    end
+}
+
+impl Listing {
+    /// Starts indexing `text` in the background.
+    pub fn new(text: GcodeText) -> Self {
+        let text = Arc::new(text);
+        let thumbnails = find_thumbnails(&text.slice(0..header(text.as_bytes())))
+            .into_iter()
+            .map(|thumbnail| Arc::new(ThumbnailPreview::new(thumbnail)))
+            .collect();
+        Self {
+            index: LineIndex::spawn(Arc::clone(&text)),
+            text,
+            thumbnails,
+        }
+    }
+
+    pub fn text(&self) -> &Arc<GcodeText> {
+        &self.text
+    }
+
+    pub fn index(&self) -> &LineIndex {
+        &self.index
+    }
+
+    /// Number of rows indexed so far.
+    pub fn len(&self) -> usize {
+        self.row_of_line(self.index.lines())
+    }
+
+    /// Number of lines of the file indexed so far.
+    pub fn line_count(&self) -> usize {
+        self.index.lines()
+    }
+
+    /// The line of the file shown at `row`.
+    pub fn line_of_row(&self, row: usize) -> usize {
+        let mut line = row;
+        for preview in self.thumbnails.iter() {
+            let lines = &preview.thumbnail.lines;
+            if lines.start >= line {
+                break;
+            }
+            line += lines.len() - 1;
+        }
+        line
+    }
+
+    /// The row showing `line`, the row of its thumbnail for collapsed lines.
+    pub fn row_of_line(&self, line: usize) -> usize {
+        let mut row = line;
+        for preview in self.thumbnails.iter() {
+            let lines = &preview.thumbnail.lines;
+            if lines.start >= line {
+                break;
+            }
+            row -= (line - lines.start).min(lines.len() - 1);
+        }
+        row
+    }
+
+    /// The row `row`, if it is indexed.
+    pub fn row(&self, row: usize) -> Option<ListingLine> {
+        let number = self.line_of_row(row);
+        if let Some(preview) = self
+            .thumbnails
+            .iter()
+            .find(|preview| preview.thumbnail.lines.start == number)
+        {
+            return Some(ListingLine::thumbnail(Arc::clone(preview)));
+        }
+        let range = self.index.line_range(self.text.as_bytes(), number)?;
+        Some(ListingLine::new(number, &self.text.slice(range)))
+    }
+
+    /// The rows from `start` on, at most `count` of them.
+    pub fn rows(&self, start: usize, count: usize) -> Vec<ListingLine> {
+        (start..start + count)
+            .map_while(|row| self.row(row))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_map_rows_and_lines_around_thumbnails() {
+        // lines 1 to 4 and 6 to 8 are thumbnails
+        let src = "; header\n; thumbnail begin 16x16 8\n; AAAA\n; AAAA\n; thumbnail end\n;\n\
+                   ; thumbnail_JPG begin 8x8 4\n; AAAA\n; thumbnail_JPG end\nG28\nG1 X1 E1\n";
+        let listing = Listing::new(GcodeText::Owned(src.to_string()));
+        while !listing.index().is_complete() {
+            std::thread::yield_now();
+        }
+        assert_eq!(listing.line_count(), 11);
+        assert_eq!(listing.len(), 6);
+
+        let lines: Vec<usize> = (0..6).map(|row| listing.line_of_row(row)).collect();
+        assert_eq!(lines, vec![0, 1, 5, 6, 9, 10]);
+        for (row, line) in lines.iter().enumerate() {
+            assert_eq!(listing.row_of_line(*line), row);
+        }
+        // the lines of a thumbnail are on its row
+        assert_eq!(listing.row_of_line(3), 1);
+        assert_eq!(listing.row_of_line(4), 1);
+        assert_eq!(listing.row_of_line(8), 3);
+
+        let rows = listing.rows(0, 10);
+        assert_eq!(rows.len(), 6);
+        assert!(rows[1].thumbnail.is_some());
+        assert_eq!(rows[4].text, "G28");
+        assert_eq!(rows[5].opcode.as_deref(), Some("G1"));
+        assert!(listing.row(6).is_none());
+    }
+}
@@ -1,45 +1,109 @@
-use crate::ui::app::AppReturn;
+use crate::ui::app::{AppReturn, SearchPopup, ThumbnailPreview};
+use crate::ui::layer_view::LayerView;
 use app::App;
-use crossterm::event;
 use events::Events;
+use groups::{GroupTree, TreeRow};
 use io::IoEvent;
 use key::Key;
 use std::io::stdout;
-use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
 use tui::backend::{Backend, CrosstermBackend};
 use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Style};
-use tui::widgets::{Block, BorderType, Borders, Paragraph};
+use tui::text::{Span, Spans, Text};
+use tui::widgets::{Block, BorderType, Borders, Clear, Paragraph, Wrap};
 use tui::{Frame, Terminal};
 
 mod actions;
 pub mod app;
+mod docs;
 mod events;
+mod groups;
 pub mod io;
 mod key;
+mod layer_view;
+pub mod listing;
+mod thumbnail;
 
-pub fn draw<B>(rect: &mut Frame<B>, _app: &App)
+pub fn draw<B>(rect: &mut Frame<B>, app: &mut App)
 where
     B: Backend,
 {
     let size = rect.size();
     check_size(&size);
 
-    let _chunks = Layout::default()
+    let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(3)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(3),
+                Constraint::Min(10),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
         .split(size);
 
-    let block = draw_title();
+    rect.render_widget(draw_title(app), chunks[0]);
+
+    let body = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)].as_ref())
+        .split(chunks[1]);
+
+    // minus the borders
+    app.scroll_to_cursor(body[0].height.saturating_sub(2) as usize);
+    match app.groups() {
+        Some(groups) => rect.render_widget(draw_groups(app, groups, body[0].height), body[0]),
+        None => rect.render_widget(draw_listing(app, body[0].height), body[0]),
+    }
+    let line = app.selected_line();
+    if let Some(view) = app.layer_view() {
+        rect.render_widget(draw_layer(view, line, body[1]), body[1]);
+    } else {
+        match app.selected_thumbnail() {
+            Some(preview) => rect.render_widget(draw_thumbnail(&preview, body[1]), body[1]),
+            None => rect.render_widget(draw_doc(app), body[1]),
+        }
+    }
+    rect.render_widget(draw_help(app), chunks[2]);
 
-    rect.render_widget(block, size);
+    if let Some(search) = app.search() {
+        let area = centered_rect(60, 60, size);
+        rect.render_widget(Clear, area);
+        rect.render_widget(draw_search(search, area.height), area);
+    }
+    if let Some(goto) = app.goto() {
+        let width = 40.min(size.width);
+        let area = Rect::new(
+            (size.width - width) / 2,
+            size.height / 3,
+            width,
+            3.min(size.height),
+        );
+        rect.render_widget(Clear, area);
+        rect.render_widget(draw_goto(goto), area);
+    }
 }
 
-fn draw_title<'a>() -> Paragraph<'a> {
-    Paragraph::new("Plop with TUI")
+fn draw_title<'a>(app: &App) -> Paragraph<'a> {
+    let listing = app.listing();
+    let index = listing.index();
+    let line = listing.line_of_row(app.selected());
+    let mut title = format!(
+        "{} - line {}/{}",
+        app.title(),
+        (line + 1).min(listing.line_count()),
+        listing.line_count()
+    );
+    if let Some(layer) = index.layer_of(line) {
+        title.push_str(&format!(", layer {}/{}", layer, index.layers().len()));
+    }
+    if !index.is_complete() {
+        title.push_str(&format!(" (indexing {:.0}%)", index.progress() * 100.0));
+    }
+    Paragraph::new(title)
         .style(Style::default().fg(Color::LightCyan))
         .alignment(Alignment::Center)
         .block(
@@ -50,6 +114,242 @@ fn draw_title<'a>() -> Paragraph<'a> {
         )
 }
 
+fn draw_listing<'a>(app: &App, height: u16) -> Paragraph<'a> {
+    let lines: Vec<Spans> = app
+        .visible_rows(height as usize)
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let i = i + app.offset();
+            let style = if line.thumbnail.is_some() {
+                Style::default().fg(Color::Magenta)
+            } else if line.opcode.is_none() {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default()
+            };
+            let style = if i == app.selected() {
+                style.bg(Color::Blue).fg(Color::White)
+            } else {
+                style
+            };
+            Spans::from(vec![
+                Span::styled(
+                    format!("{:>7} ", line.number + 1),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled(line.text, style),
+            ])
+        })
+        .collect();
+
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("GCode"))
+}
+
+fn draw_groups<'a>(app: &App, groups: &GroupTree, height: u16) -> Paragraph<'a> {
+    let visible = height.saturating_sub(2).max(1) as usize;
+    let skip = (groups.selected() + 1).saturating_sub(visible);
+    let lines: Vec<Spans> = groups
+        .rows()
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .take(visible)
+        .map(|(i, (_, row))| {
+            let (text, style) = match row {
+                TreeRow::Group {
+                    name,
+                    descriptions,
+                    expanded,
+                } => {
+                    let uses: usize = descriptions.iter().map(|od| app.doc_usage(od)).sum();
+                    (
+                        format!(
+                            "{} {} ({} commands, {} uses in file)",
+                            if *expanded { "▾" } else { "▸" },
+                            name,
+                            descriptions.len(),
+                            uses
+                        ),
+                        Style::default().fg(Color::LightCyan),
+                    )
+                }
+                TreeRow::Command(od) => {
+                    let uses = app.doc_usage(od);
+                    let style = if uses > 0 {
+                        Style::default()
+                    } else {
+                        Style::default().fg(Color::DarkGray)
+                    };
+                    (
+                        format!("    {:<12} {} ({})", od.codes.join(","), od.title, uses),
+                        style,
+                    )
+                }
+            };
+            let style = if i == groups.selected() {
+                style.bg(Color::Blue).fg(Color::White)
+            } else {
+                style
+            };
+            Spans::from(Span::styled(text, style))
+        })
+        .collect();
+
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Groups (Enter/Right: expand, Left: collapse, Esc: close)"),
+    )
+}
+
+fn draw_doc<'a>(app: &App) -> Paragraph<'a> {
+    let text = match app.current_doc() {
+        Some(od) => docs::doc_text(&od, app.xrefs(), app.selected_link().as_ref()),
+        None => Text::from("No documentation for this line"),
+    };
+    Paragraph::new(text).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Documentation"),
+    )
+}
+
+fn draw_thumbnail<'a>(preview: &ThumbnailPreview, area: Rect) -> Paragraph<'a> {
+    let thumbnail = &preview.thumbnail;
+    let mut title = format!(
+        "Thumbnail {}x{} {}",
+        thumbnail.width, thumbnail.height, thumbnail.format
+    );
+    if !thumbnail.is_complete() {
+        title.push_str(", incomplete");
+    }
+    let block = Block::default().borders(Borders::ALL).title(title);
+    match &preview.image {
+        Ok(image) => Paragraph::new(thumbnail::half_blocks(
+            image,
+            area.width.saturating_sub(2),
+            area.height.saturating_sub(2),
+        ))
+        .block(block),
+        Err(err) => Paragraph::new(format!("Can't decode the thumbnail: {}", err))
+            .wrap(Wrap { trim: false })
+            .block(block),
+    }
+}
+
+fn draw_layer<'a>(view: &mut LayerView, line: usize, area: Rect) -> Paragraph<'a> {
+    let block = Block::default().borders(Borders::ALL);
+    let Some(layer) = view.layer_of(line) else {
+        let message = if view.is_ready() {
+            "Nothing is printed"
+        } else {
+            "Simulating the file…"
+        };
+        return Paragraph::new(message).block(block.title("Layer widths"));
+    };
+    let outliers = view.outliers(layer);
+    let title = format!(
+        "Layer {} widths, blue narrow, red wide: {} over, {} under",
+        layer, outliers.over, outliers.under
+    );
+    let (columns, rows) = (area.width.saturating_sub(2), area.height.saturating_sub(2));
+    match view.image(layer, columns, rows) {
+        Some(image) => {
+            Paragraph::new(thumbnail::half_blocks(&image, columns, rows)).block(block.title(title))
+        }
+        None => Paragraph::new("").block(block.title(title)),
+    }
+}
+
+fn draw_help<'a>(app: &App) -> Paragraph<'a> {
+    let help = app
+        .actions()
+        .actions()
+        .iter()
+        .map(|action| format!("{}: {}", action.keys()[0], action))
+        .collect::<Vec<_>>()
+        .join("  ");
+    Paragraph::new(help).style(Style::default().fg(Color::DarkGray))
+}
+
+fn draw_goto<'a>(goto: &str) -> Paragraph<'a> {
+    Paragraph::new(Spans::from(vec![
+        Span::styled("> ", Style::default().fg(Color::Yellow)),
+        Span::raw(goto.to_string()),
+    ]))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Go to line, or layer with l12"),
+    )
+}
+
+fn draw_search<'a>(search: &SearchPopup, height: u16) -> Paragraph<'a> {
+    let mut lines = vec![
+        Spans::from(vec![
+            Span::styled("> ", Style::default().fg(Color::Yellow)),
+            Span::raw(search.query.clone()),
+        ]),
+        Spans::default(),
+    ];
+    // keep the selected hit visible
+    let visible = height.saturating_sub(4).max(1) as usize;
+    let skip = (search.selected + 1).saturating_sub(visible);
+    lines.extend(
+        search
+            .hits
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take(visible)
+            .map(|(i, hit)| {
+                let od = &hit.description;
+                let style = if i == search.selected {
+                    Style::default().bg(Color::Blue).fg(Color::White)
+                } else {
+                    Style::default()
+                };
+                Spans::from(Span::styled(
+                    format!("{:<12} {} - {}", od.codes.join(","), od.title, od.brief),
+                    style,
+                ))
+            }),
+    );
+
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Search documentation (Enter: show, Esc: close)"),
+    )
+}
+
+/// A rectangle of `percent_x` by `percent_y` of `r`, centered in it.
+fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_y) / 2),
+                Constraint::Percentage(percent_y),
+                Constraint::Percentage((100 - percent_y) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(r);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_x) / 2),
+                Constraint::Percentage(percent_x),
+                Constraint::Percentage((100 - percent_x) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(vertical[1])[1]
+}
+
 fn check_size(rect: &Rect) {
     if rect.width < 52 || rect.height < 28 {
         panic!("Terminal too small");
@@ -79,13 +379,13 @@ pub async fn start_ui(app: Arc<tokio::sync::Mutex<App>>) -> Result<(), eyre::Err
 
     {
         let mut app = app.lock().await;
-        app.dispatch(IoEvent::Initialize);
+        app.dispatch(IoEvent::Initialize).await;
     }
 
     loop {
         let mut app = app.lock().await;
 
-        terminal.draw(|rect| draw(rect, &app))?;
+        terminal.draw(|rect| draw(rect, &mut app))?;
 
         let result = match events.next().await {
             InputEvent::Input(key) => app.do_action(key).await,
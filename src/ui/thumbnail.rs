@@ -0,0 +1,57 @@
+use gcode_viz::render::raster::Image;
+use tui::style::{Color, Style};
+use tui::text::{Span, Spans, Text};
+
+/// Draws `image` in at most `columns` by `rows` cells, two pixels per cell: the upper half
+/// block takes the colour of the top pixel and its background the one of the bottom pixel.
+pub fn half_blocks<'a>(image: &Image, columns: u16, rows: u16) -> Text<'a> {
+    if image.width == 0 || image.height == 0 || columns == 0 || rows == 0 {
+        return Text::default();
+    }
+    let scale = (columns as f64 / image.width as f64).min(rows as f64 * 2.0 / image.height as f64);
+    let width = ((image.width as f64 * scale).floor() as u32).max(1);
+    let height = ((image.height as f64 * scale).floor() as u32).max(1);
+
+    let lines: Vec<Spans> = (0..height.div_ceil(2))
+        .map(|row| {
+            let spans: Vec<Span> = (0..width)
+                .map(|x| {
+                    let top = average(image, x, row * 2, width, height);
+                    let style = Style::default().fg(top);
+                    let style = if row * 2 + 1 < height {
+                        style.bg(average(image, x, row * 2 + 1, width, height))
+                    } else {
+                        style
+                    };
+                    Span::styled("▀", style)
+                })
+                .collect();
+            Spans::from(spans)
+        })
+        .collect();
+    Text::from(lines)
+}
+
+/// The mean colour of the pixels of `image` covered by the pixel `x`, `y` of the image scaled
+/// to `width` by `height`.
+fn average(image: &Image, x: u32, y: u32, width: u32, height: u32) -> Color {
+    let x0 = x * image.width / width;
+    let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+    let y0 = y * image.height / height;
+    let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+    let mut sum = [0u32; 3];
+    for py in y0..y1.min(image.height) {
+        for px in x0..x1.min(image.width) {
+            let pixel = image.pixel(px, py);
+            sum[0] += pixel.0 as u32;
+            sum[1] += pixel.1 as u32;
+            sum[2] += pixel.2 as u32;
+        }
+    }
+    let count = ((x1.min(image.width) - x0) * (y1.min(image.height) - y0)).max(1);
+    Color::Rgb(
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    )
+}